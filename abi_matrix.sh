@@ -0,0 +1,40 @@
+#!/usr/bin/env bash
+
+# Runs the mount tests once for every abi-7-XX feature and reports which ones work on the
+# running kernel, to catch regressions in the negotiation with the kernel. Docker containers
+# share the kernel of the host, so to cover several kernels, run it on hosts (or VMs) with
+# different kernels and combine the reports in logs/.
+
+export RUST_BACKTRACE=1
+
+NC="\e[39m"
+GREEN="\e[32m"
+RED="\e[31m"
+
+KERNEL=$(uname -r)
+REPORT="logs/abi_matrix-${KERNEL}.tsv"
+FEATURES="none $(sed -n 's/^\(abi-7-[0-9]*\) = .*/\1/p' Cargo.toml)"
+
+mkdir -p logs
+printf "kernel\tfeature\tresult\n" > "$REPORT"
+
+TEST_EXIT_STATUS=0
+for feature in $FEATURES; do
+  if [[ "$feature" == "none" ]]; then
+      FLAGS="--no-default-features"
+  else
+      FLAGS="--no-default-features --features=$feature"
+  fi
+  if cargo test $FLAGS --test integration_tests > "logs/abi_matrix-${KERNEL}-${feature}.log" 2>&1; then
+      echo -e "$GREEN OK $feature $NC"
+      RESULT=ok
+  else
+      echo -e "$RED FAILED $feature (see logs/abi_matrix-${KERNEL}-${feature}.log) $NC"
+      RESULT=failed
+      TEST_EXIT_STATUS=1
+  fi
+  printf "%s\t%s\t%s\n" "$KERNEL" "$feature" "$RESULT" >> "$REPORT"
+done
+
+cat "$REPORT"
+exit $TEST_EXIT_STATUS
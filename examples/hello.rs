@@ -1,7 +1,7 @@
 use clap::{crate_version, Arg, ArgAction, Command};
 use fuser::{
-    FileAttr, FileType, Filesystem, MountOption, ReplyAttr, ReplyData, ReplyDirectory, ReplyEntry,
-    Request,
+    DirEntOffset, FileAttr, FileType, Filesystem, MountOption, ReplyAttr, ReplyData,
+    ReplyDirectory, ReplyEntry, Request,
 };
 use libc::ENOENT;
 use std::ffi::OsStr;
@@ -52,7 +52,7 @@ struct HelloFS;
 impl Filesystem for HelloFS {
     fn lookup(&mut self, _req: &Request, parent: u64, name: &OsStr, reply: ReplyEntry) {
         if parent == 1 && name.to_str() == Some("hello.txt") {
-            reply.entry(&TTL, &HELLO_TXT_ATTR, 0);
+            reply.entry(TTL, &HELLO_TXT_ATTR, 0);
         } else {
             reply.error(ENOENT);
         }
@@ -60,8 +60,8 @@ impl Filesystem for HelloFS {
 
     fn getattr(&mut self, _req: &Request, ino: u64, _fh: Option<u64>, reply: ReplyAttr) {
         match ino {
-            1 => reply.attr(&TTL, &HELLO_DIR_ATTR),
-            2 => reply.attr(&TTL, &HELLO_TXT_ATTR),
+            1 => reply.attr(TTL, &HELLO_DIR_ATTR),
+            2 => reply.attr(TTL, &HELLO_TXT_ATTR),
             _ => reply.error(ENOENT),
         }
     }
@@ -104,8 +104,13 @@ impl Filesystem for HelloFS {
         ];
 
         for (i, entry) in entries.into_iter().enumerate().skip(offset as usize) {
-            // i + 1 means the index of the next entry
-            if reply.add(entry.0, (i + 1) as i64, entry.1, entry.2) {
+            // The offset of an entry is the index of the next entry, never 0
+            if reply.add(
+                entry.0,
+                DirEntOffset::after_index(i).get(),
+                entry.1,
+                entry.2,
+            ) {
                 break;
             }
         }
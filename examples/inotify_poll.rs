@@ -0,0 +1,283 @@
+// Exposes the inotify events of a directory as a file that can be polled.
+//
+// Each open of `events` gets its own queue of events, one line per event, which becomes
+// readable when the watched directory changes. Poll handles are kept in a PollRegistry: the
+// filesystem registers them when the kernel asks to be notified and forgets them on release,
+// and the inotify thread uses them up when it queues an event.
+//
+// Try it with the poll_client example, or with:
+//     python3 -c 'import select; p = select.poll(); f = open("MNT/events"); \
+//         p.register(f); p.poll(); print(f.read())'
+
+use std::{
+    collections::HashMap,
+    ffi::{CString, OsStr},
+    io,
+    os::unix::ffi::OsStringExt,
+    path::PathBuf,
+    sync::{Arc, Mutex},
+    thread,
+    time::{Duration, UNIX_EPOCH},
+};
+
+use clap::Parser;
+use libc::{EACCES, EBADF, ENOENT, ENOTDIR};
+
+use fuser::{
+    consts::{FOPEN_DIRECT_IO, FOPEN_NONSEEKABLE, FUSE_POLL_SCHEDULE_NOTIFY},
+    FileAttr, FileType, Filesystem, MountOption, PollEvents, PollHandle, PollRegistry, ReplyAttr,
+    ReplyData, ReplyDirectory, ReplyEmpty, ReplyEntry, ReplyOpen, ReplyPoll, Request, FUSE_ROOT_ID,
+};
+
+const EVENTS_INO: u64 = 2;
+const EVENTS_NAME: &str = "events";
+
+/// Events not read yet, by file handle
+type Queues = Arc<Mutex<HashMap<u64, Vec<u8>>>>;
+
+struct InotifyFS {
+    queues: Queues,
+    polls: PollRegistry,
+    next_fh: u64,
+}
+
+fn attr(ino: u64) -> FileAttr {
+    let (kind, perm, nlink) = if ino == FUSE_ROOT_ID {
+        (FileType::Directory, 0o555, 2)
+    } else {
+        (FileType::RegularFile, 0o444, 1)
+    };
+    FileAttr {
+        ino,
+        size: 0,
+        blocks: 0,
+        atime: UNIX_EPOCH,
+        mtime: UNIX_EPOCH,
+        ctime: UNIX_EPOCH,
+        crtime: UNIX_EPOCH,
+        kind,
+        perm,
+        nlink,
+        uid: 0,
+        gid: 0,
+        rdev: 0,
+        flags: 0,
+        blksize: 0,
+    }
+}
+
+impl Filesystem for InotifyFS {
+    fn lookup(&mut self, _req: &Request, parent: u64, name: &OsStr, reply: ReplyEntry) {
+        if parent == FUSE_ROOT_ID && name == EVENTS_NAME {
+            reply.entry(Duration::MAX, &attr(EVENTS_INO), 0);
+        } else {
+            reply.error(ENOENT);
+        }
+    }
+
+    fn getattr(&mut self, _req: &Request, ino: u64, _fh: Option<u64>, reply: ReplyAttr) {
+        match ino {
+            FUSE_ROOT_ID | EVENTS_INO => reply.attr(Duration::MAX, &attr(ino)),
+            _ => reply.error(ENOENT),
+        }
+    }
+
+    fn readdir(
+        &mut self,
+        _req: &Request,
+        ino: u64,
+        _fh: u64,
+        offset: i64,
+        mut reply: ReplyDirectory,
+    ) {
+        if ino != FUSE_ROOT_ID {
+            reply.error(ENOTDIR);
+            return;
+        }
+        if offset == 0 {
+            let _ = reply.add(EVENTS_INO, 1, FileType::RegularFile, EVENTS_NAME);
+        }
+        reply.ok();
+    }
+
+    fn open(&mut self, _req: &Request, ino: u64, flags: i32, reply: ReplyOpen) {
+        if ino != EVENTS_INO {
+            reply.error(ENOENT);
+            return;
+        }
+        if (flags & libc::O_ACCMODE) != libc::O_RDONLY {
+            reply.error(EACCES);
+            return;
+        }
+        let fh = self.next_fh;
+        self.next_fh += 1;
+        self.queues.lock().unwrap().insert(fh, vec![]);
+        reply.opened(fh, FOPEN_DIRECT_IO | FOPEN_NONSEEKABLE);
+    }
+
+    fn release(
+        &mut self,
+        _req: &Request,
+        _ino: u64,
+        fh: u64,
+        _flags: i32,
+        _lock_owner: Option<u64>,
+        _flush: bool,
+        reply: ReplyEmpty,
+    ) {
+        // A poll still waiting on the handle must not be notified anymore
+        self.polls.release(fh);
+        self.queues.lock().unwrap().remove(&fh);
+        reply.ok();
+    }
+
+    fn read(
+        &mut self,
+        _req: &Request,
+        _ino: u64,
+        fh: u64,
+        _offset: i64,
+        size: u32,
+        _flags: i32,
+        _lock_owner: Option<u64>,
+        reply: ReplyData,
+    ) {
+        let mut queues = self.queues.lock().unwrap();
+        let Some(queue) = queues.get_mut(&fh) else {
+            reply.error(EBADF);
+            return;
+        };
+        let len = queue.len().min(size as usize);
+        reply.data(&queue.drain(..len).collect::<Vec<u8>>());
+    }
+
+    fn poll(
+        &mut self,
+        _req: &Request,
+        _ino: u64,
+        fh: u64,
+        ph: PollHandle,
+        _events: PollEvents,
+        flags: u32,
+        reply: ReplyPoll,
+    ) {
+        let queues = self.queues.lock().unwrap();
+        let Some(queue) = queues.get(&fh) else {
+            reply.error(EBADF);
+            return;
+        };
+        // Register before answering, while holding the lock, so that an event queued right
+        // after this poll finds the handle
+        if flags & FUSE_POLL_SCHEDULE_NOTIFY != 0 {
+            self.polls.register(fh, ph);
+        }
+        if queue.is_empty() {
+            reply.poll(PollEvents::empty());
+        } else {
+            reply.poll(PollEvents::IN);
+        }
+    }
+}
+
+fn event_name(mask: u32) -> &'static str {
+    [
+        (libc::IN_CREATE, "create"),
+        (libc::IN_DELETE, "delete"),
+        (libc::IN_MODIFY, "modify"),
+        (libc::IN_ATTRIB, "attrib"),
+        (libc::IN_CLOSE_WRITE, "close_write"),
+        (libc::IN_MOVED_FROM, "moved_from"),
+        (libc::IN_MOVED_TO, "moved_to"),
+        (libc::IN_DELETE_SELF, "delete_self"),
+        (libc::IN_MOVE_SELF, "move_self"),
+        (libc::IN_Q_OVERFLOW, "overflow"),
+    ]
+    .into_iter()
+    .find(|(bit, _)| mask & bit != 0)
+    .map_or("other", |(_, name)| name)
+}
+
+/// Queue every inotify event of `dir` for all open file handles, and notify their polls
+fn watch(dir: PathBuf, queues: Queues, polls: PollRegistry) -> io::Result<()> {
+    let fd = unsafe { libc::inotify_init1(libc::IN_CLOEXEC) };
+    if fd < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    let path = CString::new(dir.into_os_string().into_vec())?;
+    let mask = libc::IN_CREATE
+        | libc::IN_DELETE
+        | libc::IN_MODIFY
+        | libc::IN_ATTRIB
+        | libc::IN_CLOSE_WRITE
+        | libc::IN_MOVE
+        | libc::IN_DELETE_SELF
+        | libc::IN_MOVE_SELF;
+    if unsafe { libc::inotify_add_watch(fd, path.as_ptr(), mask) } < 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    let header = std::mem::size_of::<libc::inotify_event>();
+    let mut buf = vec![0u8; 64 * 1024];
+    loop {
+        let n = unsafe { libc::read(fd, buf.as_mut_ptr().cast(), buf.len()) };
+        if n < 0 {
+            let err = io::Error::last_os_error();
+            if err.kind() == io::ErrorKind::Interrupted {
+                continue;
+            }
+            return Err(err);
+        }
+
+        let mut lines = vec![];
+        let mut pos = 0;
+        while pos + header <= n as usize {
+            let event: libc::inotify_event =
+                unsafe { std::ptr::read_unaligned(buf[pos..].as_ptr().cast()) };
+            let name = &buf[pos + header..pos + header + event.len as usize];
+            let name = &name[..name.iter().position(|&b| b == 0).unwrap_or(name.len())];
+            lines.extend_from_slice(event_name(event.mask).as_bytes());
+            lines.push(b' ');
+            lines.extend_from_slice(name);
+            lines.push(b'\n');
+            pos += header + event.len as usize;
+        }
+
+        let mut queues = queues.lock().unwrap();
+        for (fh, queue) in queues.iter_mut() {
+            queue.extend_from_slice(&lines);
+            if let Err(e) = polls.notify(*fh) {
+                eprintln!("poll notification failed: {}", e);
+            }
+        }
+    }
+}
+
+#[derive(Parser)]
+struct Options {
+    /// Mount demo filesystem at given path
+    mount_point: String,
+
+    /// Directory to watch
+    #[clap(short, long, default_value = ".")]
+    watch: PathBuf,
+}
+
+fn main() {
+    let opts = Options::parse();
+    let options = vec![MountOption::RO, MountOption::FSName("inotify".to_string())];
+    let queues = Queues::default();
+    let polls = PollRegistry::new();
+    let fs = InotifyFS {
+        queues: queues.clone(),
+        polls: polls.clone(),
+        next_fh: 1,
+    };
+
+    let session = fuser::Session::new(fs, opts.mount_point, &options).unwrap();
+    let _bg = session.spawn().unwrap();
+
+    let watcher = thread::spawn(move || watch(opts.watch, queues, polls));
+    if let Err(e) = watcher.join().unwrap() {
+        eprintln!("watching failed: {}", e);
+    }
+}
@@ -72,7 +72,7 @@ impl FiocFS {
 impl Filesystem for FiocFS {
     fn lookup(&mut self, _req: &Request, parent: u64, name: &OsStr, reply: ReplyEntry) {
         if parent == 1 && name.to_str() == Some("fioc") {
-            reply.entry(&TTL, &self.fioc_file_attr, 0);
+            reply.entry(TTL, &self.fioc_file_attr, 0);
         } else {
             reply.error(ENOENT);
         }
@@ -80,8 +80,8 @@ impl Filesystem for FiocFS {
 
     fn getattr(&mut self, _req: &Request, ino: u64, _fh: Option<u64>, reply: ReplyAttr) {
         match ino {
-            1 => reply.attr(&TTL, &self.root_attr),
-            2 => reply.attr(&TTL, &self.fioc_file_attr),
+            1 => reply.attr(TTL, &self.root_attr),
+            2 => reply.attr(TTL, &self.fioc_file_attr),
             _ => reply.error(ENOENT),
         }
     }
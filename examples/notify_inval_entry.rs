@@ -75,7 +75,7 @@ impl<'a> Filesystem for ClockFS<'a> {
         }
 
         self.lookup_cnt.fetch_add(1, SeqCst);
-        reply.entry(&self.timeout, &ClockFS::stat(ClockFS::FILE_INO).unwrap(), 0);
+        reply.entry(self.timeout, &ClockFS::stat(ClockFS::FILE_INO).unwrap(), 0);
     }
 
     fn forget(&mut self, _req: &Request, ino: u64, nlookup: u64) {
@@ -89,7 +89,7 @@ impl<'a> Filesystem for ClockFS<'a> {
 
     fn getattr(&mut self, _req: &Request, ino: u64, _fh: Option<u64>, reply: ReplyAttr) {
         match ClockFS::stat(ino) {
-            Some(a) => reply.attr(&self.timeout, &a),
+            Some(a) => reply.attr(self.timeout, &a),
             None => reply.error(ENOENT),
         }
     }
@@ -74,7 +74,7 @@ impl<'a> Filesystem for ClockFS<'a> {
         }
 
         self.lookup_cnt.fetch_add(1, SeqCst);
-        reply.entry(&Duration::MAX, &self.stat(ClockFS::FILE_INO).unwrap(), 0);
+        reply.entry(Duration::MAX, &self.stat(ClockFS::FILE_INO).unwrap(), 0);
     }
 
     fn forget(&mut self, _req: &Request, ino: u64, nlookup: u64) {
@@ -88,7 +88,7 @@ impl<'a> Filesystem for ClockFS<'a> {
 
     fn getattr(&mut self, _req: &Request, ino: u64, _fh: Option<u64>, reply: ReplyAttr) {
         match self.stat(ino) {
-            Some(a) => reply.attr(&Duration::MAX, &a),
+            Some(a) => reply.attr(Duration::MAX, &a),
             None => reply.error(ENOENT),
         }
     }
@@ -23,7 +23,7 @@ use libc::{EACCES, EBADF, EBUSY, EINVAL, ENOENT, ENOTDIR};
 
 use fuser::{
     consts::{FOPEN_DIRECT_IO, FOPEN_NONSEEKABLE, FUSE_POLL_SCHEDULE_NOTIFY},
-    FileAttr, FileType, MountOption, PollHandle, Request, FUSE_ROOT_ID,
+    FileAttr, FileType, MountOption, PollEvents, PollHandle, Request, Ttl, FUSE_ROOT_ID,
 };
 
 const NUMFILES: u8 = 16;
@@ -98,7 +98,7 @@ impl fuser::Filesystem for FSelFS {
             }
         };
 
-        reply.entry(&Duration::ZERO, &self.get_data().filestat(idx), 0);
+        reply.entry(Ttl::NONE, &self.get_data().filestat(idx), 0);
     }
 
     fn getattr(&mut self, _req: &Request, ino: u64, _fh: Option<u64>, reply: fuser::ReplyAttr) {
@@ -120,12 +120,12 @@ impl fuser::Filesystem for FSelFS {
                 flags: 0,
                 blksize: 0,
             };
-            reply.attr(&Duration::ZERO, &a);
+            reply.attr(Ttl::NONE, &a);
             return;
         }
         let idx = FSelData::ino_to_idx(ino);
         if idx < NUMFILES {
-            reply.attr(&Duration::ZERO, &self.get_data().filestat(idx));
+            reply.attr(Ttl::NONE, &self.get_data().filestat(idx));
         } else {
             reply.error(ENOENT);
         }
@@ -252,7 +252,7 @@ impl fuser::Filesystem for FSelFS {
         _ino: u64,
         fh: u64,
         ph: PollHandle,
-        _events: u32,
+        _events: PollEvents,
         flags: u32,
         reply: fuser::ReplyPoll,
     ) {
@@ -282,10 +282,10 @@ impl fuser::Filesystem for FSelFS {
                     nbytes,
                     POLLED_ZERO.swap(0, SeqCst)
                 );
-                libc::POLLIN.try_into().unwrap()
+                PollEvents::IN
             } else {
                 POLLED_ZERO.fetch_add(1, SeqCst);
-                0
+                PollEvents::empty()
             }
         };
 
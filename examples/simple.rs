@@ -11,7 +11,7 @@ use fuser::TimeOrNow::Now;
 use fuser::{
     Filesystem, KernelConfig, MountOption, ReplyAttr, ReplyCreate, ReplyData, ReplyDirectory,
     ReplyEmpty, ReplyEntry, ReplyOpen, ReplyStatfs, ReplyWrite, ReplyXattr, Request, TimeOrNow,
-    FUSE_ROOT_ID,
+    Ttl, FUSE_ROOT_ID,
 };
 #[cfg(feature = "abi-7-26")]
 use log::info;
@@ -45,15 +45,30 @@ const FILE_HANDLE_WRITE_BIT: u64 = 1 << 62;
 
 const FMODE_EXEC: i32 = 0x20;
 
+// Version of the layout of the data directory. Bump it when the layout of the files in it
+// changes, e.g. when adding a field to InodeAttributes
+const FORMAT_VERSION: u32 = 2;
+
 type Inode = u64;
 
 type DirectoryDescriptor = BTreeMap<Vec<u8>, (Inode, FileKind)>;
 
+#[derive(Serialize, Deserialize)]
+struct Superblock {
+    format_version: u32,
+    // Last allocated inode
+    last_inode: Inode,
+}
+
 #[derive(Serialize, Deserialize, Copy, Clone, PartialEq)]
 enum FileKind {
     File,
     Directory,
     Symlink,
+    NamedPipe,
+    CharDevice,
+    BlockDevice,
+    Socket,
 }
 
 impl From<FileKind> for fuser::FileType {
@@ -62,6 +77,10 @@ impl From<FileKind> for fuser::FileType {
             FileKind::File => fuser::FileType::RegularFile,
             FileKind::Directory => fuser::FileType::Directory,
             FileKind::Symlink => fuser::FileType::Symlink,
+            FileKind::NamedPipe => fuser::FileType::NamedPipe,
+            FileKind::CharDevice => fuser::FileType::CharDevice,
+            FileKind::BlockDevice => fuser::FileType::BlockDevice,
+            FileKind::Socket => fuser::FileType::Socket,
         }
     }
 }
@@ -213,6 +232,8 @@ struct InodeAttributes {
     pub hardlinks: u32,
     pub uid: u32,
     pub gid: u32,
+    // Device number of character and block devices
+    pub rdev: u32,
     pub xattrs: BTreeMap<Vec<u8>, Vec<u8>>,
 }
 
@@ -234,7 +255,7 @@ impl From<InodeAttributes> for fuser::FileAttr {
             nlink: attrs.hardlinks,
             uid: attrs.uid,
             gid: attrs.gid,
-            rdev: 0,
+            rdev: attrs.rdev,
             blksize: BLOCK_SIZE as u32,
             flags: 0,
         }
@@ -285,22 +306,29 @@ impl SimpleFS {
     }
 
     fn allocate_next_inode(&self) -> Inode {
+        let mut superblock = self.read_superblock().unwrap();
+        superblock.last_inode += 1;
+        self.write_superblock(&superblock);
+
+        superblock.last_inode
+    }
+
+    // Returns None if the superblock is missing or has an older layout
+    fn read_superblock(&self) -> Option<Superblock> {
         let path = Path::new(&self.data_dir).join("superblock");
-        let current_inode = if let Ok(file) = File::open(&path) {
-            bincode::deserialize_from(file).unwrap()
-        } else {
-            fuser::FUSE_ROOT_ID
-        };
+        let file = File::open(path).ok()?;
+        bincode::deserialize_from(file).ok()
+    }
 
+    fn write_superblock(&self, superblock: &Superblock) {
+        let path = Path::new(&self.data_dir).join("superblock");
         let file = OpenOptions::new()
             .write(true)
             .create(true)
             .truncate(true)
-            .open(&path)
+            .open(path)
             .unwrap();
-        bincode::serialize_into(file, &(current_inode + 1)).unwrap();
-
-        current_inode + 1
+        bincode::serialize_into(file, superblock).unwrap();
     }
 
     fn allocate_next_file_handle(&self, read: bool, write: bool) -> u64 {
@@ -484,11 +512,31 @@ impl Filesystem for SimpleFS {
     ) -> Result<(), c_int> {
         #[cfg(feature = "abi-7-26")]
         config.add_capabilities(FUSE_HANDLE_KILLPRIV).unwrap();
+        // Truncate in open() instead of a separate setattr()
+        #[cfg(feature = "abi-7-9")]
+        let _ = config.set_atomic_o_trunc(true);
 
         fs::create_dir_all(Path::new(&self.data_dir).join("inodes")).unwrap();
         fs::create_dir_all(Path::new(&self.data_dir).join("contents")).unwrap();
-        if self.get_inode(FUSE_ROOT_ID).is_err() {
+        let root_path = Path::new(&self.data_dir)
+            .join("inodes")
+            .join(FUSE_ROOT_ID.to_string());
+        if root_path.exists() {
+            let format_version = self.read_superblock().map_or(1, |sb| sb.format_version);
+            if format_version != FORMAT_VERSION {
+                error!(
+                    "{} was written with data format {}, but this version only supports format {}. \
+                    Use a new data directory.",
+                    self.data_dir, format_version, FORMAT_VERSION
+                );
+                return Err(libc::EINVAL);
+            }
+        } else {
             // Initialize with empty filesystem
+            self.write_superblock(&Superblock {
+                format_version: FORMAT_VERSION,
+                last_inode: FUSE_ROOT_ID,
+            });
             let root = InodeAttributes {
                 inode: FUSE_ROOT_ID,
                 open_file_handles: 0,
@@ -501,6 +549,7 @@ impl Filesystem for SimpleFS {
                 hardlinks: 2,
                 uid: 0,
                 gid: 0,
+                rdev: 0,
                 xattrs: Default::default(),
             };
             self.write_inode(&root);
@@ -530,7 +579,7 @@ impl Filesystem for SimpleFS {
         }
 
         match self.lookup_name(parent, name) {
-            Ok(attrs) => reply.entry(&Duration::new(0, 0), &attrs.into(), 0),
+            Ok(attrs) => reply.entry(Ttl::NONE, &attrs.into(), 0),
             Err(error_code) => reply.error(error_code),
         }
     }
@@ -539,7 +588,7 @@ impl Filesystem for SimpleFS {
 
     fn getattr(&mut self, _req: &Request, inode: u64, _fh: Option<u64>, reply: ReplyAttr) {
         match self.get_inode(inode) {
-            Ok(attrs) => reply.attr(&Duration::new(0, 0), &attrs.into()),
+            Ok(attrs) => reply.attr(Ttl::NONE, &attrs.into()),
             Err(error_code) => reply.error(error_code),
         }
     }
@@ -588,7 +637,7 @@ impl Filesystem for SimpleFS {
             }
             attrs.last_metadata_changed = time_now();
             self.write_inode(&attrs);
-            reply.attr(&Duration::new(0, 0), &attrs.into());
+            reply.attr(Ttl::NONE, &attrs.into());
             return;
         }
 
@@ -635,7 +684,7 @@ impl Filesystem for SimpleFS {
             }
             attrs.last_metadata_changed = time_now();
             self.write_inode(&attrs);
-            reply.attr(&Duration::new(0, 0), &attrs.into());
+            reply.attr(Ttl::NONE, &attrs.into());
             return;
         }
 
@@ -722,7 +771,7 @@ impl Filesystem for SimpleFS {
         }
 
         let attrs = self.get_inode(inode).unwrap();
-        reply.attr(&Duration::new(0, 0), &attrs.into());
+        reply.attr(Ttl::NONE, &attrs.into());
         return;
     }
 
@@ -746,18 +795,12 @@ impl Filesystem for SimpleFS {
         name: &OsStr,
         mut mode: u32,
         _umask: u32,
-        _rdev: u32,
+        rdev: u32,
         reply: ReplyEntry,
     ) {
-        let file_type = mode & libc::S_IFMT as u32;
-
-        if file_type != libc::S_IFREG as u32
-            && file_type != libc::S_IFLNK as u32
-            && file_type != libc::S_IFDIR as u32
-        {
-            // TODO
-            warn!("mknod() implementation is incomplete. Only supports regular files, symlinks, and directories. Got {:o}", mode);
-            reply.error(libc::ENOSYS);
+        if fuser::FileType::from_mode(mode).is_none() {
+            warn!("mknod() called with invalid file type. Got {:o}", mode);
+            reply.error(libc::EINVAL);
             return;
         }
 
@@ -806,6 +849,7 @@ impl Filesystem for SimpleFS {
             hardlinks: 1,
             uid: req.uid(),
             gid: creation_gid(&parent_attrs, req.gid()),
+            rdev,
             xattrs: Default::default(),
         };
         self.write_inode(&attrs);
@@ -823,7 +867,7 @@ impl Filesystem for SimpleFS {
         self.write_directory_content(parent, entries);
 
         // TODO: implement flags
-        reply.entry(&Duration::new(0, 0), &attrs.into(), 0);
+        reply.entry(Ttl::NONE, &attrs.into(), 0);
     }
 
     fn mkdir(
@@ -884,6 +928,7 @@ impl Filesystem for SimpleFS {
             hardlinks: 2, // Directories start with link count of 2, since they have a self link
             uid: req.uid(),
             gid: creation_gid(&parent_attrs, req.gid()),
+            rdev: 0,
             xattrs: Default::default(),
         };
         self.write_inode(&attrs);
@@ -897,7 +942,7 @@ impl Filesystem for SimpleFS {
         entries.insert(name.as_bytes().to_vec(), (inode, FileKind::Directory));
         self.write_directory_content(parent, entries);
 
-        reply.entry(&Duration::new(0, 0), &attrs.into(), 0);
+        reply.entry(Ttl::NONE, &attrs.into(), 0);
     }
 
     fn unlink(&mut self, req: &Request, parent: u64, name: &OsStr, reply: ReplyEmpty) {
@@ -1066,6 +1111,7 @@ impl Filesystem for SimpleFS {
             hardlinks: 1,
             uid: req.uid(),
             gid: creation_gid(&parent_attrs, req.gid()),
+            rdev: 0,
             xattrs: Default::default(),
         };
 
@@ -1085,7 +1131,7 @@ impl Filesystem for SimpleFS {
             .unwrap();
         file.write_all(target.as_os_str().as_bytes()).unwrap();
 
-        reply.entry(&Duration::new(0, 0), &attrs.into(), 0);
+        reply.entry(Ttl::NONE, &attrs.into(), 0);
     }
 
     fn rename(
@@ -1324,7 +1370,7 @@ impl Filesystem for SimpleFS {
             attrs.hardlinks += 1;
             attrs.last_metadata_changed = time_now();
             self.write_inode(&attrs);
-            reply.entry(&Duration::new(0, 0), &attrs.into(), 0);
+            reply.entry(Ttl::NONE, &attrs.into(), 0);
         }
     }
 
@@ -1363,6 +1409,15 @@ impl Filesystem for SimpleFS {
                     req.gid(),
                     access_mask,
                 ) {
+                    if flags & libc::O_TRUNC != 0 {
+                        match self.truncate(inode, 0, req.uid(), req.gid()) {
+                            Ok(truncated) => attr = truncated,
+                            Err(error_code) => {
+                                reply.error(error_code);
+                                return;
+                            }
+                        }
+                    }
                     attr.open_file_handles += 1;
                     self.write_inode(&attr);
                     let open_flags = if self.direct_io { FOPEN_DIRECT_IO } else { 0 };
@@ -1761,6 +1816,7 @@ impl Filesystem for SimpleFS {
             hardlinks: 1,
             uid: req.uid(),
             gid: creation_gid(&parent_attrs, req.gid()),
+            rdev: 0,
             xattrs: Default::default(),
         };
         self.write_inode(&attrs);
@@ -1779,7 +1835,7 @@ impl Filesystem for SimpleFS {
 
         // TODO: implement flags
         reply.created(
-            &Duration::new(0, 0),
+            Ttl::NONE,
             &attrs.into(),
             0,
             self.allocate_next_file_handle(read, write),
@@ -1911,17 +1967,16 @@ pub fn check_access(
     return access_mask == 0;
 }
 
-fn as_file_kind(mut mode: u32) -> FileKind {
-    mode &= libc::S_IFMT as u32;
-
-    if mode == libc::S_IFREG as u32 {
-        return FileKind::File;
-    } else if mode == libc::S_IFLNK as u32 {
-        return FileKind::Symlink;
-    } else if mode == libc::S_IFDIR as u32 {
-        return FileKind::Directory;
-    } else {
-        unimplemented!("{}", mode);
+fn as_file_kind(mode: u32) -> FileKind {
+    match fuser::FileType::from_mode(mode) {
+        Some(fuser::FileType::RegularFile) => FileKind::File,
+        Some(fuser::FileType::Symlink) => FileKind::Symlink,
+        Some(fuser::FileType::Directory) => FileKind::Directory,
+        Some(fuser::FileType::NamedPipe) => FileKind::NamedPipe,
+        Some(fuser::FileType::CharDevice) => FileKind::CharDevice,
+        Some(fuser::FileType::BlockDevice) => FileKind::BlockDevice,
+        Some(fuser::FileType::Socket) => FileKind::Socket,
+        None => unimplemented!("{}", mode),
     }
 }
 
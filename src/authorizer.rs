@@ -0,0 +1,211 @@
+//! Permission checks delegated to a policy service
+//!
+//! Deployments that keep their access rules in an external policy service (e.g. OPA or an LDAP
+//! directory) shouldn't have to weave calls to it into every filesystem. An [`Authorizer`]
+//! installed on the session is asked before `open` and every operation that changes the
+//! namespace (`create`, `mknod`, `mkdir`, `symlink`, `link`, `unlink`, `rmdir` and `rename`)
+//! reaches the filesystem, and a denied operation fails without calling the filesystem.
+//!
+//! The authorizer is called on the thread running the session, so a slow policy service stalls
+//! all requests. Decisions are therefore cached for a configurable time, and implementations
+//! can keep their own state behind `&self` (e.g. a cache refreshed by a background thread).
+
+use libc::c_int;
+use std::collections::HashMap;
+use std::ffi::OsString;
+use std::fmt;
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+
+/// Maximum number of cached decisions. Once reached, expired decisions are dropped, and if
+/// that isn't enough, all of them.
+const MAX_DECISIONS: usize = 4096;
+
+/// The kind of operation to authorize, see [`Access`]
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum AccessKind {
+    /// Opening an existing file, with the flags passed to `open`
+    Open {
+        /// Flags of the `open` call
+        flags: i32,
+    },
+    /// Creating and opening a file
+    Create {
+        /// File mode of the new file
+        mode: u32,
+        /// Flags of the `open` call
+        flags: i32,
+    },
+    /// Creating a file node, e.g. a device or a fifo
+    MkNod {
+        /// File mode of the new node
+        mode: u32,
+        /// Device number of the new node
+        rdev: u32,
+    },
+    /// Creating a directory
+    MkDir {
+        /// File mode of the new directory
+        mode: u32,
+    },
+    /// Creating a symbolic link
+    SymLink {
+        /// The path the link points to
+        target: PathBuf,
+    },
+    /// Creating a hard link to an existing file
+    Link,
+    /// Removing a directory entry
+    Unlink,
+    /// Removing a directory
+    RmDir,
+    /// Moving a directory entry
+    Rename {
+        /// Flags of the `renameat2` call
+        flags: u32,
+    },
+}
+
+/// An operation to authorize, with the identity of the caller and the entries it refers to
+///
+/// The kernel refers to files by inode and to directory entries by parent inode and name, so
+/// that is what an authorizer gets. Authorizers that decide by path have to resolve it from
+/// these themselves, e.g. by asking the filesystem. The pid of the caller isn't part of the
+/// access, so that decisions can be cached across processes.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub struct Access {
+    /// The operation
+    pub kind: AccessKind,
+    /// User id of the caller, see [`Request::uid`](crate::Request::uid)
+    pub uid: u32,
+    /// Group id of the caller, see [`Request::gid`](crate::Request::gid)
+    pub gid: u32,
+    /// The file to open, for `open`, or to link to, for `link`
+    pub ino: Option<u64>,
+    /// The directory containing the entry, for all operations but `open`
+    pub parent: Option<u64>,
+    /// The name of the entry, for all operations but `open`
+    pub name: Option<OsString>,
+    /// The directory the entry is moved into, for `rename`
+    pub new_parent: Option<u64>,
+    /// The new name of the entry, for `rename`
+    pub new_name: Option<OsString>,
+}
+
+impl Access {
+    pub(crate) fn new(kind: AccessKind, uid: u32, gid: u32) -> Self {
+        Self {
+            kind,
+            uid,
+            gid,
+            ino: None,
+            parent: None,
+            name: None,
+            new_parent: None,
+            new_name: None,
+        }
+    }
+}
+
+/// Decides whether an operation is allowed (see
+/// [`Session::set_authorizer`](crate::Session::set_authorizer)).
+pub trait Authorizer: Send + Sync {
+    /// Returns `Ok(())` if the operation is allowed, or the error code to fail it with
+    /// (usually `EACCES` or `EPERM`).
+    fn authorize(&self, access: &Access) -> Result<(), c_int>;
+}
+
+impl<F: Fn(&Access) -> Result<(), c_int> + Send + Sync> Authorizer for F {
+    fn authorize(&self, access: &Access) -> Result<(), c_int> {
+        self(access)
+    }
+}
+
+/// An authorizer with a cache of its decisions
+pub(crate) struct CachedAuthorizer {
+    authorizer: Box<dyn Authorizer>,
+    /// How long decisions are reused
+    ttl: Duration,
+    /// Decisions and when they expire
+    decisions: HashMap<Access, (Instant, Result<(), c_int>)>,
+}
+
+impl fmt::Debug for CachedAuthorizer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("CachedAuthorizer")
+            .field("ttl", &self.ttl)
+            .field("decisions", &self.decisions.len())
+            .finish()
+    }
+}
+
+impl CachedAuthorizer {
+    pub(crate) fn new(authorizer: Box<dyn Authorizer>, ttl: Duration) -> Self {
+        Self {
+            authorizer,
+            ttl,
+            decisions: HashMap::new(),
+        }
+    }
+
+    /// Returns the decision for the given access, asking the authorizer unless a decision made
+    /// less than the TTL before `now` is cached
+    pub(crate) fn check(&mut self, access: Access, now: Instant) -> Result<(), c_int> {
+        if let Some((expiry, decision)) = self.decisions.get(&access) {
+            if now < *expiry {
+                return *decision;
+            }
+        }
+        let decision = self.authorizer.authorize(&access);
+        if self.ttl.is_zero() {
+            return decision;
+        }
+        if self.decisions.len() >= MAX_DECISIONS {
+            self.decisions.retain(|_, (expiry, _)| now < *expiry);
+            if self.decisions.len() >= MAX_DECISIONS {
+                self.decisions.clear();
+            }
+        }
+        self.decisions.insert(access, (now + self.ttl, decision));
+        decision
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Access, AccessKind, CachedAuthorizer};
+    use libc::EACCES;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use std::time::{Duration, Instant};
+
+    #[test]
+    fn cached_decisions() {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let counter = calls.clone();
+        let mut authorizer = CachedAuthorizer::new(
+            Box::new(move |access: &Access| {
+                counter.fetch_add(1, Ordering::SeqCst);
+                if access.uid == 0 {
+                    Ok(())
+                } else {
+                    Err(EACCES)
+                }
+            }),
+            Duration::from_secs(10),
+        );
+        let now = Instant::now();
+        let root = Access::new(AccessKind::Unlink, 0, 0);
+        let user = Access::new(AccessKind::Unlink, 1000, 1000);
+        assert_eq!(authorizer.check(root.clone(), now), Ok(()));
+        assert_eq!(authorizer.check(user.clone(), now), Err(EACCES));
+        assert_eq!(authorizer.check(root.clone(), now), Ok(()));
+        assert_eq!(authorizer.check(user.clone(), now), Err(EACCES));
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+        let later = now + Duration::from_secs(10);
+        assert_eq!(authorizer.check(root, later), Ok(()));
+        assert_eq!(calls.load(Ordering::SeqCst), 3);
+    }
+}
@@ -0,0 +1,86 @@
+//! Consistent caching configurations
+//!
+//! How much the kernel caches is controlled by several independent knobs: the TTLs of entry and
+//! attribute replies, the flags of open replies, and init flags such as the writeback cache.
+//! Mixing them inconsistently (e.g. caching data with `FOPEN_KEEP_CACHE` while replying with
+//! zero attribute TTLs) gives neither coherence nor performance.
+
+use std::time::Duration;
+
+use crate::ll::fuse_abi::consts::*;
+use crate::KernelConfig;
+
+/// A caching configuration for a coherence model. Filesystems use its TTLs for entry and
+/// attribute replies and its open flags for open and create replies, and apply it to the
+/// [`KernelConfig`] in [`Filesystem::init`](crate::Filesystem::init).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum CachePreset {
+    /// Every operation goes to the filesystem: nothing is cached and file I/O bypasses the page
+    /// cache. For filesystems whose backend is modified by others, e.g. network filesystems.
+    Coherent,
+    /// Short TTLs, data is cached while a file is open. This is what the kernel does if the
+    /// filesystem doesn't ask for anything else.
+    #[default]
+    Default,
+    /// Long TTLs, data is cached across opens and writes are buffered by the kernel
+    /// (writeback cache, if the ABI supports it). For filesystems that are only modified through
+    /// the mount.
+    Aggressive,
+}
+
+impl CachePreset {
+    /// TTL of entry replies (`lookup`, `mknod`, `mkdir`, `symlink`, `link` and `create`)
+    pub fn entry_ttl(&self) -> Duration {
+        match self {
+            CachePreset::Coherent => Duration::ZERO,
+            CachePreset::Default => Duration::from_secs(1),
+            CachePreset::Aggressive => Duration::from_secs(3600),
+        }
+    }
+
+    /// TTL of attribute replies. Same as the entry TTL, since the kernel uses cached attributes
+    /// of cached entries.
+    pub fn attr_ttl(&self) -> Duration {
+        self.entry_ttl()
+    }
+
+    /// Flags for replies to `open` and `create`
+    pub fn open_flags(&self) -> u32 {
+        match self {
+            CachePreset::Coherent => FOPEN_DIRECT_IO,
+            CachePreset::Default => 0,
+            CachePreset::Aggressive => FOPEN_KEEP_CACHE,
+        }
+    }
+
+    /// Request the init flags of the preset. On error returns the flags the kernel doesn't
+    /// support, like [`KernelConfig::add_capabilities`].
+    pub fn configure(&self, config: &mut KernelConfig) -> Result<(), u32> {
+        #[cfg(feature = "abi-7-23")]
+        if *self == CachePreset::Aggressive {
+            return config.add_capabilities(FUSE_WRITEBACK_CACHE);
+        } else {
+            config.requested &= !FUSE_WRITEBACK_CACHE;
+        }
+        #[cfg(not(feature = "abi-7-23"))]
+        let _ = config;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::CachePreset;
+    use crate::ll::fuse_abi::consts::*;
+    use std::time::Duration;
+
+    #[test]
+    fn presets() {
+        assert_eq!(CachePreset::Coherent.entry_ttl(), Duration::ZERO);
+        assert_eq!(CachePreset::Coherent.attr_ttl(), Duration::ZERO);
+        assert_eq!(CachePreset::Coherent.open_flags(), FOPEN_DIRECT_IO);
+        assert_eq!(CachePreset::default(), CachePreset::Default);
+        assert!(CachePreset::Aggressive.attr_ttl() > CachePreset::Default.attr_ttl());
+        assert_eq!(CachePreset::Aggressive.open_flags(), FOPEN_KEEP_CACHE);
+    }
+}
@@ -0,0 +1,136 @@
+//! Kernel capabilities a filesystem can't work without
+//!
+//! Kernels offer the init flags they support, and filesystems that need one the kernel lacks
+//! are left to limp along or panic. [`KernelConfig::require`](crate::KernelConfig::require)
+//! fails the mount instead, with an error naming the missing capabilities and the kernel
+//! version that introduced them, which `mount2` and [`Session::run`](crate::Session::run)
+//! return.
+
+use std::fmt;
+
+use crate::ll::fuse_abi::consts::*;
+
+/// An init flag of the FUSE protocol, with the Linux version that introduced it
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Capability {
+    flag: u32,
+    name: &'static str,
+    since: &'static str,
+}
+
+impl Capability {
+    const fn new(flag: u32, name: &'static str, since: &'static str) -> Self {
+        Self { flag, name, since }
+    }
+
+    /// Remote locking for POSIX file locks
+    pub const POSIX_LOCKS: Self = Self::new(FUSE_POSIX_LOCKS, "FUSE_POSIX_LOCKS", "2.6.18");
+    /// Truncating files on open (`O_TRUNC`) in the filesystem
+    #[cfg(feature = "abi-7-9")]
+    pub const ATOMIC_O_TRUNC: Self =
+        Self::new(FUSE_ATOMIC_O_TRUNC, "FUSE_ATOMIC_O_TRUNC", "2.6.24");
+    /// Lookups of "." and ".." for NFS exports
+    #[cfg(feature = "abi-7-10")]
+    pub const EXPORT_SUPPORT: Self =
+        Self::new(FUSE_EXPORT_SUPPORT, "FUSE_EXPORT_SUPPORT", "2.6.26");
+    /// Writes larger than a page
+    #[cfg(feature = "abi-7-9")]
+    pub const BIG_WRITES: Self = Self::new(FUSE_BIG_WRITES, "FUSE_BIG_WRITES", "2.6.26");
+    /// File modes of new files without the umask applied
+    #[cfg(feature = "abi-7-12")]
+    pub const DONT_MASK: Self = Self::new(FUSE_DONT_MASK, "FUSE_DONT_MASK", "2.6.31");
+    /// Remote locking for BSD style file locks
+    #[cfg(feature = "abi-7-17")]
+    pub const FLOCK_LOCKS: Self = Self::new(FUSE_FLOCK_LOCKS, "FUSE_FLOCK_LOCKS", "3.1");
+    /// `ioctl` on directories
+    #[cfg(feature = "abi-7-18")]
+    pub const IOCTL_DIR: Self = Self::new(FUSE_HAS_IOCTL_DIR, "FUSE_HAS_IOCTL_DIR", "3.3");
+    /// Invalidating cached pages when the modification time changes
+    #[cfg(feature = "abi-7-20")]
+    pub const AUTO_INVAL_DATA: Self =
+        Self::new(FUSE_AUTO_INVAL_DATA, "FUSE_AUTO_INVAL_DATA", "3.6");
+    /// `readdirplus`
+    #[cfg(feature = "abi-7-21")]
+    pub const DO_READDIRPLUS: Self = Self::new(FUSE_DO_READDIRPLUS, "FUSE_DO_READDIRPLUS", "3.9");
+    /// Asynchronous direct I/O
+    #[cfg(feature = "abi-7-22")]
+    pub const ASYNC_DIO: Self = Self::new(FUSE_ASYNC_DIO, "FUSE_ASYNC_DIO", "3.13");
+    /// Write-back caching of buffered writes
+    #[cfg(feature = "abi-7-23")]
+    pub const WRITEBACK_CACHE: Self =
+        Self::new(FUSE_WRITEBACK_CACHE, "FUSE_WRITEBACK_CACHE", "3.15");
+    /// Opens without a message to the filesystem
+    #[cfg(feature = "abi-7-23")]
+    pub const NO_OPEN_SUPPORT: Self =
+        Self::new(FUSE_NO_OPEN_SUPPORT, "FUSE_NO_OPEN_SUPPORT", "4.5");
+    /// Parallel lookups and readdir in the same directory
+    #[cfg(feature = "abi-7-25")]
+    pub const PARALLEL_DIROPS: Self =
+        Self::new(FUSE_PARALLEL_DIROPS, "FUSE_PARALLEL_DIROPS", "4.7");
+    /// Killing suid/sgid bits and capabilities in the filesystem
+    #[cfg(feature = "abi-7-26")]
+    pub const HANDLE_KILLPRIV: Self =
+        Self::new(FUSE_HANDLE_KILLPRIV, "FUSE_HANDLE_KILLPRIV", "4.9");
+    /// POSIX ACLs
+    #[cfg(feature = "abi-7-26")]
+    pub const POSIX_ACL: Self = Self::new(FUSE_POSIX_ACL, "FUSE_POSIX_ACL", "4.9");
+    /// Reads of the device failing with `ECONNABORTED` after an abort
+    #[cfg(feature = "abi-7-27")]
+    pub const ABORT_ERROR: Self = Self::new(FUSE_ABORT_ERROR, "FUSE_ABORT_ERROR", "4.19");
+    /// Requests of more than 32 pages
+    #[cfg(feature = "abi-7-28")]
+    pub const MAX_PAGES: Self = Self::new(FUSE_MAX_PAGES, "FUSE_MAX_PAGES", "4.20");
+    /// Caching of `readlink` replies
+    #[cfg(feature = "abi-7-28")]
+    pub const CACHE_SYMLINKS: Self = Self::new(FUSE_CACHE_SYMLINKS, "FUSE_CACHE_SYMLINKS", "4.20");
+    /// Opening directories without a message to the filesystem
+    #[cfg(feature = "abi-7-29")]
+    pub const NO_OPENDIR_SUPPORT: Self =
+        Self::new(FUSE_NO_OPENDIR_SUPPORT, "FUSE_NO_OPENDIR_SUPPORT", "5.1");
+    /// Invalidating cached pages only on explicit request
+    #[cfg(feature = "abi-7-30")]
+    pub const EXPLICIT_INVAL_DATA: Self =
+        Self::new(FUSE_EXPLICIT_INVAL_DATA, "FUSE_EXPLICIT_INVAL_DATA", "5.2");
+
+    /// Returns the init flag
+    pub fn flag(&self) -> u32 {
+        self.flag
+    }
+
+    /// Returns the name of the init flag, e.g. `FUSE_WRITEBACK_CACHE`
+    pub fn name(&self) -> &'static str {
+        self.name
+    }
+
+    /// Returns the first Linux version that supports it
+    pub fn since(&self) -> &'static str {
+        self.since
+    }
+}
+
+impl fmt::Display for Capability {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} (needs >= {})", self.name, self.since)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::Capability;
+    use crate::KernelConfig;
+
+    #[test]
+    fn require() {
+        let mut config = KernelConfig::new(Capability::POSIX_LOCKS.flag(), 0);
+        assert!(config.require(Capability::POSIX_LOCKS).is_ok());
+        assert!(config.unmet_requirements().is_none());
+        #[cfg(feature = "abi-7-23")]
+        {
+            assert!(config.require(Capability::WRITEBACK_CACHE).is_err());
+            assert_eq!(
+                config.unmet_requirements().unwrap().to_string(),
+                "kernel lacks FUSE_WRITEBACK_CACHE (needs >= 3.15)"
+            );
+        }
+    }
+}
@@ -0,0 +1,258 @@
+//! Journal of changes made through a session
+//!
+//! External tools that keep an index of a filesystem (e.g. a search indexer or a sync client)
+//! need to know what changed. Instead of every filesystem wiring up its own event bus, a
+//! [`ChangeLog`] installed on the session is told about every successful mutating operation.
+
+use std::convert::TryFrom;
+use std::ffi::{OsStr, OsString};
+use std::fmt;
+use std::sync::Arc;
+
+use crate::ll::fuse_abi::fuse_opcode;
+use crate::ll::{self, Operation, Request as _};
+
+/// A successful mutating operation (see [`ChangeLog`])
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Change {
+    /// The operation
+    pub op: fuse_opcode,
+    /// The inode that was changed or created. `None` for operations that only refer to it by
+    /// name, like `unlink` and `rename`.
+    pub ino: Option<u64>,
+    /// The directory containing the changed entry, for operations on directory entries
+    pub parent: Option<u64>,
+    /// The name of the changed entry, or the name of the extended attribute for `setxattr` and
+    /// `removexattr`
+    pub name: Option<OsString>,
+    /// The directory the entry was moved into, for `rename` and `exchange`
+    pub new_parent: Option<u64>,
+    /// The new name of the entry, for `rename` and `exchange`
+    pub new_name: Option<OsString>,
+}
+
+impl Change {
+    pub(crate) fn new(op: fuse_opcode) -> Self {
+        Self {
+            op,
+            ino: None,
+            parent: None,
+            name: None,
+            new_parent: None,
+            new_name: None,
+        }
+    }
+}
+
+/// Receives changes made through a session (see
+/// [`Session::set_change_log`](crate::Session::set_change_log)).
+///
+/// Changes are recorded when the reply is sent, which may happen on any thread the filesystem
+/// replies from. Failed operations are not recorded.
+pub trait ChangeLog: Send + Sync {
+    /// Record a successful mutating operation
+    fn record(&self, change: &Change);
+}
+
+impl<F: Fn(&Change) + Send + Sync> ChangeLog for F {
+    fn record(&self, change: &Change) {
+        self(change)
+    }
+}
+
+/// Describe the change a request makes if it succeeds, or `None` if it doesn't mutate anything.
+/// The inode of newly created entries is filled in from the reply.
+pub(crate) fn describe(request: &ll::AnyRequest<'_>) -> Option<Change> {
+    let op = request.operation().ok()?;
+    let opcode = fuse_opcode::try_from(request.opcode()).ok()?;
+    let nodeid = request.nodeid().0;
+    let mut change = Change::new(opcode);
+    match op {
+        Operation::MkNod(x) => change.entry(nodeid, x.name().as_os_str()),
+        Operation::MkDir(x) => change.entry(nodeid, x.name().as_os_str()),
+        Operation::Create(x) => change.entry(nodeid, x.name().as_os_str()),
+        Operation::SymLink(x) => change.entry(nodeid, x.link_name().as_os_str()),
+        Operation::Unlink(x) => change.entry(nodeid, x.name().as_os_str()),
+        Operation::RmDir(x) => change.entry(nodeid, x.name().as_os_str()),
+        Operation::Rename(x) => change.moved(x.src(), x.dest()),
+        #[cfg(feature = "abi-7-23")]
+        Operation::Rename2(x) => change.moved(x.from(), x.to()),
+        #[cfg(target_os = "macos")]
+        Operation::Exchange(x) => change.moved(x.from(), x.to()),
+        Operation::Link(x) => {
+            let dest = x.dest();
+            change.ino = Some(x.inode_no().0);
+            change.entry(dest.dir.0, dest.name.as_os_str());
+        }
+        Operation::SetAttr(_) | Operation::Write(_) => change.ino = Some(nodeid),
+        Operation::SetXAttr(x) => change.attribute(nodeid, x.name()),
+        Operation::RemoveXAttr(x) => change.attribute(nodeid, x.name()),
+        #[cfg(feature = "abi-7-19")]
+        Operation::FAllocate(_) => change.ino = Some(nodeid),
+        #[cfg(feature = "abi-7-28")]
+        Operation::CopyFileRange(x) => change.ino = Some(x.dest().inode.0),
+        _ => return None,
+    }
+    Some(change)
+}
+
+impl Change {
+    fn entry(&mut self, parent: u64, name: &OsStr) {
+        self.parent = Some(parent);
+        self.name = Some(name.to_owned());
+    }
+
+    fn attribute(&mut self, ino: u64, name: &OsStr) {
+        self.ino = Some(ino);
+        self.name = Some(name.to_owned());
+    }
+
+    fn moved(&mut self, from: ll::FilenameInDir<'_>, to: ll::FilenameInDir<'_>) {
+        self.entry(from.dir.0, from.name.as_os_str());
+        self.new_parent = Some(to.dir.0);
+        self.new_name = Some(to.name.as_os_str().to_owned());
+    }
+
+    /// Whether the inode of this change is taken from the entry in the reply
+    pub(crate) fn creates_entry(&self) -> bool {
+        matches!(
+            self.op,
+            fuse_opcode::FUSE_MKNOD
+                | fuse_opcode::FUSE_MKDIR
+                | fuse_opcode::FUSE_CREATE
+                | fuse_opcode::FUSE_SYMLINK
+                | fuse_opcode::FUSE_LINK
+        )
+    }
+}
+
+#[derive(Clone)]
+pub(crate) struct ChangeLogHandle(pub(crate) Arc<dyn ChangeLog>);
+
+impl fmt::Debug for ChangeLogHandle {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "ChangeLog")
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{describe, Change, ChangeLogHandle};
+    use crate::channel::Channel;
+    use crate::ll::fuse_abi::{fuse_in_header, fuse_opcode};
+    use crate::ll::AnyRequest;
+    use crate::reply::ReplySender;
+    use crate::InterruptedReply;
+    use std::convert::{TryFrom, TryInto};
+    use std::ffi::OsString;
+    use std::fs::OpenOptions;
+    use std::io::IoSlice;
+    use std::mem::size_of;
+    use std::sync::{Arc, Mutex};
+    use zerocopy::IntoBytes;
+
+    fn request(opcode: fuse_opcode, nodeid: u64, body: &[u8]) -> Vec<u64> {
+        let len = size_of::<fuse_in_header>() + body.len();
+        let mut data = Vec::with_capacity(len);
+        data.extend_from_slice(&(len as u32).to_ne_bytes());
+        data.extend_from_slice(&(opcode as u32).to_ne_bytes());
+        data.extend_from_slice(&1u64.to_ne_bytes());
+        data.extend_from_slice(&nodeid.to_ne_bytes());
+        // uid, gid, pid and padding are all zero
+        data.resize(size_of::<fuse_in_header>(), 0);
+        data.extend_from_slice(body);
+        data.resize((len + 7) / 8 * 8, 0);
+        data.chunks(size_of::<u64>())
+            .map(|word| u64::from_ne_bytes(word.try_into().unwrap()))
+            .collect()
+    }
+
+    #[test]
+    fn describe_requests() {
+        let data = request(fuse_opcode::FUSE_UNLINK, 5, b"foo.txt\0");
+        let change = describe(&AnyRequest::try_from(data.as_bytes()).unwrap()).unwrap();
+        assert_eq!(change.op, fuse_opcode::FUSE_UNLINK);
+        assert_eq!(change.ino, None);
+        assert_eq!(change.parent, Some(5));
+        assert_eq!(change.name, Some(OsString::from("foo.txt")));
+
+        let mut body = 7u64.to_ne_bytes().to_vec();
+        body.extend_from_slice(b"a\0b\0");
+        let data = request(fuse_opcode::FUSE_RENAME, 5, &body);
+        let change = describe(&AnyRequest::try_from(data.as_bytes()).unwrap()).unwrap();
+        assert_eq!(change.parent, Some(5));
+        assert_eq!(change.name, Some(OsString::from("a")));
+        assert_eq!(change.new_parent, Some(7));
+        assert_eq!(change.new_name, Some(OsString::from("b")));
+
+        let data = request(fuse_opcode::FUSE_GETATTR, 5, &[0; 16]);
+        assert_eq!(
+            describe(&AnyRequest::try_from(data.as_bytes()).unwrap()),
+            None
+        );
+    }
+
+    #[test]
+    fn record_successful_replies() {
+        let device = OpenOptions::new().write(true).open("/dev/null").unwrap();
+        let mut channel = Channel::new(Arc::new(device));
+        let changes = Arc::new(Mutex::new(vec![]));
+        let log = changes.clone();
+        channel.set_change_log(ChangeLogHandle(Arc::new(move |c: &Change| {
+            log.lock().unwrap().push(c.clone())
+        })));
+
+        let mut change = Change::new(fuse_opcode::FUSE_MKDIR);
+        change.parent = Some(1);
+        change.name = Some(OsString::from("dir"));
+        let sender = channel
+            .sender()
+            .for_opcode(fuse_opcode::FUSE_MKDIR as u32)
+            .with_change(change);
+
+        let mut error = [0u8; 16];
+        error[0] = 16;
+        error[4..8].copy_from_slice(&(-libc::EEXIST).to_ne_bytes());
+        sender.send(&[IoSlice::new(&error)]).unwrap();
+        assert!(changes.lock().unwrap().is_empty());
+
+        let mut header = [0u8; 16];
+        header[0] = 24;
+        sender
+            .send(&[IoSlice::new(&header), IoSlice::new(&42u64.to_ne_bytes())])
+            .unwrap();
+        let changes = changes.lock().unwrap();
+        assert_eq!(changes.len(), 1);
+        assert_eq!(changes[0].ino, Some(42));
+        assert_eq!(changes[0].parent, Some(1));
+    }
+
+    #[test]
+    fn record_discarded_replies() {
+        let device = OpenOptions::new().write(true).open("/dev/null").unwrap();
+        let mut channel = Channel::new(Arc::new(device));
+        channel.set_interrupted_reply(InterruptedReply::Discard);
+        let changes = Arc::new(Mutex::new(vec![]));
+        let log = changes.clone();
+        channel.set_change_log(ChangeLogHandle(Arc::new(move |c: &Change| {
+            log.lock().unwrap().push(c.clone())
+        })));
+
+        let mut change = Change::new(fuse_opcode::FUSE_UNLINK);
+        change.parent = Some(1);
+        change.name = Some(OsString::from("file"));
+        let sender = channel
+            .sender()
+            .for_opcode(fuse_opcode::FUSE_UNLINK as u32)
+            .with_change(change);
+        sender.track(3, 16);
+        assert!(sender.interrupt(3));
+
+        // The kernel gets EINTR, but the file was removed
+        let mut header = [0u8; 16];
+        header[0] = 16;
+        header[8..16].copy_from_slice(&3u64.to_ne_bytes());
+        sender.send(&[IoSlice::new(&header)]).unwrap();
+        assert_eq!(changes.lock().unwrap().len(), 1);
+    }
+}
@@ -1,24 +1,238 @@
 use std::{
+    collections::HashMap,
     fs::File,
     io,
+    mem::size_of,
     os::{
         fd::{AsFd, BorrowedFd},
         unix::prelude::AsRawFd,
     },
-    sync::Arc,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Condvar, Mutex,
+    },
+    time::{Duration, Instant},
 };
 
+#[cfg(feature = "abi-7-12")]
+use std::{ffi::OsString, sync::mpsc, thread};
+
 use libc::{c_int, c_void, size_t};
+use log::{debug, info, warn};
+use smallvec::SmallVec;
+
+use crate::change_log::{Change, ChangeLogHandle};
+use crate::clock::{Clock, SystemClock};
+use crate::debug_dump::PendingRequest;
+use crate::hook::{Hooks, RequestInfo};
+use crate::ll::fuse_abi::{fuse_opcode, fuse_out_header};
+use crate::rename_journal::PendingRename;
+use crate::request::InterruptToken;
+#[cfg(feature = "abi-7-12")]
+use crate::Notifier;
+use crate::{
+    reply::ReplySender, Checksum, DroppedReply, ErrnoPolicy, IdMap, Ino32Remap, InterruptedReply,
+    RequestHook,
+};
+
+/// Requests that weren't answered yet, whether the kernel interrupted them, and how much memory
+/// is held to answer them
+#[derive(Debug, Default)]
+pub(crate) struct Outstanding {
+    state: Mutex<OutstandingState>,
+    /// Notified when requests holding memory are answered
+    answered: Condvar,
+}
+
+#[derive(Debug, Default)]
+struct OutstandingState {
+    requests: HashMap<u64, PendingRequest>,
+    /// When each request was received, and the request as passed to the hooks. Only recorded
+    /// once requests time out, see [`Outstanding::enable_timeouts`].
+    received: Option<HashMap<u64, (Instant, Option<RequestInfo>)>>,
+    /// Memory held to answer requests, see [`Outstanding::charge`]
+    held: HashMap<u64, usize>,
+    bytes: usize,
+}
+
+/// A request that wasn't answered in time, see [`Outstanding::expired`]
+#[derive(Debug)]
+struct Expired {
+    unique: u64,
+    opcode: u32,
+    received: Instant,
+    hooked: Option<RequestInfo>,
+}
+
+impl Outstanding {
+    fn insert(
+        &self,
+        unique: u64,
+        opcode: u32,
+        size: usize,
+        received: Instant,
+        hooked: Option<RequestInfo>,
+    ) {
+        let mut state = self.state.lock().unwrap();
+        let request = PendingRequest {
+            unique,
+            opcode,
+            interrupted: false,
+            size,
+            correlation: None,
+        };
+        state.requests.insert(unique, request);
+        if let Some(requests) = &mut state.received {
+            requests.insert(unique, (received, hooked));
+        }
+    }
+
+    /// Record when requests are received from now on, to find those that time out
+    pub(crate) fn enable_timeouts(&self) {
+        self.state
+            .lock()
+            .unwrap()
+            .received
+            .get_or_insert_with(HashMap::new);
+    }
+
+    /// Account for `bytes` of memory held until the request is answered. Returns false if it
+    /// was already answered.
+    fn charge(&self, unique: u64, bytes: usize) -> bool {
+        let mut state = self.state.lock().unwrap();
+        if !state.requests.contains_key(&unique) {
+            return false;
+        }
+        *state.held.entry(unique).or_default() += bytes;
+        state.bytes += bytes;
+        true
+    }
+
+    /// Mark a request as interrupted. Returns false if it was already answered.
+    fn interrupt(&self, unique: u64) -> bool {
+        match self.state.lock().unwrap().requests.get_mut(&unique) {
+            Some(request) => {
+                request.interrupted = true;
+                true
+            }
+            None => false,
+        }
+    }
 
-use crate::reply::ReplySender;
+    /// Whether the kernel interrupted a request that wasn't answered yet
+    pub(crate) fn is_interrupted(&self, unique: u64) -> bool {
+        self.state
+            .lock()
+            .unwrap()
+            .requests
+            .get(&unique)
+            .is_some_and(|r| r.interrupted)
+    }
+
+    /// Attach a correlation token to a request. Returns false if it was already answered.
+    fn correlate(&self, unique: u64, token: String) -> bool {
+        match self.state.lock().unwrap().requests.get_mut(&unique) {
+            Some(request) => {
+                request.correlation = Some(token);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Forget an answered request. Returns it, or `None` if it isn't outstanding.
+    fn finish(&self, unique: u64) -> Option<PendingRequest> {
+        let mut state = self.state.lock().unwrap();
+        let request = state.requests.remove(&unique)?;
+        if let Some(requests) = &mut state.received {
+            requests.remove(&unique);
+        }
+        if let Some(held) = state.held.remove(&unique) {
+            state.bytes -= held;
+            self.answered.notify_all();
+        }
+        Some(request)
+    }
+
+    /// Returns the requests that were received at least `timeout` before `now`, oldest first.
+    /// They stay outstanding until they are answered.
+    fn expired(&self, now: Instant, timeout: Duration) -> Vec<Expired> {
+        let state = self.state.lock().unwrap();
+        let Some(received) = &state.received else {
+            return vec![];
+        };
+        let mut expired: Vec<Expired> = received
+            .iter()
+            .filter(|(_, (received, _))| now.saturating_duration_since(*received) >= timeout)
+            .map(|(unique, (received, hooked))| Expired {
+                unique: *unique,
+                opcode: state.requests[unique].opcode,
+                received: *received,
+                hooked: *hooked,
+            })
+            .collect();
+        expired.sort_unstable_by_key(|r| r.unique);
+        expired
+    }
+
+    /// Returns the outstanding requests, oldest (by unique id) first
+    pub(crate) fn snapshot(&self) -> Vec<PendingRequest> {
+        let mut requests: Vec<_> = self
+            .state
+            .lock()
+            .unwrap()
+            .requests
+            .values()
+            .cloned()
+            .collect();
+        requests.sort_by_key(|r| r.unique);
+        requests
+    }
+
+    /// Total memory held to answer outstanding requests
+    pub(crate) fn bytes(&self) -> usize {
+        self.state.lock().unwrap().bytes
+    }
+
+    /// Block until outstanding requests hold at most `budget` bytes, or `timeout` passed.
+    /// Returns whether they are within the budget.
+    pub(crate) fn wait_for_budget(&self, budget: usize, timeout: Duration) -> bool {
+        let state = self.state.lock().unwrap();
+        let (state, _) = self
+            .answered
+            .wait_timeout_while(state, timeout, |state| state.bytes > budget)
+            .unwrap();
+        state.bytes <= budget
+    }
+}
 
 /// A raw communication channel to the FUSE kernel driver
 #[derive(Debug)]
-pub struct Channel(Arc<File>);
+pub struct Channel {
+    device: Arc<File>,
+    remap: Option<Arc<Ino32Remap>>,
+    errno_policy: Option<Arc<ErrnoPolicy>>,
+    change_log: Option<ChangeLogHandle>,
+    id_map: Option<Arc<IdMap>>,
+    write_checksum: Option<Arc<dyn Checksum>>,
+    hooks: Hooks,
+    clock: Arc<dyn Clock>,
+    outstanding: Arc<Outstanding>,
+    interrupted_reply: InterruptedReply,
+    dropped_reply: DroppedReply,
+    /// Number of replies dropped without answering the request
+    dropped_replies: Arc<AtomicU64>,
+    /// Held while sending notifications, so that those of a transaction aren't interleaved
+    #[cfg(feature = "abi-7-11")]
+    notifications: Arc<Mutex<()>>,
+    /// Invalidates removed and renamed entries once the reply was sent, if enabled
+    #[cfg(feature = "abi-7-12")]
+    inval_entries: Option<EntryInvalidator>,
+}
 
 impl AsFd for Channel {
     fn as_fd(&self) -> BorrowedFd<'_> {
-        self.0.as_fd()
+        self.device.as_fd()
     }
 }
 
@@ -27,14 +241,112 @@ impl Channel {
     /// given path. The kernel driver will delegate filesystem operations of
     /// the given path to the channel.
     pub(crate) fn new(device: Arc<File>) -> Self {
-        Self(device)
+        Self {
+            device,
+            remap: None,
+            errno_policy: None,
+            change_log: None,
+            id_map: None,
+            write_checksum: None,
+            hooks: Hooks::default(),
+            clock: Arc::new(SystemClock),
+            outstanding: Arc::default(),
+            interrupted_reply: InterruptedReply::default(),
+            dropped_reply: DroppedReply::default(),
+            dropped_replies: Arc::default(),
+            #[cfg(feature = "abi-7-11")]
+            notifications: Arc::default(),
+            #[cfg(feature = "abi-7-12")]
+            inval_entries: None,
+        }
+    }
+
+    /// Translate inode numbers of all messages received through this channel and sent through
+    /// senders created afterwards.
+    pub(crate) fn set_ino32_remap(&mut self, remap: Ino32Remap) {
+        self.remap = Some(Arc::new(remap));
+    }
+
+    /// Rewrite the error codes of replies sent through senders created afterwards.
+    pub(crate) fn set_errno_policy(&mut self, policy: ErrnoPolicy) {
+        self.errno_policy = Some(Arc::new(policy));
+    }
+
+    /// Record successful mutating operations answered through senders created afterwards.
+    pub(crate) fn set_change_log(&mut self, log: ChangeLogHandle) {
+        self.change_log = Some(log);
+    }
+
+    /// Invalidate the entries removed or renamed by requests answered through senders created
+    /// afterwards.
+    #[cfg(feature = "abi-7-12")]
+    pub(crate) fn set_inval_entries(&mut self, enabled: bool) {
+        self.inval_entries = None;
+        if enabled {
+            let invalidator = EntryInvalidator::spawn(Notifier::new(self.sender()));
+            self.inval_entries = Some(invalidator);
+        }
+    }
+
+    /// Map the ids of requests and of attributes replied through senders created afterwards.
+    pub(crate) fn set_id_map(&mut self, map: IdMap) {
+        self.id_map = Some(Arc::new(map));
+    }
+
+    /// Compute checksums of the data of write requests received through senders created
+    /// afterwards.
+    pub(crate) fn set_write_checksum(&mut self, checksum: Arc<dyn Checksum>) {
+        self.write_checksum = Some(checksum);
+    }
+
+    /// Call the given hook around requests received through senders created afterwards.
+    pub(crate) fn add_hook(&mut self, hook: Arc<dyn RequestHook>) {
+        self.hooks.push(hook);
+    }
+
+    /// Read the time from the given clock in senders created afterwards.
+    pub(crate) fn set_clock(&mut self, clock: Arc<dyn Clock>) {
+        self.clock = clock;
+    }
+
+    /// Requests received through this channel that weren't answered yet
+    pub(crate) fn outstanding(&self) -> &Outstanding {
+        &self.outstanding
+    }
+
+    /// Set what senders created afterwards do with replies to interrupted requests.
+    pub(crate) fn set_interrupted_reply(&mut self, policy: InterruptedReply) {
+        self.interrupted_reply = policy;
+    }
+
+    /// Set what senders created afterwards do when their reply is dropped without being sent.
+    pub(crate) fn set_dropped_reply(&mut self, policy: DroppedReply) {
+        self.dropped_reply = policy;
+    }
+
+    /// Number of replies to requests received through this channel that were dropped without
+    /// answering the request
+    pub(crate) fn dropped_replies(&self) -> u64 {
+        self.dropped_replies.load(Ordering::Relaxed)
+    }
+
+    /// Whether the kernel ended the connection, e.g. because the filesystem was unmounted. The
+    /// next `receive` reports why.
+    pub(crate) fn is_disconnected(&self) -> bool {
+        let mut pollfd = libc::pollfd {
+            fd: self.device.as_raw_fd(),
+            events: 0,
+            revents: 0,
+        };
+        let rc = unsafe { libc::poll(&mut pollfd, 1, 0) };
+        rc > 0 && pollfd.revents & (libc::POLLERR | libc::POLLHUP) != 0
     }
 
     /// Receives data up to the capacity of the given buffer (can block).
     pub fn receive(&self, buffer: &mut [u8]) -> io::Result<usize> {
         let rc = unsafe {
             libc::read(
-                self.0.as_raw_fd(),
+                self.device.as_raw_fd(),
                 buffer.as_ptr() as *mut c_void,
                 buffer.len() as size_t,
             )
@@ -42,6 +354,9 @@ impl Channel {
         if rc < 0 {
             Err(io::Error::last_os_error())
         } else {
+            if let Some(remap) = &self.remap {
+                remap.translate_request(&mut buffer[..rc as usize]);
+            }
             Ok(rc as usize)
         }
     }
@@ -52,18 +367,410 @@ impl Channel {
     pub fn sender(&self) -> ChannelSender {
         // Since write/writev syscalls are threadsafe, we can simply create
         // a sender by using the same file and use it in other threads.
-        ChannelSender(self.0.clone())
+        ChannelSender {
+            device: self.device.clone(),
+            remap: self.remap.clone(),
+            errno_policy: self.errno_policy.clone(),
+            change_log: self.change_log.clone(),
+            id_map: self.id_map.clone(),
+            write_checksum: self.write_checksum.clone(),
+            hooks: self.hooks.clone(),
+            clock: self.clock.clone(),
+            outstanding: self.outstanding.clone(),
+            interrupted_reply: self.interrupted_reply,
+            dropped_reply: self.dropped_reply,
+            dropped_replies: self.dropped_replies.clone(),
+            #[cfg(feature = "abi-7-11")]
+            notifications: self.notifications.clone(),
+            #[cfg(feature = "abi-7-12")]
+            inval_entries: self.inval_entries.clone(),
+            opcode: None,
+            #[cfg(feature = "usdt")]
+            nodeid: 0,
+            change: None,
+            rename: None,
+            time_gran: None,
+            hooked: None,
+        }
     }
 }
 
 #[derive(Clone, Debug)]
-pub struct ChannelSender(Arc<File>);
+pub struct ChannelSender {
+    device: Arc<File>,
+    remap: Option<Arc<Ino32Remap>>,
+    errno_policy: Option<Arc<ErrnoPolicy>>,
+    change_log: Option<ChangeLogHandle>,
+    /// Opcode of the request answered through this sender
+    opcode: Option<u32>,
+    /// Inode of the request answered through this sender, for tracing
+    #[cfg(feature = "usdt")]
+    nodeid: u64,
+    /// Change made by the request answered through this sender, if it succeeds
+    change: Option<Arc<Change>>,
+    /// Rename answered through this sender, which is completed in the journal once the sender
+    /// and its clones are gone
+    rename: Option<Arc<PendingRename>>,
+    id_map: Option<Arc<IdMap>>,
+    write_checksum: Option<Arc<dyn Checksum>>,
+    hooks: Hooks,
+    /// Request answered through this sender and when it was received, for the hooks
+    hooked: Option<(RequestInfo, Instant)>,
+    clock: Arc<dyn Clock>,
+    outstanding: Arc<Outstanding>,
+    interrupted_reply: InterruptedReply,
+    dropped_reply: DroppedReply,
+    dropped_replies: Arc<AtomicU64>,
+    /// Granularity the times of attributes replied through this sender are rounded to
+    time_gran: Option<Duration>,
+    /// Shared with the channel, see [`ChannelSender::lock_notifications`]
+    #[cfg(feature = "abi-7-11")]
+    notifications: Arc<Mutex<()>>,
+    #[cfg(feature = "abi-7-12")]
+    inval_entries: Option<EntryInvalidator>,
+}
+
+/// Sends the entry invalidations of [`Channel::set_inval_entries`] from a thread of its own.
+/// The kernel holds the lock of the directory while invalidating one of its entries, so
+/// sending them from the thread answering requests deadlocks with a lookup in that directory
+/// waiting for its answer. The thread ends once the channel and all its senders are gone.
+#[cfg(feature = "abi-7-12")]
+#[derive(Debug, Clone)]
+struct EntryInvalidator(mpsc::Sender<Vec<(u64, OsString)>>);
+
+#[cfg(feature = "abi-7-12")]
+impl EntryInvalidator {
+    fn spawn(notifier: Notifier) -> Self {
+        let (sender, receiver) = mpsc::channel::<Vec<(u64, OsString)>>();
+        thread::spawn(move || {
+            for entries in receiver {
+                for (parent, name) in entries {
+                    if let Err(err) = notifier.inval_entry(parent, &name) {
+                        warn!(
+                            "Failed to invalidate entry {:?} of {}: {}",
+                            name, parent, err
+                        );
+                    }
+                }
+            }
+        });
+        Self(sender)
+    }
+}
+
+impl ChannelSender {
+    /// Lock the channel for sending notifications
+    #[cfg(feature = "abi-7-11")]
+    pub(crate) fn lock_notifications(&self) -> std::sync::MutexGuard<'_, ()> {
+        self.notifications.lock().unwrap()
+    }
+
+    /// Returns a sender for replying to a request with the given opcode.
+    pub(crate) fn for_opcode(&self, opcode: u32) -> ChannelSender {
+        ChannelSender {
+            opcode: Some(opcode),
+            ..self.clone()
+        }
+    }
+
+    /// Returns a sender for replying to a request on the given inode.
+    #[cfg(feature = "usdt")]
+    pub(crate) fn with_nodeid(&self, nodeid: u64) -> ChannelSender {
+        ChannelSender {
+            nodeid,
+            ..self.clone()
+        }
+    }
+
+    /// The hooks called around requests received through this sender
+    pub(crate) fn hooks(&self) -> &Hooks {
+        &self.hooks
+    }
+
+    /// Call the hooks with the given request once it is answered.
+    pub(crate) fn with_hooked_request(self, request: RequestInfo, received: Instant) -> Self {
+        ChannelSender {
+            hooked: Some((request, received)),
+            ..self
+        }
+    }
+
+    /// Remember that the request with the given unique ID and message size awaits a reply.
+    pub(crate) fn track(&self, unique: u64, size: usize) {
+        let (received, hooked) = match self.hooked {
+            Some((request, received)) => (received, Some(request)),
+            None => (self.clock.now(), None),
+        };
+        self.outstanding
+            .insert(unique, self.opcode.unwrap_or(0), size, received, hooked);
+    }
+
+    /// Account for `bytes` of memory held to answer the request with the given unique ID,
+    /// until it is answered. Returns false if it was already answered.
+    pub(crate) fn charge(&self, unique: u64, bytes: usize) -> bool {
+        self.outstanding.charge(unique, bytes)
+    }
+
+    /// Mark the request with the given unique ID as interrupted. Returns false if it was
+    /// already answered.
+    pub(crate) fn interrupt(&self, unique: u64) -> bool {
+        self.outstanding.interrupt(unique)
+    }
+
+    /// Fail the requests that weren't answered within `timeout` with the given error code.
+    /// Returns how many were failed.
+    pub(crate) fn expire(&self, timeout: Duration, error: c_int) -> io::Result<usize> {
+        let mut failed = 0;
+        for request in self.outstanding.expired(self.clock.now(), timeout) {
+            let opcode = fuse_opcode::try_from(request.opcode)
+                .map_or_else(|_| request.opcode.to_string(), |op| format!("{:?}", op));
+            warn!(
+                "Request {} ({}) wasn't answered within {:?}, replying with error {}",
+                request.unique, opcode, timeout, error
+            );
+            let mut reply = [0; size_of::<fuse_out_header>()];
+            reply[..4].copy_from_slice(&(size_of::<fuse_out_header>() as u32).to_ne_bytes());
+            reply[4..8].copy_from_slice(&(-error).to_ne_bytes());
+            reply[8..16].copy_from_slice(&request.unique.to_ne_bytes());
+            // Sent like the reply of the filesystem would have been
+            let sender = ChannelSender {
+                opcode: Some(request.opcode),
+                hooked: request.hooked.map(|info| (info, request.received)),
+                ..self.clone()
+            };
+            match sender.send(&[io::IoSlice::new(&reply)]) {
+                Ok(()) => failed += 1,
+                // Answered by the filesystem in the meantime
+                Err(err) if err.kind() == io::ErrorKind::InvalidInput => {}
+                // The process waiting for the request is gone
+                Err(err) if err.kind() == io::ErrorKind::NotFound => failed += 1,
+                Err(err) => return Err(err),
+            }
+        }
+        Ok(failed)
+    }
+
+    /// Returns a token telling whether the request with the given unique ID was interrupted
+    pub(crate) fn interrupt_token(&self, unique: u64) -> InterruptToken {
+        InterruptToken::new(self.outstanding.clone(), unique)
+    }
+
+    /// Attach a correlation token to the request with the given unique ID. Returns false if it
+    /// was already answered.
+    pub(crate) fn correlate(&self, unique: u64, token: String) -> bool {
+        self.outstanding.correlate(unique, token)
+    }
+
+    /// Whether the change made by the request answered through this sender is needed, for
+    /// the change log or to invalidate entries.
+    pub(crate) fn tracks_changes(&self) -> bool {
+        #[cfg(feature = "abi-7-12")]
+        if self.inval_entries.is_some() {
+            return true;
+        }
+        self.change_log.is_some()
+    }
+
+    /// Record the given change in the change log once the request succeeds.
+    pub(crate) fn with_change(mut self, change: Change) -> ChannelSender {
+        self.change = Some(Arc::new(change));
+        self
+    }
+
+    /// Forget the request answered by the reply. Returns the reply to send instead, if the
+    /// request was interrupted and its reply is discarded. Fails if the request isn't
+    /// outstanding, since the kernel would reject the reply with an opaque error.
+    fn finish(
+        &self,
+        bufs: &[io::IoSlice<'_>],
+    ) -> io::Result<Option<[u8; size_of::<fuse_out_header>()]>> {
+        let Some(header) = bufs
+            .first()
+            .filter(|h| h.len() == size_of::<fuse_out_header>())
+        else {
+            return Ok(None);
+        };
+        let unique = u64::from_ne_bytes(header[8..16].try_into().unwrap());
+        if unique == 0 {
+            // Notification
+            return Ok(None);
+        }
+        let interrupted = match self.outstanding.finish(unique) {
+            Some(request) => {
+                if let Some(correlation) = request.correlation {
+                    let error = i32::from_ne_bytes(header[4..8].try_into().unwrap());
+                    debug!(
+                        "Reply to request {} (correlation {}): error {}",
+                        unique, correlation, -error
+                    );
+                }
+                request.interrupted
+            }
+            None => {
+                let opcode = self
+                    .opcode
+                    .and_then(|op| fuse_opcode::try_from(op).ok())
+                    .map_or_else(|| "unknown".to_owned(), |op| format!("{:?}", op));
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!(
+                        "Not sending reply to request {} ({}), which was already answered or \
+                         doesn't expect a reply",
+                        unique, opcode
+                    ),
+                ));
+            }
+        };
+        if !interrupted {
+            return Ok(None);
+        }
+        Ok(match self.interrupted_reply {
+            InterruptedReply::Send => None,
+            InterruptedReply::Log => {
+                info!("Replying to interrupted request {}", unique);
+                None
+            }
+            InterruptedReply::Discard => {
+                info!("Discarding reply to interrupted request {}", unique);
+                let mut reply = [0; size_of::<fuse_out_header>()];
+                reply[..4].copy_from_slice(&(size_of::<fuse_out_header>() as u32).to_ne_bytes());
+                reply[4..8].copy_from_slice(&(-libc::EINTR).to_ne_bytes());
+                reply[8..16].copy_from_slice(&unique.to_ne_bytes());
+                Some(reply)
+            }
+        })
+    }
+
+    /// Complete the given rename in its journal once the request was answered.
+    pub(crate) fn with_rename(mut self, rename: PendingRename) -> ChannelSender {
+        self.rename = Some(Arc::new(rename));
+        self
+    }
+
+    /// Round the times of attributes replied through this sender to the given granularity.
+    pub(crate) fn with_time_granularity(mut self, gran: Duration) -> ChannelSender {
+        self.time_gran = Some(gran);
+        self
+    }
+
+    /// Returns the granularity the times of attributes replied through this sender are rounded
+    /// to, if any
+    pub(crate) fn time_granularity(&self) -> Option<Duration> {
+        self.time_gran
+    }
+
+    /// Count a reply that is dropped without answering the request. Returns what to do about it
+    /// and the opcode of the request, if known.
+    pub(crate) fn reply_dropped(&self) -> (DroppedReply, Option<u32>) {
+        self.dropped_replies.fetch_add(1, Ordering::Relaxed);
+        (self.dropped_reply, self.opcode)
+    }
+
+    /// Returns the map of the ids of requests and replied attributes, if any
+    pub(crate) fn id_map(&self) -> Option<&IdMap> {
+        self.id_map.as_deref()
+    }
+
+    /// Returns the algorithm computing checksums of the data of write requests, if any
+    pub(crate) fn write_checksum(&self) -> Option<&dyn Checksum> {
+        self.write_checksum.as_deref()
+    }
+
+    /// Invalidate the entries the request answered through this sender removed or renamed, so
+    /// that they are looked up again. Queued after the reply, because the kernel holds the lock
+    /// of the directory until the request was answered, and sent by the [`EntryInvalidator`].
+    #[cfg(feature = "abi-7-12")]
+    fn invalidate_entries(&self) {
+        let (Some(invalidator), Some(change)) = (&self.inval_entries, self.change.as_deref())
+        else {
+            return;
+        };
+        let (Some(parent), Some(name)) = (change.parent, &change.name) else {
+            return;
+        };
+        let mut entries = vec![(parent, name.clone())];
+        match change.op {
+            fuse_opcode::FUSE_UNLINK | fuse_opcode::FUSE_RMDIR => {}
+            _ => match (change.new_parent, &change.new_name) {
+                (Some(new_parent), Some(new_name)) => entries.push((new_parent, new_name.clone())),
+                // Not a removal or rename
+                _ => return,
+            },
+        }
+        // The thread is only gone if the channel is, and then the kernel is as well
+        let _ = invalidator.0.send(entries);
+    }
+
+    fn record_change(&self, bufs: &[io::IoSlice<'_>]) {
+        let (Some(log), Some(change)) = (&self.change_log, &self.change) else {
+            return;
+        };
+        let header: Vec<u8> = bufs
+            .iter()
+            .flat_map(|b| b.iter().copied())
+            .take(24)
+            .collect();
+        if header.len() < size_of::<fuse_out_header>() || header[4..8] != [0; 4] {
+            return;
+        }
+        if change.creates_entry() {
+            // The entry reply starts with the inode number of the new entry
+            let Some(ino) = header.get(16..24) else {
+                return;
+            };
+            let mut change = Change::clone(change);
+            change.ino = Some(u64::from_ne_bytes(ino.try_into().unwrap()));
+            log.0.record(&change);
+        } else {
+            log.0.record(change);
+        }
+    }
+}
 
 impl ReplySender for ChannelSender {
     fn send(&self, bufs: &[io::IoSlice<'_>]) -> io::Result<()> {
+        let interrupted_header;
+        let interrupted_bufs;
+        let discarded = self.finish(bufs)?;
+        // The filesystem made the change even if its reply is discarded. Changes are recorded
+        // with the inode numbers of the filesystem, not the kernel.
+        self.record_change(bufs);
+        let bufs = match discarded {
+            Some(header) => {
+                interrupted_header = header;
+                interrupted_bufs = [io::IoSlice::new(&interrupted_header)];
+                &interrupted_bufs[..]
+            }
+            None => bufs,
+        };
+        let mut header = [0; size_of::<fuse_out_header>()];
+        let rewritten: SmallVec<[io::IoSlice<'_>; 4]>;
+        let bufs = match (&self.errno_policy, self.opcode) {
+            (Some(policy), Some(opcode)) if policy.rewrite(opcode, bufs, &mut header) => {
+                rewritten = std::iter::once(io::IoSlice::new(&header))
+                    .chain(bufs[1..].iter().map(|b| io::IoSlice::new(b)))
+                    .collect();
+                &rewritten[..]
+            }
+            _ => bufs,
+        };
+        let translated = self.remap.as_ref().and_then(|r| r.translate_response(bufs));
+        let translated_bufs;
+        let bufs = match &translated {
+            Some(data) => {
+                translated_bufs = [io::IoSlice::new(data)];
+                &translated_bufs[..]
+            }
+            None => bufs,
+        };
+        #[cfg(feature = "abi-7-12")]
+        let succeeded = bufs
+            .first()
+            .filter(|h| h.len() >= size_of::<fuse_out_header>())
+            .is_some_and(|h| h[4..8] == [0; 4] && h[8..16] != [0; 8]);
         let rc = unsafe {
             libc::writev(
-                self.0.as_raw_fd(),
+                self.device.as_raw_fd(),
                 bufs.as_ptr() as *const libc::iovec,
                 bufs.len() as c_int,
             )
@@ -72,7 +779,340 @@ impl ReplySender for ChannelSender {
             Err(io::Error::last_os_error())
         } else {
             debug_assert_eq!(bufs.iter().map(|b| b.len()).sum::<usize>(), rc as usize);
+            #[cfg(feature = "usdt")]
+            if let Some(header) = bufs.first().filter(|h| h.len() >= 16) {
+                let error = i32::from_ne_bytes(header[4..8].try_into().unwrap());
+                let unique = u64::from_ne_bytes(header[8..16].try_into().unwrap());
+                crate::usdt::request_end(self.opcode.unwrap_or(0), self.nodeid, unique, error);
+            }
+            if let Some((request, received)) = &self.hooked {
+                if let Some(header) = bufs.first().filter(|h| h.len() >= 8) {
+                    let error = i32::from_ne_bytes(header[4..8].try_into().unwrap());
+                    let latency = self.clock.now().saturating_duration_since(*received);
+                    self.hooks.after_reply(request, latency, -error);
+                }
+            }
+            #[cfg(feature = "abi-7-12")]
+            if succeeded {
+                self.invalidate_entries();
+            }
             Ok(())
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::Channel;
+    use crate::reply::ReplySender;
+    use crate::InterruptedReply;
+    use std::io::{IoSlice, Read, Seek, SeekFrom};
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    /// Returns what was written to the device, once it is `len` bytes long or after 5 seconds.
+    /// For messages sent by other threads.
+    #[cfg(feature = "abi-7-12")]
+    fn wait_for_sent(device: &mut std::fs::File, len: usize) -> Vec<u8> {
+        let deadline = std::time::Instant::now() + Duration::from_secs(5);
+        let mut sent = vec![];
+        while sent.len() < len && std::time::Instant::now() < deadline {
+            std::thread::sleep(Duration::from_millis(1));
+            sent.clear();
+            device.seek(SeekFrom::Start(0)).unwrap();
+            device.read_to_end(&mut sent).unwrap();
+        }
+        sent
+    }
+
+    fn reply(unique: u64, error: i32) -> [u8; 16] {
+        let mut header = [0; 16];
+        header[..4].copy_from_slice(&16u32.to_ne_bytes());
+        header[4..8].copy_from_slice(&error.to_ne_bytes());
+        header[8..16].copy_from_slice(&unique.to_ne_bytes());
+        header
+    }
+
+    #[test]
+    fn reply_after_interrupt() {
+        let mut device = tempfile::tempfile().unwrap();
+        let mut channel = Channel::new(Arc::new(device.try_clone().unwrap()));
+        channel.set_interrupted_reply(InterruptedReply::Discard);
+        let sender = channel.sender();
+        sender.track(2, 16);
+        sender.track(4, 16);
+        assert!(sender.interrupt(2));
+
+        // The interrupted request is failed, the other one answered as usual
+        sender.send(&[IoSlice::new(&reply(2, 0))]).unwrap();
+        sender.send(&[IoSlice::new(&reply(4, 0))]).unwrap();
+        // Interrupts that come too late are ignored
+        assert!(!sender.interrupt(2));
+
+        let mut sent = vec![];
+        device.seek(SeekFrom::Start(0)).unwrap();
+        device.read_to_end(&mut sent).unwrap();
+        assert_eq!(sent.len(), 32);
+        assert_eq!(sent[..16], reply(2, -libc::EINTR));
+        assert_eq!(sent[16..], reply(4, 0));
+    }
+
+    #[test]
+    fn expire() {
+        use crate::hook::RequestInfo;
+        use crate::{Clock, ManualClock, RequestHook};
+        use std::sync::Mutex;
+
+        #[derive(Default)]
+        struct Errors(Mutex<Vec<(u64, Duration, i32)>>);
+        impl RequestHook for Errors {
+            fn after_reply(&self, request: &RequestInfo, latency: Duration, errno: i32) {
+                self.0
+                    .lock()
+                    .unwrap()
+                    .push((request.unique, latency, errno));
+            }
+        }
+
+        let mut device = tempfile::tempfile().unwrap();
+        let mut channel = Channel::new(Arc::new(device.try_clone().unwrap()));
+        let clock = ManualClock::new();
+        channel.set_clock(Arc::new(clock.clone()));
+        let errors = Arc::new(Errors::default());
+        channel.add_hook(errors.clone());
+        channel.outstanding().enable_timeouts();
+        let sender = channel.sender();
+        let info = |unique| RequestInfo {
+            unique,
+            opcode: 1,
+            ino: 1,
+            uid: 0,
+            pid: 0,
+        };
+        sender
+            .clone()
+            .with_hooked_request(info(2), clock.now())
+            .track(2, 16);
+        clock.advance(Duration::from_millis(500));
+        sender
+            .clone()
+            .with_hooked_request(info(4), clock.now())
+            .track(4, 16);
+        clock.advance(Duration::from_millis(500));
+
+        // Times are taken from the clock of the channel
+        assert_eq!(
+            sender
+                .expire(Duration::from_secs(1), libc::ETIMEDOUT)
+                .unwrap(),
+            1
+        );
+        assert_eq!(channel.outstanding().snapshot().len(), 1);
+        assert_eq!(sender.expire(Duration::ZERO, libc::ETIMEDOUT).unwrap(), 1);
+        assert!(channel.outstanding().snapshot().is_empty());
+        // The filesystem answers too late
+        assert!(sender.send(&[IoSlice::new(&reply(4, 0))]).is_err());
+
+        let mut sent = vec![];
+        device.seek(SeekFrom::Start(0)).unwrap();
+        device.read_to_end(&mut sent).unwrap();
+        assert_eq!(sent[..16], reply(2, -libc::ETIMEDOUT));
+        assert_eq!(sent[16..], reply(4, -libc::ETIMEDOUT));
+        // The failed requests are reported like answered ones, with latencies by the clock
+        assert_eq!(
+            *errors.0.lock().unwrap(),
+            [
+                (2, Duration::from_secs(1), libc::ETIMEDOUT),
+                (4, Duration::from_millis(500), libc::ETIMEDOUT)
+            ]
+        );
+    }
+
+    #[test]
+    fn interrupt_token() {
+        let device = tempfile::tempfile().unwrap();
+        let channel = Channel::new(Arc::new(device));
+        let sender = channel.sender();
+        sender.track(2, 16);
+        let token = sender.interrupt_token(2);
+        assert!(!token.is_interrupted());
+        assert!(sender.interrupt(2));
+        assert!(token.clone().is_interrupted());
+        sender.send(&[IoSlice::new(&reply(2, 0))]).unwrap();
+        assert!(!token.is_interrupted());
+    }
+
+    #[test]
+    fn correlation() {
+        use crate::reply::{Reply, ReplyEmpty};
+
+        let device = tempfile::tempfile().unwrap();
+        let channel = Channel::new(Arc::new(device));
+        let sender = channel.sender();
+        sender.track(2, 16);
+        sender.track(4, 16);
+        let empty: ReplyEmpty = Reply::new(2, sender.clone());
+        empty.set_correlation("s3-req-42");
+        let pending = channel.outstanding().snapshot();
+        assert_eq!(pending[0].correlation.as_deref(), Some("s3-req-42"));
+        assert_eq!(pending[1].correlation, None);
+
+        empty.ok();
+        assert_eq!(channel.outstanding().snapshot().len(), 1);
+        // Answered requests can't be correlated anymore
+        assert!(!sender.correlate(2, "late".to_owned()));
+        assert!(sender.correlate(4, "s3-req-43".to_owned()));
+    }
+
+    #[test]
+    fn dropped_reply() {
+        use crate::reply::{Reply, ReplyEmpty};
+        use crate::DroppedReply;
+
+        let mut device = tempfile::tempfile().unwrap();
+        let mut channel = Channel::new(Arc::new(device.try_clone().unwrap()));
+        channel.set_dropped_reply(DroppedReply::Strict);
+        let sender = channel.sender().for_opcode(1);
+        sender.track(2, 16);
+        let empty: ReplyEmpty = Reply::new(2, sender);
+        let res = std::panic::catch_unwind(std::panic::AssertUnwindSafe(move || drop(empty)));
+        assert_eq!(res.is_err(), cfg!(debug_assertions));
+        assert_eq!(channel.dropped_replies(), 1);
+
+        // The request is failed before panicking
+        let mut sent = vec![];
+        device.seek(SeekFrom::Start(0)).unwrap();
+        device.read_to_end(&mut sent).unwrap();
+        assert_eq!(sent, reply(2, -libc::EIO));
+    }
+
+    #[test]
+    fn memory_budget() {
+        let device = tempfile::tempfile().unwrap();
+        let channel = Channel::new(Arc::new(device));
+        let sender = channel.sender();
+        sender.track(2, 16);
+        sender.track(4, 16);
+        sender.track(6, 16);
+        // Only memory held to answer requests counts, not their messages
+        assert_eq!(channel.outstanding().bytes(), 0);
+        assert!(sender.charge(2, 100));
+        assert!(sender.charge(4, 50));
+        assert_eq!(channel.outstanding().bytes(), 150);
+        sender.send(&[IoSlice::new(&reply(6, 0))]).unwrap();
+        assert!(!sender.charge(6, 10));
+        assert!(!channel
+            .outstanding()
+            .wait_for_budget(60, Duration::from_millis(1)));
+
+        let waiter = std::thread::spawn({
+            let sender = sender.clone();
+            move || {
+                sender
+                    .outstanding
+                    .wait_for_budget(60, Duration::from_secs(5))
+            }
+        });
+        sender.send(&[IoSlice::new(&reply(2, 0))]).unwrap();
+        assert!(waiter.join().unwrap());
+        assert_eq!(channel.outstanding().bytes(), 50);
+    }
+
+    #[test]
+    fn duplicate_reply() {
+        let mut device = tempfile::tempfile().unwrap();
+        let channel = Channel::new(Arc::new(device.try_clone().unwrap()));
+        let sender = channel.sender();
+        sender.track(2, 16);
+        sender.send(&[IoSlice::new(&reply(2, 0))]).unwrap();
+        // Answered twice, or never received
+        assert!(sender.send(&[IoSlice::new(&reply(2, 0))]).is_err());
+        assert!(sender.send(&[IoSlice::new(&reply(6, 0))]).is_err());
+        // Notifications aren't replies
+        sender.send(&[IoSlice::new(&reply(0, 0))]).unwrap();
+
+        let mut sent = vec![];
+        device.seek(SeekFrom::Start(0)).unwrap();
+        device.read_to_end(&mut sent).unwrap();
+        assert_eq!(sent.len(), 32);
+    }
+
+    #[cfg(feature = "abi-7-12")]
+    #[test]
+    fn inval_entries() {
+        use crate::change_log::Change;
+        use crate::ll::fuse_abi::fuse_opcode;
+
+        let mut device = tempfile::tempfile().unwrap();
+        let mut channel = Channel::new(Arc::new(device.try_clone().unwrap()));
+        channel.set_inval_entries(true);
+        let unlink = |unique: u64| {
+            let mut change = Change::new(fuse_opcode::FUSE_UNLINK);
+            change.parent = Some(1);
+            change.name = Some("a".into());
+            let sender = channel
+                .sender()
+                .for_opcode(fuse_opcode::FUSE_UNLINK as u32)
+                .with_change(change);
+            sender.track(unique, 16);
+            sender
+        };
+        unlink(2)
+            .send(&[IoSlice::new(&reply(2, -libc::EACCES))])
+            .unwrap();
+        unlink(3).send(&[IoSlice::new(&reply(3, 0))]).unwrap();
+
+        // Only the successful unlink is followed by an invalidation of the entry, which is sent
+        // by another thread
+        let sent = wait_for_sent(&mut device, 16 + 16 + 16 + 16 + 2);
+        assert_eq!(sent.len(), 16 + 16 + 16 + 16 + 2);
+        assert_eq!(sent[16..32], reply(3, 0));
+        assert_eq!(sent[36..40], 3i32.to_ne_bytes());
+        assert_eq!(sent[48..56], 1u64.to_ne_bytes());
+        assert_eq!(sent[64..], *b"a\0");
+    }
+
+    #[cfg(feature = "abi-7-12")]
+    #[test]
+    fn inval_entries_rmdir_rename() {
+        use crate::change_log::Change;
+        use crate::ll::fuse_abi::fuse_opcode;
+
+        let mut device = tempfile::tempfile().unwrap();
+        let mut channel = Channel::new(Arc::new(device.try_clone().unwrap()));
+        channel.set_inval_entries(true);
+        let send = |unique: u64, change: Change, error: i32| {
+            let sender = channel
+                .sender()
+                .for_opcode(change.op as u32)
+                .with_change(change);
+            sender.track(unique, 16);
+            sender.send(&[IoSlice::new(&reply(unique, error))]).unwrap();
+        };
+        let mut rmdir = Change::new(fuse_opcode::FUSE_RMDIR);
+        rmdir.parent = Some(1);
+        rmdir.name = Some("d".into());
+        let mut rename = Change::new(fuse_opcode::FUSE_RENAME);
+        rename.parent = Some(1);
+        rename.name = Some("b".into());
+        rename.new_parent = Some(4);
+        rename.new_name = Some("c".into());
+        send(2, rmdir.clone(), -libc::ENOTEMPTY);
+        send(3, rename.clone(), -libc::EXDEV);
+        send(4, rmdir, 0);
+        send(5, rename, 0);
+
+        // Four replies, then the entry of the rmdir and both entries of the rename
+        let len = 4 * 16 + 3 * (32 + 2);
+        let sent = wait_for_sent(&mut device, len);
+        assert_eq!(sent.len(), len);
+        let notifications: Vec<(u64, &[u8])> = sent[64..]
+            .chunks(34)
+            .map(|n| (u64::from_ne_bytes(n[16..24].try_into().unwrap()), &n[32..]))
+            .collect();
+        assert_eq!(
+            notifications,
+            [(1, &b"d\0"[..]), (1, &b"b\0"[..]), (4, &b"c\0"[..])]
+        );
+    }
+}
@@ -0,0 +1,71 @@
+//! Checksums of written data
+//!
+//! Storage backends which store a checksum with each block would otherwise have to pass over
+//! the data of every write a second time. With a [`Checksum`] installed through
+//! [`Session::set_write_checksum`](crate::Session::set_write_checksum), the session computes
+//! it while the data is still in the cache after reading the request, and
+//! [`Request::write_checksum`](crate::Request::write_checksum) returns it to the `write`
+//! handler.
+
+use std::fmt;
+
+/// An algorithm computing a checksum of written data
+pub trait Checksum: Send + Sync + fmt::Debug {
+    /// Returns the checksum of the given data
+    fn checksum(&self, data: &[u8]) -> u64;
+}
+
+/// CRC-32C (Castagnoli), as used by iSCSI, ext4 and many object stores
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Crc32c;
+
+const CRC32C_TABLE: [u32; 256] = crc32c_table();
+
+const fn crc32c_table() -> [u32; 256] {
+    // Reversed polynomial of CRC-32C
+    const POLY: u32 = 0x82f6_3b78;
+    let mut table = [0; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = i as u32;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 1 != 0 {
+                (crc >> 1) ^ POLY
+            } else {
+                crc >> 1
+            };
+            bit += 1;
+        }
+        table[i] = crc;
+        i += 1;
+    }
+    table
+}
+
+impl Crc32c {
+    /// Returns the CRC-32C of the given data
+    pub fn crc(data: &[u8]) -> u32 {
+        !data.iter().fold(!0, |crc, byte| {
+            CRC32C_TABLE[((crc ^ u32::from(*byte)) & 0xff) as usize] ^ (crc >> 8)
+        })
+    }
+}
+
+impl Checksum for Crc32c {
+    fn checksum(&self, data: &[u8]) -> u64 {
+        Crc32c::crc(data).into()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Checksum, Crc32c};
+
+    #[test]
+    fn crc32c() {
+        assert_eq!(Crc32c.checksum(b""), 0);
+        assert_eq!(Crc32c.checksum(b"123456789"), 0xe306_9283);
+        assert_eq!(Crc32c::crc(&[0; 32]), 0x8a91_36aa);
+    }
+}
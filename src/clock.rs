@@ -0,0 +1,103 @@
+//! Source of time for a session
+//!
+//! Everything timing related in a session (batching forgets, uptime statistics) reads the time
+//! from a [`Clock`], so that tests can replace the system clock with a [`ManualClock`] and
+//! control how time passes (see [`Session::set_clock`](crate::Session::set_clock)).
+
+use std::fmt;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime};
+
+/// A source of monotonic and wall clock time
+pub trait Clock: Send + Sync + fmt::Debug {
+    /// Returns the current monotonic time
+    fn now(&self) -> Instant;
+
+    /// Returns the current wall clock time
+    fn system_time(&self) -> SystemTime;
+}
+
+/// The clock of the operating system. Used by sessions unless configured otherwise.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+
+    fn system_time(&self) -> SystemTime {
+        SystemTime::now()
+    }
+}
+
+/// A clock that only moves when it is advanced. Clones share the same time, so a test can keep
+/// a clone to advance the clock of a session.
+#[derive(Debug, Clone)]
+pub struct ManualClock {
+    instant: Instant,
+    system_time: SystemTime,
+    elapsed: Arc<Mutex<Duration>>,
+}
+
+impl ManualClock {
+    /// Create a clock that stands still at the current time
+    pub fn new() -> Self {
+        Self::starting_at(SystemTime::now())
+    }
+
+    /// Create a clock that stands still at the given wall clock time
+    pub fn starting_at(system_time: SystemTime) -> Self {
+        Self {
+            instant: Instant::now(),
+            system_time,
+            elapsed: Arc::default(),
+        }
+    }
+
+    /// Move the clock forward by the given duration
+    pub fn advance(&self, duration: Duration) {
+        *self.elapsed.lock().unwrap() += duration;
+    }
+
+    /// Returns how far the clock was advanced since it was created
+    pub fn elapsed(&self) -> Duration {
+        *self.elapsed.lock().unwrap()
+    }
+}
+
+impl Default for ManualClock {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Clock for ManualClock {
+    fn now(&self) -> Instant {
+        self.instant + self.elapsed()
+    }
+
+    fn system_time(&self) -> SystemTime {
+        self.system_time + self.elapsed()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Clock, ManualClock};
+    use std::time::{Duration, UNIX_EPOCH};
+
+    #[test]
+    fn manual_clock() {
+        let clock = ManualClock::starting_at(UNIX_EPOCH);
+        let start = clock.now();
+        assert_eq!(clock.now(), start);
+        assert_eq!(clock.system_time(), UNIX_EPOCH);
+
+        let handle = clock.clone();
+        handle.advance(Duration::from_secs(5));
+        assert_eq!(clock.now() - start, Duration::from_secs(5));
+        assert_eq!(clock.system_time(), UNIX_EPOCH + Duration::from_secs(5));
+        assert_eq!(clock.elapsed(), Duration::from_secs(5));
+    }
+}
@@ -0,0 +1,147 @@
+//! Control interface of a session
+//!
+//! Extended attributes of the mount root in the `user.fuser.` namespace, handled by the session
+//! instead of the filesystem (see [`Session::enable_control`](crate::Session::enable_control)).
+
+use std::collections::BTreeMap;
+use std::ffi::OsStr;
+use std::fmt::Write;
+use std::os::unix::ffi::OsStrExt;
+use std::str::FromStr;
+use std::time::Instant;
+
+use log::{info, LevelFilter};
+
+use crate::ll::{Errno, Response};
+use crate::session::Session;
+use crate::Filesystem;
+
+/// Namespace of the attributes handled by the session
+pub(crate) const CONTROL_PREFIX: &str = "user.fuser.";
+
+/// Counters of a session shown by `user.fuser.stats`
+#[derive(Debug)]
+pub(crate) struct Stats {
+    pub(crate) started: Instant,
+    pub(crate) requests: u64,
+    pub(crate) requests_by_opcode: BTreeMap<u32, u64>,
+}
+
+impl Stats {
+    pub(crate) fn new(started: Instant) -> Self {
+        Self {
+            started,
+            requests: 0,
+            requests_by_opcode: BTreeMap::new(),
+        }
+    }
+}
+
+/// Returns whether the attribute is handled by the session rather than the filesystem.
+pub(crate) fn is_control_xattr<FS: Filesystem>(se: &Session<FS>, ino: u64, name: &OsStr) -> bool {
+    se.control
+        && ino == crate::FUSE_ROOT_ID
+        && name.as_bytes().starts_with(CONTROL_PREFIX.as_bytes())
+}
+
+fn stats<FS: Filesystem>(se: &Session<FS>) -> String {
+    let mut out = String::new();
+    let (major, minor) = se.connection.protocol_version();
+    writeln!(out, "protocol: {}.{}", major, minor).unwrap();
+    let uptime = se.clock.now().saturating_duration_since(se.stats.started);
+    writeln!(out, "uptime_secs: {}", uptime.as_secs()).unwrap();
+    writeln!(out, "requests: {}", se.stats.requests).unwrap();
+    writeln!(out, "max_write: {}", se.connection.max_write()).unwrap();
+    writeln!(out, "memory_in_use: {}", se.memory_in_use()).unwrap();
+    writeln!(out, "dropped_replies: {}", se.ch.dropped_replies()).unwrap();
+    writeln!(out, "log_level: {}", log::max_level()).unwrap();
+    #[cfg(feature = "abi-7-16")]
+    writeln!(out, "pending_forgets: {}", se.pending_forgets()).unwrap();
+    out
+}
+
+/// Reply to `getxattr` of a control attribute
+pub(crate) fn getxattr<FS: Filesystem>(
+    se: &Session<FS>,
+    name: &OsStr,
+    size: u32,
+) -> Result<Response<'static>, Errno> {
+    let value = match &name.as_bytes()[CONTROL_PREFIX.len()..] {
+        b"version" => format!("{}\n", env!("CARGO_PKG_VERSION")),
+        b"stats" => stats(se),
+        _ => return Err(Errno::NO_XATTR),
+    };
+    if size == 0 {
+        Ok(Response::new_xattr_size(value.len() as u32))
+    } else if (size as usize) < value.len() {
+        Err(Errno::ERANGE)
+    } else {
+        Ok(Response::new_data(value))
+    }
+}
+
+/// Run the command set as the value of `user.fuser.command`
+pub(crate) fn setxattr<FS: Filesystem>(
+    se: &mut Session<FS>,
+    name: &OsStr,
+    value: &[u8],
+    uid: u32,
+) -> Result<Response<'static>, Errno> {
+    if name.as_bytes() != format!("{}command", CONTROL_PREFIX).as_bytes() {
+        return Err(Errno::EPERM);
+    }
+    if uid != se.session_owner && uid != 0 {
+        return Err(Errno::EACCES);
+    }
+    let command = Command::parse(value).ok_or(Errno::EINVAL)?;
+    info!("Control command: {:?}", command);
+    match command {
+        Command::LogLevel(level) => log::set_max_level(level),
+        #[cfg(feature = "abi-7-16")]
+        Command::FlushForgets => se.flush_forgets(),
+        #[cfg(not(feature = "abi-7-16"))]
+        Command::FlushForgets => {}
+    }
+    Ok(Response::new_empty())
+}
+
+#[derive(Debug, PartialEq, Eq)]
+enum Command {
+    LogLevel(LevelFilter),
+    FlushForgets,
+}
+
+impl Command {
+    fn parse(value: &[u8]) -> Option<Command> {
+        let command = std::str::from_utf8(value)
+            .ok()?
+            .trim_end_matches(|c| c == '\n' || c == '\0');
+        if let Some(level) = command.strip_prefix("log-level=") {
+            LevelFilter::from_str(level).ok().map(Command::LogLevel)
+        } else if command == "flush-forgets" {
+            Some(Command::FlushForgets)
+        } else {
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::Command;
+    use log::LevelFilter;
+
+    #[test]
+    fn parse_commands() {
+        assert_eq!(
+            Command::parse(b"log-level=debug\n"),
+            Some(Command::LogLevel(LevelFilter::Debug))
+        );
+        assert_eq!(
+            Command::parse(b"flush-forgets"),
+            Some(Command::FlushForgets)
+        );
+        assert_eq!(Command::parse(b"log-level=loud"), None);
+        assert_eq!(Command::parse(b"reboot"), None);
+    }
+}
@@ -0,0 +1,90 @@
+//! Typed access to the flags of `copy_file_range` requests
+
+/// Flags of a [`Filesystem::copy_file_range`](crate::Filesystem::copy_file_range) request.
+///
+/// No flags are defined yet, and the kernel rejects nonzero flags before sending the request.
+/// Requests with unknown flags are answered with `EINVAL` before they reach the filesystem.
+///
+/// The kernel only sends copies between files of the same mount. A filesystem that can't copy
+/// between two of its files directly, for example because they are stored on different
+/// backends, should reply `EXDEV`: the kernel then falls back to copying the data through
+/// `read` and `write`. Replying `ENOSYS` instead disables `copy_file_range` for the whole
+/// mount.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub struct CopyFileRangeFlags(pub u64);
+
+impl CopyFileRangeFlags {
+    /// The flags that are known to this crate
+    pub const KNOWN: u64 = 0;
+
+    /// Returns the raw flags
+    pub fn bits(&self) -> u64 {
+        self.0
+    }
+
+    /// Whether no flags are set
+    pub fn is_empty(&self) -> bool {
+        self.0 == 0
+    }
+
+    /// Returns the flags that are not known to this crate
+    pub fn unknown(&self) -> u64 {
+        self.0 & !Self::KNOWN
+    }
+}
+
+impl From<u64> for CopyFileRangeFlags {
+    fn from(flags: u64) -> Self {
+        CopyFileRangeFlags(flags)
+    }
+}
+
+impl From<u32> for CopyFileRangeFlags {
+    fn from(flags: u32) -> Self {
+        CopyFileRangeFlags(flags.into())
+    }
+}
+
+/// Checks a copy the way the kernel does before it sends one: offsets must not be negative, and
+/// a copy within a file must not overlap itself.
+#[cfg_attr(not(feature = "abi-7-28"), allow(dead_code))]
+pub(crate) fn valid_copy(
+    ino_in: u64,
+    offset_in: i64,
+    ino_out: u64,
+    offset_out: i64,
+    len: u64,
+) -> bool {
+    if offset_in < 0 || offset_out < 0 {
+        return false;
+    }
+    let (Some(end_in), Some(end_out)) = (
+        (offset_in as u64).checked_add(len),
+        (offset_out as u64).checked_add(len),
+    ) else {
+        return false;
+    };
+    ino_in != ino_out || end_in <= offset_out as u64 || end_out <= offset_in as u64
+}
+
+#[cfg(test)]
+mod test {
+    use super::{valid_copy, CopyFileRangeFlags};
+
+    #[test]
+    fn flags() {
+        assert!(CopyFileRangeFlags::from(0u32).is_empty());
+        assert_eq!(CopyFileRangeFlags::from(4u64).unknown(), 4);
+    }
+
+    #[test]
+    fn overlap() {
+        assert!(valid_copy(1, 0, 2, 0, 100));
+        assert!(valid_copy(1, 0, 1, 100, 100));
+        assert!(valid_copy(1, 100, 1, 0, 100));
+        assert!(!valid_copy(1, 0, 1, 50, 100));
+        assert!(!valid_copy(1, 50, 1, 0, 100));
+        assert!(!valid_copy(1, -1, 2, 0, 100));
+        assert!(!valid_copy(1, 0, 2, 1, u64::MAX));
+    }
+}
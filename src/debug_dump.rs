@@ -0,0 +1,145 @@
+//! Snapshot of the state of a session
+//!
+//! When a mount hangs, the most useful information is which requests the filesystem hasn't
+//! answered yet. [`Session::debug_dump`](crate::Session::debug_dump) collects it together with
+//! the negotiated settings and request counters into a [`DebugDump`], which can be inspected
+//! or written to a log with its `Display` implementation.
+
+use std::collections::BTreeMap;
+use std::convert::TryFrom;
+use std::fmt;
+use std::time::Duration;
+
+use crate::ll::fuse_abi::fuse_opcode;
+use crate::ConnectionInfo;
+
+/// A request the filesystem hasn't answered yet
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PendingRequest {
+    /// Unique id of the request, see [`Request::unique`](crate::Request::unique)
+    pub unique: u64,
+    /// Raw opcode of the request
+    pub opcode: u32,
+    /// Whether the kernel interrupted the request
+    pub interrupted: bool,
+    /// Size of the message of the request
+    pub size: usize,
+    /// Token the filesystem attached to the request, see
+    /// [`Request::set_correlation`](crate::Request::set_correlation)
+    pub correlation: Option<String>,
+}
+
+/// Snapshot of the state of a session, see
+/// [`Session::debug_dump`](crate::Session::debug_dump).
+///
+/// Notifications are written to the kernel when they are sent, so there is no queue of them
+/// to report.
+#[derive(Debug, Clone)]
+#[non_exhaustive]
+pub struct DebugDump {
+    /// Settings negotiated with the kernel
+    pub connection: ConnectionInfo,
+    /// Time since the session was created
+    pub uptime: Duration,
+    /// Number of requests received
+    pub requests: u64,
+    /// Number of requests received by raw opcode
+    pub requests_by_opcode: BTreeMap<u32, u64>,
+    /// Requests that weren't answered yet, oldest first
+    pub pending: Vec<PendingRequest>,
+    /// Memory held by pending requests, see [`Session::memory_in_use`](crate::Session::memory_in_use)
+    pub memory_in_use: usize,
+    /// Number of replies the filesystem dropped without answering the request
+    pub dropped_replies: u64,
+    /// Forgets waiting to be delivered in a batch
+    pub pending_forgets: usize,
+}
+
+pub(crate) fn opcode_name(opcode: u32) -> String {
+    fuse_opcode::try_from(opcode).map_or_else(|_| opcode.to_string(), |op| format!("{:?}", op))
+}
+
+impl fmt::Display for DebugDump {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let (major, minor) = self.connection.protocol_version();
+        writeln!(f, "protocol: {}.{}", major, minor)?;
+        writeln!(f, "capabilities: {:#x}", self.connection.capabilities())?;
+        writeln!(f, "flags: {:#x}", self.connection.flags())?;
+        writeln!(f, "max_write: {}", self.connection.max_write())?;
+        writeln!(f, "max_readahead: {}", self.connection.max_readahead())?;
+        #[cfg(feature = "abi-7-13")]
+        {
+            writeln!(f, "max_background: {}", self.connection.max_background())?;
+            writeln!(
+                f,
+                "congestion_threshold: {}",
+                self.connection.congestion_threshold()
+            )?;
+        }
+        #[cfg(feature = "abi-7-23")]
+        writeln!(f, "time_gran: {:?}", self.connection.time_granularity())?;
+        writeln!(f, "uptime_secs: {}", self.uptime.as_secs())?;
+        writeln!(f, "requests: {}", self.requests)?;
+        for (opcode, count) in &self.requests_by_opcode {
+            writeln!(f, "  {}: {}", opcode_name(*opcode), count)?;
+        }
+        writeln!(f, "pending: {}", self.pending.len())?;
+        for request in &self.pending {
+            writeln!(
+                f,
+                "  {} {} ({} bytes){}",
+                request.unique,
+                opcode_name(request.opcode),
+                request.size,
+                if request.interrupted {
+                    ", interrupted"
+                } else {
+                    ""
+                }
+            )?;
+            if let Some(correlation) = &request.correlation {
+                writeln!(f, "    correlation: {}", correlation)?;
+            }
+        }
+        writeln!(f, "memory_in_use: {}", self.memory_in_use)?;
+        writeln!(f, "dropped_replies: {}", self.dropped_replies)?;
+        writeln!(f, "pending_forgets: {}", self.pending_forgets)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{DebugDump, PendingRequest};
+    use crate::ll::fuse_abi::fuse_opcode;
+    use crate::ConnectionInfo;
+    use std::collections::BTreeMap;
+    use std::time::Duration;
+
+    #[test]
+    fn display() {
+        let dump = DebugDump {
+            connection: ConnectionInfo::default(),
+            uptime: Duration::from_secs(3),
+            requests: 2,
+            requests_by_opcode: BTreeMap::from([(fuse_opcode::FUSE_READ as u32, 2)]),
+            pending: vec![PendingRequest {
+                unique: 8,
+                opcode: fuse_opcode::FUSE_READ as u32,
+                interrupted: true,
+                size: 80,
+                correlation: Some("s3-req-42".to_owned()),
+            }],
+            memory_in_use: 80,
+            dropped_replies: 0,
+            pending_forgets: 0,
+        };
+        let out = dump.to_string();
+        assert!(out.contains("  FUSE_READ: 2\n"), "{}", out);
+        assert!(out.contains("flags: 0x0\nmax_write: 0\nmax_readahead: 0\n"));
+        assert!(
+            out.contains("  8 FUSE_READ (80 bytes), interrupted\n    correlation: s3-req-42\n"),
+            "{}",
+            out
+        );
+    }
+}
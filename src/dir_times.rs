@@ -0,0 +1,162 @@
+//! Times of directories whose entries changed
+//!
+//! POSIX requires creating, removing and renaming entries to update the modification and
+//! change times of the directories involved, and forgetting to do so is one of the most common
+//! failures pjdfstest reports. A [`DirTimes`] installed as the change log of the session (see
+//! [`Session::set_change_log`](crate::Session::set_change_log)) records when the entries of
+//! each directory last changed, and the filesystem applies these times to the attributes it
+//! replies with.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::{Arc, Mutex};
+use std::time::SystemTime;
+
+use crate::ll::fuse_abi::fuse_opcode;
+use crate::{Change, ChangeLog, Clock, FileAttr, SystemClock};
+
+/// When the entries of each directory last changed
+///
+/// Clones share the same times, so one can be installed on the session and another kept by the
+/// filesystem. A session has a single change log: to also record changes elsewhere, install a
+/// closure that passes them on to both.
+#[derive(Clone)]
+pub struct DirTimes {
+    clock: Arc<dyn Clock>,
+    changed: Arc<Mutex<HashMap<u64, SystemTime>>>,
+}
+
+impl fmt::Debug for DirTimes {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("DirTimes")
+            .field("clock", &self.clock)
+            .field("changed", &self.changed.lock().unwrap().len())
+            .finish()
+    }
+}
+
+impl Default for DirTimes {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl DirTimes {
+    /// Create an empty record, which takes times from the system clock.
+    pub fn new() -> Self {
+        Self::with_clock(SystemClock)
+    }
+
+    /// Create an empty record, which takes times from the given clock.
+    pub fn with_clock<C: Clock + 'static>(clock: C) -> Self {
+        Self {
+            clock: Arc::new(clock),
+            changed: Arc::default(),
+        }
+    }
+
+    /// Record that the entries of `dir` changed now, for changes the session doesn't see.
+    pub fn touch(&self, dir: u64) {
+        let now = self.clock.system_time();
+        self.changed.lock().unwrap().insert(dir, now);
+    }
+
+    /// Returns when the entries of `dir` last changed, if they changed since it was forgotten
+    pub fn changed(&self, dir: u64) -> Option<SystemTime> {
+        self.changed.lock().unwrap().get(&dir).copied()
+    }
+
+    /// Move the modification and change time of `attr` forward to when the entries of the
+    /// directory last changed. Times that are already later, e.g. because of a `setattr`, are
+    /// kept.
+    pub fn apply(&self, attr: &mut FileAttr) {
+        if let Some(changed) = self.changed(attr.ino) {
+            attr.mtime = attr.mtime.max(changed);
+            attr.ctime = attr.ctime.max(changed);
+        }
+    }
+
+    /// Forget the times of `dir`. Call it when the filesystem stored them, or when the
+    /// directory is gone.
+    pub fn forget(&self, dir: u64) {
+        self.changed.lock().unwrap().remove(&dir);
+    }
+}
+
+impl ChangeLog for DirTimes {
+    fn record(&self, change: &Change) {
+        let entries_changed = matches!(
+            change.op,
+            fuse_opcode::FUSE_MKNOD
+                | fuse_opcode::FUSE_MKDIR
+                | fuse_opcode::FUSE_CREATE
+                | fuse_opcode::FUSE_SYMLINK
+                | fuse_opcode::FUSE_LINK
+                | fuse_opcode::FUSE_UNLINK
+                | fuse_opcode::FUSE_RMDIR
+        ) || change.new_parent.is_some();
+        if !entries_changed {
+            return;
+        }
+        for dir in change.parent.iter().chain(change.new_parent.iter()) {
+            self.touch(*dir);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::DirTimes;
+    use crate::change_log::Change;
+    use crate::ll::fuse_abi::fuse_opcode;
+    use crate::{ChangeLog, FileAttr, FileType, ManualClock};
+    use std::time::{Duration, UNIX_EPOCH};
+
+    #[test]
+    fn record_changes() {
+        let clock = ManualClock::starting_at(UNIX_EPOCH + Duration::from_secs(100));
+        let times = DirTimes::with_clock(clock.clone());
+
+        let mut rename = Change::new(fuse_opcode::FUSE_RENAME);
+        rename.parent = Some(2);
+        rename.new_parent = Some(3);
+        times.record(&rename);
+        clock.advance(Duration::from_secs(5));
+        let mut unlink = Change::new(fuse_opcode::FUSE_UNLINK);
+        unlink.parent = Some(3);
+        times.record(&unlink);
+        // Changes to the inode itself don't touch its directory
+        let mut write = Change::new(fuse_opcode::FUSE_WRITE);
+        write.ino = Some(4);
+        times.record(&write);
+
+        let start = UNIX_EPOCH + Duration::from_secs(100);
+        assert_eq!(times.changed(2), Some(start));
+        assert_eq!(times.changed(3), Some(start + Duration::from_secs(5)));
+        assert_eq!(times.changed(4), None);
+
+        let mut attr = FileAttr {
+            ino: 2,
+            size: 0,
+            blocks: 0,
+            atime: UNIX_EPOCH,
+            mtime: UNIX_EPOCH,
+            ctime: start + Duration::from_secs(60),
+            crtime: UNIX_EPOCH,
+            kind: FileType::Directory,
+            perm: 0o755,
+            nlink: 2,
+            uid: 0,
+            gid: 0,
+            rdev: 0,
+            blksize: 512,
+            flags: 0,
+        };
+        times.apply(&mut attr);
+        assert_eq!(attr.mtime, start);
+        assert_eq!(attr.ctime, start + Duration::from_secs(60));
+
+        times.forget(2);
+        assert_eq!(times.changed(2), None);
+    }
+}
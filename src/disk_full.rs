@@ -0,0 +1,295 @@
+//! Disk-full simulation
+//!
+//! Applications rarely get their handling of a full disk tested, because filling a real disk is
+//! slow and hard to do deterministically. [`DiskFullFs`] wraps any filesystem and lets writes
+//! fail with `ENOSPC` once a configurable amount of data was written.
+
+use libc::ENOSPC;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+use crate::forward::forward_filesystem;
+use crate::{Filesystem, ReplyEmpty, ReplyStatfs, ReplyWrite, Request, WriteData};
+
+/// A filesystem wrapper that simulates a disk of limited capacity.
+///
+/// All data passed to `write`, `fallocate` and `copy_file_range` of the inner filesystem counts
+/// against the capacity. Once it is used up, these operations fail with `ENOSPC` (writes that
+/// don't fit completely are shortened first). Space is only given back when the inner filesystem
+/// fails an operation or writes less than it was passed, not when files are truncated or removed,
+/// which keeps the point of failure independent of the inner filesystem.
+/// `statfs` reports the simulated capacity instead of asking the inner filesystem.
+#[derive(Debug)]
+pub struct DiskFullFs<F> {
+    inner: F,
+    /// Simulated capacity in bytes
+    capacity: u64,
+    /// Bytes written so far, shared with the replies that refund failed writes
+    used: Arc<AtomicU64>,
+    block_size: u32,
+    files: u64,
+    files_free: u64,
+}
+
+impl<F: Filesystem> DiskFullFs<F> {
+    /// Wrap a filesystem, allowing `capacity` bytes to be written to it.
+    pub fn new(inner: F, capacity: u64) -> Self {
+        Self {
+            inner,
+            capacity,
+            used: Arc::new(AtomicU64::new(0)),
+            block_size: 4096,
+            files: 0,
+            files_free: 0,
+        }
+    }
+
+    /// Set the block size reported by `statfs`. Defaults to 4096.
+    pub fn with_block_size(mut self, block_size: u32) -> Self {
+        self.block_size = block_size.max(1);
+        self
+    }
+
+    /// Set the total and free number of inodes reported by `statfs`. Both default to 0.
+    pub fn with_inodes(mut self, files: u64, files_free: u64) -> Self {
+        self.files = files;
+        self.files_free = files_free;
+        self
+    }
+
+    /// Returns the number of bytes that can still be written.
+    pub fn remaining(&self) -> u64 {
+        self.capacity
+            .saturating_sub(self.used.load(Ordering::Relaxed))
+    }
+
+    /// Make the given number of bytes available again (e.g. to simulate a user freeing space).
+    pub fn release(&mut self, bytes: u64) {
+        refund(&self.used, bytes);
+    }
+
+    /// Returns a reference to the inner filesystem.
+    pub fn inner(&self) -> &F {
+        &self.inner
+    }
+
+    /// Returns a mutable reference to the inner filesystem.
+    pub fn inner_mut(&mut self) -> &mut F {
+        &mut self.inner
+    }
+
+    /// Unwrap the inner filesystem.
+    pub fn into_inner(self) -> F {
+        self.inner
+    }
+
+    /// Account for writing up to `len` bytes. Returns how many bytes may be written, or `None`
+    /// if the disk is full.
+    fn reserve(&mut self, len: u64) -> Option<u64> {
+        let len = len.min(self.remaining());
+        if len == 0 && self.remaining() == 0 {
+            return None;
+        }
+        self.used.fetch_add(len, Ordering::Relaxed);
+        Some(len)
+    }
+
+    /// Refund the part of `reserved` bytes that a write reply doesn't report as written
+    fn refund_unwritten(&self, reply: ReplyWrite, reserved: u64) -> ReplyWrite {
+        let used = self.used.clone();
+        reply.on_written(move |written| {
+            let written = written.map_or(0, u64::from).min(reserved);
+            refund(&used, reserved - written);
+        })
+    }
+}
+
+fn refund(used: &AtomicU64, bytes: u64) {
+    let _ = used.fetch_update(Ordering::Relaxed, Ordering::Relaxed, |used| {
+        Some(used - bytes.min(used))
+    });
+}
+
+impl<F: Filesystem> Filesystem for DiskFullFs<F> {
+    forward_filesystem!(inner, except: write, write_stream, statfs, fallocate, copy_file_range);
+
+    fn write(
+        &mut self,
+        req: &Request<'_>,
+        ino: u64,
+        fh: u64,
+        offset: i64,
+        data: &[u8],
+        write_flags: u32,
+        flags: i32,
+        lock_owner: Option<u64>,
+        reply: ReplyWrite,
+    ) {
+        match self.reserve(data.len() as u64) {
+            // Short write of what still fits. FUSE doesn't retry the rest: the short count is
+            // returned from write(2), and an application that writes the rest gets ENOSPC
+            Some(len) => {
+                let reply = self.refund_unwritten(reply, len);
+                self.inner.write(
+                    req,
+                    ino,
+                    fh,
+                    offset,
+                    &data[..len as usize],
+                    write_flags,
+                    flags,
+                    lock_owner,
+                    reply,
+                )
+            }
+            None => reply.error(ENOSPC),
+        }
+    }
+
+    fn write_stream(
+        &mut self,
+        req: &Request<'_>,
+        ino: u64,
+        fh: u64,
+        offset: i64,
+        data: WriteData<'_>,
+        write_flags: u32,
+        flags: i32,
+        lock_owner: Option<u64>,
+        reply: ReplyWrite,
+    ) {
+        match self.reserve(data.len() as u64) {
+            Some(len) => {
+                let reply = self.refund_unwritten(reply, len);
+                self.inner.write_stream(
+                    req,
+                    ino,
+                    fh,
+                    offset,
+                    data.truncate(len as usize),
+                    write_flags,
+                    flags,
+                    lock_owner,
+                    reply,
+                )
+            }
+            None => reply.error(ENOSPC),
+        }
+    }
+
+    fn statfs(&mut self, _req: &Request<'_>, _ino: u64, reply: ReplyStatfs) {
+        let bsize = u64::from(self.block_size);
+        let bfree = self.remaining() / bsize;
+        reply.statfs(
+            self.capacity / bsize,
+            bfree,
+            bfree,
+            self.files,
+            self.files_free,
+            self.block_size,
+            255,
+            self.block_size,
+        );
+    }
+
+    fn fallocate(
+        &mut self,
+        req: &Request<'_>,
+        ino: u64,
+        fh: u64,
+        offset: i64,
+        length: i64,
+        mode: i32,
+        reply: ReplyEmpty,
+    ) {
+        // Allocations can't be partial, and only the default mode allocates space
+        let charge = if mode == 0 { length.max(0) as u64 } else { 0 };
+        if charge > self.remaining() {
+            reply.error(ENOSPC);
+            return;
+        }
+        self.used.fetch_add(charge, Ordering::Relaxed);
+        let used = self.used.clone();
+        let reply = reply.on_sent(move |err| {
+            if err != 0 {
+                refund(&used, charge);
+            }
+        });
+        self.inner
+            .fallocate(req, ino, fh, offset, length, mode, reply)
+    }
+
+    fn copy_file_range(
+        &mut self,
+        req: &Request<'_>,
+        ino_in: u64,
+        fh_in: u64,
+        offset_in: i64,
+        ino_out: u64,
+        fh_out: u64,
+        offset_out: i64,
+        len: u64,
+        flags: u32,
+        reply: ReplyWrite,
+    ) {
+        match self.reserve(len) {
+            Some(len) => {
+                let reply = self.refund_unwritten(reply, len);
+                self.inner.copy_file_range(
+                    req, ino_in, fh_in, offset_in, ino_out, fh_out, offset_out, len, flags, reply,
+                )
+            }
+            None => reply.error(ENOSPC),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::DiskFullFs;
+    use crate::reply::{Reply, ReplySender, ReplyWrite};
+    use std::io::IoSlice;
+
+    struct NullFs;
+    impl crate::Filesystem for NullFs {}
+
+    struct NullSender;
+    impl ReplySender for NullSender {
+        fn send(&self, _: &[IoSlice<'_>]) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn reserve_until_full() {
+        let mut fs = DiskFullFs::new(NullFs, 10);
+        assert_eq!(fs.reserve(4), Some(4));
+        assert_eq!(fs.reserve(0), Some(0));
+        assert_eq!(fs.reserve(8), Some(6));
+        assert_eq!(fs.remaining(), 0);
+        assert_eq!(fs.reserve(1), None);
+        assert_eq!(fs.reserve(0), None);
+        fs.release(3);
+        assert_eq!(fs.remaining(), 3);
+        assert_eq!(fs.reserve(5), Some(3));
+        fs.release(100);
+        assert_eq!(fs.remaining(), 10);
+    }
+
+    #[test]
+    fn refund_unwritten() {
+        let mut fs = DiskFullFs::new(NullFs, 10);
+        assert_eq!(fs.reserve(8), Some(8));
+        let reply: ReplyWrite = Reply::new(1, NullSender);
+        fs.refund_unwritten(reply, 8).written(5);
+        assert_eq!(fs.remaining(), 5);
+        assert_eq!(fs.reserve(5), Some(5));
+        let reply: ReplyWrite = Reply::new(2, NullSender);
+        fs.refund_unwritten(reply, 5).error(libc::EIO);
+        assert_eq!(fs.remaining(), 5);
+        // Dropped replies are sent as errors, too
+        assert_eq!(fs.reserve(5), Some(5));
+        drop(fs.refund_unwritten(Reply::new(3, NullSender), 5));
+        assert_eq!(fs.remaining(), 5);
+    }
+}
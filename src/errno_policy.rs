@@ -0,0 +1,133 @@
+//! Rewriting of error codes sent to the kernel
+//!
+//! Filesystems that forward errors of some backend (e.g. an object store client) don't always
+//! get errors with the semantics applications expect. An [`ErrnoPolicy`] installed on the session
+//! rewrites error codes at the reply boundary, without having to touch the handler code.
+
+use libc::c_int;
+use std::collections::HashMap;
+use std::io::IoSlice;
+
+use crate::ll::fuse_abi::{fuse_opcode, fuse_out_header};
+
+/// Rewrites error codes of replies before they are sent to the kernel (see
+/// [`Session::set_errno_policy`](crate::Session::set_errno_policy)).
+///
+/// Rules for a specific operation take precedence over rules for all operations. Rewritten
+/// error codes aren't rewritten again.
+#[derive(Debug, Clone, Default)]
+pub struct ErrnoPolicy {
+    /// (opcode or any, error code) -> error code to reply with
+    rules: HashMap<(Option<u32>, c_int), c_int>,
+}
+
+impl ErrnoPolicy {
+    /// Create a policy that doesn't rewrite any error codes.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Reply with `to` whenever an operation fails with `from`.
+    pub fn map(&mut self, from: c_int, to: c_int) -> &mut Self {
+        self.rules.insert((None, from), to);
+        self
+    }
+
+    /// Reply with `to` whenever the given operation fails with `from`.
+    pub fn map_op(&mut self, op: fuse_opcode, from: c_int, to: c_int) -> &mut Self {
+        self.rules.insert((Some(op as u32), from), to);
+        self
+    }
+
+    /// Returns the error code to reply with if the given operation fails with `errno`.
+    pub fn translate(&self, op: fuse_opcode, errno: c_int) -> c_int {
+        self.translate_raw(op as u32, errno)
+    }
+
+    fn translate_raw(&self, opcode: u32, errno: c_int) -> c_int {
+        self.rules
+            .get(&(Some(opcode), errno))
+            .or_else(|| self.rules.get(&(None, errno)))
+            .copied()
+            .unwrap_or(errno)
+    }
+
+    /// Rewrite the error code of a reply to a request with the given opcode into `header`.
+    /// Returns false if the reply isn't an error or the error code isn't rewritten.
+    pub(crate) fn rewrite(
+        &self,
+        opcode: u32,
+        bufs: &[IoSlice<'_>],
+        header: &mut [u8; std::mem::size_of::<fuse_out_header>()],
+    ) -> bool {
+        let Some(first) = bufs.first() else {
+            return false;
+        };
+        if first.len() != header.len() {
+            return false;
+        }
+        header.copy_from_slice(first);
+        let error = i32::from_ne_bytes(header[4..8].try_into().unwrap());
+        if error == 0 {
+            return false;
+        }
+        let rewritten = -self.translate_raw(opcode, -error);
+        if rewritten == error {
+            return false;
+        }
+        header[4..8].copy_from_slice(&rewritten.to_ne_bytes());
+        true
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::ErrnoPolicy;
+    use crate::ll::fuse_abi::fuse_opcode;
+    use std::io::IoSlice;
+
+    #[test]
+    fn translate() {
+        let mut policy = ErrnoPolicy::new();
+        policy
+            .map(libc::EACCES, libc::EIO)
+            .map(libc::EIO, libc::EPERM)
+            .map_op(fuse_opcode::FUSE_LOOKUP, libc::EACCES, libc::ENOENT);
+        assert_eq!(
+            policy.translate(fuse_opcode::FUSE_READ, libc::EACCES),
+            libc::EIO
+        );
+        assert_eq!(
+            policy.translate(fuse_opcode::FUSE_LOOKUP, libc::EACCES),
+            libc::ENOENT
+        );
+        assert_eq!(
+            policy.translate(fuse_opcode::FUSE_READ, libc::ENOENT),
+            libc::ENOENT
+        );
+    }
+
+    #[test]
+    fn rewrite_header() {
+        let mut policy = ErrnoPolicy::new();
+        policy.map(libc::ENOTSUP, libc::ENOSYS);
+        let mut reply = [0u8; 16];
+        reply[0] = 16;
+        reply[4..8].copy_from_slice(&(-libc::ENOTSUP).to_ne_bytes());
+        let mut header = [0; 16];
+        assert!(policy.rewrite(
+            fuse_opcode::FUSE_GETXATTR as u32,
+            &[IoSlice::new(&reply)],
+            &mut header
+        ));
+        assert_eq!(&header[4..8], &(-libc::ENOSYS).to_ne_bytes());
+
+        // Successful replies are never rewritten
+        let ok = [16u8, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0];
+        assert!(!policy.rewrite(
+            fuse_opcode::FUSE_GETXATTR as u32,
+            &[IoSlice::new(&ok)],
+            &mut header
+        ));
+    }
+}
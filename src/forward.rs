@@ -0,0 +1,214 @@
+//! Forwarding of filesystem methods
+//!
+//! Filesystem wrappers like [`DiskFullFs`](crate::DiskFullFs) change a few methods of the
+//! filesystem they wrap and pass all others through. [`forward_filesystem`] generates the
+//! methods that are passed through, so that a method added to [`Filesystem`](crate::Filesystem)
+//! is only added here instead of to every wrapper.
+
+/// Implement every [`Filesystem`](crate::Filesystem) method not listed after `except` by
+/// calling the same method of the given field. Used inside the `impl Filesystem` block of a
+/// wrapper, which implements the listed methods itself or leaves them at their defaults:
+///
+/// ```ignore
+/// impl<F: Filesystem> Filesystem for Wrapper<F> {
+///     forward_filesystem!(inner, except: write);
+///
+///     fn write(/* ... */) {}
+/// }
+/// ```
+macro_rules! forward_filesystem {
+    ($inner:ident, except: $($skip:ident),* $(,)?) => {
+        $crate::forward::forward_filesystem! { @methods $inner [$($skip)*]
+            fn init(&mut self, req: &$crate::Request<'_>,
+                config: &mut $crate::KernelConfig) -> Result<(), libc::c_int>;
+            fn destroy(&mut self);
+            fn interrupt(&mut self, req: &$crate::Request<'_>, unique: u64);
+            fn aborted(&mut self);
+            fn unimplemented(&self, op: $crate::fuse_opcode) -> libc::c_int;
+            fn on_panic(&mut self, req: &$crate::Request<'_>, message: &str);
+            fn unknown(&mut self, req: &$crate::Request<'_>, opcode: u32, payload: &[u8],
+                reply: $crate::ReplyData);
+            fn raw_intercept(&self, opcode: u32) -> bool;
+            fn handle_raw(&mut self, req: &$crate::Request<'_>, opcode: u32, payload: &[u8],
+                reply: $crate::ReplyData);
+            fn lookup(&mut self, req: &$crate::Request<'_>, parent: u64, name: &std::ffi::OsStr,
+                reply: $crate::ReplyEntry);
+            fn forget(&mut self, req: &$crate::Request<'_>, ino: u64, nlookup: u64);
+            #[cfg(feature = "abi-7-16")]
+            fn batch_forget(&mut self, req: &$crate::Request<'_>,
+                nodes: &[$crate::fuse_forget_one]);
+            fn getattr(&mut self, req: &$crate::Request<'_>, ino: u64, fh: Option<u64>,
+                reply: $crate::ReplyAttr);
+            fn setattr(&mut self, req: &$crate::Request<'_>, ino: u64, mode: Option<u32>,
+                uid: Option<u32>, gid: Option<u32>, size: Option<u64>,
+                atime: Option<$crate::TimeOrNow>, mtime: Option<$crate::TimeOrNow>,
+                ctime: Option<std::time::SystemTime>, fh: Option<u64>,
+                crtime: Option<std::time::SystemTime>, chgtime: Option<std::time::SystemTime>,
+                bkuptime: Option<std::time::SystemTime>, flags: Option<u32>,
+                reply: $crate::ReplyAttr);
+            fn readlink(&mut self, req: &$crate::Request<'_>, ino: u64, reply: $crate::ReplyData);
+            fn mknod(&mut self, req: &$crate::Request<'_>, parent: u64, name: &std::ffi::OsStr,
+                mode: u32, umask: u32, rdev: u32, reply: $crate::ReplyEntry);
+            fn mkdir(&mut self, req: &$crate::Request<'_>, parent: u64, name: &std::ffi::OsStr,
+                mode: u32, umask: u32, reply: $crate::ReplyEntry);
+            fn unlink(&mut self, req: &$crate::Request<'_>, parent: u64, name: &std::ffi::OsStr,
+                reply: $crate::ReplyEmpty);
+            fn rmdir(&mut self, req: &$crate::Request<'_>, parent: u64, name: &std::ffi::OsStr,
+                reply: $crate::ReplyEmpty);
+            fn symlink(&mut self, req: &$crate::Request<'_>, parent: u64,
+                link_name: &std::ffi::OsStr, target: &std::path::Path, reply: $crate::ReplyEntry);
+            fn rename(&mut self, req: &$crate::Request<'_>, parent: u64, name: &std::ffi::OsStr,
+                newparent: u64, newname: &std::ffi::OsStr, flags: u32, reply: $crate::ReplyEmpty);
+            fn link(&mut self, req: &$crate::Request<'_>, ino: u64, newparent: u64,
+                newname: &std::ffi::OsStr, reply: $crate::ReplyEntry);
+            fn open(&mut self, req: &$crate::Request<'_>, ino: u64, flags: i32,
+                reply: $crate::ReplyOpen);
+            fn read(&mut self, req: &$crate::Request<'_>, ino: u64, fh: u64, offset: i64, size: u32,
+                flags: i32, lock_owner: Option<u64>, reply: $crate::ReplyData);
+            fn write(&mut self, req: &$crate::Request<'_>, ino: u64, fh: u64, offset: i64,
+                data: &[u8], write_flags: u32, flags: i32, lock_owner: Option<u64>,
+                reply: $crate::ReplyWrite);
+            fn write_stream(&mut self, req: &$crate::Request<'_>, ino: u64, fh: u64, offset: i64,
+                data: $crate::WriteData<'_>, write_flags: u32, flags: i32, lock_owner: Option<u64>,
+                reply: $crate::ReplyWrite);
+            fn flush(&mut self, req: &$crate::Request<'_>, ino: u64, fh: u64, lock_owner: u64,
+                reply: $crate::ReplyEmpty);
+            fn release(&mut self, req: &$crate::Request<'_>, ino: u64, fh: u64, flags: i32,
+                lock_owner: Option<u64>, flush: bool, reply: $crate::ReplyEmpty);
+            fn fsync(&mut self, req: &$crate::Request<'_>, ino: u64, fh: u64, datasync: bool,
+                reply: $crate::ReplyEmpty);
+            fn opendir(&mut self, req: &$crate::Request<'_>, ino: u64, flags: i32,
+                reply: $crate::ReplyOpen);
+            fn readdir(&mut self, req: &$crate::Request<'_>, ino: u64, fh: u64, offset: i64,
+                reply: $crate::ReplyDirectory);
+            fn readdirplus(&mut self, req: &$crate::Request<'_>, ino: u64, fh: u64, offset: i64,
+                reply: $crate::ReplyDirectoryPlus);
+            fn releasedir(&mut self, req: &$crate::Request<'_>, ino: u64, fh: u64, flags: i32,
+                reply: $crate::ReplyEmpty);
+            fn fsyncdir(&mut self, req: &$crate::Request<'_>, ino: u64, fh: u64, datasync: bool,
+                reply: $crate::ReplyEmpty);
+            fn statfs(&mut self, req: &$crate::Request<'_>, ino: u64, reply: $crate::ReplyStatfs);
+            fn setxattr(&mut self, req: &$crate::Request<'_>, ino: u64, name: &std::ffi::OsStr,
+                value: &[u8], flags: i32, position: u32, reply: $crate::ReplyEmpty);
+            fn getxattr(&mut self, req: &$crate::Request<'_>, ino: u64, name: &std::ffi::OsStr,
+                size: u32, reply: $crate::ReplyXattr);
+            fn listxattr(&mut self, req: &$crate::Request<'_>, ino: u64, size: u32,
+                reply: $crate::ReplyXattr);
+            fn removexattr(&mut self, req: &$crate::Request<'_>, ino: u64, name: &std::ffi::OsStr,
+                reply: $crate::ReplyEmpty);
+            fn access(&mut self, req: &$crate::Request<'_>, ino: u64, mask: i32,
+                reply: $crate::ReplyEmpty);
+            fn create(&mut self, req: &$crate::Request<'_>, parent: u64, name: &std::ffi::OsStr,
+                mode: u32, umask: u32, flags: i32, reply: $crate::ReplyCreate);
+            fn getlk(&mut self, req: &$crate::Request<'_>, ino: u64, fh: u64, lock_owner: u64,
+                start: u64, end: u64, typ: i32, pid: u32, reply: $crate::ReplyLock);
+            fn setlk(&mut self, req: &$crate::Request<'_>, ino: u64, fh: u64, lock_owner: u64,
+                start: u64, end: u64, typ: i32, pid: u32, sleep: bool, reply: $crate::ReplyEmpty);
+            fn bmap(&mut self, req: &$crate::Request<'_>, ino: u64, blocksize: u32, idx: u64,
+                reply: $crate::ReplyBmap);
+            fn ioctl(&mut self, req: &$crate::Request<'_>, ino: u64, fh: u64, flags: u32, cmd: u32,
+                in_data: &[u8], out_size: u32, reply: $crate::ReplyIoctl);
+            #[cfg(feature = "abi-7-11")]
+            fn poll(&mut self, req: &$crate::Request<'_>, ino: u64, fh: u64, ph: $crate::PollHandle,
+                events: $crate::PollEvents, flags: u32, reply: $crate::ReplyPoll);
+            #[cfg(feature = "abi-7-11")]
+            fn default_poll_events(&self) -> Option<$crate::PollEvents>;
+            fn fallocate(&mut self, req: &$crate::Request<'_>, ino: u64, fh: u64, offset: i64,
+                length: i64, mode: i32, reply: $crate::ReplyEmpty);
+            fn lseek(&mut self, req: &$crate::Request<'_>, ino: u64, fh: u64, offset: i64,
+                whence: i32, reply: $crate::ReplyLseek);
+            fn copy_file_range(&mut self, req: &$crate::Request<'_>, ino_in: u64, fh_in: u64,
+                offset_in: i64, ino_out: u64, fh_out: u64, offset_out: i64, len: u64, flags: u32,
+                reply: $crate::ReplyWrite);
+            #[cfg(target_os = "macos")]
+            fn setvolname(&mut self, req: &$crate::Request<'_>, name: &std::ffi::OsStr,
+                reply: $crate::ReplyEmpty);
+            #[cfg(target_os = "macos")]
+            fn exchange(&mut self, req: &$crate::Request<'_>, parent: u64, name: &std::ffi::OsStr,
+                newparent: u64, newname: &std::ffi::OsStr, options: u64, reply: $crate::ReplyEmpty);
+            #[cfg(target_os = "macos")]
+            fn getxtimes(&mut self, req: &$crate::Request<'_>, ino: u64,
+                reply: $crate::ReplyXTimes);
+        }
+    };
+    (@methods $inner:ident $skip:tt) => {};
+    (@methods $inner:ident $skip:tt $(#[$attr:meta])* fn $name:ident($($sig:tt)*)
+        $(-> $ret:ty)?; $($rest:tt)*) => {
+        $(#[$attr])*
+        $crate::forward::forward_filesystem!(@method $inner $skip fn $name($($sig)*) $(-> $ret)?);
+        $crate::forward::forward_filesystem! { @methods $inner $skip $($rest)* }
+    };
+    // A method is skipped once it's found in the list of excluded methods. Identifiers can't be
+    // compared generically, so each method needs its own rule.
+    (@method $inner:ident [init $($skip:ident)*] fn init $($sig:tt)*) => {};
+    (@method $inner:ident [destroy $($skip:ident)*] fn destroy $($sig:tt)*) => {};
+    (@method $inner:ident [interrupt $($skip:ident)*] fn interrupt $($sig:tt)*) => {};
+    (@method $inner:ident [aborted $($skip:ident)*] fn aborted $($sig:tt)*) => {};
+    (@method $inner:ident [unimplemented $($skip:ident)*] fn unimplemented $($sig:tt)*) => {};
+    (@method $inner:ident [on_panic $($skip:ident)*] fn on_panic $($sig:tt)*) => {};
+    (@method $inner:ident [unknown $($skip:ident)*] fn unknown $($sig:tt)*) => {};
+    (@method $inner:ident [raw_intercept $($skip:ident)*] fn raw_intercept $($sig:tt)*) => {};
+    (@method $inner:ident [handle_raw $($skip:ident)*] fn handle_raw $($sig:tt)*) => {};
+    (@method $inner:ident [lookup $($skip:ident)*] fn lookup $($sig:tt)*) => {};
+    (@method $inner:ident [forget $($skip:ident)*] fn forget $($sig:tt)*) => {};
+    (@method $inner:ident [batch_forget $($skip:ident)*] fn batch_forget $($sig:tt)*) => {};
+    (@method $inner:ident [getattr $($skip:ident)*] fn getattr $($sig:tt)*) => {};
+    (@method $inner:ident [setattr $($skip:ident)*] fn setattr $($sig:tt)*) => {};
+    (@method $inner:ident [readlink $($skip:ident)*] fn readlink $($sig:tt)*) => {};
+    (@method $inner:ident [mknod $($skip:ident)*] fn mknod $($sig:tt)*) => {};
+    (@method $inner:ident [mkdir $($skip:ident)*] fn mkdir $($sig:tt)*) => {};
+    (@method $inner:ident [unlink $($skip:ident)*] fn unlink $($sig:tt)*) => {};
+    (@method $inner:ident [rmdir $($skip:ident)*] fn rmdir $($sig:tt)*) => {};
+    (@method $inner:ident [symlink $($skip:ident)*] fn symlink $($sig:tt)*) => {};
+    (@method $inner:ident [rename $($skip:ident)*] fn rename $($sig:tt)*) => {};
+    (@method $inner:ident [link $($skip:ident)*] fn link $($sig:tt)*) => {};
+    (@method $inner:ident [open $($skip:ident)*] fn open $($sig:tt)*) => {};
+    (@method $inner:ident [read $($skip:ident)*] fn read $($sig:tt)*) => {};
+    (@method $inner:ident [write $($skip:ident)*] fn write $($sig:tt)*) => {};
+    (@method $inner:ident [write_stream $($skip:ident)*] fn write_stream $($sig:tt)*) => {};
+    (@method $inner:ident [flush $($skip:ident)*] fn flush $($sig:tt)*) => {};
+    (@method $inner:ident [release $($skip:ident)*] fn release $($sig:tt)*) => {};
+    (@method $inner:ident [fsync $($skip:ident)*] fn fsync $($sig:tt)*) => {};
+    (@method $inner:ident [opendir $($skip:ident)*] fn opendir $($sig:tt)*) => {};
+    (@method $inner:ident [readdir $($skip:ident)*] fn readdir $($sig:tt)*) => {};
+    (@method $inner:ident [readdirplus $($skip:ident)*] fn readdirplus $($sig:tt)*) => {};
+    (@method $inner:ident [releasedir $($skip:ident)*] fn releasedir $($sig:tt)*) => {};
+    (@method $inner:ident [fsyncdir $($skip:ident)*] fn fsyncdir $($sig:tt)*) => {};
+    (@method $inner:ident [statfs $($skip:ident)*] fn statfs $($sig:tt)*) => {};
+    (@method $inner:ident [setxattr $($skip:ident)*] fn setxattr $($sig:tt)*) => {};
+    (@method $inner:ident [getxattr $($skip:ident)*] fn getxattr $($sig:tt)*) => {};
+    (@method $inner:ident [listxattr $($skip:ident)*] fn listxattr $($sig:tt)*) => {};
+    (@method $inner:ident [removexattr $($skip:ident)*] fn removexattr $($sig:tt)*) => {};
+    (@method $inner:ident [access $($skip:ident)*] fn access $($sig:tt)*) => {};
+    (@method $inner:ident [create $($skip:ident)*] fn create $($sig:tt)*) => {};
+    (@method $inner:ident [getlk $($skip:ident)*] fn getlk $($sig:tt)*) => {};
+    (@method $inner:ident [setlk $($skip:ident)*] fn setlk $($sig:tt)*) => {};
+    (@method $inner:ident [bmap $($skip:ident)*] fn bmap $($sig:tt)*) => {};
+    (@method $inner:ident [ioctl $($skip:ident)*] fn ioctl $($sig:tt)*) => {};
+    (@method $inner:ident [poll $($skip:ident)*] fn poll $($sig:tt)*) => {};
+    (@method $inner:ident [default_poll_events $($skip:ident)*]
+        fn default_poll_events $($sig:tt)*) => {};
+    (@method $inner:ident [fallocate $($skip:ident)*] fn fallocate $($sig:tt)*) => {};
+    (@method $inner:ident [lseek $($skip:ident)*] fn lseek $($sig:tt)*) => {};
+    (@method $inner:ident [copy_file_range $($skip:ident)*] fn copy_file_range $($sig:tt)*) => {};
+    (@method $inner:ident [setvolname $($skip:ident)*] fn setvolname $($sig:tt)*) => {};
+    (@method $inner:ident [exchange $($skip:ident)*] fn exchange $($sig:tt)*) => {};
+    (@method $inner:ident [getxtimes $($skip:ident)*] fn getxtimes $($sig:tt)*) => {};
+    // Not excluded
+    (@method $inner:ident [] fn $name:ident(&mut self $(, $arg:ident: $ty:ty)*) $(-> $ret:ty)?) => {
+        fn $name(&mut self $(, $arg: $ty)*) $(-> $ret)? {
+            self.$inner.$name($($arg),*)
+        }
+    };
+    (@method $inner:ident [] fn $name:ident(&self $(, $arg:ident: $ty:ty)*) $(-> $ret:ty)?) => {
+        fn $name(&self $(, $arg: $ty)*) $(-> $ret)? {
+            self.$inner.$name($($arg),*)
+        }
+    };
+    // Not this excluded method, try the next one
+    (@method $inner:ident [$other:ident $($skip:ident)*] $($sig:tt)*) => {
+        $crate::forward::forward_filesystem!(@method $inner [$($skip)*] $($sig)*);
+    };
+}
+
+pub(crate) use forward_filesystem;
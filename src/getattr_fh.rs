@@ -0,0 +1,28 @@
+//! Typed access to the file handle of `getattr` requests
+
+/// What a `getattr` request refers to, see [`Request::getattr_fh`](crate::Request::getattr_fh)
+///
+/// Since ABI 7.9, the kernel passes a file handle (with the `FUSE_GETATTR_FH` flag) when the
+/// attributes of an open file are requested, e.g. by `fstat`. File handles are chosen by the
+/// filesystem and may be 0, so only the flag tells whether there is one.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum GetattrFh {
+    /// The ABI (before 7.9) can't pass a file handle, so it is unknown whether the request is
+    /// for an open file
+    Unknown,
+    /// The request is for the inode, not for an open file (e.g. by `stat`)
+    Inode,
+    /// The request is for the file opened with this handle
+    Handle(u64),
+}
+
+impl GetattrFh {
+    /// Returns the file handle, if the request carried one. This is what
+    /// [`Filesystem::getattr`](crate::Filesystem::getattr) gets as `fh`.
+    pub fn handle(&self) -> Option<u64> {
+        match self {
+            GetattrFh::Handle(fh) => Some(*fh),
+            GetattrFh::Unknown | GetattrFh::Inode => None,
+        }
+    }
+}
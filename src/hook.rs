@@ -0,0 +1,81 @@
+//! Hooks around every request
+//!
+//! Logging, auditing or injecting faults shouldn't require wrapping every method of the
+//! [`Filesystem`](crate::Filesystem) trait. A [`RequestHook`] added to the session (see
+//! [`Session::add_hook`](crate::Session::add_hook)) is called before each request reaches the
+//! filesystem, where it can fail the request instead, and once the reply was sent, with how
+//! long the request took and how it ended.
+
+use std::fmt;
+use std::sync::Arc;
+use std::time::Duration;
+
+use libc::c_int;
+
+/// A request passed to a [`RequestHook`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub struct RequestInfo {
+    /// Unique id of the request, see [`Request::unique`](crate::Request::unique)
+    pub unique: u64,
+    /// Opcode of the request, see [`fuse_opcode`](crate::fuse_opcode)
+    pub opcode: u32,
+    /// Inode the request refers to, or 0 for requests that don't refer to one
+    pub ino: u64,
+    /// User id of the caller
+    pub uid: u32,
+    /// Process id of the caller
+    pub pid: u32,
+}
+
+/// Called around every request of a session (see
+/// [`Session::add_hook`](crate::Session::add_hook)).
+///
+/// `before_dispatch` is called on the thread running the session, `after_reply` on whichever
+/// thread the filesystem replies from. Requests the kernel doesn't expect a reply to, like
+/// `forget`, never reach `after_reply`.
+pub trait RequestHook: Send + Sync {
+    /// Called before the request is dispatched. Returning an error code fails the request with
+    /// it without calling the filesystem, e.g. to inject faults. Errors are ignored for
+    /// requests that don't expect a reply.
+    fn before_dispatch(&self, _request: &RequestInfo) -> Result<(), c_int> {
+        Ok(())
+    }
+
+    /// Called once the reply was sent, with the time since the request was received and the
+    /// error code it was answered with, or 0 on success.
+    fn after_reply(&self, _request: &RequestInfo, _latency: Duration, _errno: c_int) {}
+}
+
+/// The hooks of a session, in the order they were added
+#[derive(Clone, Default)]
+pub(crate) struct Hooks(Arc<Vec<Arc<dyn RequestHook>>>);
+
+impl fmt::Debug for Hooks {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Hooks({})", self.0.len())
+    }
+}
+
+impl Hooks {
+    pub(crate) fn push(&mut self, hook: Arc<dyn RequestHook>) {
+        Arc::make_mut(&mut self.0).push(hook);
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// Ask every hook in turn, stopping at the first that fails the request
+    pub(crate) fn before_dispatch(&self, request: &RequestInfo) -> Result<(), c_int> {
+        self.0
+            .iter()
+            .try_for_each(|hook| hook.before_dispatch(request))
+    }
+
+    pub(crate) fn after_reply(&self, request: &RequestInfo, latency: Duration, errno: c_int) {
+        for hook in self.0.iter() {
+            hook.after_reply(request, latency, errno);
+        }
+    }
+}
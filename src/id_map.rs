@@ -0,0 +1,142 @@
+//! Translation of user and group ids between the mount and the filesystem
+//!
+//! When a mount is shared with a container whose user namespace is offset from the host, the
+//! ids of the processes using the mount differ from the ids the filesystem stores. An
+//! [`IdMap`] installed on the session translates them in both directions, so that filesystems
+//! don't have to: the ids of requests and of `setattr` are mapped to the ids of the
+//! filesystem, and the owners of replied attributes back to the ids of the mount.
+
+use crate::FileAttr;
+
+/// Ids that aren't mapped are translated to this id, like the kernel's overflow id
+const OVERFLOW_ID: u32 = 65534;
+
+/// A range of `count` ids starting at `mount` on the mount and at `fs` in the filesystem
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct IdRange {
+    mount: u32,
+    fs: u32,
+    count: u32,
+}
+
+impl IdRange {
+    fn to_fs(self, id: u32) -> Option<u32> {
+        let offset = id.checked_sub(self.mount).filter(|o| *o < self.count)?;
+        self.fs.checked_add(offset)
+    }
+
+    fn to_mount(self, id: u32) -> Option<u32> {
+        let offset = id.checked_sub(self.fs).filter(|o| *o < self.count)?;
+        self.mount.checked_add(offset)
+    }
+}
+
+/// Maps user and group ids of a mount to the ids of the filesystem (see
+/// [`Session::set_id_map`](crate::Session::set_id_map)).
+///
+/// The map is a table of id ranges, like `/proc/<pid>/uid_map`. Ids outside of all ranges are
+/// seen as the overflow id 65534 (`nobody`) on the other side, and changing the owner of a file
+/// to such an id fails with EOVERFLOW.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct IdMap {
+    uids: Vec<IdRange>,
+    gids: Vec<IdRange>,
+}
+
+impl IdMap {
+    /// Create a map without any ranges, which maps all ids to the overflow id.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Create a map that shifts all ids: id `n` of the filesystem is id `n + offset` on the
+    /// mount. Ids below `offset` on the mount are unmapped.
+    pub fn shift(offset: u32) -> Self {
+        let mut map = Self::new();
+        map.uid_range(offset, 0, u32::MAX - offset)
+            .gid_range(offset, 0, u32::MAX - offset);
+        map
+    }
+
+    /// Map the `count` uids starting at `mount` on the mount to the uids starting at `fs` in the
+    /// filesystem. If ranges overlap, the first one added wins.
+    pub fn uid_range(&mut self, mount: u32, fs: u32, count: u32) -> &mut Self {
+        self.uids.push(IdRange { mount, fs, count });
+        self
+    }
+
+    /// Map the `count` gids starting at `mount` on the mount to the gids starting at `fs` in the
+    /// filesystem. If ranges overlap, the first one added wins.
+    pub fn gid_range(&mut self, mount: u32, fs: u32, count: u32) -> &mut Self {
+        self.gids.push(IdRange { mount, fs, count });
+        self
+    }
+
+    /// Returns the filesystem's uid for a uid of the mount, if it is mapped
+    pub fn uid_to_fs(&self, uid: u32) -> Option<u32> {
+        self.uids.iter().find_map(|r| r.to_fs(uid))
+    }
+
+    /// Returns the filesystem's gid for a gid of the mount, if it is mapped
+    pub fn gid_to_fs(&self, gid: u32) -> Option<u32> {
+        self.gids.iter().find_map(|r| r.to_fs(gid))
+    }
+
+    /// Returns the mount's uid for a uid of the filesystem, if it is mapped
+    pub fn uid_to_mount(&self, uid: u32) -> Option<u32> {
+        self.uids.iter().find_map(|r| r.to_mount(uid))
+    }
+
+    /// Returns the mount's gid for a gid of the filesystem, if it is mapped
+    pub fn gid_to_mount(&self, gid: u32) -> Option<u32> {
+        self.gids.iter().find_map(|r| r.to_mount(gid))
+    }
+
+    /// Returns the uid of a request as seen by the filesystem
+    pub(crate) fn request_uid(&self, uid: u32) -> u32 {
+        self.uid_to_fs(uid).unwrap_or(OVERFLOW_ID)
+    }
+
+    /// Returns the gid of a request as seen by the filesystem
+    pub(crate) fn request_gid(&self, gid: u32) -> u32 {
+        self.gid_to_fs(gid).unwrap_or(OVERFLOW_ID)
+    }
+
+    /// Returns the attributes with their owner as seen on the mount
+    pub(crate) fn attr_to_mount(&self, attr: &FileAttr) -> FileAttr {
+        FileAttr {
+            uid: self.uid_to_mount(attr.uid).unwrap_or(OVERFLOW_ID),
+            gid: self.gid_to_mount(attr.gid).unwrap_or(OVERFLOW_ID),
+            ..*attr
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::IdMap;
+
+    #[test]
+    fn shift() {
+        let map = IdMap::shift(100_000);
+        assert_eq!(map.uid_to_fs(100_000), Some(0));
+        assert_eq!(map.gid_to_fs(101_000), Some(1000));
+        assert_eq!(map.uid_to_fs(1000), None);
+        assert_eq!(map.request_uid(1000), 65534);
+        assert_eq!(map.uid_to_mount(1000), Some(101_000));
+        assert_eq!(map.gid_to_mount(u32::MAX - 100_000), None);
+    }
+
+    #[test]
+    fn table() {
+        let mut map = IdMap::new();
+        map.uid_range(1000, 0, 1).uid_range(0, 1000, 1000);
+        assert_eq!(map.uid_to_fs(1000), Some(0));
+        assert_eq!(map.uid_to_fs(0), Some(1000));
+        assert_eq!(map.uid_to_fs(999), Some(1999));
+        assert_eq!(map.uid_to_fs(1001), None);
+        assert_eq!(map.uid_to_mount(0), Some(1000));
+        assert_eq!(map.uid_to_mount(1500), Some(500));
+        assert_eq!(map.gid_to_fs(0), None);
+    }
+}
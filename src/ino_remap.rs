@@ -0,0 +1,494 @@
+//! Remapping of 64-bit inode numbers into a dense 32-bit space
+//!
+//! Applications built without large file support fail `stat()` with `EOVERFLOW` as soon as an
+//! inode number doesn't fit into 32 bits. Filesystems that pass through inode numbers of another
+//! filesystem (or use hashes as inode numbers) can install an [`Ino32Remap`] on their session,
+//! which translates the inode numbers of all messages exchanged with the kernel. The filesystem
+//! implementation only ever sees its own inode numbers, the kernel only ever sees small ones.
+
+use log::warn;
+use std::collections::HashMap;
+use std::io::IoSlice;
+use std::mem::size_of;
+use std::sync::Mutex;
+
+#[cfg(feature = "abi-7-21")]
+use crate::ll::fuse_abi::fuse_entry_out;
+#[cfg(feature = "abi-7-12")]
+use crate::ll::fuse_abi::fuse_notify_code as notify_code;
+use crate::ll::fuse_abi::{
+    fuse_dirent, fuse_in_header, fuse_opcode as op, fuse_out_header, FUSE_ROOT_ID,
+};
+
+// Offsets of the inode numbers within the respective kernel messages
+const IN_HEADER_NODEID: usize = 16;
+const OUT_HEADER_ERROR: usize = 4;
+const OUT_HEADER_UNIQUE: usize = 8;
+const ENTRY_OUT_NODEID: usize = 0;
+const ENTRY_OUT_ATTR_INO: usize = 40;
+const ATTR_OUT_ATTR_INO: usize = 16;
+const DIRENT_NAMELEN: usize = 16;
+
+/// Translates the inode numbers of a filesystem into a dense 32-bit space (see
+/// [`Session::set_ino32_remap`](crate::Session::set_ino32_remap)).
+///
+/// Kernel-visible numbers are handed out in ascending order starting at 2 (the root inode is
+/// never remapped), so unlike hashing, two backend inodes never collide. Numbers are recycled
+/// once the kernel forgot all lookups of an inode. Inode numbers that are only reported in
+/// directory listings (`readdir` without `readdirplus`) are not reference counted by the kernel,
+/// so their numbers stay assigned for the lifetime of the session.
+///
+/// If the 32-bit space is exhausted, replies that would need a new number fail with
+/// `EOVERFLOW`.
+#[derive(Debug)]
+pub struct Ino32Remap {
+    /// Highest kernel-visible inode number to hand out
+    limit: u32,
+    state: Mutex<RemapState>,
+}
+
+#[derive(Debug, Default)]
+struct RemapState {
+    /// Kernel-visible inode number -> (backend inode number, kernel lookup count)
+    to_backend: HashMap<u32, (u64, u64)>,
+    /// Backend inode number -> kernel-visible inode number
+    to_kernel: HashMap<u64, u32>,
+    /// Numbers that were forgotten by the kernel and can be handed out again
+    free: Vec<u32>,
+    /// Next never used number
+    next: u32,
+    /// Opcodes of in-flight requests whose replies contain inode numbers
+    pending: HashMap<u64, u32>,
+}
+
+impl Default for Ino32Remap {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Ino32Remap {
+    /// Create a remapping that uses the whole 32-bit space.
+    pub fn new() -> Self {
+        Self::with_limit(u32::MAX)
+    }
+
+    /// Create a remapping that hands out kernel-visible inode numbers up to `limit`.
+    pub fn with_limit(limit: u32) -> Self {
+        Self {
+            limit,
+            state: Mutex::new(RemapState {
+                next: FUSE_ROOT_ID as u32 + 1,
+                ..Default::default()
+            }),
+        }
+    }
+
+    /// Returns the number of inodes that currently have a kernel-visible number assigned.
+    pub fn len(&self) -> usize {
+        self.state.lock().unwrap().to_backend.len()
+    }
+
+    /// Returns true if no inode has a kernel-visible number assigned.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Returns the backend inode number for a kernel-visible one.
+    pub fn backend_ino(&self, ino: u64) -> Option<u64> {
+        self.state.lock().unwrap().backend_ino(ino)
+    }
+
+    /// Returns the kernel-visible inode number of a backend inode, if it has one assigned.
+    pub fn kernel_ino(&self, ino: u64) -> Option<u64> {
+        if ino == FUSE_ROOT_ID {
+            return Some(ino);
+        }
+        let state = self.state.lock().unwrap();
+        state.to_kernel.get(&ino).map(|&k| k.into())
+    }
+
+    /// Translate the inode numbers of a request from the kernel into backend numbers in place.
+    pub(crate) fn translate_request(&self, data: &mut [u8]) {
+        if data.len() < size_of::<fuse_in_header>() {
+            return;
+        }
+        let opcode = u32::from_ne_bytes(data[4..8].try_into().unwrap());
+        let unique = u64::from_ne_bytes(data[8..16].try_into().unwrap());
+        let (header, body) = data.split_at_mut(size_of::<fuse_in_header>());
+        let mut state = self.state.lock().unwrap();
+        let nodeid = read_u64(header, IN_HEADER_NODEID).unwrap();
+        if let Some(backend) = state.backend_ino(nodeid) {
+            write_u64(header, IN_HEADER_NODEID, backend);
+        }
+        match opcode {
+            x if x == op::FUSE_FORGET as u32 => {
+                if let Some(nlookup) = read_u64(body, 0) {
+                    state.forget(nodeid, nlookup);
+                }
+            }
+            #[cfg(feature = "abi-7-16")]
+            x if x == op::FUSE_BATCH_FORGET as u32 => {
+                let count = body
+                    .get(..4)
+                    .map_or(0, |b| u32::from_ne_bytes(b.try_into().unwrap()) as usize);
+                for i in 0..count {
+                    let offset = 8 + i * 16;
+                    if let (Some(nodeid), Some(nlookup)) =
+                        (read_u64(body, offset), read_u64(body, offset + 8))
+                    {
+                        state.translate(body, offset);
+                        state.forget(nodeid, nlookup);
+                    }
+                }
+            }
+            x if x == op::FUSE_RENAME as u32 || x == op::FUSE_LINK as u32 => {
+                state.translate(body, 0);
+            }
+            #[cfg(feature = "abi-7-23")]
+            x if x == op::FUSE_RENAME2 as u32 => {
+                state.translate(body, 0);
+            }
+            #[cfg(feature = "abi-7-28")]
+            x if x == op::FUSE_COPY_FILE_RANGE as u32 => {
+                state.translate(body, 16);
+            }
+            #[cfg(target_os = "macos")]
+            x if x == op::FUSE_EXCHANGE as u32 => {
+                state.translate(body, 0);
+                state.translate(body, 8);
+            }
+            _ => {}
+        }
+        if reply_has_inodes(opcode) {
+            state.pending.insert(unique, opcode);
+        }
+    }
+
+    /// Translate the inode numbers of a reply or notification to the kernel into kernel-visible
+    /// numbers. Returns `None` if the message doesn't contain any inode numbers.
+    pub(crate) fn translate_response(&self, bufs: &[IoSlice<'_>]) -> Option<Vec<u8>> {
+        let header = bufs.first()?;
+        if header.len() < size_of::<fuse_out_header>() {
+            return None;
+        }
+        let error = i32::from_ne_bytes(header[OUT_HEADER_ERROR..8].try_into().unwrap());
+        let unique = u64::from_ne_bytes(header[OUT_HEADER_UNIQUE..16].try_into().unwrap());
+        let mut state = self.state.lock().unwrap();
+        let opcode = if unique == 0 {
+            None
+        } else {
+            Some(state.pending.remove(&unique)?)
+        };
+        if opcode.is_some() && error != 0 {
+            return None;
+        }
+        let mut data: Vec<u8> = bufs.iter().flat_map(|b| b.iter().copied()).collect();
+        let body = &mut data[size_of::<fuse_out_header>()..];
+        let translated = match opcode {
+            Some(opcode) => self.translate_reply(&mut state, opcode, body),
+            None => {
+                state.translate_notification(error, body);
+                Some(())
+            }
+        };
+        if translated.is_none() {
+            warn!("No 32-bit inode number left, failing request {}", unique);
+            data.truncate(size_of::<fuse_out_header>());
+            data[..4].copy_from_slice(&(size_of::<fuse_out_header>() as u32).to_ne_bytes());
+            data[OUT_HEADER_ERROR..8].copy_from_slice(&(-libc::EOVERFLOW).to_ne_bytes());
+        }
+        Some(data)
+    }
+
+    fn translate_reply(&self, state: &mut RemapState, opcode: u32, body: &mut [u8]) -> Option<()> {
+        match opcode {
+            x if x == op::FUSE_GETATTR as u32 || x == op::FUSE_SETATTR as u32 => {
+                self.assign(state, body, ATTR_OUT_ATTR_INO)?;
+            }
+            x if x == op::FUSE_READDIR as u32 => {
+                let mut offset = 0;
+                while let Some(len) = dirent_len(body, offset) {
+                    self.assign(state, body, offset)?;
+                    offset += len;
+                }
+            }
+            #[cfg(feature = "abi-7-21")]
+            x if x == op::FUSE_READDIRPLUS as u32 => {
+                // Assign all numbers first, so that lookup counts aren't taken if the reply fails
+                let mut entries = vec![];
+                let mut offset = 0;
+                while let Some(len) = dirent_len(body, offset + size_of::<fuse_entry_out>()) {
+                    let dirent = offset + size_of::<fuse_entry_out>();
+                    let kernel = self.assign(state, body, offset + ENTRY_OUT_NODEID)?;
+                    self.assign(state, body, offset + ENTRY_OUT_ATTR_INO)?;
+                    self.assign(state, body, dirent)?;
+                    let name_start = dirent + size_of::<fuse_dirent>();
+                    let namelen = read_u32(body, dirent + DIRENT_NAMELEN)? as usize;
+                    let name = &body[name_start..name_start + namelen];
+                    // The kernel doesn't take lookups for "." and ".."
+                    if name != b"." && name != b".." {
+                        entries.push(kernel);
+                    }
+                    offset = dirent + len;
+                }
+                for kernel in entries {
+                    state.add_lookup(kernel);
+                }
+            }
+            // Entry replies (lookup, mknod, mkdir, symlink, link, create)
+            _ => {
+                let kernel = self.assign(state, body, ENTRY_OUT_NODEID)?;
+                self.assign(state, body, ENTRY_OUT_ATTR_INO)?;
+                state.add_lookup(kernel);
+            }
+        }
+        Some(())
+    }
+
+    /// Replace the backend inode number at `offset` with its kernel-visible number, assigning one
+    /// if needed.
+    fn assign(&self, state: &mut RemapState, data: &mut [u8], offset: usize) -> Option<u32> {
+        let ino = read_u64(data, offset)?;
+        if ino == 0 || ino == FUSE_ROOT_ID {
+            return Some(ino as u32);
+        }
+        let kernel = match state.to_kernel.get(&ino) {
+            Some(&kernel) => kernel,
+            None => {
+                let kernel = match state.free.pop() {
+                    Some(kernel) => kernel,
+                    None if state.next <= self.limit && state.next != 0 => {
+                        state.next = state.next.wrapping_add(1);
+                        state.next.wrapping_sub(1)
+                    }
+                    None => return None,
+                };
+                state.to_kernel.insert(ino, kernel);
+                state.to_backend.insert(kernel, (ino, 0));
+                kernel
+            }
+        };
+        write_u64(data, offset, kernel.into());
+        Some(kernel)
+    }
+}
+
+impl RemapState {
+    fn backend_ino(&self, ino: u64) -> Option<u64> {
+        if ino == FUSE_ROOT_ID {
+            return Some(ino);
+        }
+        let ino = u32::try_from(ino).ok()?;
+        self.to_backend.get(&ino).map(|&(backend, _)| backend)
+    }
+
+    /// Translate the kernel-visible inode number at `offset` into a backend number.
+    fn translate(&self, data: &mut [u8], offset: usize) {
+        if let Some(backend) = read_u64(data, offset).and_then(|ino| self.backend_ino(ino)) {
+            write_u64(data, offset, backend);
+        }
+    }
+
+    /// Translate the backend inode number at `offset` into a kernel-visible number.
+    #[cfg(feature = "abi-7-12")]
+    fn translate_to_kernel(&self, data: &mut [u8], offset: usize) {
+        if let Some(&kernel) = read_u64(data, offset).and_then(|ino| self.to_kernel.get(&ino)) {
+            write_u64(data, offset, kernel.into());
+        }
+    }
+
+    #[allow(unused_variables)]
+    fn translate_notification(&self, code: i32, body: &mut [u8]) {
+        // Notifications about inodes that the kernel doesn't know are left untouched. They refer
+        // to numbers the kernel never handed out, so it will reply with ENOENT.
+        match code {
+            #[cfg(feature = "abi-7-12")]
+            x if x == notify_code::FUSE_NOTIFY_INVAL_INODE as i32
+                || x == notify_code::FUSE_NOTIFY_INVAL_ENTRY as i32 =>
+            {
+                self.translate_to_kernel(body, 0);
+            }
+            #[cfg(feature = "abi-7-15")]
+            x if x == notify_code::FUSE_NOTIFY_STORE as i32 => {
+                self.translate_to_kernel(body, 0);
+            }
+            #[cfg(feature = "abi-7-15")]
+            x if x == notify_code::FUSE_NOTIFY_RETRIEVE as i32 => {
+                self.translate_to_kernel(body, 8);
+            }
+            #[cfg(feature = "abi-7-18")]
+            x if x == notify_code::FUSE_NOTIFY_DELETE as i32 => {
+                self.translate_to_kernel(body, 0);
+                self.translate_to_kernel(body, 8);
+            }
+            _ => {}
+        }
+    }
+
+    fn add_lookup(&mut self, kernel: u32) {
+        if let Some((_, nlookup)) = self.to_backend.get_mut(&kernel) {
+            *nlookup += 1;
+        }
+    }
+
+    fn forget(&mut self, ino: u64, nlookup: u64) {
+        let Ok(kernel) = u32::try_from(ino) else {
+            return;
+        };
+        if let Some((backend, count)) = self.to_backend.get_mut(&kernel) {
+            *count = count.saturating_sub(nlookup);
+            if *count == 0 {
+                let backend = *backend;
+                self.to_backend.remove(&kernel);
+                self.to_kernel.remove(&backend);
+                self.free.push(kernel);
+            }
+        }
+    }
+}
+
+/// Returns true if the reply to a request with the given opcode contains inode numbers.
+fn reply_has_inodes(opcode: u32) -> bool {
+    #[cfg(feature = "abi-7-21")]
+    if opcode == op::FUSE_READDIRPLUS as u32 {
+        return true;
+    }
+    [
+        op::FUSE_LOOKUP as u32,
+        op::FUSE_GETATTR as u32,
+        op::FUSE_SETATTR as u32,
+        op::FUSE_SYMLINK as u32,
+        op::FUSE_MKNOD as u32,
+        op::FUSE_MKDIR as u32,
+        op::FUSE_LINK as u32,
+        op::FUSE_READDIR as u32,
+        op::FUSE_CREATE as u32,
+    ]
+    .contains(&opcode)
+}
+
+/// Returns the padded length of the dirent at `offset`, if a complete one is present.
+fn dirent_len(data: &[u8], offset: usize) -> Option<usize> {
+    let namelen = read_u32(data, offset + DIRENT_NAMELEN)? as usize;
+    let len = (size_of::<fuse_dirent>() + namelen + 7) & !7;
+    if offset + size_of::<fuse_dirent>() + namelen > data.len() {
+        return None;
+    }
+    Some(len.min(data.len() - offset))
+}
+
+fn read_u32(data: &[u8], offset: usize) -> Option<u32> {
+    let bytes = data.get(offset..offset + 4)?;
+    Some(u32::from_ne_bytes(bytes.try_into().unwrap()))
+}
+
+fn read_u64(data: &[u8], offset: usize) -> Option<u64> {
+    let bytes = data.get(offset..offset + 8)?;
+    Some(u64::from_ne_bytes(bytes.try_into().unwrap()))
+}
+
+fn write_u64(data: &mut [u8], offset: usize, value: u64) {
+    data[offset..offset + 8].copy_from_slice(&value.to_ne_bytes());
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::ll::fuse_abi::fuse_entry_out;
+    use std::io::IoSlice;
+
+    const BIG: u64 = 0x1234_5678_9abc_def0;
+
+    fn request(opcode: op, unique: u64, nodeid: u64, body: &[u8]) -> Vec<u8> {
+        let mut data = vec![];
+        data.extend_from_slice(&((40 + body.len()) as u32).to_ne_bytes());
+        data.extend_from_slice(&(opcode as u32).to_ne_bytes());
+        data.extend_from_slice(&unique.to_ne_bytes());
+        data.extend_from_slice(&nodeid.to_ne_bytes());
+        data.extend_from_slice(&[0; 16]);
+        data.extend_from_slice(body);
+        data
+    }
+
+    fn entry_reply(remap: &Ino32Remap, unique: u64, ino: u64) -> Vec<u8> {
+        let mut body = vec![0; size_of::<fuse_entry_out>()];
+        write_u64(&mut body, ENTRY_OUT_NODEID, ino);
+        write_u64(&mut body, ENTRY_OUT_ATTR_INO, ino);
+        let mut header = vec![0; 16];
+        header[..4].copy_from_slice(&((16 + body.len()) as u32).to_ne_bytes());
+        header[8..].copy_from_slice(&unique.to_ne_bytes());
+        remap
+            .translate_response(&[IoSlice::new(&header), IoSlice::new(&body)])
+            .unwrap()
+    }
+
+    fn lookup(remap: &Ino32Remap, unique: u64, ino: u64) -> Vec<u8> {
+        let mut req = request(op::FUSE_LOOKUP, unique, FUSE_ROOT_ID, b"name\0");
+        remap.translate_request(&mut req);
+        entry_reply(remap, unique, ino)
+    }
+
+    #[test]
+    fn lookup_is_remapped() {
+        let remap = Ino32Remap::new();
+        let reply = lookup(&remap, 1, BIG);
+        assert_eq!(read_u64(&reply, 16 + ENTRY_OUT_NODEID), Some(2));
+        assert_eq!(read_u64(&reply, 16 + ENTRY_OUT_ATTR_INO), Some(2));
+        assert_eq!(remap.backend_ino(2), Some(BIG));
+        assert_eq!(remap.kernel_ino(BIG), Some(2));
+
+        // The same inode keeps its number, the root is never remapped
+        let reply = lookup(&remap, 2, BIG);
+        assert_eq!(read_u64(&reply, 16 + ENTRY_OUT_NODEID), Some(2));
+        let reply = lookup(&remap, 3, FUSE_ROOT_ID);
+        assert_eq!(read_u64(&reply, 16 + ENTRY_OUT_NODEID), Some(FUSE_ROOT_ID));
+
+        let mut req = request(op::FUSE_GETATTR, 4, 2, &[0; 16]);
+        remap.translate_request(&mut req);
+        assert_eq!(read_u64(&req, IN_HEADER_NODEID), Some(BIG));
+    }
+
+    #[test]
+    fn forget_recycles_numbers() {
+        let remap = Ino32Remap::new();
+        lookup(&remap, 1, BIG);
+        lookup(&remap, 2, BIG);
+        let mut req = request(op::FUSE_FORGET, 3, 2, &1u64.to_ne_bytes());
+        remap.translate_request(&mut req);
+        assert_eq!(read_u64(&req, IN_HEADER_NODEID), Some(BIG));
+        assert_eq!(remap.len(), 1);
+        let mut req = request(op::FUSE_FORGET, 4, 2, &1u64.to_ne_bytes());
+        remap.translate_request(&mut req);
+        assert!(remap.is_empty());
+
+        let reply = lookup(&remap, 5, BIG + 1);
+        assert_eq!(read_u64(&reply, 16 + ENTRY_OUT_NODEID), Some(2));
+        assert_eq!(remap.backend_ino(2), Some(BIG + 1));
+    }
+
+    #[test]
+    fn exhausted_space_fails_reply() {
+        let remap = Ino32Remap::with_limit(2);
+        lookup(&remap, 1, BIG);
+        let reply = lookup(&remap, 2, BIG + 1);
+        assert_eq!(reply.len(), 16);
+        assert_eq!(
+            i32::from_ne_bytes(reply[4..8].try_into().unwrap()),
+            -libc::EOVERFLOW
+        );
+    }
+
+    #[test]
+    fn unrelated_replies_are_untouched() {
+        let remap = Ino32Remap::new();
+        let mut req = request(op::FUSE_READ, 1, FUSE_ROOT_ID, &[0; 40]);
+        remap.translate_request(&mut req);
+        let header = [0u8; 16];
+        let mut header = header.to_vec();
+        header[8..].copy_from_slice(&1u64.to_ne_bytes());
+        assert!(remap
+            .translate_response(&[IoSlice::new(&header), IoSlice::new(b"data")])
+            .is_none());
+    }
+}
@@ -0,0 +1,99 @@
+//! Hints for the size of I/O requests
+//!
+//! Backends with a large optimal request size, like object stores, are slow when reads and
+//! writes arrive in small pieces. Open replies can't carry a preferred size, but the size of
+//! requests is steered from other places: applications size their buffers by the block size of
+//! the file (`st_blksize`), the kernel bounds requests by the readahead and write sizes
+//! negotiated during init, and direct I/O passes reads through in the size applications issue
+//! them. An [`IoSizeHint`] sets all of them consistently.
+
+use crate::ll::fuse_abi::consts::FOPEN_DIRECT_IO;
+use crate::{FileAttr, KernelConfig};
+
+/// A preferred size of read and write requests. Filesystems apply it to the [`KernelConfig`]
+/// in [`Filesystem::init`](crate::Filesystem::init), to the attributes they reply with, and
+/// use its open flags for open and create replies.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct IoSizeHint {
+    size: u32,
+    direct_io: bool,
+}
+
+impl IoSizeHint {
+    /// Prefer requests of `size` bytes.
+    pub fn new(size: u32) -> Self {
+        Self {
+            size: size.max(1),
+            direct_io: false,
+        }
+    }
+
+    /// Bypass the page cache, so that reads reach the filesystem in the size applications
+    /// issue them instead of in pages and readahead windows. Defaults to false.
+    pub fn with_direct_io(mut self, direct_io: bool) -> Self {
+        self.direct_io = direct_io;
+        self
+    }
+
+    /// Returns the preferred size of requests
+    pub fn size(&self) -> u32 {
+        self.size
+    }
+
+    /// Returns the attributes with the preferred size as their block size, which applications
+    /// see as `st_blksize`. The block size is only sent to kernels with ABI 7.9 or later.
+    pub fn attr(&self, attr: &FileAttr) -> FileAttr {
+        FileAttr {
+            blksize: self.size,
+            ..*attr
+        }
+    }
+
+    /// Flags for replies to `open` and `create`
+    pub fn open_flags(&self) -> u32 {
+        if self.direct_io {
+            FOPEN_DIRECT_IO
+        } else {
+            0
+        }
+    }
+
+    /// Raise the maximum readahead and write sizes to at least the preferred size, as far as
+    /// the kernel allows. Smaller preferred sizes leave them unchanged.
+    pub fn configure(&self, config: &mut KernelConfig) {
+        if self.size > config.max_readahead {
+            let _ = config
+                .set_max_readahead(self.size)
+                .or_else(|nearest| config.set_max_readahead(nearest));
+        }
+        if self.size > config.max_write {
+            let _ = config
+                .set_max_write(self.size)
+                .or_else(|nearest| config.set_max_write(nearest));
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::IoSizeHint;
+    use crate::ll::fuse_abi::consts::FOPEN_DIRECT_IO;
+    use crate::KernelConfig;
+
+    #[test]
+    fn configure() {
+        let mut config = KernelConfig::new(0, 128 * 1024);
+        let previous_write = config.max_write;
+        IoSizeHint::new(4096).configure(&mut config);
+        assert_eq!(config.max_readahead, 128 * 1024);
+        assert_eq!(config.max_write, previous_write);
+        IoSizeHint::new(8 * 1024 * 1024).configure(&mut config);
+        assert_eq!(config.max_readahead, 128 * 1024);
+        assert!(config.max_write >= previous_write);
+        assert_eq!(IoSizeHint::new(1 << 20).open_flags(), 0);
+        assert_eq!(
+            IoSizeHint::new(1 << 20).with_direct_io(true).open_flags(),
+            FOPEN_DIRECT_IO
+        );
+    }
+}
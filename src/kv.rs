@@ -0,0 +1,719 @@
+//! Key-value store adapter
+//!
+//! A lot of filesystems expose an object store or database as files: keys are paths, values are
+//! file contents. [`KvFilesystem`] maps the flat keyspace of a [`KvStore`] onto the
+//! [`Filesystem`] trait, assigning inode numbers and emulating directories from the `/`
+//! separated prefixes of the keys.
+
+use libc::{c_int, EEXIST, EINVAL, EISDIR, ENOENT, ENOTDIR, ENOTEMPTY};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::ffi::OsStr;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::{
+    DirEntOffset, FileAttr, FileType, Filesystem, ReplyAttr, ReplyCreate, ReplyData,
+    ReplyDirectory, ReplyEmpty, ReplyEntry, ReplyOpen, ReplyWrite, Request, TimeOrNow, Ttl,
+    WholeFileFilesystem, WholeFileFs, FUSE_ROOT_ID,
+};
+
+/// A flat keyspace exposed by [`KvFilesystem`]. Keys are relative paths with `/` separated
+/// components, e.g. `config/app.toml`.
+pub trait KvStore {
+    /// Returns all keys starting with the given prefix
+    fn list(&mut self, prefix: &str) -> Result<Vec<String>, c_int>;
+
+    /// Returns the names one level below `prefix`, which is empty or ends with `/`: the rest of
+    /// the keys without a further `/`, and once for every other first component, that
+    /// component followed by `/`. The default filters [`list`](KvStore::list), stores that
+    /// can list by delimiter (like S3) should do so instead.
+    fn list_level(&mut self, prefix: &str) -> Result<Vec<String>, c_int> {
+        let mut names = BTreeSet::new();
+        for key in self.list(prefix)? {
+            let rest = &key[prefix.len()..];
+            names.insert(match rest.split_once('/') {
+                Some((dir, _)) => format!("{}/", dir),
+                None => rest.to_owned(),
+            });
+        }
+        Ok(names.into_iter().collect())
+    }
+
+    /// Returns whether any key starts with the given prefix. The default filters
+    /// [`list`](KvStore::list).
+    fn has_prefix(&mut self, prefix: &str) -> Result<bool, c_int> {
+        Ok(!self.list(prefix)?.is_empty())
+    }
+
+    /// Returns the value of a key, or `None` if it doesn't exist
+    fn get(&mut self, key: &str) -> Result<Option<Vec<u8>>, c_int>;
+
+    /// Set the value of a key, creating it if it doesn't exist
+    fn put(&mut self, key: &str, value: Vec<u8>) -> Result<(), c_int>;
+
+    /// Remove a key
+    fn delete(&mut self, key: &str) -> Result<(), c_int>;
+}
+
+/// An in-memory store
+impl KvStore for BTreeMap<String, Vec<u8>> {
+    fn list(&mut self, prefix: &str) -> Result<Vec<String>, c_int> {
+        Ok(self
+            .range(prefix.to_owned()..)
+            .take_while(|(key, _)| key.starts_with(prefix))
+            .map(|(key, _)| key.clone())
+            .collect())
+    }
+
+    fn list_level(&mut self, prefix: &str) -> Result<Vec<String>, c_int> {
+        let mut names = vec![];
+        let mut start = prefix.to_owned();
+        while let Some((key, _)) = self.range(start..).next() {
+            let Some(rest) = key.strip_prefix(prefix) else {
+                break;
+            };
+            start = match rest.split_once('/') {
+                Some((dir, _)) => {
+                    names.push(format!("{}/", dir));
+                    // Skip the rest of the directory, '0' sorts right after '/'
+                    format!("{}{}0", prefix, dir)
+                }
+                None => {
+                    names.push(rest.to_owned());
+                    format!("{}\0", key)
+                }
+            };
+        }
+        Ok(names)
+    }
+
+    fn has_prefix(&mut self, prefix: &str) -> Result<bool, c_int> {
+        Ok(self
+            .range(prefix.to_owned()..)
+            .next()
+            .is_some_and(|(key, _)| key.starts_with(prefix)))
+    }
+
+    fn get(&mut self, key: &str) -> Result<Option<Vec<u8>>, c_int> {
+        Ok(BTreeMap::get(self, key).cloned())
+    }
+
+    fn put(&mut self, key: &str, value: Vec<u8>) -> Result<(), c_int> {
+        self.insert(key.to_owned(), value);
+        Ok(())
+    }
+
+    fn delete(&mut self, key: &str) -> Result<(), c_int> {
+        self.remove(key);
+        Ok(())
+    }
+}
+
+/// Exposes a [`KvStore`] as a filesystem.
+///
+/// Every key is a regular file, every prefix up to a `/` a directory. A key that is also the
+/// prefix of other keys (`a` next to `a/b`) is shown as a directory only. Files are read and
+/// written as a whole (see [`WholeFileFs`]); changes are stored when the file is flushed or
+/// closed. Directories created with `mkdir` only exist in memory until a file is created in
+/// them, since the keyspace has no place for empty directories. Inode numbers stay the same for
+/// a path while the session runs.
+#[derive(Debug)]
+pub struct KvFilesystem<S> {
+    files: WholeFileFs<KvNodes<S>>,
+}
+
+impl<S: KvStore> KvFilesystem<S> {
+    /// Expose the given store
+    pub fn new(store: S) -> Self {
+        Self {
+            files: WholeFileFs::new(KvNodes {
+                store,
+                paths: HashMap::from([(FUSE_ROOT_ID, String::new())]),
+                inodes: HashMap::from([(String::new(), FUSE_ROOT_ID)]),
+                empty_dirs: BTreeSet::new(),
+                ttl: Duration::from_secs(1),
+                uid: nix::unistd::geteuid().as_raw(),
+                gid: nix::unistd::getegid().as_raw(),
+            }),
+        }
+    }
+
+    /// Set how long the kernel caches attributes and entries. Defaults to 1 second. Use zero if
+    /// the store is changed by others while it is mounted.
+    pub fn with_ttl(mut self, ttl: Duration) -> Self {
+        self.files.inner_mut().ttl = ttl;
+        self
+    }
+
+    /// Set the owner of all files. Defaults to the user running the process.
+    pub fn with_owner(mut self, uid: u32, gid: u32) -> Self {
+        let nodes = self.files.inner_mut();
+        nodes.uid = uid;
+        nodes.gid = gid;
+        self
+    }
+
+    /// Returns a reference to the store.
+    pub fn store(&self) -> &S {
+        &self.files.inner().store
+    }
+
+    /// Returns a mutable reference to the store.
+    pub fn store_mut(&mut self) -> &mut S {
+        &mut self.files.inner_mut().store
+    }
+}
+
+/// The inodes of a [`KvFilesystem`]
+#[derive(Debug)]
+struct KvNodes<S> {
+    store: S,
+    /// ino -> path, which is empty for the root
+    paths: HashMap<u64, String>,
+    inodes: HashMap<String, u64>,
+    /// Directories created with `mkdir` that have no keys yet
+    empty_dirs: BTreeSet<String>,
+    ttl: Duration,
+    uid: u32,
+    gid: u32,
+}
+
+impl<S: KvStore> KvNodes<S> {
+    /// Returns the inode number of a path, assigning one if it has none yet
+    fn ino(&mut self, path: &str) -> u64 {
+        if let Some(&ino) = self.inodes.get(path) {
+            return ino;
+        }
+        let ino = self.paths.len() as u64 + FUSE_ROOT_ID;
+        self.paths.insert(ino, path.to_owned());
+        self.inodes.insert(path.to_owned(), ino);
+        ino
+    }
+
+    fn path(&self, ino: u64) -> Result<&str, c_int> {
+        self.paths.get(&ino).map(String::as_str).ok_or(ENOENT)
+    }
+
+    fn child_path(&self, parent: u64, name: &OsStr) -> Result<String, c_int> {
+        let name = name.to_str().ok_or(EINVAL)?;
+        Ok(match self.path(parent)? {
+            "" => name.to_owned(),
+            parent => format!("{}/{}", parent, name),
+        })
+    }
+
+    /// Returns whether there are keys below a directory
+    fn has_keys(&mut self, path: &str) -> Result<bool, c_int> {
+        self.store.has_prefix(&dir_prefix(path))
+    }
+
+    fn is_dir(&mut self, path: &str) -> Result<bool, c_int> {
+        Ok(path.is_empty() || self.empty_dirs.contains(path) || self.has_keys(path)?)
+    }
+
+    /// Returns the attributes of the file or directory at `path`
+    fn attr(&mut self, path: &str) -> Result<FileAttr, c_int> {
+        let (kind, size) = if self.is_dir(path)? {
+            (FileType::Directory, 0)
+        } else {
+            let value = self.store.get(path)?.ok_or(ENOENT)?;
+            (FileType::RegularFile, value.len() as u64)
+        };
+        Ok(FileAttr {
+            ino: self.ino(path),
+            size,
+            blocks: size.div_ceil(512),
+            atime: UNIX_EPOCH,
+            mtime: UNIX_EPOCH,
+            ctime: UNIX_EPOCH,
+            crtime: UNIX_EPOCH,
+            kind,
+            perm: if kind == FileType::Directory {
+                0o755
+            } else {
+                0o644
+            },
+            nlink: if kind == FileType::Directory { 2 } else { 1 },
+            uid: self.uid,
+            gid: self.gid,
+            rdev: 0,
+            blksize: 4096,
+            flags: 0,
+        })
+    }
+
+    /// Returns the entries of a directory, sorted by name
+    fn children(&mut self, path: &str) -> Result<Vec<(String, FileType)>, c_int> {
+        let prefix = dir_prefix(path);
+        let mut children = BTreeMap::new();
+        for name in self.store.list_level(&prefix)? {
+            match name.strip_suffix('/') {
+                Some(dir) => {
+                    children.insert(dir.to_owned(), FileType::Directory);
+                }
+                None if !name.is_empty() => {
+                    children.entry(name).or_insert(FileType::RegularFile);
+                }
+                None => {}
+            }
+        }
+        for dir in self.empty_dirs.range(prefix.clone()..) {
+            let Some(name) = dir.strip_prefix(&prefix) else {
+                break;
+            };
+            if !name.contains('/') {
+                children.insert(name.to_owned(), FileType::Directory);
+            }
+        }
+        Ok(children.into_iter().collect())
+    }
+
+    /// Forget that the parent directories of a new key are empty
+    fn created(&mut self, path: &str) {
+        let mut dir = path;
+        while let Some((parent, _)) = dir.rsplit_once('/') {
+            self.empty_dirs.remove(parent);
+            dir = parent;
+        }
+    }
+}
+
+/// Returns the prefix of the keys below a directory
+fn dir_prefix(path: &str) -> String {
+    if path.is_empty() {
+        String::new()
+    } else {
+        format!("{}/", path)
+    }
+}
+
+impl<S: KvStore> WholeFileFilesystem for KvNodes<S> {
+    fn read_file(&mut self, ino: u64) -> Result<Vec<u8>, c_int> {
+        let path = self.path(ino)?.to_owned();
+        if self.is_dir(&path)? {
+            return Err(EISDIR);
+        }
+        self.store.get(&path)?.ok_or(ENOENT)
+    }
+
+    fn write_file(&mut self, ino: u64, data: Vec<u8>) -> Result<(), c_int> {
+        let path = self.path(ino)?.to_owned();
+        self.store.put(&path, data)
+    }
+
+    fn create_file(
+        &mut self,
+        parent: u64,
+        name: &OsStr,
+        _mode: u32,
+        _umask: u32,
+    ) -> Result<(Ttl, FileAttr), c_int> {
+        let path = self.child_path(parent, name)?;
+        if self.is_dir(&path)? || self.store.get(&path)?.is_some() {
+            return Err(EEXIST);
+        }
+        self.store.put(&path, vec![])?;
+        self.created(&path);
+        Ok((self.ttl.into(), self.attr(&path)?))
+    }
+}
+
+impl<S: KvStore> Filesystem for KvNodes<S> {
+    fn lookup(&mut self, _req: &Request<'_>, parent: u64, name: &OsStr, reply: ReplyEntry) {
+        match self
+            .child_path(parent, name)
+            .and_then(|path| self.attr(&path))
+        {
+            Ok(attr) => reply.entry(self.ttl, &attr, 0),
+            Err(err) => reply.error(err),
+        }
+    }
+
+    fn getattr(&mut self, _req: &Request<'_>, ino: u64, _fh: Option<u64>, reply: ReplyAttr) {
+        match self
+            .path(ino)
+            .map(str::to_owned)
+            .and_then(|p| self.attr(&p))
+        {
+            Ok(attr) => reply.attr(self.ttl, &attr),
+            Err(err) => reply.error(err),
+        }
+    }
+
+    fn setattr(
+        &mut self,
+        req: &Request<'_>,
+        ino: u64,
+        _mode: Option<u32>,
+        _uid: Option<u32>,
+        _gid: Option<u32>,
+        _size: Option<u64>,
+        _atime: Option<TimeOrNow>,
+        _mtime: Option<TimeOrNow>,
+        _ctime: Option<SystemTime>,
+        fh: Option<u64>,
+        _crtime: Option<SystemTime>,
+        _chgtime: Option<SystemTime>,
+        _bkuptime: Option<SystemTime>,
+        _flags: Option<u32>,
+        reply: ReplyAttr,
+    ) {
+        // Only the size can be changed, which `WholeFileFs` did already
+        self.getattr(req, ino, fh, reply)
+    }
+
+    fn mkdir(
+        &mut self,
+        _req: &Request<'_>,
+        parent: u64,
+        name: &OsStr,
+        _mode: u32,
+        _umask: u32,
+        reply: ReplyEntry,
+    ) {
+        let result = self.child_path(parent, name).and_then(|path| {
+            if self.is_dir(&path)? || self.store.get(&path)?.is_some() {
+                return Err(EEXIST);
+            }
+            self.empty_dirs.insert(path.clone());
+            self.attr(&path)
+        });
+        match result {
+            Ok(attr) => reply.entry(self.ttl, &attr, 0),
+            Err(err) => reply.error(err),
+        }
+    }
+
+    fn unlink(&mut self, _req: &Request<'_>, parent: u64, name: &OsStr, reply: ReplyEmpty) {
+        let result = self.child_path(parent, name).and_then(|path| {
+            if self.is_dir(&path)? {
+                return Err(EISDIR);
+            }
+            self.store.get(&path)?.ok_or(ENOENT)?;
+            self.store.delete(&path)
+        });
+        match result {
+            Ok(()) => reply.ok(),
+            Err(err) => reply.error(err),
+        }
+    }
+
+    fn rmdir(&mut self, _req: &Request<'_>, parent: u64, name: &OsStr, reply: ReplyEmpty) {
+        let result = self.child_path(parent, name).and_then(|path| {
+            if self.has_keys(&path)? {
+                return Err(ENOTEMPTY);
+            }
+            if !self.empty_dirs.remove(&path) {
+                return Err(if self.store.get(&path)?.is_some() {
+                    ENOTDIR
+                } else {
+                    ENOENT
+                });
+            }
+            Ok(())
+        });
+        match result {
+            Ok(()) => reply.ok(),
+            Err(err) => reply.error(err),
+        }
+    }
+
+    fn opendir(&mut self, _req: &Request<'_>, ino: u64, _flags: i32, reply: ReplyOpen) {
+        match self
+            .path(ino)
+            .map(str::to_owned)
+            .and_then(|p| self.is_dir(&p))
+        {
+            Ok(true) => reply.opened(0, 0),
+            Ok(false) => reply.error(ENOTDIR),
+            Err(err) => reply.error(err),
+        }
+    }
+
+    fn readdir(
+        &mut self,
+        _req: &Request<'_>,
+        ino: u64,
+        _fh: u64,
+        offset: i64,
+        mut reply: ReplyDirectory,
+    ) {
+        let path = match self.path(ino) {
+            Ok(path) => path.to_owned(),
+            Err(err) => return reply.error(err),
+        };
+        let children = match self.children(&path) {
+            Ok(children) => children,
+            Err(err) => return reply.error(err),
+        };
+        let parent = match path.rsplit_once('/') {
+            Some((parent, _)) => self.ino(parent),
+            None => FUSE_ROOT_ID,
+        };
+        let mut entries = vec![
+            (ino, FileType::Directory, ".".to_owned()),
+            (parent, FileType::Directory, "..".to_owned()),
+        ];
+        for (name, kind) in children {
+            let child = match path.as_str() {
+                "" => name.clone(),
+                path => format!("{}/{}", path, name),
+            };
+            entries.push((self.ino(&child), kind, name));
+        }
+        for (i, (ino, kind, name)) in entries.into_iter().enumerate().skip(offset as usize) {
+            if reply.add(ino, DirEntOffset::after_index(i).get(), kind, name) {
+                break;
+            }
+        }
+        reply.ok();
+    }
+}
+
+impl<S: KvStore> Filesystem for KvFilesystem<S> {
+    fn lookup(&mut self, req: &Request<'_>, parent: u64, name: &OsStr, reply: ReplyEntry) {
+        self.files.lookup(req, parent, name, reply)
+    }
+
+    fn getattr(&mut self, req: &Request<'_>, ino: u64, fh: Option<u64>, reply: ReplyAttr) {
+        self.files.getattr(req, ino, fh, reply)
+    }
+
+    fn setattr(
+        &mut self,
+        req: &Request<'_>,
+        ino: u64,
+        mode: Option<u32>,
+        uid: Option<u32>,
+        gid: Option<u32>,
+        size: Option<u64>,
+        atime: Option<TimeOrNow>,
+        mtime: Option<TimeOrNow>,
+        ctime: Option<SystemTime>,
+        fh: Option<u64>,
+        crtime: Option<SystemTime>,
+        chgtime: Option<SystemTime>,
+        bkuptime: Option<SystemTime>,
+        flags: Option<u32>,
+        reply: ReplyAttr,
+    ) {
+        self.files.setattr(
+            req, ino, mode, uid, gid, size, atime, mtime, ctime, fh, crtime, chgtime, bkuptime,
+            flags, reply,
+        )
+    }
+
+    fn mkdir(
+        &mut self,
+        req: &Request<'_>,
+        parent: u64,
+        name: &OsStr,
+        mode: u32,
+        umask: u32,
+        reply: ReplyEntry,
+    ) {
+        self.files.mkdir(req, parent, name, mode, umask, reply)
+    }
+
+    fn unlink(&mut self, req: &Request<'_>, parent: u64, name: &OsStr, reply: ReplyEmpty) {
+        self.files.unlink(req, parent, name, reply)
+    }
+
+    fn rmdir(&mut self, req: &Request<'_>, parent: u64, name: &OsStr, reply: ReplyEmpty) {
+        self.files.rmdir(req, parent, name, reply)
+    }
+
+    fn open(&mut self, req: &Request<'_>, ino: u64, flags: i32, reply: ReplyOpen) {
+        self.files.open(req, ino, flags, reply)
+    }
+
+    fn read(
+        &mut self,
+        req: &Request<'_>,
+        ino: u64,
+        fh: u64,
+        offset: i64,
+        size: u32,
+        flags: i32,
+        lock_owner: Option<u64>,
+        reply: ReplyData,
+    ) {
+        self.files
+            .read(req, ino, fh, offset, size, flags, lock_owner, reply)
+    }
+
+    fn write(
+        &mut self,
+        req: &Request<'_>,
+        ino: u64,
+        fh: u64,
+        offset: i64,
+        data: &[u8],
+        write_flags: u32,
+        flags: i32,
+        lock_owner: Option<u64>,
+        reply: ReplyWrite,
+    ) {
+        self.files.write(
+            req,
+            ino,
+            fh,
+            offset,
+            data,
+            write_flags,
+            flags,
+            lock_owner,
+            reply,
+        )
+    }
+
+    fn flush(&mut self, req: &Request<'_>, ino: u64, fh: u64, lock_owner: u64, reply: ReplyEmpty) {
+        self.files.flush(req, ino, fh, lock_owner, reply)
+    }
+
+    fn release(
+        &mut self,
+        req: &Request<'_>,
+        ino: u64,
+        fh: u64,
+        flags: i32,
+        lock_owner: Option<u64>,
+        flush: bool,
+        reply: ReplyEmpty,
+    ) {
+        self.files
+            .release(req, ino, fh, flags, lock_owner, flush, reply)
+    }
+
+    fn fsync(&mut self, req: &Request<'_>, ino: u64, fh: u64, datasync: bool, reply: ReplyEmpty) {
+        self.files.fsync(req, ino, fh, datasync, reply)
+    }
+
+    fn opendir(&mut self, req: &Request<'_>, ino: u64, flags: i32, reply: ReplyOpen) {
+        self.files.opendir(req, ino, flags, reply)
+    }
+
+    fn readdir(
+        &mut self,
+        req: &Request<'_>,
+        ino: u64,
+        fh: u64,
+        offset: i64,
+        reply: ReplyDirectory,
+    ) {
+        self.files.readdir(req, ino, fh, offset, reply)
+    }
+
+    fn create(
+        &mut self,
+        req: &Request<'_>,
+        parent: u64,
+        name: &OsStr,
+        mode: u32,
+        umask: u32,
+        flags: i32,
+        reply: ReplyCreate,
+    ) {
+        self.files
+            .create(req, parent, name, mode, umask, flags, reply)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{KvFilesystem, KvStore};
+    use crate::{FileType, WholeFileFilesystem};
+    use std::collections::BTreeMap;
+    use std::ffi::OsStr;
+
+    fn store() -> BTreeMap<String, Vec<u8>> {
+        BTreeMap::from([
+            ("a".to_owned(), b"1".to_vec()),
+            ("b/c".to_owned(), b"22".to_vec()),
+            ("b/d/e".to_owned(), b"333".to_vec()),
+        ])
+    }
+
+    #[test]
+    fn directories() {
+        let mut fs = KvFilesystem::new(store());
+        let nodes = fs.files.inner_mut();
+        assert_eq!(
+            nodes.children("").unwrap(),
+            vec![
+                ("a".to_owned(), FileType::RegularFile),
+                ("b".to_owned(), FileType::Directory)
+            ]
+        );
+        assert_eq!(
+            nodes.children("b").unwrap(),
+            vec![
+                ("c".to_owned(), FileType::RegularFile),
+                ("d".to_owned(), FileType::Directory)
+            ]
+        );
+        assert_eq!(nodes.attr("b/d/e").unwrap().size, 3);
+        assert_eq!(nodes.attr("b/d").unwrap().kind, FileType::Directory);
+        assert_eq!(nodes.attr("x"), Err(libc::ENOENT));
+
+        // Inode numbers are stable
+        let ino = nodes.ino("b/c");
+        assert_eq!(nodes.attr("b/c").unwrap().ino, ino);
+        assert_eq!(nodes.path(ino), Ok("b/c"));
+    }
+
+    #[test]
+    fn list_level() {
+        /// Lists through the default methods of the trait
+        struct Flat(BTreeMap<String, Vec<u8>>);
+        impl KvStore for Flat {
+            fn list(&mut self, prefix: &str) -> Result<Vec<String>, libc::c_int> {
+                self.0.list(prefix)
+            }
+            fn get(&mut self, key: &str) -> Result<Option<Vec<u8>>, libc::c_int> {
+                KvStore::get(&mut self.0, key)
+            }
+            fn put(&mut self, key: &str, value: Vec<u8>) -> Result<(), libc::c_int> {
+                self.0.put(key, value)
+            }
+            fn delete(&mut self, key: &str) -> Result<(), libc::c_int> {
+                self.0.delete(key)
+            }
+        }
+
+        let mut map = store();
+        // Sorts between "b/c" and "b/d/"
+        map.insert("b/c-x".to_owned(), vec![]);
+        let mut flat = Flat(map.clone());
+        for store in [&mut map as &mut dyn KvStore, &mut flat] {
+            assert_eq!(store.list_level("").unwrap(), ["a", "b/"]);
+            assert_eq!(store.list_level("b/").unwrap(), ["c", "c-x", "d/"]);
+            assert_eq!(store.list_level("b/d/").unwrap(), ["e"]);
+            assert!(store.list_level("x/").unwrap().is_empty());
+            assert!(store.has_prefix("b/d/").unwrap());
+            assert!(!store.has_prefix("b/e").unwrap());
+        }
+    }
+
+    #[test]
+    fn create_in_empty_dir() {
+        let mut fs = KvFilesystem::new(store());
+        let nodes = fs.files.inner_mut();
+        nodes.empty_dirs.insert("new".to_owned());
+        nodes.empty_dirs.insert("new/sub".to_owned());
+        assert_eq!(
+            nodes.children("").unwrap()[2],
+            ("new".to_owned(), FileType::Directory)
+        );
+        let dir = nodes.ino("new/sub");
+        let (_, attr) = nodes.create_file(dir, OsStr::new("f"), 0o644, 0).unwrap();
+        assert_eq!(attr.kind, FileType::RegularFile);
+        assert!(nodes.empty_dirs.is_empty());
+        assert_eq!(
+            nodes
+                .create_file(dir, OsStr::new("f"), 0o644, 0)
+                .unwrap_err(),
+            libc::EEXIST
+        );
+        assert_eq!(fs.store()["new/sub/f"], b"");
+    }
+}
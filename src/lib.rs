@@ -6,7 +6,7 @@
 
 #![warn(missing_docs, missing_debug_implementations, rust_2018_idioms)]
 
-use libc::{c_int, ENOSYS, EPERM};
+use libc::{c_int, ENOSYS};
 use log::{debug, warn};
 use mnt::mount_options::parse_options_from_args;
 #[cfg(feature = "serializable")]
@@ -14,46 +14,128 @@ use serde::{Deserialize, Serialize};
 use std::ffi::OsStr;
 use std::io;
 use std::path::Path;
-#[cfg(feature = "abi-7-23")]
 use std::time::Duration;
 use std::time::SystemTime;
 use std::{convert::AsRef, io::ErrorKind};
 
 use crate::ll::fuse_abi::consts::*;
-pub use crate::ll::fuse_abi::FUSE_ROOT_ID;
+pub use crate::ll::fuse_abi::{fuse_opcode, FUSE_ROOT_ID};
+use crate::ll::fuse_abi::{FUSE_KERNEL_MINOR_VERSION, FUSE_KERNEL_VERSION};
+pub use crate::ll::reply::DirEntOffset;
 pub use crate::ll::{fuse_abi::consts, TimeOrNow};
 use crate::mnt::mount_options::check_option_conflicts;
 use crate::session::MAX_WRITE_SIZE;
+pub use authorizer::{Access, AccessKind, Authorizer};
+pub use cache_preset::CachePreset;
+pub use capability::Capability;
+pub use change_log::{Change, ChangeLog};
+pub use checksum::{Checksum, Crc32c};
+pub use clock::{Clock, ManualClock, SystemClock};
+pub use copy_flags::CopyFileRangeFlags;
+pub use debug_dump::{DebugDump, PendingRequest};
+pub use dir_times::DirTimes;
+pub use disk_full::DiskFullFs;
+pub use errno_policy::ErrnoPolicy;
+pub use getattr_fh::GetattrFh;
+pub use hook::{RequestHook, RequestInfo};
+pub use id_map::IdMap;
+pub use ino_remap::Ino32Remap;
+pub use io_size::IoSizeHint;
+pub use kv::{KvFilesystem, KvStore};
 #[cfg(feature = "abi-7-16")]
 pub use ll::fuse_abi::fuse_forget_one;
-pub use mnt::mount_options::MountOption;
+pub use lock_table::{LockTable, PosixLock};
+pub use metrics::{LatencyHistogram, Metrics, OpMetrics};
+pub use mnt::mount_options::{MountOption, MountPropagation};
+#[cfg(feature = "abi-7-12")]
+pub use notify::NotifyTransaction;
 #[cfg(feature = "abi-7-11")]
 pub use notify::{Notifier, PollHandle};
+pub use null_fs::NullFs;
+pub use open_flags::OpenFlags;
+pub use platform::Platform;
+#[cfg(feature = "abi-7-11")]
+pub use poll_events::PollEvents;
+#[cfg(feature = "abi-7-11")]
+pub use poll_registry::PollRegistry;
+pub use rename_journal::{RenameIntent, RenameJournal};
 #[cfg(feature = "abi-7-11")]
 pub use reply::ReplyPoll;
 #[cfg(target_os = "macos")]
 pub use reply::ReplyXTimes;
 pub use reply::ReplyXattr;
-pub use reply::{Reply, ReplyAttr, ReplyData, ReplyEmpty, ReplyEntry, ReplyOpen};
+pub use reply::{Bytes, Reply, ReplyAttr, ReplyData, ReplyEmpty, ReplyEntry, ReplyOpen};
 pub use reply::{
     ReplyBmap, ReplyCreate, ReplyDirectory, ReplyDirectoryPlus, ReplyIoctl, ReplyLock, ReplyLseek,
     ReplyStatfs, ReplyWrite,
 };
-pub use request::Request;
-pub use session::{BackgroundSession, Session, SessionACL, SessionUnmounter};
+pub use request::{InterruptToken, Request};
+pub use scoped_root::ScopedRootFs;
+pub use session::{
+    BackgroundSession, DroppedReply, EmptyIoPolicy, InterruptedReply, MemoryBudgetPolicy,
+    OperationFamily, PanicPolicy, Session, SessionACL, SessionExit, SessionUnmounter,
+    UnknownOpcodePolicy,
+};
 #[cfg(feature = "abi-7-28")]
 use std::cmp::max;
 #[cfg(feature = "abi-7-13")]
 use std::cmp::min;
-
+pub use synthetic::{SyntheticDir, SyntheticFs};
+pub use sysfs::KernelConnection;
+pub use ttl::Ttl;
+pub use whole_file::{WholeFileFilesystem, WholeFileFs};
+pub use write_data::WriteData;
+pub use write_journal::WriteJournal;
+pub use xattr_policy::XattrPolicy;
+
+mod authorizer;
+mod cache_preset;
+mod capability;
+mod change_log;
 mod channel;
+mod checksum;
+mod clock;
+mod control;
+mod copy_flags;
+mod debug_dump;
+mod dir_times;
+mod disk_full;
+mod errno_policy;
+mod forward;
+mod getattr_fh;
+mod hook;
+mod id_map;
+mod ino_remap;
+mod io_size;
+mod kv;
 mod ll;
+mod lock_table;
+mod metrics;
 mod mnt;
 #[cfg(feature = "abi-7-11")]
 mod notify;
+mod null_fs;
+mod open_flags;
+mod platform;
+#[cfg(feature = "abi-7-11")]
+mod poll_events;
+#[cfg(feature = "abi-7-11")]
+mod poll_registry;
+mod rename_journal;
 mod reply;
 mod request;
+mod scoped_root;
 mod session;
+mod synthetic;
+mod sysfs;
+mod time_gran;
+mod ttl;
+#[cfg(feature = "usdt")]
+mod usdt;
+mod whole_file;
+mod write_data;
+mod write_journal;
+mod xattr_policy;
 
 /// We generally support async reads
 #[cfg(all(not(target_os = "macos"), not(feature = "abi-7-10")))]
@@ -69,19 +151,18 @@ const INIT_FLAGS: u32 = FUSE_ASYNC_READ | FUSE_CASE_INSENSITIVE | FUSE_VOL_RENAM
 // TODO: Add FUSE_EXPORT_SUPPORT and FUSE_BIG_WRITES (requires ABI 7.10)
 
 const fn default_init_flags(#[allow(unused_variables)] capabilities: u32) -> u32 {
-    #[cfg(not(feature = "abi-7-28"))]
-    {
-        INIT_FLAGS
+    #[allow(unused_mut)]
+    let mut flags = INIT_FLAGS;
+    // Tell aborts apart from unmounts
+    #[cfg(feature = "abi-7-27")]
+    if capabilities & FUSE_ABORT_ERROR != 0 {
+        flags |= FUSE_ABORT_ERROR;
     }
-
     #[cfg(feature = "abi-7-28")]
-    {
-        let mut flags = INIT_FLAGS;
-        if capabilities & FUSE_MAX_PAGES != 0 {
-            flags |= FUSE_MAX_PAGES;
-        }
-        flags
+    if capabilities & FUSE_MAX_PAGES != 0 {
+        flags |= FUSE_MAX_PAGES;
     }
+    flags
 }
 
 /// File types
@@ -104,6 +185,26 @@ pub enum FileType {
     Socket,
 }
 
+impl FileType {
+    /// Returns the file type encoded in the `S_IFMT` bits of a mode, like the one passed to
+    /// `mknod`
+    // mode_t is u16 on some platforms like macOS, so silence the lint about trivial casts
+    #[allow(trivial_numeric_casts)]
+    #[allow(clippy::unnecessary_cast)]
+    pub fn from_mode(mode: u32) -> Option<FileType> {
+        match mode & libc::S_IFMT as u32 {
+            m if m == libc::S_IFIFO as u32 => Some(FileType::NamedPipe),
+            m if m == libc::S_IFCHR as u32 => Some(FileType::CharDevice),
+            m if m == libc::S_IFBLK as u32 => Some(FileType::BlockDevice),
+            m if m == libc::S_IFDIR as u32 => Some(FileType::Directory),
+            m if m == libc::S_IFREG as u32 => Some(FileType::RegularFile),
+            m if m == libc::S_IFLNK as u32 => Some(FileType::Symlink),
+            m if m == libc::S_IFSOCK as u32 => Some(FileType::Socket),
+            _ => None,
+        }
+    }
+}
+
 /// File attributes
 #[derive(Clone, Copy, Debug, Eq, PartialEq)]
 #[cfg_attr(feature = "serializable", derive(Serialize, Deserialize))]
@@ -154,6 +255,8 @@ pub struct KernelConfig {
     max_write: u32,
     #[cfg(feature = "abi-7-23")]
     time_gran: Duration,
+    /// Required capabilities the kernel lacks
+    unmet: Vec<Capability>,
 }
 
 impl KernelConfig {
@@ -172,6 +275,7 @@ impl KernelConfig {
             // 1ns means nano-second granularity.
             #[cfg(feature = "abi-7-23")]
             time_gran: Duration::new(0, 1),
+            unmet: vec![],
         }
     }
 
@@ -179,6 +283,8 @@ impl KernelConfig {
     ///
     /// Must be a power of 10 nanoseconds. i.e. 1s, 0.1s, 0.01s, 1ms, 0.1ms...etc
     ///
+    /// Times in replied attributes and in `setattr` requests are truncated to it.
+    ///
     /// On success returns the previous value. On error returns the nearest value which will succeed
     #[cfg(feature = "abi-7-23")]
     pub fn set_time_granularity(&mut self, value: Duration) -> Result<Duration, Duration> {
@@ -219,6 +325,8 @@ impl KernelConfig {
     /// Set the maximum readahead size
     ///
     /// On success returns the previous value. On error returns the nearest value which will succeed
+    ///
+    /// To change it after init, see [`Session::set_max_readahead`].
     pub fn set_max_readahead(&mut self, value: u32) -> Result<u32, u32> {
         if value == 0 {
             return Err(1);
@@ -242,6 +350,47 @@ impl KernelConfig {
         Ok(())
     }
 
+    /// Request a capability the filesystem can't work without. If the kernel lacks it, the
+    /// mount fails once `init` returns, with an error naming every missing capability (e.g.
+    /// "kernel lacks FUSE_WRITEBACK_CACHE (needs >= 3.15)"), even if `init` ignores the error
+    /// returned here.
+    pub fn require(&mut self, capability: Capability) -> Result<(), c_int> {
+        if self.add_capabilities(capability.flag()).is_err() {
+            self.unmet.push(capability);
+            return Err(libc::ENOSYS);
+        }
+        Ok(())
+    }
+
+    /// Returns the error to fail the mount with if required capabilities are missing
+    pub(crate) fn unmet_requirements(&self) -> Option<io::Error> {
+        if self.unmet.is_empty() {
+            return None;
+        }
+        let missing: Vec<String> = self.unmet.iter().map(|c| c.to_string()).collect();
+        Some(io::Error::new(
+            ErrorKind::Unsupported,
+            format!("kernel lacks {}", missing.join(", ")),
+        ))
+    }
+
+    /// Let the filesystem truncate files on open (`FUSE_ATOMIC_O_TRUNC`).
+    ///
+    /// When enabled, `O_TRUNC` is passed to [`Filesystem::open`], which must truncate the file
+    /// itself. Otherwise the kernel removes `O_TRUNC`, and once `open` returned truncates the
+    /// file with a separate `setattr` with size 0 and the new file handle, which costs a round
+    /// trip and lets other clients see the file opened but not yet truncated.
+    ///
+    /// On error returns the flag, if the kernel doesn't support it
+    #[cfg(feature = "abi-7-9")]
+    pub fn set_atomic_o_trunc(&mut self, enabled: bool) -> Result<(), u32> {
+        if !enabled {
+            self.requested &= !FUSE_ATOMIC_O_TRUNC;
+            return Ok(());
+        }
+        self.add_capabilities(FUSE_ATOMIC_O_TRUNC)
+    }
+
     /// Set the maximum number of pending background requests. Such as readahead requests.
     ///
     /// On success returns the previous value. On error returns the nearest value which will succeed
@@ -284,6 +433,112 @@ impl KernelConfig {
     }
 }
 
+/// Connection settings negotiated with the kernel during init.
+///
+/// Available to all requests after init through [`Request::connection`]. Before init (and for
+/// the init request itself), all values are zero.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ConnectionInfo {
+    proto_major: u32,
+    proto_minor: u32,
+    capabilities: u32,
+    flags: u32,
+    max_write: u32,
+    max_readahead: u32,
+    #[cfg(feature = "abi-7-13")]
+    max_background: u16,
+    #[cfg(feature = "abi-7-13")]
+    congestion_threshold: u16,
+    #[cfg(feature = "abi-7-23")]
+    time_gran: Duration,
+}
+
+impl ConnectionInfo {
+    /// Record the outcome of init, given the kernel's protocol version and the config replied
+    /// with
+    fn negotiated(kernel_major: u32, kernel_minor: u32, config: &KernelConfig) -> Self {
+        let (proto_major, proto_minor) = if kernel_major == FUSE_KERNEL_VERSION {
+            (kernel_major, kernel_minor.min(FUSE_KERNEL_MINOR_VERSION))
+        } else {
+            (FUSE_KERNEL_VERSION, FUSE_KERNEL_MINOR_VERSION)
+        };
+        Self {
+            proto_major,
+            proto_minor,
+            capabilities: config.capabilities,
+            flags: config.capabilities & config.requested,
+            max_write: config.max_write,
+            max_readahead: config.max_readahead,
+            #[cfg(feature = "abi-7-13")]
+            max_background: config.max_background,
+            #[cfg(feature = "abi-7-13")]
+            congestion_threshold: config.congestion_threshold(),
+            #[cfg(feature = "abi-7-23")]
+            time_gran: config.time_gran,
+        }
+    }
+
+    /// Returns the negotiated protocol version as (major, minor)
+    pub fn protocol_version(&self) -> (u32, u32) {
+        (self.proto_major, self.proto_minor)
+    }
+
+    /// Returns the init flags the kernel offered
+    pub fn capabilities(&self) -> u32 {
+        self.capabilities
+    }
+
+    /// Returns the init flags that are enabled for the connection
+    pub fn flags(&self) -> u32 {
+        self.flags
+    }
+
+    /// Returns whether `open` receives `O_TRUNC` and truncates the file itself, see
+    /// [`KernelConfig::set_atomic_o_trunc`]
+    #[cfg(feature = "abi-7-9")]
+    pub fn atomic_o_trunc(&self) -> bool {
+        self.flags & FUSE_ATOMIC_O_TRUNC != 0
+    }
+
+    /// Returns the maximum size of a write request
+    pub fn max_write(&self) -> u32 {
+        self.max_write
+    }
+
+    /// Returns the maximum readahead size
+    pub fn max_readahead(&self) -> u32 {
+        self.max_readahead
+    }
+
+    /// Returns the maximum number of pending background requests
+    #[cfg(feature = "abi-7-13")]
+    pub fn max_background(&self) -> u16 {
+        self.max_background
+    }
+
+    /// Returns the number of pending background requests at which the kernel considers the
+    /// filesystem congested
+    #[cfg(feature = "abi-7-13")]
+    pub fn congestion_threshold(&self) -> u16 {
+        self.congestion_threshold
+    }
+
+    /// Returns the timestamp granularity
+    #[cfg(feature = "abi-7-23")]
+    pub fn time_granularity(&self) -> Duration {
+        self.time_gran
+    }
+
+    /// Returns the granularity timestamps are rounded to, if it is coarser than 1ns
+    pub(crate) fn rounding_granularity(&self) -> Option<Duration> {
+        #[cfg(feature = "abi-7-23")]
+        if self.time_gran > Duration::from_nanos(1) {
+            return Some(self.time_gran);
+        }
+        None
+    }
+}
+
 /// Filesystem trait.
 ///
 /// This trait must be implemented to provide a userspace filesystem via FUSE.
@@ -303,13 +558,86 @@ pub trait Filesystem {
     /// Called on filesystem exit.
     fn destroy(&mut self) {}
 
+    /// Called when the kernel interrupts the request `unique`, which wasn't answered yet,
+    /// because the process waiting for it got a signal. `req` is the interrupt request, from
+    /// the same process. Filesystems that answer requests from other threads can use it to
+    /// cancel the operation and reply with `EINTR`, or check the request's
+    /// [`InterruptToken`] instead. The request must still be answered.
+    fn interrupt(&mut self, _req: &Request<'_>, _unique: u64) {}
+
+    /// Called when the kernel aborted the connection, through the `abort` file of fusectl or a
+    /// forced unmount, instead of the filesystem being unmounted. The kernel already failed the
+    /// requests that weren't answered, and discards their replies. The session then ends with
+    /// [`SessionExit::Aborted`]. Kernels only report aborts if `FUSE_ABORT_ERROR` was
+    /// negotiated (Linux 4.19 and the `abi-7-27` feature); otherwise an abort looks like an
+    /// unmount.
+    fn aborted(&mut self) {}
+
+    /// Returns the error code the default implementation of the given operation replies with.
+    /// Override it to choose, per operation, how the kernel treats operations the filesystem
+    /// doesn't implement. Defaults to ENOSYS for all of them.
+    ///
+    /// * ENOSYS: for `getxattr`, `setxattr`, `listxattr`, `removexattr`, `fallocate`, `lseek`,
+    ///   `copy_file_range`, `flush`, `fsync`, `fsyncdir`, `access`, `create`, `getlk`/`setlk` (with
+    ///   lock support requested), `bmap`, `poll`, `link` and `symlink`, the kernel remembers that
+    ///   the operation isn't implemented and stops sending it. Applications then get EOPNOTSUPP for
+    ///   xattrs and `fallocate`, success for `flush`, `fsync` and `access`, and the kernel's
+    ///   generic behavior for the rest (e.g. `mknod` and `open` instead of `create`). For all other
+    ///   operations, ENOSYS is returned to the application every time.
+    /// * EOPNOTSUPP: the error is returned to the application and the kernel keeps sending the
+    ///   operation, e.g. for filesystems that support xattrs on some inodes only.
+    /// * EPERM: what applications expect from `link` and `symlink` on filesystems that don't
+    ///   support links.
+    fn unimplemented(&self, _op: fuse_opcode) -> c_int {
+        ENOSYS
+    }
+
+    /// Called after a filesystem method panicked while handling a request. The request has
+    /// been failed with EIO. What happens next depends on the session's
+    /// [`PanicPolicy`](crate::PanicPolicy).
+    fn on_panic(&mut self, _req: &Request<'_>, _message: &str) {}
+
+    /// Handle a request whose opcode this crate doesn't know. Only called if the session's
+    /// [`UnknownOpcodePolicy`](crate::UnknownOpcodePolicy) is `Forward`. `payload` holds the
+    /// arguments of the request as the kernel sent them, without the request header, and the
+    /// reply is sent as is, so the filesystem has to encode it the way the kernel expects.
+    fn unknown(&mut self, _req: &Request<'_>, opcode: u32, payload: &[u8], reply: ReplyData) {
+        debug!(
+            "[Not Implemented] unknown(opcode: {}, payload: {} bytes)",
+            opcode,
+            payload.len()
+        );
+        reply.error(ENOSYS);
+    }
+
+    /// Returns whether requests with `opcode` are passed to [`Filesystem::handle_raw`] instead
+    /// of being parsed and dispatched to their method. Asked for every request except `init`,
+    /// `destroy`, `forget`, `batch_forget` and `interrupt`, and for opcodes unknown to this
+    /// crate regardless of the session's [`UnknownOpcodePolicy`](crate::UnknownOpcodePolicy).
+    fn raw_intercept(&self, _opcode: u32) -> bool {
+        false
+    }
+
+    /// Handle a request [`Filesystem::raw_intercept`] took over. `payload` holds the arguments
+    /// of the request as the kernel sent them, without the request header, whose fields are
+    /// available from `req`. The reply is sent as is, so it has to be encoded the way the
+    /// kernel expects for `opcode`. Intercepted requests bypass the session's lock table.
+    fn handle_raw(&mut self, _req: &Request<'_>, opcode: u32, payload: &[u8], reply: ReplyData) {
+        debug!(
+            "[Not Implemented] handle_raw(opcode: {}, payload: {} bytes)",
+            opcode,
+            payload.len()
+        );
+        reply.error(ENOSYS);
+    }
+
     /// Look up a directory entry by name and get its attributes.
     fn lookup(&mut self, _req: &Request<'_>, parent: u64, name: &OsStr, reply: ReplyEntry) {
         warn!(
             "[Not Implemented] lookup(parent: {:#x?}, name {:?})",
             parent, name
         );
-        reply.error(ENOSYS);
+        reply.error(self.unimplemented(fuse_opcode::FUSE_LOOKUP));
     }
 
     /// Forget about an inode.
@@ -336,7 +664,7 @@ pub trait Filesystem {
             "[Not Implemented] getattr(ino: {:#x?}, fh: {:#x?})",
             ino, fh
         );
-        reply.error(ENOSYS);
+        reply.error(self.unimplemented(fuse_opcode::FUSE_GETATTR));
     }
 
     /// Set file attributes.
@@ -363,13 +691,13 @@ pub trait Filesystem {
             gid: {:?}, size: {:?}, fh: {:?}, flags: {:?})",
             ino, mode, uid, gid, size, fh, flags
         );
-        reply.error(ENOSYS);
+        reply.error(self.unimplemented(fuse_opcode::FUSE_SETATTR));
     }
 
     /// Read symbolic link.
     fn readlink(&mut self, _req: &Request<'_>, ino: u64, reply: ReplyData) {
         debug!("[Not Implemented] readlink(ino: {:#x?})", ino);
-        reply.error(ENOSYS);
+        reply.error(self.unimplemented(fuse_opcode::FUSE_READLINK));
     }
 
     /// Create file node.
@@ -389,7 +717,7 @@ pub trait Filesystem {
             umask: {:#x?}, rdev: {})",
             parent, name, mode, umask, rdev
         );
-        reply.error(ENOSYS);
+        reply.error(self.unimplemented(fuse_opcode::FUSE_MKNOD));
     }
 
     /// Create a directory.
@@ -406,7 +734,7 @@ pub trait Filesystem {
             "[Not Implemented] mkdir(parent: {:#x?}, name: {:?}, mode: {}, umask: {:#x?})",
             parent, name, mode, umask
         );
-        reply.error(ENOSYS);
+        reply.error(self.unimplemented(fuse_opcode::FUSE_MKDIR));
     }
 
     /// Remove a file.
@@ -415,7 +743,7 @@ pub trait Filesystem {
             "[Not Implemented] unlink(parent: {:#x?}, name: {:?})",
             parent, name,
         );
-        reply.error(ENOSYS);
+        reply.error(self.unimplemented(fuse_opcode::FUSE_UNLINK));
     }
 
     /// Remove a directory.
@@ -424,7 +752,7 @@ pub trait Filesystem {
             "[Not Implemented] rmdir(parent: {:#x?}, name: {:?})",
             parent, name,
         );
-        reply.error(ENOSYS);
+        reply.error(self.unimplemented(fuse_opcode::FUSE_RMDIR));
     }
 
     /// Create a symbolic link.
@@ -440,7 +768,7 @@ pub trait Filesystem {
             "[Not Implemented] symlink(parent: {:#x?}, link_name: {:?}, target: {:?})",
             parent, link_name, target,
         );
-        reply.error(EPERM);
+        reply.error(self.unimplemented(fuse_opcode::FUSE_SYMLINK));
     }
 
     /// Rename a file.
@@ -459,7 +787,7 @@ pub trait Filesystem {
             newname: {:?}, flags: {})",
             parent, name, newparent, newname, flags,
         );
-        reply.error(ENOSYS);
+        reply.error(self.unimplemented(fuse_opcode::FUSE_RENAME));
     }
 
     /// Create a hard link.
@@ -475,17 +803,18 @@ pub trait Filesystem {
             "[Not Implemented] link(ino: {:#x?}, newparent: {:#x?}, newname: {:?})",
             ino, newparent, newname
         );
-        reply.error(EPERM);
+        reply.error(self.unimplemented(fuse_opcode::FUSE_LINK));
     }
 
     /// Open a file.
     /// Open flags (with the exception of O_CREAT, O_EXCL, O_NOCTTY and O_TRUNC) are
-    /// available in flags. Filesystem may store an arbitrary file handle (pointer, index,
-    /// etc) in fh, and use this in other all other file operations (read, write, flush,
-    /// release, fsync). Filesystem may also implement stateless file I/O and not store
-    /// anything in fh. There are also some flags (direct_io, keep_cache) which the
-    /// filesystem may set, to change the way the file is opened. See fuse_file_info
-    /// structure in <fuse_common.h> for more details.
+    /// available in flags. O_TRUNC is passed, and must be handled by truncating the file, if
+    /// it was enabled with `KernelConfig::set_atomic_o_trunc`. Filesystem may store an
+    /// arbitrary file handle (pointer, index, etc) in fh, and use this in other all other
+    /// file operations (read, write, flush, release, fsync). Filesystem may also implement
+    /// stateless file I/O and not store anything in fh. There are also some flags (direct_io,
+    /// keep_cache) which the filesystem may set, to change the way the file is opened. See
+    /// fuse_file_info structure in <fuse_common.h> for more details.
     fn open(&mut self, _req: &Request<'_>, _ino: u64, _flags: i32, reply: ReplyOpen) {
         reply.opened(0, 0);
     }
@@ -516,7 +845,7 @@ pub trait Filesystem {
             flags: {:#x?}, lock_owner: {:?})",
             ino, fh, offset, size, flags, lock_owner
         );
-        reply.error(ENOSYS);
+        reply.error(self.unimplemented(fuse_opcode::FUSE_READ));
     }
 
     /// Write data.
@@ -554,7 +883,38 @@ pub trait Filesystem {
             flags,
             lock_owner
         );
-        reply.error(ENOSYS);
+        reply.error(self.unimplemented(fuse_opcode::FUSE_WRITE));
+    }
+
+    /// Write data, read as a stream.
+    /// This is what the session calls for write requests. The default implementation passes
+    /// the data as a single slice to [`write`](Filesystem::write); filesystems that forward
+    /// large writes in chunks can implement this instead, and read the data in place with
+    /// [`WriteData`] (see [`Request::body_size`] for the size of the whole request). The
+    /// reply is the number of bytes written, as for `write`.
+    fn write_stream(
+        &mut self,
+        req: &Request<'_>,
+        ino: u64,
+        fh: u64,
+        offset: i64,
+        data: WriteData<'_>,
+        write_flags: u32,
+        flags: i32,
+        lock_owner: Option<u64>,
+        reply: ReplyWrite,
+    ) {
+        self.write(
+            req,
+            ino,
+            fh,
+            offset,
+            data.unread(),
+            write_flags,
+            flags,
+            lock_owner,
+            reply,
+        );
     }
 
     /// Flush method.
@@ -567,12 +927,14 @@ pub trait Filesystem {
     /// is not forced to flush pending writes. One reason to flush data, is if the
     /// filesystem wants to return write errors. If the filesystem supports file locking
     /// operations (setlk, getlk) it should remove all locks belonging to 'lock_owner'.
+    /// Replying to open with `FOPEN_NOFLUSH` keeps newer kernels from sending flush for the
+    /// file, see [`Session::set_noop_flush`] for filesystems whose flush does nothing.
     fn flush(&mut self, _req: &Request<'_>, ino: u64, fh: u64, lock_owner: u64, reply: ReplyEmpty) {
         debug!(
             "[Not Implemented] flush(ino: {:#x?}, fh: {}, lock_owner: {:?})",
             ino, fh, lock_owner
         );
-        reply.error(ENOSYS);
+        reply.error(self.unimplemented(fuse_opcode::FUSE_FLUSH));
     }
 
     /// Release an open file.
@@ -604,7 +966,7 @@ pub trait Filesystem {
             "[Not Implemented] fsync(ino: {:#x?}, fh: {}, datasync: {})",
             ino, fh, datasync
         );
-        reply.error(ENOSYS);
+        reply.error(self.unimplemented(fuse_opcode::FUSE_FSYNC));
     }
 
     /// Open a directory.
@@ -623,19 +985,28 @@ pub trait Filesystem {
     /// requested size. Send an empty buffer on end of stream. fh will contain the
     /// value set by the opendir method, or will be undefined if the opendir method
     /// didn't set any value.
+    /// If not implemented, readdirplus is called and the attributes of its entries are dropped.
     fn readdir(
         &mut self,
-        _req: &Request<'_>,
+        req: &Request<'_>,
         ino: u64,
         fh: u64,
         offset: i64,
         reply: ReplyDirectory,
     ) {
-        warn!(
-            "[Not Implemented] readdir(ino: {:#x?}, fh: {}, offset: {})",
-            ino, fh, offset
-        );
-        reply.error(ENOSYS);
+        if reply.answers_readdirplus() {
+            // Called by the default readdirplus, so neither is implemented
+            debug!(
+                "[Not Implemented] readdirplus(ino: {:#x?}, fh: {}, offset: {})",
+                ino, fh, offset
+            );
+            #[cfg(feature = "abi-7-21")]
+            reply.error(self.unimplemented(fuse_opcode::FUSE_READDIRPLUS));
+            #[cfg(not(feature = "abi-7-21"))]
+            reply.error(ENOSYS);
+        } else {
+            self.readdirplus(req, ino, fh, offset, reply.into_plus());
+        }
     }
 
     /// Read directory.
@@ -643,19 +1014,27 @@ pub trait Filesystem {
     /// requested size. Send an empty buffer on end of stream. fh will contain the
     /// value set by the opendir method, or will be undefined if the opendir method
     /// didn't set any value.
+    /// If not implemented, readdir is called and its entries are sent without attributes.
+    /// Implementations are also called for plain readdir requests if readdir isn't
+    /// implemented. The kernel doesn't look up the entries listed for those.
     fn readdirplus(
         &mut self,
-        _req: &Request<'_>,
+        req: &Request<'_>,
         ino: u64,
         fh: u64,
         offset: i64,
         reply: ReplyDirectoryPlus,
     ) {
-        debug!(
-            "[Not Implemented] readdirplus(ino: {:#x?}, fh: {}, offset: {})",
-            ino, fh, offset
-        );
-        reply.error(ENOSYS);
+        if reply.answers_readdirplus() {
+            self.readdir(req, ino, fh, offset, reply.into_plain());
+        } else {
+            // Called by the default readdir, so neither is implemented
+            warn!(
+                "[Not Implemented] readdir(ino: {:#x?}, fh: {}, offset: {})",
+                ino, fh, offset
+            );
+            reply.error(self.unimplemented(fuse_opcode::FUSE_READDIR));
+        }
     }
 
     /// Release an open directory.
@@ -689,7 +1068,7 @@ pub trait Filesystem {
             "[Not Implemented] fsyncdir(ino: {:#x?}, fh: {}, datasync: {})",
             ino, fh, datasync
         );
-        reply.error(ENOSYS);
+        reply.error(self.unimplemented(fuse_opcode::FUSE_FSYNCDIR));
     }
 
     /// Get file system statistics.
@@ -712,7 +1091,7 @@ pub trait Filesystem {
             "[Not Implemented] setxattr(ino: {:#x?}, name: {:?}, flags: {:#x?}, position: {})",
             ino, name, flags, position
         );
-        reply.error(ENOSYS);
+        reply.error(self.unimplemented(fuse_opcode::FUSE_SETXATTR));
     }
 
     /// Get an extended attribute.
@@ -731,7 +1110,7 @@ pub trait Filesystem {
             "[Not Implemented] getxattr(ino: {:#x?}, name: {:?}, size: {})",
             ino, name, size
         );
-        reply.error(ENOSYS);
+        reply.error(self.unimplemented(fuse_opcode::FUSE_GETXATTR));
     }
 
     /// List extended attribute names.
@@ -743,7 +1122,7 @@ pub trait Filesystem {
             "[Not Implemented] listxattr(ino: {:#x?}, size: {})",
             ino, size
         );
-        reply.error(ENOSYS);
+        reply.error(self.unimplemented(fuse_opcode::FUSE_LISTXATTR));
     }
 
     /// Remove an extended attribute.
@@ -752,7 +1131,7 @@ pub trait Filesystem {
             "[Not Implemented] removexattr(ino: {:#x?}, name: {:?})",
             ino, name
         );
-        reply.error(ENOSYS);
+        reply.error(self.unimplemented(fuse_opcode::FUSE_REMOVEXATTR));
     }
 
     /// Check file access permissions.
@@ -761,7 +1140,7 @@ pub trait Filesystem {
     /// under Linux kernel versions 2.4.x
     fn access(&mut self, _req: &Request<'_>, ino: u64, mask: i32, reply: ReplyEmpty) {
         debug!("[Not Implemented] access(ino: {:#x?}, mask: {})", ino, mask);
-        reply.error(ENOSYS);
+        reply.error(self.unimplemented(fuse_opcode::FUSE_ACCESS));
     }
 
     /// Create and open a file.
@@ -789,7 +1168,7 @@ pub trait Filesystem {
             flags: {:#x?})",
             parent, name, mode, umask, flags
         );
-        reply.error(ENOSYS);
+        reply.error(self.unimplemented(fuse_opcode::FUSE_CREATE));
     }
 
     /// Test for a POSIX file lock.
@@ -810,7 +1189,7 @@ pub trait Filesystem {
             end: {}, typ: {}, pid: {})",
             ino, fh, lock_owner, start, end, typ, pid
         );
-        reply.error(ENOSYS);
+        reply.error(self.unimplemented(fuse_opcode::FUSE_GETLK));
     }
 
     /// Acquire, modify or release a POSIX file lock.
@@ -838,7 +1217,7 @@ pub trait Filesystem {
             end: {}, typ: {}, pid: {}, sleep: {})",
             ino, fh, lock_owner, start, end, typ, pid, sleep
         );
-        reply.error(ENOSYS);
+        reply.error(self.unimplemented(fuse_opcode::FUSE_SETLK));
     }
 
     /// Map block index within file to block index within device.
@@ -849,7 +1228,7 @@ pub trait Filesystem {
             "[Not Implemented] bmap(ino: {:#x?}, blocksize: {}, idx: {})",
             ino, blocksize, idx,
         );
-        reply.error(ENOSYS);
+        reply.error(self.unimplemented(fuse_opcode::FUSE_BMAP));
     }
 
     /// control device
@@ -874,10 +1253,15 @@ pub trait Filesystem {
             in_data.len(),
             out_size,
         );
+        #[cfg(feature = "abi-7-11")]
+        reply.error(self.unimplemented(fuse_opcode::FUSE_IOCTL));
+        #[cfg(not(feature = "abi-7-11"))]
         reply.error(ENOSYS);
     }
 
-    /// Poll for events
+    /// Poll for events.
+    /// If not implemented, replies with the events of [`Filesystem::default_poll_events`] if
+    /// it returns any, and fails with the error of [`Filesystem::unimplemented`] otherwise.
     #[cfg(feature = "abi-7-11")]
     fn poll(
         &mut self,
@@ -885,15 +1269,32 @@ pub trait Filesystem {
         ino: u64,
         fh: u64,
         ph: PollHandle,
-        events: u32,
+        events: PollEvents,
         flags: u32,
         reply: ReplyPoll,
     ) {
         debug!(
-            "[Not Implemented] poll(ino: {:#x?}, fh: {}, ph: {:?}, events: {}, flags: {})",
+            "[Not Implemented] poll(ino: {:#x?}, fh: {}, ph: {:?}, events: {:?}, flags: {})",
             ino, fh, ph, events, flags
         );
-        reply.error(ENOSYS);
+        match self.default_poll_events() {
+            Some(revents) => reply.poll(revents),
+            None => reply.error(self.unimplemented(fuse_opcode::FUSE_POLL)),
+        }
+    }
+
+    /// Returns the events the default implementation of `poll` reports as ready. Defaults to
+    /// `None`, which fails `poll` with the error of [`Filesystem::unimplemented`]: with ENOSYS
+    /// the kernel stops sending `poll` and reports `DEFAULT_POLLMASK` itself, with other
+    /// errors applications see POLLERR.
+    ///
+    /// Filesystems that fail unimplemented requests with another error can return
+    /// [`PollEvents::DEFAULT`], so `poll` and `select` on files behave like on a regular
+    /// filesystem, whose files are always ready. Filesystems with files that aren't always
+    /// ready, like character devices, should implement `poll` instead.
+    #[cfg(feature = "abi-7-11")]
+    fn default_poll_events(&self) -> Option<PollEvents> {
+        None
     }
 
     /// Preallocate or deallocate space to a file
@@ -912,6 +1313,9 @@ pub trait Filesystem {
             length: {}, mode: {})",
             ino, fh, offset, length, mode
         );
+        #[cfg(feature = "abi-7-19")]
+        reply.error(self.unimplemented(fuse_opcode::FUSE_FALLOCATE));
+        #[cfg(not(feature = "abi-7-19"))]
         reply.error(ENOSYS);
     }
 
@@ -929,10 +1333,16 @@ pub trait Filesystem {
             "[Not Implemented] lseek(ino: {:#x?}, fh: {}, offset: {}, whence: {})",
             ino, fh, offset, whence
         );
+        #[cfg(feature = "abi-7-24")]
+        reply.error(self.unimplemented(fuse_opcode::FUSE_LSEEK));
+        #[cfg(not(feature = "abi-7-24"))]
         reply.error(ENOSYS);
     }
 
-    /// Copy the specified range from the source inode to the destination inode
+    /// Copy the specified range from the source inode to the destination inode. See
+    /// [`CopyFileRangeFlags`] for the flags, and for how to let the kernel fall back to
+    /// copying through `read` and `write`. Requests with unknown flags, negative offsets or a
+    /// range that overlaps itself are rejected with `EINVAL` without calling this method.
     fn copy_file_range(
         &mut self,
         _req: &Request<'_>,
@@ -952,6 +1362,9 @@ pub trait Filesystem {
             len: {}, flags: {})",
             ino_in, fh_in, offset_in, ino_out, fh_out, offset_out, len, flags
         );
+        #[cfg(feature = "abi-7-28")]
+        reply.error(self.unimplemented(fuse_opcode::FUSE_COPY_FILE_RANGE));
+        #[cfg(not(feature = "abi-7-28"))]
         reply.error(ENOSYS);
     }
 
@@ -960,7 +1373,7 @@ pub trait Filesystem {
     #[cfg(target_os = "macos")]
     fn setvolname(&mut self, _req: &Request<'_>, name: &OsStr, reply: ReplyEmpty) {
         debug!("[Not Implemented] setvolname(name: {:?})", name);
-        reply.error(ENOSYS);
+        reply.error(self.unimplemented(fuse_opcode::FUSE_SETVOLNAME));
     }
 
     /// macOS only (undocumented)
@@ -980,7 +1393,7 @@ pub trait Filesystem {
             newname: {:?}, options: {})",
             parent, name, newparent, newname, options
         );
-        reply.error(ENOSYS);
+        reply.error(self.unimplemented(fuse_opcode::FUSE_EXCHANGE));
     }
 
     /// macOS only: Query extended times (bkuptime and crtime). Set fuse_init_out.flags
@@ -988,7 +1401,7 @@ pub trait Filesystem {
     #[cfg(target_os = "macos")]
     fn getxtimes(&mut self, _req: &Request<'_>, ino: u64, reply: ReplyXTimes) {
         debug!("[Not Implemented] getxtimes(ino: {:#x?})", ino);
-        reply.error(ENOSYS);
+        reply.error(self.unimplemented(fuse_opcode::FUSE_GETXTIMES));
     }
 }
 
@@ -1017,7 +1430,7 @@ pub fn mount2<FS: Filesystem, P: AsRef<Path>>(
     options: &[MountOption],
 ) -> io::Result<()> {
     check_option_conflicts(options)?;
-    Session::new(filesystem, mountpoint.as_ref(), options).and_then(|mut se| se.run())
+    Session::new(filesystem, mountpoint.as_ref(), options).and_then(|mut se| se.run().into_result())
 }
 
 /// Mount the given filesystem to the given mountpoint. This function spawns
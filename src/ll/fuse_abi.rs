@@ -176,6 +176,9 @@ pub mod consts {
     #[cfg(feature = "abi-7-31")]
     pub const FOPEN_STREAM: u32 = 1 << 4; // the file is stream-like (no file position at all)
 
+    // don't flush on close (protocol 7.35, but older kernels ignore unknown open flags)
+    pub const FOPEN_NOFLUSH: u32 = 1 << 5;
+
     #[cfg(target_os = "macos")]
     pub const FOPEN_PURGE_ATTR: u32 = 1 << 30;
     #[cfg(target_os = "macos")]
@@ -294,6 +297,11 @@ pub mod consts {
     #[cfg(feature = "abi-7-9")]
     pub const FUSE_POLL_SCHEDULE_NOTIFY: u32 = 1 << 0; // request poll notify
 
+    // Events the kernel reports for files of filesystems that don't implement poll
+    #[cfg(feature = "abi-7-11")]
+    pub const DEFAULT_POLLMASK: u32 =
+        (libc::POLLIN | libc::POLLOUT | libc::POLLRDNORM | libc::POLLWRNORM) as u32;
+
     // fsync flags
     pub const FUSE_FSYNC_FDATASYNC: u32 = 1 << 0; // Sync data only, not metadata
 
@@ -306,7 +314,7 @@ pub mod consts {
 pub struct InvalidOpcodeError;
 
 #[repr(C)]
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 #[allow(non_camel_case_types)]
 pub enum fuse_opcode {
     FUSE_LOOKUP = 1,
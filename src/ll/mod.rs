@@ -10,7 +10,10 @@ mod request;
 use std::{convert::TryInto, num::NonZeroI32, time::SystemTime};
 
 pub use reply::Response;
-pub use request::{AnyRequest, FileHandle, INodeNo, Lock, Operation, Request, RequestId, Version};
+pub use request::{
+    AnyRequest, FileHandle, FilenameInDir, INodeNo, Lock, Operation, Request, RequestError,
+    RequestId, Version,
+};
 
 #[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
 /// Possible input arguments for atime & mtime, which can either be set to a specified time,
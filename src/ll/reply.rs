@@ -1,5 +1,6 @@
 use std::{
     convert::TryInto,
+    ffi::OsStr,
     io::IoSlice,
     mem::size_of,
     os::unix::prelude::OsStrExt,
@@ -22,6 +23,7 @@ pub enum Response<'a> {
     Error(i32),
     Data(ResponseBuf),
     Slice(&'a [u8]),
+    Vectored(Vec<IoSlice<'a>>),
 }
 
 impl<'a> Response<'a> {
@@ -34,6 +36,7 @@ impl<'a> Response<'a> {
             Response::Error(_) => 0,
             Response::Data(v) => v.len(),
             Response::Slice(d) => d.len(),
+            Response::Vectored(d) => d.iter().map(|b| b.len()).sum(),
         };
         let header = abi::fuse_out_header {
             unique: unique.0,
@@ -51,6 +54,7 @@ impl<'a> Response<'a> {
             Response::Error(_) => {}
             Response::Data(d) => v.push(IoSlice::new(d)),
             Response::Slice(d) => v.push(IoSlice::new(d)),
+            Response::Vectored(d) => v.extend(d.iter().map(|b| IoSlice::new(b))),
         }
         f(&v)
     }
@@ -76,6 +80,10 @@ impl<'a> Response<'a> {
         Self::Slice(data)
     }
 
+    pub(crate) fn new_vectored(data: Vec<IoSlice<'a>>) -> Self {
+        Self::Vectored(data)
+    }
+
     pub(crate) fn new_entry(
         ino: INodeNo,
         generation: Generation,
@@ -350,6 +358,18 @@ impl EntListBuf {
         }
     }
 
+    /// Make room for `bytes` of entries in total, at most the maximum size
+    fn reserve(&mut self, bytes: usize) {
+        let additional = bytes.min(self.max_size).saturating_sub(self.buf.len());
+        self.buf.reserve(additional);
+    }
+
+    /// Whether an entry of `entlen` bytes (before padding) fits in the buffer
+    fn fits(&self, entlen: usize) -> bool {
+        let entsize = (entlen + size_of::<u64>() - 1) & !(size_of::<u64>() - 1);
+        self.buf.len() + entsize <= self.max_size
+    }
+
     /// Add an entry to the directory reply buffer. Returns true if the buffer is full.
     /// A transparent offset value can be provided for each entry. The kernel uses these
     /// value to request the next entries in further readdir calls
@@ -357,7 +377,7 @@ impl EntListBuf {
     fn push(&mut self, ent: [&[u8]; 2]) -> bool {
         let entlen = ent[0].len() + ent[1].len();
         let entsize = (entlen + size_of::<u64>() - 1) & !(size_of::<u64>() - 1); // 64bit align
-        if self.buf.len() + entsize > self.max_size {
+        if !self.fits(entlen) {
             return true;
         }
         self.buf.extend_from_slice(ent[0]);
@@ -368,8 +388,30 @@ impl EntListBuf {
     }
 }
 
+/// Offset of a directory entry, which the kernel passes back to `readdir` to continue the
+/// listing after that entry. Offset 0 means "start of the directory", so an entry can never
+/// have it: the kernel would list the directory from the start again, forever.
 #[derive(Debug, PartialEq, Eq, Clone, Copy, PartialOrd, Ord)]
-pub struct DirEntOffset(pub i64);
+pub struct DirEntOffset(pub(crate) i64);
+
+impl DirEntOffset {
+    /// Create an offset. Returns `None` for 0, which is not a valid entry offset.
+    pub fn new(offset: i64) -> Option<DirEntOffset> {
+        (offset != 0).then_some(DirEntOffset(offset))
+    }
+
+    /// The offset of the entry at `index` in a listing that is resumed by index, that is the
+    /// index of the entry after it.
+    pub fn after_index(index: usize) -> DirEntOffset {
+        DirEntOffset(index as i64 + 1)
+    }
+
+    /// Returns the offset as the raw value passed to and from the kernel
+    pub fn get(self) -> i64 {
+        self.0
+    }
+}
+
 impl From<DirEntOffset> for i64 {
     fn from(x: DirEntOffset) -> Self {
         x.0
@@ -409,6 +451,16 @@ impl DirEntList {
     pub(crate) fn new(max_size: usize) -> Self {
         Self(EntListBuf::new(max_size))
     }
+
+    /// Returns the size the listing may grow to
+    pub(crate) fn max_size(&self) -> usize {
+        self.0.max_size
+    }
+
+    /// Make room for `bytes` of entries up front, see [`EntListBuf::reserve`]
+    pub(crate) fn reserve(&mut self, bytes: usize) {
+        self.0.reserve(bytes);
+    }
     /// Add an entry to the directory reply buffer. Returns true if the buffer is full.
     /// A transparent offset value can be provided for each entry. The kernel uses these
     /// value to request the next entries in further readdir calls
@@ -423,6 +475,18 @@ impl DirEntList {
         };
         self.0.push([header.as_bytes(), name])
     }
+
+    /// Whether an entry with the given name fits in the buffer
+    pub(crate) fn fits(&self, name: &OsStr) -> bool {
+        self.0.fits(size_of::<abi::fuse_dirent>() + name.len())
+    }
+
+    /// Add an entry of a listing with attributes, dropping the attributes.
+    #[must_use]
+    pub fn push_plus<T: AsRef<Path>>(&mut self, x: &DirEntryPlus<T>) -> bool {
+        let name = x.name.as_ref().as_os_str().as_bytes();
+        self.0.push([x.dirent(name).as_bytes(), name])
+    }
 }
 
 #[derive(Debug)]
@@ -457,6 +521,15 @@ impl<T: AsRef<Path>> DirEntryPlus<T> {
             attr_valid,
         }
     }
+
+    fn dirent(&self, name: &[u8]) -> abi::fuse_dirent {
+        abi::fuse_dirent {
+            ino: self.attr.attr.ino,
+            off: self.offset.into(),
+            namelen: name.len().try_into().expect("Name too long"),
+            typ: self.attr.attr.mode >> 12,
+        }
+    }
 }
 
 /// Used to respond to [ReadDir] requests.
@@ -473,6 +546,16 @@ impl DirEntPlusList {
     pub(crate) fn new(max_size: usize) -> Self {
         Self(EntListBuf::new(max_size))
     }
+
+    /// Returns the size the listing may grow to
+    pub(crate) fn max_size(&self) -> usize {
+        self.0.max_size
+    }
+
+    /// Make room for `bytes` of entries up front, see [`EntListBuf::reserve`]
+    pub(crate) fn reserve(&mut self, bytes: usize) {
+        self.0.reserve(bytes);
+    }
     /// Add an entry to the directory reply buffer. Returns true if the buffer is full.
     /// A transparent offset value can be provided for each entry. The kernel uses these
     /// value to request the next entries in further readdir calls
@@ -489,15 +572,31 @@ impl DirEntPlusList {
                 attr_valid_nsec: x.attr_valid.subsec_nanos(),
                 attr: x.attr.attr,
             },
-            dirent: abi::fuse_dirent {
-                ino: x.attr.attr.ino,
-                off: x.offset.into(),
-                namelen: name.len().try_into().expect("Name too long"),
-                typ: x.attr.attr.mode >> 12,
-            },
+            dirent: x.dirent(name),
         };
         self.0.push([header.as_bytes(), name])
     }
+
+    /// Whether an entry with the given name fits in the buffer
+    pub(crate) fn fits(&self, name: &OsStr) -> bool {
+        self.0.fits(size_of::<abi::fuse_direntplus>() + name.len())
+    }
+
+    /// Add an entry without attributes. The kernel lists it, but doesn't look it up (the node
+    /// ID of the entry is zero).
+    #[must_use]
+    pub fn push_plain<T: AsRef<Path>>(&mut self, ent: &DirEntry<T>) -> bool {
+        let name = ent.name.as_ref().as_os_str().as_bytes();
+        let dirent = abi::fuse_dirent {
+            ino: ent.ino.into(),
+            off: ent.offset.0,
+            namelen: name.len().try_into().expect("Name too long"),
+            typ: mode_from_kind_and_perm(ent.kind, 0) >> 12,
+        };
+        let mut header = [0u8; size_of::<abi::fuse_direntplus>()];
+        header[size_of::<abi::fuse_entry_out>()..].copy_from_slice(dirent.as_bytes());
+        self.0.push([&header, name])
+    }
 }
 
 #[cfg(test)]
@@ -605,6 +704,25 @@ mod test {
         );
     }
 
+    #[test]
+    #[allow(clippy::unnecessary_cast)]
+    fn special_file_modes() {
+        for (kind, fmt) in [
+            (FileType::NamedPipe, libc::S_IFIFO),
+            (FileType::CharDevice, libc::S_IFCHR),
+            (FileType::BlockDevice, libc::S_IFBLK),
+            (FileType::Directory, libc::S_IFDIR),
+            (FileType::RegularFile, libc::S_IFREG),
+            (FileType::Symlink, libc::S_IFLNK),
+            (FileType::Socket, libc::S_IFSOCK),
+        ] {
+            let mode = mode_from_kind_and_perm(kind, 0o640);
+            assert_eq!(mode, fmt as u32 | 0o640);
+            assert_eq!(FileType::from_mode(mode), Some(kind));
+        }
+        assert_eq!(FileType::from_mode(0o644), None);
+    }
+
     #[test]
     fn reply_attr() {
         let mut expected = if cfg!(target_os = "macos") {
@@ -879,4 +997,12 @@ mod test {
             expected
         );
     }
+    #[test]
+    fn reserve_directory() {
+        let mut buf = DirEntList::new(1 << 16);
+        assert!(buf.0.buf.capacity() < 1 << 16);
+        buf.reserve(1 << 20);
+        assert!(buf.0.buf.capacity() >= 1 << 16);
+        assert_eq!(buf.max_size(), 1 << 16);
+    }
 }
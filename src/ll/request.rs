@@ -266,6 +266,11 @@ macro_rules! impl_request {
 
 mod op {
     use crate::ll::Response;
+    #[cfg(feature = "abi-7-28")]
+    use crate::CopyFileRangeFlags;
+    use crate::GetattrFh;
+    #[cfg(feature = "abi-7-11")]
+    use crate::PollEvents;
 
     use super::{
         super::{argument::ArgumentIterator, TimeOrNow},
@@ -335,14 +340,17 @@ mod op {
     }
     impl_request!(GetAttr<'_>);
 
-    #[cfg(feature = "abi-7-9")]
     impl<'a> GetAttr<'a> {
-        pub fn file_handle(&self) -> Option<FileHandle> {
+        /// Whether the request is for an open file, and which
+        pub fn fh(&self) -> GetattrFh {
+            #[cfg(feature = "abi-7-9")]
             if self.arg.getattr_flags & crate::FUSE_GETATTR_FH != 0 {
-                Some(FileHandle(self.arg.fh))
+                GetattrFh::Handle(self.arg.fh)
             } else {
-                None
+                GetattrFh::Inode
             }
+            #[cfg(not(feature = "abi-7-9"))]
+            GetattrFh::Unknown
         }
     }
 
@@ -1359,11 +1367,11 @@ mod op {
         }
 
         /// The requested poll events
-        pub fn events(&self) -> u32 {
+        pub fn events(&self) -> PollEvents {
             #[cfg(feature = "abi-7-21")]
-            return self.arg.events;
+            return PollEvents::from_bits_retain(self.arg.events);
             #[cfg(not(feature = "abi-7-21"))]
-            return 0;
+            return PollEvents::empty();
         }
 
         /// The poll request's flags
@@ -1560,9 +1568,8 @@ mod op {
         pub fn len(&self) -> u64 {
             self.arg.len
         }
-        // API TODO: Return a specific flags type
-        pub fn flags(&self) -> u64 {
-            self.arg.flags
+        pub fn flags(&self) -> CopyFileRangeFlags {
+            CopyFileRangeFlags(self.arg.flags)
         }
     }
 
@@ -2164,6 +2171,11 @@ pub struct AnyRequest<'a> {
 impl_request!(AnyRequest<'_>);
 
 impl<'a> AnyRequest<'a> {
+    /// Returns the raw opcode of the request
+    pub fn opcode(&self) -> u32 {
+        self.header.opcode
+    }
+
     pub fn operation(&self) -> Result<Operation<'a>, RequestError> {
         // Parse/check opcode
         let opcode = fuse_opcode::try_from(self.header.opcode)
@@ -2218,6 +2230,7 @@ impl<'a> TryFrom<&'a [u8]> for AnyRequest<'a> {
 mod tests {
     use super::super::test::AlignedData;
     use super::*;
+    use crate::GetattrFh;
     use std::ffi::OsStr;
 
     #[cfg(target_endian = "big")]
@@ -2276,6 +2289,53 @@ mod tests {
         0x66, 0x6f, 0x6f, 0x2e, 0x74, 0x78, 0x74, 0x00, // name
     ]);
 
+    /// A getattr request, with the fuse_getattr_in argument of ABI 7.9 if `flags` is given
+    fn getattr_request(flags: Option<u32>, fh: u64) -> AlignedData<[u8; 56]> {
+        let len: u32 = if flags.is_some() { 56 } else { 40 };
+        let mut data = [0; 56];
+        data[0..4].copy_from_slice(&len.to_ne_bytes());
+        data[4..8].copy_from_slice(&(fuse_opcode::FUSE_GETATTR as u32).to_ne_bytes());
+        data[8..16].copy_from_slice(&1u64.to_ne_bytes());
+        data[16..24].copy_from_slice(&2u64.to_ne_bytes());
+        if let Some(flags) = flags {
+            data[40..44].copy_from_slice(&flags.to_ne_bytes());
+            data[48..56].copy_from_slice(&fh.to_ne_bytes());
+        }
+        AlignedData(data)
+    }
+
+    fn getattr_fh(data: &[u8]) -> GetattrFh {
+        match AnyRequest::try_from(data).unwrap().operation().unwrap() {
+            Operation::GetAttr(x) => x.fh(),
+            _ => panic!("Unexpected request operation"),
+        }
+    }
+
+    #[cfg(feature = "abi-7-9")]
+    #[test]
+    fn getattr_fh_flag() {
+        use crate::FUSE_GETATTR_FH;
+
+        // A handle of 0 is still a handle if the flag is set
+        let req = getattr_request(Some(FUSE_GETATTR_FH), 0);
+        assert_eq!(getattr_fh(&req[..]), GetattrFh::Handle(0));
+        assert_eq!(getattr_fh(&req[..]).handle(), Some(0));
+        let req = getattr_request(Some(FUSE_GETATTR_FH), 7);
+        assert_eq!(getattr_fh(&req[..]), GetattrFh::Handle(7));
+        // Without the flag, the handle is ignored
+        let req = getattr_request(Some(0), 7);
+        assert_eq!(getattr_fh(&req[..]), GetattrFh::Inode);
+        assert_eq!(getattr_fh(&req[..]).handle(), None);
+    }
+
+    #[cfg(not(feature = "abi-7-9"))]
+    #[test]
+    fn getattr_fh_unknown() {
+        let req = getattr_request(None, 0);
+        assert_eq!(getattr_fh(&req[..40]), GetattrFh::Unknown);
+        assert_eq!(getattr_fh(&req[..40]).handle(), None);
+    }
+
     #[test]
     fn short_read_header() {
         match AnyRequest::try_from(&INIT_REQUEST[..20]) {
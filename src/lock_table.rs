@@ -0,0 +1,295 @@
+//! POSIX record locks managed by the session
+//!
+//! Filesystems that implement `getlk` and `setlk` have to drop all locks of a lock owner when
+//! it flushes or releases the file, because that is when a process closing the file loses its
+//! locks. Forgetting this leaves locks behind that nobody can remove. A [`LockTable`] installed
+//! on the session (see [`Session::set_lock_table`](crate::Session::set_lock_table)) keeps the
+//! locks instead: the session answers `getlk` and `setlk` from it, queues blocking lock
+//! requests until they can be granted, and drops the locks of an owner on `flush` and `release`
+//! before the filesystem sees them.
+
+use libc::{c_int, EAGAIN, EINTR, EINVAL, F_RDLCK, F_UNLCK, F_WRLCK};
+use std::collections::HashMap;
+
+use crate::reply::ReplyEmpty;
+
+/// A lock on a byte range of a file
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PosixLock {
+    /// The owner of the lock, see `lock_owner` of [`Filesystem::setlk`](crate::Filesystem::setlk)
+    pub owner: u64,
+    /// First byte of the range
+    pub start: u64,
+    /// Last byte of the range (inclusive)
+    pub end: u64,
+    /// `F_RDLCK` or `F_WRLCK`, or `F_UNLCK` to unlock the range
+    pub typ: i32,
+    /// Pid of the process that took the lock
+    pub pid: u32,
+}
+
+impl PosixLock {
+    fn overlaps(&self, other: &PosixLock) -> bool {
+        self.start <= other.end && other.start <= self.end
+    }
+
+    fn conflicts(&self, other: &PosixLock) -> bool {
+        self.owner != other.owner
+            && self.overlaps(other)
+            && (self.typ == F_WRLCK || other.typ == F_WRLCK)
+    }
+}
+
+/// The POSIX locks held on each inode
+///
+/// Locks of the same owner never overlap: locking a range replaces the owner's locks in it, and
+/// unlocking a part of a lock splits it. Locks of different owners may overlap if all of them
+/// are read locks.
+#[derive(Debug, Default)]
+pub struct LockTable {
+    locks: HashMap<u64, Vec<PosixLock>>,
+}
+
+impl LockTable {
+    /// Create an empty table.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns a lock held on `ino` that prevents `lock` from being taken, if any.
+    pub fn test(&self, ino: u64, lock: &PosixLock) -> Option<PosixLock> {
+        if lock.typ == F_UNLCK {
+            return None;
+        }
+        self.locks(ino).iter().find(|l| l.conflicts(lock)).copied()
+    }
+
+    /// Take, change or (with `F_UNLCK`) remove a lock on `ino`. Fails with `EAGAIN` if another
+    /// owner holds a conflicting lock.
+    pub fn set(&mut self, ino: u64, lock: PosixLock) -> Result<(), c_int> {
+        if ![F_RDLCK, F_WRLCK, F_UNLCK].contains(&lock.typ) || lock.start > lock.end {
+            return Err(EINVAL);
+        }
+        if self.test(ino, &lock).is_some() {
+            return Err(EAGAIN);
+        }
+        let locks = self.locks.entry(ino).or_default();
+        // Carve the range out of the owner's locks, then add the new one
+        let mut kept = Vec::with_capacity(locks.len() + 1);
+        for l in locks.drain(..) {
+            if l.owner != lock.owner || !l.overlaps(&lock) {
+                kept.push(l);
+                continue;
+            }
+            if l.start < lock.start {
+                kept.push(PosixLock {
+                    end: lock.start - 1,
+                    ..l
+                });
+            }
+            if l.end > lock.end {
+                kept.push(PosixLock {
+                    start: lock.end + 1,
+                    ..l
+                });
+            }
+        }
+        if lock.typ != F_UNLCK {
+            kept.push(lock);
+        }
+        if kept.is_empty() {
+            self.locks.remove(&ino);
+        } else {
+            kept.sort_unstable_by_key(|l| (l.start, l.owner));
+            *locks = kept;
+        }
+        Ok(())
+    }
+
+    /// Remove all locks of `owner` on `ino`. Returns whether it held any.
+    pub fn release(&mut self, ino: u64, owner: u64) -> bool {
+        let Some(locks) = self.locks.get_mut(&ino) else {
+            return false;
+        };
+        let len = locks.len();
+        locks.retain(|l| l.owner != owner);
+        let released = locks.len() != len;
+        if locks.is_empty() {
+            self.locks.remove(&ino);
+        }
+        released
+    }
+
+    /// Returns the locks held on `ino`, ordered by their start
+    pub fn locks(&self, ino: u64) -> &[PosixLock] {
+        self.locks.get(&ino).map_or(&[], |l| l)
+    }
+}
+
+/// A blocking lock request that can't be granted yet
+#[derive(Debug)]
+struct Waiter {
+    unique: u64,
+    ino: u64,
+    lock: PosixLock,
+    reply: ReplyEmpty,
+}
+
+/// The lock table of a session, with the blocking lock requests waiting for it
+#[derive(Debug)]
+pub(crate) struct SessionLocks {
+    pub(crate) table: LockTable,
+    waiters: Vec<Waiter>,
+}
+
+impl SessionLocks {
+    pub(crate) fn new(table: LockTable) -> Self {
+        Self {
+            table,
+            waiters: vec![],
+        }
+    }
+
+    /// Take, change or remove a lock, see [`LockTable::set`]
+    pub(crate) fn set(&mut self, ino: u64, lock: PosixLock) -> Result<(), c_int> {
+        self.table.set(ino, lock)?;
+        if lock.typ != F_WRLCK {
+            self.wake();
+        }
+        Ok(())
+    }
+
+    /// Take a lock, or reply once it can be taken. Deadlocks aren't detected, waiting requests
+    /// are only failed when the kernel interrupts them.
+    pub(crate) fn set_wait(&mut self, unique: u64, ino: u64, lock: PosixLock, reply: ReplyEmpty) {
+        match self.set(ino, lock) {
+            Ok(()) => reply.ok(),
+            Err(EAGAIN) => self.waiters.push(Waiter {
+                unique,
+                ino,
+                lock,
+                reply,
+            }),
+            Err(err) => reply.error(err),
+        }
+    }
+
+    /// Remove all locks of `owner` on `ino`, see [`LockTable::release`]
+    pub(crate) fn release(&mut self, ino: u64, owner: u64) {
+        if self.table.release(ino, owner) {
+            self.wake();
+        }
+    }
+
+    /// Fail the waiting request `unique` with `EINTR`. Returns false if no such request waits.
+    pub(crate) fn interrupt(&mut self, unique: u64) -> bool {
+        match self.waiters.iter().position(|w| w.unique == unique) {
+            Some(index) => {
+                self.waiters.remove(index).reply.error(EINTR);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Grant waiting requests that no longer conflict, in the order they arrived
+    fn wake(&mut self) {
+        let mut index = 0;
+        while index < self.waiters.len() {
+            let waiter = &self.waiters[index];
+            if self.table.set(waiter.ino, waiter.lock).is_ok() {
+                self.waiters.remove(index).reply.ok();
+                // The next waiter moved to this index
+                continue;
+            }
+            index += 1;
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{LockTable, PosixLock, SessionLocks};
+    use crate::channel::Channel;
+    use crate::reply::{Reply, ReplyEmpty};
+    use libc::{EAGAIN, EINTR, F_RDLCK, F_UNLCK, F_WRLCK};
+    use std::io::{Read, Seek, SeekFrom};
+    use std::sync::Arc;
+
+    fn lock(owner: u64, start: u64, end: u64, typ: i32) -> PosixLock {
+        PosixLock {
+            owner,
+            start,
+            end,
+            typ,
+            pid: owner as u32,
+        }
+    }
+
+    #[test]
+    fn conflicts_and_splits() {
+        let mut table = LockTable::new();
+        table.set(1, lock(10, 0, 99, F_RDLCK)).unwrap();
+        table.set(1, lock(20, 50, 149, F_RDLCK)).unwrap();
+        assert_eq!(table.set(1, lock(20, 0, 9, F_WRLCK)), Err(EAGAIN));
+        assert_eq!(
+            table.test(1, &lock(30, 120, 130, F_WRLCK)).unwrap().owner,
+            20
+        );
+        // Another inode is independent
+        table.set(2, lock(30, 0, 9, F_WRLCK)).unwrap();
+
+        // Unlocking the middle of a lock splits it
+        table.set(1, lock(10, 40, 59, F_UNLCK)).unwrap();
+        assert_eq!(
+            table.locks(1),
+            [
+                lock(10, 0, 39, F_RDLCK),
+                lock(20, 50, 149, F_RDLCK),
+                lock(10, 60, 99, F_RDLCK)
+            ]
+        );
+        assert!(table.release(1, 10));
+        assert!(!table.release(1, 10));
+        table.set(1, lock(20, 0, 9, F_WRLCK)).unwrap();
+        assert!(table.release(1, 20));
+        assert_eq!(table.locks(1), []);
+    }
+
+    #[test]
+    fn waiters() {
+        let mut device = tempfile::tempfile().unwrap();
+        let channel = Channel::new(Arc::new(device.try_clone().unwrap()));
+        let reply = |unique: u64| {
+            let sender = channel.sender();
+            sender.track(unique, 16);
+            ReplyEmpty::new(unique, sender)
+        };
+        let mut locks = SessionLocks::new(LockTable::new());
+        locks.set(1, lock(10, 0, 99, F_WRLCK)).unwrap();
+        locks.set_wait(2, 1, lock(20, 0, 9, F_RDLCK), reply(2));
+        locks.set_wait(3, 1, lock(30, 50, 59, F_WRLCK), reply(3));
+        locks.set_wait(4, 1, lock(40, 0, 0, F_RDLCK), reply(4));
+        assert!(locks.interrupt(4));
+        assert!(!locks.interrupt(4));
+
+        // Closing the file drops the lock and grants both waiting requests
+        locks.release(1, 10);
+        assert_eq!(locks.table.locks(1).len(), 2);
+        assert!(!locks.interrupt(2));
+
+        let mut sent = vec![];
+        device.seek(SeekFrom::Start(0)).unwrap();
+        device.read_to_end(&mut sent).unwrap();
+        let replies: Vec<(i32, u64)> = sent
+            .chunks(16)
+            .map(|h| {
+                (
+                    i32::from_ne_bytes(h[4..8].try_into().unwrap()),
+                    u64::from_ne_bytes(h[8..16].try_into().unwrap()),
+                )
+            })
+            .collect();
+        assert_eq!(replies, [(-EINTR, 4), (0, 2), (0, 3)]);
+    }
+}
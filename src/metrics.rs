@@ -0,0 +1,379 @@
+//! Request metrics per operation
+//!
+//! Filesystems used to count requests and time them by wrapping every method of the
+//! [`Filesystem`](crate::Filesystem) trait. With metrics enabled (see
+//! [`Session::enable_metrics`](crate::Session::enable_metrics)), the session counts the
+//! requests and errors of each opcode and keeps a histogram of how long they took to answer,
+//! from when the request was read until the reply was written.
+//! [`Session::metrics`](crate::Session::metrics) returns a snapshot of them. With the
+//! `metrics-prometheus` feature, the snapshot renders in the Prometheus text format for a
+//! daemon's HTTP endpoint.
+
+use std::collections::BTreeMap;
+use std::fmt;
+#[cfg(feature = "metrics-prometheus")]
+use std::fmt::Write;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use libc::c_int;
+
+use crate::debug_dump::opcode_name;
+use crate::{RequestHook, RequestInfo};
+
+/// Number of buckets of a [`LatencyHistogram`]. The last one holds latencies above 2^30 µs
+/// (about 18 minutes).
+const BUCKETS: usize = 32;
+
+/// Latencies in buckets of powers of two microseconds
+///
+/// Bucket `i` counts the latencies up to and including `2^i` µs that don't fit an earlier
+/// bucket, like the `le` buckets of Prometheus, so quantiles are accurate to a factor of two.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LatencyHistogram {
+    buckets: [u64; BUCKETS],
+    total: Duration,
+}
+
+impl Default for LatencyHistogram {
+    fn default() -> Self {
+        Self {
+            buckets: [0; BUCKETS],
+            total: Duration::ZERO,
+        }
+    }
+}
+
+impl LatencyHistogram {
+    fn record(&mut self, latency: Duration) {
+        // Rounded up, so that a latency just above a bound doesn't count towards it
+        let micros = ((latency.as_nanos() + 999) / 1000).min(u64::MAX as u128) as u64;
+        let bucket = (u64::BITS - micros.saturating_sub(1).leading_zeros()) as usize;
+        self.buckets[bucket.min(BUCKETS - 1)] += 1;
+        self.total += latency;
+    }
+
+    /// Returns the number of latencies recorded
+    pub fn count(&self) -> u64 {
+        self.buckets.iter().sum()
+    }
+
+    /// Returns the sum of the latencies recorded
+    pub fn total(&self) -> Duration {
+        self.total
+    }
+
+    /// Returns the average latency, or `None` if none were recorded
+    pub fn mean(&self) -> Option<Duration> {
+        match self.count() {
+            0 => None,
+            count => Some(Duration::from_nanos(
+                (self.total.as_nanos() / count as u128) as u64,
+            )),
+        }
+    }
+
+    /// Returns the inclusive upper bound of each bucket with the number of latencies in it,
+    /// smallest first. The bound of the last bucket is `Duration::MAX`.
+    pub fn buckets(&self) -> impl Iterator<Item = (Duration, u64)> + '_ {
+        self.buckets
+            .iter()
+            .enumerate()
+            .map(|(i, count)| (Self::bound(i), *count))
+    }
+
+    /// Returns the upper bound of the bucket the `q` quantile (between 0 and 1) falls into,
+    /// e.g. `quantile(0.99)` for the 99th percentile, or `None` if no latencies were recorded
+    pub fn quantile(&self, q: f64) -> Option<Duration> {
+        let count = self.count();
+        if count == 0 {
+            return None;
+        }
+        let rank = ((q.clamp(0.0, 1.0) * count as f64).ceil() as u64).max(1);
+        let mut seen = 0;
+        for (bound, n) in self.buckets() {
+            seen += n;
+            if seen >= rank {
+                return Some(bound);
+            }
+        }
+        Some(Duration::MAX)
+    }
+
+    fn bound(bucket: usize) -> Duration {
+        if bucket == BUCKETS - 1 {
+            Duration::MAX
+        } else {
+            Duration::from_micros(1 << bucket)
+        }
+    }
+}
+
+/// Metrics of the requests of one opcode
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct OpMetrics {
+    /// Number of requests received
+    pub requests: u64,
+    /// Number of requests answered with an error
+    pub errors: u64,
+    /// Time from receiving each answered request until its reply was sent
+    pub latency: LatencyHistogram,
+}
+
+/// Snapshot of the metrics of a session, see [`Session::metrics`](crate::Session::metrics)
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct Metrics {
+    /// Metrics by raw opcode, of the opcodes that were received
+    pub by_opcode: BTreeMap<u32, OpMetrics>,
+}
+
+impl Metrics {
+    /// Returns the number of requests received
+    pub fn requests(&self) -> u64 {
+        self.by_opcode.values().map(|m| m.requests).sum()
+    }
+
+    /// Returns the number of requests answered with an error
+    pub fn errors(&self) -> u64 {
+        self.by_opcode.values().map(|m| m.errors).sum()
+    }
+}
+
+#[cfg(feature = "metrics-prometheus")]
+impl Metrics {
+    /// Render the metrics in the Prometheus text exposition format, which OpenMetrics
+    /// scrapers accept as well. The operation is the `op` label, e.g. `op="read"`.
+    pub fn to_prometheus(&self) -> String {
+        let mut out = String::new();
+        self.write_counter(&mut out, "fuser_requests_total", "Requests received", |m| {
+            m.requests
+        });
+        self.write_counter(
+            &mut out,
+            "fuser_request_errors_total",
+            "Requests answered with an error",
+            |m| m.errors,
+        );
+        let name = "fuser_request_duration_seconds";
+        writeln!(
+            out,
+            "# HELP {} Time from receiving a request until its reply was sent",
+            name
+        )
+        .unwrap();
+        writeln!(out, "# TYPE {} histogram", name).unwrap();
+        for (opcode, metrics) in &self.by_opcode {
+            let op = prometheus_label(*opcode);
+            let latency = &metrics.latency;
+            let mut cumulative = 0;
+            for (bound, count) in latency.buckets() {
+                cumulative += count;
+                let le = match bound {
+                    Duration::MAX => "+Inf".to_owned(),
+                    bound => bound.as_secs_f64().to_string(),
+                };
+                writeln!(
+                    out,
+                    "{}_bucket{{op=\"{}\",le=\"{}\"}} {}",
+                    name, op, le, cumulative
+                )
+                .unwrap();
+            }
+            let sum = latency.total().as_secs_f64();
+            writeln!(out, "{}_sum{{op=\"{}\"}} {}", name, op, sum).unwrap();
+            writeln!(out, "{}_count{{op=\"{}\"}} {}", name, op, latency.count()).unwrap();
+        }
+        out
+    }
+
+    fn write_counter(
+        &self,
+        out: &mut String,
+        name: &str,
+        help: &str,
+        value: impl Fn(&OpMetrics) -> u64,
+    ) {
+        writeln!(out, "# HELP {} {}", name, help).unwrap();
+        writeln!(out, "# TYPE {} counter", name).unwrap();
+        for (opcode, metrics) in &self.by_opcode {
+            let op = prometheus_label(*opcode);
+            writeln!(out, "{}{{op=\"{}\"}} {}", name, op, value(metrics)).unwrap();
+        }
+    }
+}
+
+/// The `op` label of an opcode, e.g. `read` for `FUSE_READ`
+#[cfg(feature = "metrics-prometheus")]
+fn prometheus_label(opcode: u32) -> String {
+    let name = opcode_name(opcode);
+    name.strip_prefix("FUSE_").unwrap_or(&name).to_lowercase()
+}
+
+fn format_latency(latency: Option<Duration>) -> String {
+    match latency {
+        Some(Duration::MAX) => "inf".to_owned(),
+        Some(latency) => format!("{:?}", latency),
+        None => "-".to_owned(),
+    }
+}
+
+impl fmt::Display for Metrics {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "requests: {}", self.requests())?;
+        writeln!(f, "errors: {}", self.errors())?;
+        for (opcode, metrics) in &self.by_opcode {
+            writeln!(
+                f,
+                "  {}: {} requests, {} errors, mean {}, p50 <{}, p99 <{}",
+                opcode_name(*opcode),
+                metrics.requests,
+                metrics.errors,
+                format_latency(metrics.latency.mean()),
+                format_latency(metrics.latency.quantile(0.5)),
+                format_latency(metrics.latency.quantile(0.99)),
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// Collects the metrics of a session as one of its hooks
+#[derive(Debug, Clone, Default)]
+pub(crate) struct MetricsCollector(Arc<Mutex<Metrics>>);
+
+impl MetricsCollector {
+    pub(crate) fn snapshot(&self) -> Metrics {
+        self.0.lock().unwrap().clone()
+    }
+}
+
+impl RequestHook for MetricsCollector {
+    fn before_dispatch(&self, request: &RequestInfo) -> Result<(), c_int> {
+        let mut metrics = self.0.lock().unwrap();
+        metrics
+            .by_opcode
+            .entry(request.opcode)
+            .or_default()
+            .requests += 1;
+        Ok(())
+    }
+
+    fn after_reply(&self, request: &RequestInfo, latency: Duration, errno: c_int) {
+        let mut metrics = self.0.lock().unwrap();
+        let op = metrics.by_opcode.entry(request.opcode).or_default();
+        if errno != 0 {
+            op.errors += 1;
+        }
+        op.latency.record(latency);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{LatencyHistogram, MetricsCollector};
+    use crate::ll::fuse_abi::fuse_opcode;
+    use crate::{RequestHook, RequestInfo};
+    use std::time::Duration;
+
+    #[test]
+    fn histogram() {
+        let mut histogram = LatencyHistogram::default();
+        assert_eq!(histogram.quantile(0.5), None);
+        for micros in [0, 3, 5, 6, 7, 8, 900] {
+            histogram.record(Duration::from_micros(micros));
+        }
+        histogram.record(Duration::from_secs(3600));
+        assert_eq!(histogram.count(), 8);
+        assert_eq!(histogram.quantile(0.0), Some(Duration::from_micros(1)));
+        assert_eq!(histogram.quantile(0.5), Some(Duration::from_micros(8)));
+        assert_eq!(histogram.quantile(0.8), Some(Duration::from_micros(1024)));
+        assert_eq!(histogram.quantile(1.0), Some(Duration::MAX));
+        let buckets: Vec<_> = histogram.buckets().filter(|(_, n)| *n > 0).collect();
+        assert_eq!(
+            buckets,
+            [
+                (Duration::from_micros(1), 1),
+                (Duration::from_micros(4), 1),
+                (Duration::from_micros(8), 4),
+                (Duration::from_micros(1024), 1),
+                (Duration::MAX, 1),
+            ]
+        );
+
+        // Bounds are inclusive, latencies just above them go to the next bucket
+        let mut histogram = LatencyHistogram::default();
+        histogram.record(Duration::from_micros(2));
+        histogram.record(Duration::from_nanos(2001));
+        let buckets: Vec<_> = histogram.buckets().filter(|(_, n)| *n > 0).collect();
+        assert_eq!(
+            buckets,
+            [(Duration::from_micros(2), 1), (Duration::from_micros(4), 1)]
+        );
+    }
+
+    #[test]
+    fn collect() {
+        let collector = MetricsCollector::default();
+        let request = |unique, opcode: fuse_opcode| RequestInfo {
+            unique,
+            opcode: opcode as u32,
+            ino: 1,
+            uid: 0,
+            pid: 0,
+        };
+        let read = request(1, fuse_opcode::FUSE_READ);
+        let failed = request(2, fuse_opcode::FUSE_READ);
+        let forget = request(3, fuse_opcode::FUSE_FORGET);
+        for r in [&read, &failed, &forget] {
+            collector.before_dispatch(r).unwrap();
+        }
+        collector.after_reply(&read, Duration::from_micros(10), 0);
+        collector.after_reply(&failed, Duration::from_micros(30), libc::EIO);
+
+        let metrics = collector.snapshot();
+        assert_eq!(metrics.requests(), 3);
+        assert_eq!(metrics.errors(), 1);
+        let reads = &metrics.by_opcode[&(fuse_opcode::FUSE_READ as u32)];
+        assert_eq!(reads.requests, 2);
+        assert_eq!(reads.latency.count(), 2);
+        assert_eq!(reads.latency.mean(), Some(Duration::from_micros(20)));
+        assert!(metrics
+            .to_string()
+            .contains("FUSE_READ: 2 requests, 1 errors"));
+    }
+
+    #[cfg(feature = "metrics-prometheus")]
+    #[test]
+    fn prometheus() {
+        let collector = MetricsCollector::default();
+        let read = RequestInfo {
+            unique: 1,
+            opcode: fuse_opcode::FUSE_READ as u32,
+            ino: 1,
+            uid: 0,
+            pid: 0,
+        };
+        collector.before_dispatch(&read).unwrap();
+        collector.after_reply(&read, Duration::from_micros(3), libc::EIO);
+
+        let out = collector.snapshot().to_prometheus();
+        assert!(
+            out.contains("# TYPE fuser_requests_total counter\n"),
+            "{}",
+            out
+        );
+        assert!(
+            out.contains("fuser_requests_total{op=\"read\"} 1\n"),
+            "{}",
+            out
+        );
+        assert!(out.contains("fuser_request_errors_total{op=\"read\"} 1\n"));
+        assert!(out.contains("_bucket{op=\"read\",le=\"0.000002\"} 0\n"));
+        assert!(out.contains("_bucket{op=\"read\",le=\"0.000004\"} 1\n"));
+        assert!(out.contains("_bucket{op=\"read\",le=\"+Inf\"} 1\n"));
+        assert!(out.contains("fuser_request_duration_seconds_sum{op=\"read\"} 0.000003\n"));
+        assert!(out.contains("fuser_request_duration_seconds_count{op=\"read\"} 1\n"));
+    }
+}
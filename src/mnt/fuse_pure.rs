@@ -7,7 +7,7 @@
 #![allow(missing_docs)]
 
 use super::is_mounted;
-use super::mount_options::{option_to_string, MountOption};
+use super::mount_options::{is_fuser_option, option_to_string, MountOption};
 use libc::c_int;
 use log::{debug, error};
 use std::ffi::{CStr, CString, OsStr};
@@ -246,9 +246,13 @@ fn fuse_mount_fusermount(
 
     let mut builder = Command::new(detect_fusermount_bin());
     builder.stdout(Stdio::piped()).stderr(Stdio::piped());
-    if !options.is_empty() {
+    if options.iter().any(|x| !is_fuser_option(x)) {
         builder.arg("-o");
-        let options_strs: Vec<String> = options.iter().map(option_to_string).collect();
+        let options_strs: Vec<String> = options
+            .iter()
+            .filter(|x| !is_fuser_option(x))
+            .map(option_to_string)
+            .collect();
         builder.arg(options_strs.join(","));
     }
     builder
@@ -406,14 +410,33 @@ fn fuse_mount_sys(mountpoint: &OsStr, options: &[MountOption]) -> Result<Option<
         source = name;
     }
 
-    let c_source = CString::new(source).unwrap();
-    let c_mountpoint = CString::new(mountpoint.as_bytes()).unwrap();
+    // Match libfuse: block device backed filesystems use "fuseblk", and the subtype is shown
+    // as part of the filesystem type
+    #[cfg(target_os = "linux")]
+    let fs_type = {
+        let mut fs_type = if options.contains(&MountOption::Blkdev) {
+            "fuseblk".to_string()
+        } else {
+            "fuse".to_string()
+        };
+        if let Some(MountOption::Subtype(subtype)) = options
+            .iter()
+            .find(|x| matches!(**x, MountOption::Subtype(_)))
+        {
+            fs_type.push('.');
+            fs_type.push_str(subtype);
+        }
+        fs_type
+    };
+
+    let c_source = CString::new(source)?;
+    let c_mountpoint = CString::new(mountpoint.as_bytes())?;
 
     let result = unsafe {
         #[cfg(target_os = "linux")]
         {
             let c_options = CString::new(mount_options).unwrap();
-            let c_type = CString::new("fuse").unwrap();
+            let c_type = CString::new(fs_type).unwrap();
             libc::mount(
                 c_source.as_ptr(),
                 c_mountpoint.as_ptr(),
@@ -437,6 +460,9 @@ fn fuse_mount_sys(mountpoint: &OsStr, options: &[MountOption]) -> Result<Option<
         let err = Error::last_os_error();
         if err.kind() == ErrorKind::PermissionDenied {
             return Ok(None); // Retry with fusermount
+        } else if err.raw_os_error() == Some(libc::EBUSY) {
+            // Keep the errno, so that the mount can be retried
+            return Err(err);
         } else {
             return Err(Error::new(
                 err.kind(),
@@ -453,6 +479,7 @@ pub enum MountOptionGroup {
     KernelOption,
     KernelFlag,
     Fusermount,
+    Fuser,
 }
 
 pub fn option_group(option: &MountOption) -> MountOptionGroup {
@@ -461,6 +488,7 @@ pub fn option_group(option: &MountOption) -> MountOptionGroup {
         MountOption::Subtype(_) => MountOptionGroup::Fusermount,
         MountOption::CUSTOM(_) => MountOptionGroup::KernelOption,
         MountOption::AutoUnmount => MountOptionGroup::Fusermount,
+        MountOption::Blkdev => MountOptionGroup::Fusermount,
         MountOption::AllowOther => MountOptionGroup::KernelOption,
         MountOption::Dev => MountOptionGroup::KernelFlag,
         MountOption::NoDev => MountOptionGroup::KernelFlag,
@@ -477,6 +505,9 @@ pub fn option_group(option: &MountOption) -> MountOptionGroup {
         MountOption::Async => MountOptionGroup::KernelFlag,
         MountOption::AllowRoot => MountOptionGroup::KernelOption,
         MountOption::DefaultPermissions => MountOptionGroup::KernelOption,
+        MountOption::Propagation(_) => MountOptionGroup::Fuser,
+        MountOption::RetryOnBusy { .. } => MountOptionGroup::Fuser,
+        MountOption::Namespace(_) => MountOptionGroup::Fuser,
     }
 }
 
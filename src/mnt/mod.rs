@@ -17,23 +17,25 @@ pub mod mount_options;
 
 #[cfg(any(test, feature = "libfuse"))]
 use fuse2_sys::fuse_args;
-#[cfg(any(test, not(feature = "libfuse")))]
+use log::warn;
 use std::fs::File;
-#[cfg(any(test, fuser_mount_impl = "pure-rust", fuser_mount_impl = "libfuse2"))]
 use std::io;
+use std::path::Path;
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
 
-#[cfg(any(feature = "libfuse", test))]
-use mount_options::MountOption;
+use mount_options::{MountOption, MountPropagation};
 
 /// Helper function to provide options as a fuse_args struct
 /// (which contains an argc count and an argv pointer)
 #[cfg(any(feature = "libfuse", test))]
 fn with_fuse_args<T, F: FnOnce(&fuse_args) -> T>(options: &[MountOption], f: F) -> T {
-    use mount_options::option_to_string;
+    use mount_options::{is_fuser_option, option_to_string};
     use std::ffi::CString;
 
     let mut args = vec![CString::new("rust-fuse").unwrap()];
-    for x in options {
+    for x in options.iter().filter(|x| !is_fuser_option(x)) {
         args.extend_from_slice(&[
             CString::new("-o").unwrap(),
             CString::new(option_to_string(x)).unwrap(),
@@ -83,6 +85,124 @@ fn libc_umount(mnt: &CStr) -> io::Result<()> {
     }
 }
 
+/// Create the mount, retrying as configured by `MountOption::RetryOnBusy` while the mountpoint
+/// is busy
+pub(crate) fn mount_with_retry(
+    mountpoint: &Path,
+    options: &[MountOption],
+) -> io::Result<(Arc<File>, Mount)> {
+    let (mut retries, mut backoff) = options
+        .iter()
+        .find_map(|x| match x {
+            MountOption::RetryOnBusy { attempts, backoff } => Some((*attempts, *backoff)),
+            _ => None,
+        })
+        .unwrap_or((0, Duration::ZERO));
+    loop {
+        match Mount::new(mountpoint, options) {
+            Err(err) if retries > 0 && is_busy(&err) => {
+                warn!(
+                    "Mountpoint {} is busy, retrying in {:?}: {}",
+                    mountpoint.display(),
+                    backoff,
+                    err
+                );
+                thread::sleep(backoff);
+                retries -= 1;
+                backoff = backoff.saturating_mul(2);
+            }
+            result => return result,
+        }
+    }
+}
+
+fn is_busy(err: &io::Error) -> bool {
+    err.raw_os_error() == Some(libc::EBUSY)
+}
+
+/// Join the namespaces given by `MountOption::Namespace`, in order, before mounting
+pub(crate) fn join_namespaces(options: &[MountOption]) -> io::Result<()> {
+    for option in options {
+        if let MountOption::Namespace(fd) = option {
+            join_namespace(*fd)?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(target_os = "linux")]
+fn join_namespace(fd: std::os::fd::RawFd) -> io::Result<()> {
+    if unsafe { libc::setns(fd, 0) } == -1 {
+        let err = io::Error::last_os_error();
+        return Err(io::Error::new(
+            err.kind(),
+            format!("Error joining the namespace of file descriptor {fd}: {err}"),
+        ));
+    }
+    Ok(())
+}
+
+#[cfg(not(target_os = "linux"))]
+fn join_namespace(_fd: std::os::fd::RawFd) -> io::Result<()> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "Namespace options are only supported on Linux",
+    ))
+}
+
+/// Apply the options which can only be set once the filesystem is mounted, such as the mount
+/// propagation type
+pub(crate) fn apply_post_mount_options(
+    mountpoint: &Path,
+    options: &[MountOption],
+) -> io::Result<()> {
+    for option in options {
+        if let MountOption::Propagation(propagation) = option {
+            set_propagation(mountpoint, *propagation)?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(target_os = "linux")]
+fn set_propagation(mountpoint: &Path, propagation: MountPropagation) -> io::Result<()> {
+    use std::ffi::CString;
+    use std::os::unix::ffi::OsStrExt;
+
+    let flags = match propagation {
+        MountPropagation::Shared => libc::MS_SHARED,
+        MountPropagation::Slave => libc::MS_SLAVE,
+        MountPropagation::Private => libc::MS_PRIVATE,
+        MountPropagation::Unbindable => libc::MS_UNBINDABLE,
+    };
+    let c_mountpoint = CString::new(mountpoint.as_os_str().as_bytes())?;
+    let result = unsafe {
+        libc::mount(
+            std::ptr::null(),
+            c_mountpoint.as_ptr(),
+            std::ptr::null(),
+            flags,
+            std::ptr::null(),
+        )
+    };
+    if result == -1 {
+        let err = io::Error::last_os_error();
+        return Err(io::Error::new(
+            err.kind(),
+            format!("Error setting {propagation:?} propagation at {mountpoint:?}: {err}"),
+        ));
+    }
+    Ok(())
+}
+
+#[cfg(not(target_os = "linux"))]
+fn set_propagation(_mountpoint: &Path, _propagation: MountPropagation) -> io::Result<()> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "Mount propagation options are only supported on Linux",
+    ))
+}
+
 /// Warning: This will return true if the filesystem has been detached (lazy unmounted), but not
 /// yet destroyed by the kernel.
 #[cfg(any(test, fuser_mount_impl = "pure-rust"))]
@@ -154,6 +274,17 @@ mod test {
         .to_owned()
     }
 
+    #[test]
+    fn busy_errors() {
+        assert!(is_busy(&io::Error::from_raw_os_error(libc::EBUSY)));
+        // fusermount only reports the error on stderr
+        assert!(!is_busy(&io::Error::new(
+            io::ErrorKind::Other,
+            "fusermount3: mount failed: Device or resource busy"
+        )));
+        assert!(!is_busy(&io::Error::from_raw_os_error(libc::EPERM)));
+    }
+
     #[test]
     fn mount_unmount() {
         // We use ManuallyDrop here to leak the directory on test failure.  We don't
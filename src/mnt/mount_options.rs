@@ -1,5 +1,7 @@
 use std::io;
 use std::io::ErrorKind;
+use std::os::fd::RawFd;
+use std::time::Duration;
 use std::{collections::HashSet, ffi::OsStr};
 
 /// Mount options accepted by the FUSE filesystem type
@@ -8,8 +10,13 @@ use std::{collections::HashSet, ffi::OsStr};
 #[derive(Debug, Eq, PartialEq, Hash, Clone)]
 pub enum MountOption {
     /// Set the name of the source in mtab
+    ///
+    /// Commas and backslashes are escaped when passed to libfuse or fusermount, so any
+    /// value without a NUL byte is accepted
     FSName(String),
-    /// Set the filesystem subtype in mtab
+    /// Set the filesystem subtype in mtab. The filesystem type is shown as `fuse.<subtype>`
+    ///
+    /// Must not be empty and must not contain commas, whitespace, '/' or NUL bytes
     Subtype(String),
     /// Allows passing an option which is not otherwise supported in these enums
     #[allow(clippy::upper_case_acronyms)]
@@ -27,6 +34,9 @@ pub enum MountOption {
     AutoUnmount,
     /// Enable permission checking in the kernel
     DefaultPermissions,
+    /// Mount a filesystem backed by the block device given as `FSName`. The filesystem type is
+    /// shown as `fuseblk`. Requires root
+    Blkdev,
 
     /* Flags */
     /// Enable special character and block devices
@@ -55,10 +65,53 @@ pub enum MountOption {
     Sync,
     /// All I/O will be done asynchronously
     Async,
+
+    /* Applied after mounting */
+    /// Set the propagation type of the mount. See 'man mount_namespaces' for details
+    ///
+    /// Only supported on Linux. Changing the propagation type requires CAP_SYS_ADMIN in the
+    /// mount namespace, so this generally fails for unprivileged mounts done via fusermount
+    Propagation(MountPropagation),
+
+    /* Handled by fuser while mounting */
+    /// Retry mounting up to `attempts` more times if the mountpoint is busy (EBUSY), which
+    /// commonly happens when racing with other mounts during boot. Waits `backoff` before the
+    /// first retry and doubles the wait after each further attempt. Only mounts that fail with
+    /// EBUSY themselves are retried: `fusermount` exits with the same status for all errors, so
+    /// mounts it fails aren't
+    RetryOnBusy {
+        /// Number of retries after the first attempt
+        attempts: u32,
+        /// Wait before the first retry
+        backoff: Duration,
+    },
+    /// Join the namespace the file descriptor refers to (e.g. an open `/proc/<pid>/ns/mnt` of a
+    /// container) with `setns(2)` before mounting, so that the mount is created in it. The
+    /// namespaces are joined in the order of the options, which must put a user namespace
+    /// before the namespaces it owns. The whole process stays in them and the session runs
+    /// there. The descriptor stays owned by the caller and may be closed once mounted
+    ///
+    /// Only supported on Linux. Joining a mount or user namespace fails once the process has
+    /// started other threads
+    Namespace(RawFd),
     /* libfuse library options, such as "direct_io", are not included since they are specific
     to libfuse, and not part of the kernel ABI */
 }
 
+/// Propagation type of a mount, which controls whether mount and unmount events under the
+/// mountpoint are shared with other mount namespaces
+#[derive(Debug, Eq, PartialEq, Hash, Clone, Copy)]
+pub enum MountPropagation {
+    /// Events propagate to and from peer mounts (`mount --make-shared`)
+    Shared,
+    /// Events propagate from the master mount only (`mount --make-slave`)
+    Slave,
+    /// Events do not propagate in either direction (`mount --make-private`)
+    Private,
+    /// Like private, and the mount can't be bind mounted (`mount --make-unbindable`)
+    Unbindable,
+}
+
 impl MountOption {
     pub(crate) fn from_str(s: &str) -> MountOption {
         match s {
@@ -66,6 +119,7 @@ impl MountOption {
             "allow_other" => MountOption::AllowOther,
             "allow_root" => MountOption::AllowRoot,
             "default_permissions" => MountOption::DefaultPermissions,
+            "blkdev" => MountOption::Blkdev,
             "dev" => MountOption::Dev,
             "nodev" => MountOption::NoDev,
             "suid" => MountOption::Suid,
@@ -79,13 +133,33 @@ impl MountOption {
             "dirsync" => MountOption::DirSync,
             "sync" => MountOption::Sync,
             "async" => MountOption::Async,
-            x if x.starts_with("fsname=") => MountOption::FSName(x[7..].into()),
-            x if x.starts_with("subtype=") => MountOption::Subtype(x[8..].into()),
+            "shared" => MountOption::Propagation(MountPropagation::Shared),
+            "slave" => MountOption::Propagation(MountPropagation::Slave),
+            "private" => MountOption::Propagation(MountPropagation::Private),
+            "unbindable" => MountOption::Propagation(MountPropagation::Unbindable),
+            x if x.starts_with("retry_on_busy=") => {
+                parse_retry_on_busy(&x[14..]).unwrap_or_else(|| MountOption::CUSTOM(x.into()))
+            }
+            x if x.starts_with("namespace_fd=") => x[13..]
+                .parse()
+                .map(MountOption::Namespace)
+                .unwrap_or_else(|_| MountOption::CUSTOM(x.into())),
+            x if x.starts_with("fsname=") => MountOption::FSName(unescape_option_value(&x[7..])),
+            x if x.starts_with("subtype=") => MountOption::Subtype(unescape_option_value(&x[8..])),
             x => MountOption::CUSTOM(x.into()),
         }
     }
 }
 
+/// Parses the `<attempts>:<backoff in ms>` value of the retry_on_busy option
+fn parse_retry_on_busy(value: &str) -> Option<MountOption> {
+    let (attempts, backoff) = value.split_once(':')?;
+    Some(MountOption::RetryOnBusy {
+        attempts: attempts.parse().ok()?,
+        backoff: Duration::from_millis(backoff.parse().ok()?),
+    })
+}
+
 pub fn check_option_conflicts(options: &[MountOption]) -> Result<(), io::Error> {
     let mut options_set = HashSet::new();
     options_set.extend(options.iter().cloned());
@@ -101,6 +175,42 @@ pub fn check_option_conflicts(options: &[MountOption]) -> Result<(), io::Error>
     }
 }
 
+/// Checks that the values of the options can be passed to every mount backend unchanged
+pub fn check_option_values(options: &[MountOption]) -> Result<(), io::Error> {
+    let err = |x| Err(io::Error::new(ErrorKind::InvalidInput, x));
+    for option in options {
+        match option {
+            MountOption::FSName(name) if name.contains('\0') => {
+                return err(format!("Invalid fsname {name:?}: contains a NUL byte"));
+            }
+            MountOption::Subtype(subtype)
+                if subtype.is_empty()
+                    || subtype
+                        .chars()
+                        .any(|c| c == ',' || c == '/' || c == '\0' || c.is_whitespace()) =>
+            {
+                return err(format!(
+                    "Invalid subtype {subtype:?}: must be non-empty and must not contain \
+                    commas, whitespace, '/' or NUL bytes"
+                ));
+            }
+            MountOption::CUSTOM(value) if value.contains('\0') => {
+                return err(format!("Invalid option {value:?}: contains a NUL byte"));
+            }
+            MountOption::Namespace(fd) if *fd < 0 => {
+                return err(format!("Invalid namespace file descriptor {fd}"));
+            }
+            _ => {}
+        }
+    }
+    if options.contains(&MountOption::Blkdev)
+        && !options.iter().any(|x| matches!(x, MountOption::FSName(_)))
+    {
+        return err("blkdev requires fsname to be set to the block device".to_owned());
+    }
+    Ok(())
+}
+
 fn conflicts_with(option: &MountOption) -> Vec<MountOption> {
     match option {
         MountOption::FSName(_) => vec![],
@@ -110,6 +220,7 @@ fn conflicts_with(option: &MountOption) -> Vec<MountOption> {
         MountOption::AllowRoot => vec![MountOption::AllowOther],
         MountOption::AutoUnmount => vec![],
         MountOption::DefaultPermissions => vec![],
+        MountOption::Blkdev => vec![],
         MountOption::Dev => vec![MountOption::NoDev],
         MountOption::NoDev => vec![MountOption::Dev],
         MountOption::Suid => vec![MountOption::NoSuid],
@@ -123,14 +234,26 @@ fn conflicts_with(option: &MountOption) -> Vec<MountOption> {
         MountOption::DirSync => vec![],
         MountOption::Sync => vec![MountOption::Async],
         MountOption::Async => vec![MountOption::Sync],
+        MountOption::Propagation(x) => [
+            MountPropagation::Shared,
+            MountPropagation::Slave,
+            MountPropagation::Private,
+            MountPropagation::Unbindable,
+        ]
+        .iter()
+        .filter(|y| *y != x)
+        .map(|y| MountOption::Propagation(*y))
+        .collect(),
+        MountOption::RetryOnBusy { .. } => vec![],
+        MountOption::Namespace(_) => vec![],
     }
 }
 
 // Format option to be passed to libfuse or kernel
 pub fn option_to_string(option: &MountOption) -> String {
     match option {
-        MountOption::FSName(name) => format!("fsname={name}"),
-        MountOption::Subtype(subtype) => format!("subtype={subtype}"),
+        MountOption::FSName(name) => format!("fsname={}", escape_option_value(name)),
+        MountOption::Subtype(subtype) => format!("subtype={}", escape_option_value(subtype)),
         MountOption::CUSTOM(value) => value.to_string(),
         MountOption::AutoUnmount => "auto_unmount".to_string(),
         MountOption::AllowOther => "allow_other".to_string(),
@@ -138,6 +261,7 @@ pub fn option_to_string(option: &MountOption) -> String {
         // root + owner within fuser
         MountOption::AllowRoot => "allow_other".to_string(),
         MountOption::DefaultPermissions => "default_permissions".to_string(),
+        MountOption::Blkdev => "blkdev".to_string(),
         MountOption::Dev => "dev".to_string(),
         MountOption::NoDev => "nodev".to_string(),
         MountOption::Suid => "suid".to_string(),
@@ -151,7 +275,69 @@ pub fn option_to_string(option: &MountOption) -> String {
         MountOption::DirSync => "dirsync".to_string(),
         MountOption::Sync => "sync".to_string(),
         MountOption::Async => "async".to_string(),
+        MountOption::Propagation(MountPropagation::Shared) => "shared".to_string(),
+        MountOption::Propagation(MountPropagation::Slave) => "slave".to_string(),
+        MountOption::Propagation(MountPropagation::Private) => "private".to_string(),
+        MountOption::Propagation(MountPropagation::Unbindable) => "unbindable".to_string(),
+        MountOption::RetryOnBusy { attempts, backoff } => {
+            format!("retry_on_busy={attempts}:{}", backoff.as_millis())
+        }
+        MountOption::Namespace(fd) => format!("namespace_fd={fd}"),
+    }
+}
+
+/// Escape an option value the way libfuse and fusermount expect it, so that it can be part of a
+/// comma separated list of options
+fn escape_option_value(value: &str) -> String {
+    let mut out = String::with_capacity(value.len());
+    for c in value.chars() {
+        if c == ',' || c == '\\' {
+            out.push('\\');
+        }
+        out.push(c);
     }
+    out
+}
+
+fn unescape_option_value(value: &str) -> String {
+    let mut out = String::with_capacity(value.len());
+    let mut chars = value.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => out.extend(chars.next()),
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+/// Split a comma separated list of options, ignoring commas escaped with a backslash
+fn split_options(options: &str) -> Vec<&str> {
+    let mut out = vec![];
+    let mut start = 0;
+    let mut escaped = false;
+    for (i, c) in options.char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' => escaped = true,
+            ',' => {
+                out.push(&options[start..i]);
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+    out.push(&options[start..]);
+    out
+}
+
+/// Options which are handled by fuser itself, and not passed to libfuse, fusermount or the
+/// kernel's fuse mount
+pub(crate) fn is_fuser_option(option: &MountOption) -> bool {
+    matches!(
+        option,
+        MountOption::Propagation(_) | MountOption::RetryOnBusy { .. } | MountOption::Namespace(_)
+    )
 }
 
 /// Parses mount command args.
@@ -173,7 +359,7 @@ pub(crate) fn parse_options_from_args(args: &[&OsStr]) -> io::Result<Vec<MountOp
             Some(x) if x.starts_with("-o") => &x[2..],
             Some(x) => return Err(err(format!("Error parsing args: expected -o, got {x}"))),
         };
-        for x in opt.split(',') {
+        for x in split_options(opt) {
             out.push(MountOption::from_str(x))
         }
     }
@@ -190,6 +376,11 @@ mod test {
     fn option_checking() {
         assert!(check_option_conflicts(&[MountOption::Suid, MountOption::NoSuid]).is_err());
         assert!(check_option_conflicts(&[MountOption::Suid, MountOption::NoExec]).is_ok());
+        assert!(check_option_conflicts(&[
+            MountOption::Propagation(MountPropagation::Private),
+            MountOption::Propagation(MountPropagation::Shared)
+        ])
+        .is_err());
     }
     #[test]
     fn option_round_trip() {
@@ -201,6 +392,7 @@ mod test {
             AllowOther,
             AutoUnmount,
             DefaultPermissions,
+            Blkdev,
             Dev,
             NoDev,
             Suid,
@@ -214,6 +406,15 @@ mod test {
             DirSync,
             Sync,
             Async,
+            Propagation(MountPropagation::Shared),
+            Propagation(MountPropagation::Slave),
+            Propagation(MountPropagation::Private),
+            Propagation(MountPropagation::Unbindable),
+            RetryOnBusy {
+                attempts: 3,
+                backoff: Duration::from_millis(250),
+            },
+            Namespace(5),
         ]
         .iter()
         {
@@ -221,6 +422,35 @@ mod test {
         }
     }
 
+    #[test]
+    fn option_escaping() {
+        use super::MountOption::*;
+        let name = FSName("a,b=c\\d".to_owned());
+        assert_eq!(option_to_string(&name), "fsname=a\\,b=c\\\\d");
+        assert_eq!(
+            name,
+            MountOption::from_str(option_to_string(&name).as_ref())
+        );
+
+        let o = [OsStr::new("-o"), OsStr::new("ro,fsname=x\\,y,nodev")];
+        let out = parse_options_from_args(o.as_ref()).unwrap();
+        assert_eq!(out, [RO, FSName("x,y".to_owned()), NoDev]);
+    }
+
+    #[test]
+    fn option_values() {
+        use super::MountOption::*;
+        assert!(check_option_values(&[FSName("my,fs".to_owned())]).is_ok());
+        assert!(check_option_values(&[FSName("a\0b".to_owned())]).is_err());
+        assert!(check_option_values(&[Subtype("myfs".to_owned())]).is_ok());
+        assert!(check_option_values(&[Subtype("".to_owned())]).is_err());
+        assert!(check_option_values(&[Subtype("my fs".to_owned())]).is_err());
+        assert!(check_option_values(&[Subtype("a,b".to_owned())]).is_err());
+        assert!(check_option_values(&[Blkdev]).is_err());
+        assert!(check_option_values(&[Blkdev, FSName("/dev/sda1".to_owned())]).is_ok());
+        assert!(check_option_values(&[Namespace(-1)]).is_err());
+    }
+
     #[test]
     fn test_parse_options() {
         use super::MountOption::*;
@@ -95,6 +95,16 @@ impl Notifier {
         self.send_inval(notify_code::FUSE_NOTIFY_DELETE, &notif)
     }
 
+    /// Start a batch of notifications that are sent in order by
+    /// [`NotifyTransaction::commit`], with no other notification of the session in between.
+    #[cfg(feature = "abi-7-12")]
+    pub fn transaction(&self) -> NotifyTransaction<'_> {
+        NotifyTransaction {
+            notifier: self,
+            queued: vec![],
+        }
+    }
+
     #[allow(unused)]
     fn send_inval(&self, code: notify_code, notification: &Notification<'_>) -> io::Result<()> {
         match self.send(code, notification) {
@@ -107,6 +117,12 @@ impl Notifier {
     }
 
     fn send(&self, code: notify_code, notification: &Notification<'_>) -> io::Result<()> {
+        let _lock = self.0.lock_notifications();
+        self.send_locked(code, notification)
+    }
+
+    /// Send a notification while holding the lock of the channel
+    fn send_locked(&self, code: notify_code, notification: &Notification<'_>) -> io::Result<()> {
         notification
             .with_iovec(code, |iov| self.0.send(iov))
             .map_err(Self::too_big_err)?
@@ -119,3 +135,181 @@ impl Notifier {
         io::Error::new(io::ErrorKind::Other, format!("Data too large: {}", tfie))
     }
 }
+
+/// A notification queued in a [`NotifyTransaction`]
+#[cfg(feature = "abi-7-12")]
+#[derive(Debug)]
+enum Queued<'a> {
+    InvalEntry {
+        parent: u64,
+        name: &'a OsStr,
+    },
+    InvalInode {
+        ino: u64,
+        offset: i64,
+        len: i64,
+    },
+    #[cfg(feature = "abi-7-15")]
+    Store {
+        ino: u64,
+        offset: u64,
+        data: &'a [u8],
+    },
+    #[cfg(feature = "abi-7-18")]
+    Delete {
+        parent: u64,
+        child: u64,
+        name: &'a OsStr,
+    },
+}
+
+#[cfg(feature = "abi-7-12")]
+impl<'a> Queued<'a> {
+    fn notification(&self) -> io::Result<(notify_code, Notification<'a>)> {
+        Ok(match *self {
+            Queued::InvalEntry { parent, name } => (
+                notify_code::FUSE_NOTIFY_INVAL_ENTRY,
+                Notification::new_inval_entry(parent, name).map_err(Notifier::too_big_err)?,
+            ),
+            Queued::InvalInode { ino, offset, len } => (
+                notify_code::FUSE_NOTIFY_INVAL_INODE,
+                Notification::new_inval_inode(ino, offset, len),
+            ),
+            #[cfg(feature = "abi-7-15")]
+            Queued::Store { ino, offset, data } => (
+                notify_code::FUSE_NOTIFY_STORE,
+                Notification::new_store(ino, offset, data).map_err(Notifier::too_big_err)?,
+            ),
+            #[cfg(feature = "abi-7-18")]
+            Queued::Delete {
+                parent,
+                child,
+                name,
+            } => (
+                notify_code::FUSE_NOTIFY_DELETE,
+                Notification::new_delete(parent, child, name).map_err(Notifier::too_big_err)?,
+            ),
+        })
+    }
+}
+
+/// Notifications that are sent together, see [`Notifier::transaction`]
+///
+/// The kernel handles each notification on its own, so this isn't atomic: if sending one
+/// fails, those before it have been applied and those after it are dropped. What it does
+/// guarantee is the order, e.g. that data is stored before the entry pointing to it is
+/// invalidated, and that nothing is sent if one of the notifications can't be encoded.
+#[cfg(feature = "abi-7-12")]
+#[derive(Debug)]
+#[must_use = "notifications are only sent by commit"]
+pub struct NotifyTransaction<'a> {
+    notifier: &'a Notifier,
+    queued: Vec<Queued<'a>>,
+}
+
+#[cfg(feature = "abi-7-12")]
+impl<'a> NotifyTransaction<'a> {
+    /// Queue an invalidation of a directory entry, see [`Notifier::inval_entry`]
+    pub fn inval_entry(&mut self, parent: u64, name: &'a OsStr) -> &mut Self {
+        self.queued.push(Queued::InvalEntry { parent, name });
+        self
+    }
+
+    /// Queue an invalidation of an inode, see [`Notifier::inval_inode`]
+    pub fn inval_inode(&mut self, ino: u64, offset: i64, len: i64) -> &mut Self {
+        self.queued.push(Queued::InvalInode { ino, offset, len });
+        self
+    }
+
+    /// Queue an update of the cached data of an inode, see [`Notifier::store`]
+    #[cfg(feature = "abi-7-15")]
+    pub fn store(&mut self, ino: u64, offset: u64, data: &'a [u8]) -> &mut Self {
+        self.queued.push(Queued::Store { ino, offset, data });
+        self
+    }
+
+    /// Queue the deletion of a directory entry, see [`Notifier::delete`]
+    #[cfg(feature = "abi-7-18")]
+    pub fn delete(&mut self, parent: u64, child: u64, name: &'a OsStr) -> &mut Self {
+        self.queued.push(Queued::Delete {
+            parent,
+            child,
+            name,
+        });
+        self
+    }
+
+    /// Returns the number of queued notifications
+    pub fn len(&self) -> usize {
+        self.queued.len()
+    }
+
+    /// Returns whether no notification is queued
+    pub fn is_empty(&self) -> bool {
+        self.queued.is_empty()
+    }
+
+    /// Send the queued notifications in the order they were queued. Stops at the first one
+    /// that fails, and returns its error. As with the single notifications, `ENOENT` is
+    /// ignored.
+    pub fn commit(self) -> io::Result<()> {
+        let notifications = self
+            .queued
+            .iter()
+            .map(Queued::notification)
+            .collect::<io::Result<Vec<_>>>()?;
+        let count = notifications.len();
+        let _lock = self.notifier.0.lock_notifications();
+        for (index, (code, notification)) in notifications.into_iter().enumerate() {
+            match self.notifier.send_locked(code, &notification) {
+                Err(e) if e.kind() != io::ErrorKind::NotFound => {
+                    return Err(io::Error::new(
+                        e.kind(),
+                        format!("notification {} of {} failed: {}", index + 1, count, e),
+                    ));
+                }
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(all(test, feature = "abi-7-12"))]
+mod test {
+    use super::Notifier;
+    use crate::channel::Channel;
+    use std::ffi::OsStr;
+    use std::io::{Read, Seek, SeekFrom};
+    use std::sync::Arc;
+
+    #[test]
+    fn transaction_keeps_order() {
+        let mut device = tempfile::tempfile().unwrap();
+        let channel = Channel::new(Arc::new(device.try_clone().unwrap()));
+        let notifier = Notifier::new(channel.sender());
+        let mut transaction = notifier.transaction();
+        transaction
+            .inval_inode(2, 0, -1)
+            .inval_entry(1, OsStr::new("a"));
+        #[cfg(feature = "abi-7-15")]
+        transaction.store(2, 0, b"data");
+        transaction.inval_entry(1, OsStr::new("b"));
+        transaction.commit().unwrap();
+
+        let mut sent = vec![];
+        device.seek(SeekFrom::Start(0)).unwrap();
+        device.read_to_end(&mut sent).unwrap();
+        let mut codes = vec![];
+        while !sent.is_empty() {
+            let len = u32::from_ne_bytes(sent[..4].try_into().unwrap()) as usize;
+            codes.push(i32::from_ne_bytes(sent[4..8].try_into().unwrap()));
+            sent.drain(..len);
+        }
+        let mut expected = vec![2, 3];
+        #[cfg(feature = "abi-7-15")]
+        expected.push(4);
+        expected.push(3);
+        assert_eq!(codes, expected);
+    }
+}
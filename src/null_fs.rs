@@ -0,0 +1,364 @@
+//! A filesystem that does nothing
+//!
+//! To measure what a session costs per request, or to reproduce bugs that depend on how long
+//! the filesystem takes, the filesystem itself must not add work of its own. [`NullFs`] answers
+//! every request right away, or after a configurable latency with jitter: it has a single
+//! directory in which every name exists, reads end at once and writes are discarded.
+
+use std::ffi::OsStr;
+use std::thread;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use libc::ENOENT;
+
+use crate::consts::FOPEN_DIRECT_IO;
+use crate::{
+    FileAttr, FileType, Filesystem, ReplyAttr, ReplyCreate, ReplyData, ReplyDirectory, ReplyEmpty,
+    ReplyEntry, ReplyOpen, ReplyWrite, Request, TimeOrNow, FUSE_ROOT_ID,
+};
+
+/// The inode of every file
+const FILE_INO: u64 = 2;
+
+/// A filesystem whose operations succeed without doing anything, for benchmarks
+///
+/// The root directory looks empty, but looking up any name in it finds a file, which is
+/// always empty: reads return no data and writes succeed without storing anything. Creating,
+/// renaming and removing files succeeds as well. Files are opened with `FOPEN_DIRECT_IO` and
+/// replies aren't cached by default, so every operation reaches the filesystem.
+#[derive(Debug)]
+pub struct NullFs {
+    latency: Duration,
+    jitter: Duration,
+    ttl: Duration,
+    /// State of the generator of the jitter
+    seed: u64,
+}
+
+impl Default for NullFs {
+    fn default() -> Self {
+        Self {
+            latency: Duration::ZERO,
+            jitter: Duration::ZERO,
+            ttl: Duration::ZERO,
+            seed: 0x853c_49e6_748f_ea9b,
+        }
+    }
+}
+
+impl NullFs {
+    /// Create a filesystem that answers every request right away.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Delay every reply by `latency`.
+    pub fn with_latency(mut self, latency: Duration) -> Self {
+        self.latency = latency;
+        self
+    }
+
+    /// Delay every reply by a random duration of up to `jitter` on top of the latency.
+    pub fn with_jitter(mut self, jitter: Duration) -> Self {
+        self.jitter = jitter;
+        self
+    }
+
+    /// Seed the random jitter, to repeat the delays of an earlier run. The delays only depend
+    /// on the seed and the order of the requests.
+    pub fn with_seed(mut self, seed: u64) -> Self {
+        // xorshift gets stuck at 0
+        self.seed = seed.max(1);
+        self
+    }
+
+    /// Let the kernel cache entries and attributes for `ttl`. Defaults to zero, which sends
+    /// every lookup and `getattr` to the filesystem.
+    pub fn with_ttl(mut self, ttl: Duration) -> Self {
+        self.ttl = ttl;
+        self
+    }
+
+    /// Returns how long the next reply is delayed
+    fn next_delay(&mut self) -> Duration {
+        if self.jitter.is_zero() {
+            return self.latency;
+        }
+        // xorshift64, good enough to spread delays
+        self.seed ^= self.seed << 13;
+        self.seed ^= self.seed >> 7;
+        self.seed ^= self.seed << 17;
+        let nanos = self.seed % self.jitter.as_nanos().min(u64::MAX as u128) as u64;
+        self.latency + Duration::from_nanos(nanos)
+    }
+
+    fn delay(&mut self) {
+        let delay = self.next_delay();
+        if !delay.is_zero() {
+            thread::sleep(delay);
+        }
+    }
+
+    fn attr(&self, ino: u64) -> Option<FileAttr> {
+        let (kind, perm, nlink) = match ino {
+            FUSE_ROOT_ID => (FileType::Directory, 0o777, 2),
+            FILE_INO => (FileType::RegularFile, 0o666, 1),
+            _ => return None,
+        };
+        Some(FileAttr {
+            ino,
+            size: 0,
+            blocks: 0,
+            atime: UNIX_EPOCH,
+            mtime: UNIX_EPOCH,
+            ctime: UNIX_EPOCH,
+            crtime: UNIX_EPOCH,
+            kind,
+            perm,
+            nlink,
+            uid: 0,
+            gid: 0,
+            rdev: 0,
+            blksize: 4096,
+            flags: 0,
+        })
+    }
+
+    /// Reply with the file if `parent` is the root directory
+    fn entry(&mut self, parent: u64, reply: ReplyEntry) {
+        self.delay();
+        match parent {
+            FUSE_ROOT_ID => reply.entry(self.ttl, &self.attr(FILE_INO).unwrap(), 0),
+            _ => reply.error(ENOENT),
+        }
+    }
+
+    fn empty(&mut self, reply: ReplyEmpty) {
+        self.delay();
+        reply.ok();
+    }
+}
+
+impl Filesystem for NullFs {
+    fn lookup(&mut self, _req: &Request<'_>, parent: u64, _name: &OsStr, reply: ReplyEntry) {
+        self.entry(parent, reply);
+    }
+
+    fn getattr(&mut self, _req: &Request<'_>, ino: u64, _fh: Option<u64>, reply: ReplyAttr) {
+        self.delay();
+        match self.attr(ino) {
+            Some(attr) => reply.attr(self.ttl, &attr),
+            None => reply.error(ENOENT),
+        }
+    }
+
+    fn setattr(
+        &mut self,
+        req: &Request<'_>,
+        ino: u64,
+        _mode: Option<u32>,
+        _uid: Option<u32>,
+        _gid: Option<u32>,
+        _size: Option<u64>,
+        _atime: Option<TimeOrNow>,
+        _mtime: Option<TimeOrNow>,
+        _ctime: Option<SystemTime>,
+        fh: Option<u64>,
+        _crtime: Option<SystemTime>,
+        _chgtime: Option<SystemTime>,
+        _bkuptime: Option<SystemTime>,
+        _flags: Option<u32>,
+        reply: ReplyAttr,
+    ) {
+        // Nothing can be changed
+        self.getattr(req, ino, fh, reply);
+    }
+
+    fn mknod(
+        &mut self,
+        _req: &Request<'_>,
+        parent: u64,
+        _name: &OsStr,
+        _mode: u32,
+        _umask: u32,
+        _rdev: u32,
+        reply: ReplyEntry,
+    ) {
+        self.entry(parent, reply);
+    }
+
+    fn unlink(&mut self, _req: &Request<'_>, _parent: u64, _name: &OsStr, reply: ReplyEmpty) {
+        self.empty(reply);
+    }
+
+    fn rename(
+        &mut self,
+        _req: &Request<'_>,
+        _parent: u64,
+        _name: &OsStr,
+        _newparent: u64,
+        _newname: &OsStr,
+        _flags: u32,
+        reply: ReplyEmpty,
+    ) {
+        self.empty(reply);
+    }
+
+    fn open(&mut self, _req: &Request<'_>, _ino: u64, _flags: i32, reply: ReplyOpen) {
+        self.delay();
+        reply.opened(0, FOPEN_DIRECT_IO);
+    }
+
+    fn read(
+        &mut self,
+        _req: &Request<'_>,
+        _ino: u64,
+        _fh: u64,
+        _offset: i64,
+        _size: u32,
+        _flags: i32,
+        _lock_owner: Option<u64>,
+        reply: ReplyData,
+    ) {
+        self.delay();
+        reply.data(&[]);
+    }
+
+    fn write(
+        &mut self,
+        _req: &Request<'_>,
+        _ino: u64,
+        _fh: u64,
+        _offset: i64,
+        data: &[u8],
+        _write_flags: u32,
+        _flags: i32,
+        _lock_owner: Option<u64>,
+        reply: ReplyWrite,
+    ) {
+        self.delay();
+        reply.written(data.len() as u32);
+    }
+
+    fn flush(
+        &mut self,
+        _req: &Request<'_>,
+        _ino: u64,
+        _fh: u64,
+        _lock_owner: u64,
+        reply: ReplyEmpty,
+    ) {
+        self.empty(reply);
+    }
+
+    fn release(
+        &mut self,
+        _req: &Request<'_>,
+        _ino: u64,
+        _fh: u64,
+        _flags: i32,
+        _lock_owner: Option<u64>,
+        _flush: bool,
+        reply: ReplyEmpty,
+    ) {
+        self.empty(reply);
+    }
+
+    fn fsync(
+        &mut self,
+        _req: &Request<'_>,
+        _ino: u64,
+        _fh: u64,
+        _datasync: bool,
+        reply: ReplyEmpty,
+    ) {
+        self.empty(reply);
+    }
+
+    fn readdir(
+        &mut self,
+        _req: &Request<'_>,
+        ino: u64,
+        _fh: u64,
+        offset: i64,
+        mut reply: ReplyDirectory,
+    ) {
+        self.delay();
+        if ino != FUSE_ROOT_ID {
+            reply.error(ENOENT);
+            return;
+        }
+        if offset == 0 {
+            let _ = reply.add(FUSE_ROOT_ID, 1, FileType::Directory, ".");
+            let _ = reply.add(FUSE_ROOT_ID, 2, FileType::Directory, "..");
+        }
+        reply.ok();
+    }
+
+    fn access(&mut self, _req: &Request<'_>, _ino: u64, _mask: i32, reply: ReplyEmpty) {
+        self.empty(reply);
+    }
+
+    fn create(
+        &mut self,
+        _req: &Request<'_>,
+        parent: u64,
+        _name: &OsStr,
+        _mode: u32,
+        _umask: u32,
+        _flags: i32,
+        reply: ReplyCreate,
+    ) {
+        self.delay();
+        match parent {
+            FUSE_ROOT_ID => reply.created(
+                self.ttl,
+                &self.attr(FILE_INO).unwrap(),
+                0,
+                0,
+                FOPEN_DIRECT_IO,
+            ),
+            _ => reply.error(ENOENT),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::NullFs;
+    use crate::FileType;
+    use std::time::Duration;
+
+    #[test]
+    fn delays() {
+        let mut fs = NullFs::new();
+        assert_eq!(fs.next_delay(), Duration::ZERO);
+
+        let latency = Duration::from_millis(5);
+        let jitter = Duration::from_millis(2);
+        let mut fs = NullFs::new()
+            .with_latency(latency)
+            .with_jitter(jitter)
+            .with_seed(7);
+        let delays: Vec<Duration> = (0..100).map(|_| fs.next_delay()).collect();
+        assert!(delays
+            .iter()
+            .all(|d| *d >= latency && *d < latency + jitter));
+        assert!(delays.iter().any(|d| *d != delays[0]));
+
+        // The same seed gives the same delays
+        let mut again = NullFs::new()
+            .with_latency(latency)
+            .with_jitter(jitter)
+            .with_seed(7);
+        assert!(delays.iter().all(|d| *d == again.next_delay()));
+    }
+
+    #[test]
+    fn attrs() {
+        let fs = NullFs::new();
+        assert_eq!(fs.attr(1).unwrap().kind, FileType::Directory);
+        assert_eq!(fs.attr(2).unwrap().kind, FileType::RegularFile);
+        assert!(fs.attr(3).is_none());
+    }
+}
@@ -0,0 +1,116 @@
+//! Typed access to the flags of `open` and `create` requests
+
+use libc::c_int;
+
+/// Flags a file was opened with, as passed to [`Filesystem::open`](crate::Filesystem::open),
+/// [`Filesystem::create`](crate::Filesystem::create) and [`Filesystem::write`](crate::Filesystem::write).
+///
+/// Flags that are handled by the kernel may be missing: `O_CREAT`, `O_EXCL` and `O_NOCTTY`
+/// are never passed to `open`, and `O_TRUNC` only if atomic truncation on open
+/// (`FUSE_ATOMIC_O_TRUNC`) is enabled. Otherwise the kernel truncates with `setattr`.
+///
+/// `O_APPEND` deserves attention: unless the writeback cache is enabled, the offset of a write
+/// to a file opened with it is the end of file as cached by the kernel, which may be stale.
+/// Filesystems should append such writes to their own end of file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct OpenFlags(pub c_int);
+
+impl OpenFlags {
+    /// Returns the raw flags
+    pub fn bits(&self) -> c_int {
+        self.0
+    }
+
+    fn has(&self, flag: c_int) -> bool {
+        self.0 & flag != 0
+    }
+
+    /// Whether the file is open for reading
+    pub fn read(&self) -> bool {
+        matches!(self.0 & libc::O_ACCMODE, libc::O_RDONLY | libc::O_RDWR)
+    }
+
+    /// Whether the file is open for writing
+    pub fn write(&self) -> bool {
+        matches!(self.0 & libc::O_ACCMODE, libc::O_WRONLY | libc::O_RDWR)
+    }
+
+    /// `O_APPEND`: writes append to the end of the file
+    pub fn append(&self) -> bool {
+        self.has(libc::O_APPEND)
+    }
+
+    /// `O_TRUNC`: the file is truncated on open
+    pub fn truncate(&self) -> bool {
+        self.has(libc::O_TRUNC)
+    }
+
+    /// `O_CREAT`: the file is created if it doesn't exist
+    pub fn creat(&self) -> bool {
+        self.has(libc::O_CREAT)
+    }
+
+    /// `O_EXCL`: with `O_CREAT`, creating the file fails if it exists
+    pub fn excl(&self) -> bool {
+        self.has(libc::O_EXCL)
+    }
+
+    /// `O_SYNC`: writes return once the data and metadata are on stable storage
+    pub fn sync(&self) -> bool {
+        self.0 & libc::O_SYNC == libc::O_SYNC
+    }
+
+    /// `O_DSYNC`: writes return once the data is on stable storage
+    pub fn dsync(&self) -> bool {
+        self.has(libc::O_DSYNC)
+    }
+
+    /// `O_NONBLOCK`: operations shouldn't block
+    pub fn nonblock(&self) -> bool {
+        self.has(libc::O_NONBLOCK)
+    }
+
+    /// `O_DIRECT`: I/O should bypass caches
+    #[cfg(any(target_os = "linux", target_os = "freebsd"))]
+    pub fn direct(&self) -> bool {
+        self.has(libc::O_DIRECT)
+    }
+
+    /// `O_NOATIME`: reads don't update the access time
+    #[cfg(target_os = "linux")]
+    pub fn noatime(&self) -> bool {
+        self.has(libc::O_NOATIME)
+    }
+}
+
+impl From<c_int> for OpenFlags {
+    fn from(flags: c_int) -> Self {
+        OpenFlags(flags)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::OpenFlags;
+
+    #[test]
+    fn accessors() {
+        let flags = OpenFlags::from(libc::O_WRONLY | libc::O_APPEND | libc::O_CREAT);
+        assert!(!flags.read());
+        assert!(flags.write());
+        assert!(flags.append());
+        assert!(flags.creat());
+        assert!(!flags.truncate());
+        assert!(!flags.excl());
+
+        let flags = OpenFlags::from(libc::O_RDWR | libc::O_TRUNC | libc::O_DSYNC);
+        assert!(flags.read());
+        assert!(flags.write());
+        assert!(!flags.append());
+        assert!(flags.truncate());
+        assert!(flags.dsync());
+        assert!(!flags.sync());
+
+        assert!(OpenFlags::from(libc::O_RDONLY).read());
+    }
+}
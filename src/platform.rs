@@ -0,0 +1,153 @@
+//! Differences between the FUSE implementations of the supported platforms
+//!
+//! Filesystems that run on several platforms shouldn't need `cfg` blocks for every difference
+//! in what the kernel supports. [`Platform`] describes the current one at runtime, and has
+//! helpers to reject requests the platform would reject itself.
+
+use libc::c_int;
+
+use crate::FileType;
+
+/// Fail if the target exists (`RENAME_NOREPLACE`)
+const RENAME_NOREPLACE: u32 = 1 << 0;
+/// Atomically exchange source and target (`RENAME_EXCHANGE`)
+pub(crate) const RENAME_EXCHANGE: u32 = 1 << 1;
+/// Leave a whiteout at the source (`RENAME_WHITEOUT`)
+const RENAME_WHITEOUT: u32 = 1 << 2;
+
+/// Sticky bit of a file mode. `mode_t` isn't `u32` everywhere.
+const S_ISVTX: u32 = 0o1000;
+
+/// Features of the FUSE implementation of the platform fuser was built for
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Platform {
+    rename_flags: u32,
+    atime_now: bool,
+    sticky_file_error: Option<c_int>,
+}
+
+impl Platform {
+    /// Returns the features of the current platform
+    pub fn current() -> Platform {
+        if cfg!(target_os = "linux") {
+            Platform {
+                rename_flags: RENAME_NOREPLACE | RENAME_EXCHANGE | RENAME_WHITEOUT,
+                atime_now: true,
+                sticky_file_error: None,
+            }
+        } else if cfg!(target_os = "freebsd") {
+            Platform {
+                rename_flags: 0,
+                // fusefs sets atime to the current time explicitly
+                atime_now: false,
+                sticky_file_error: Some(EFTYPE),
+            }
+        } else {
+            Platform {
+                rename_flags: 0,
+                atime_now: cfg!(target_os = "macos"),
+                sticky_file_error: None,
+            }
+        }
+    }
+
+    /// Whether `rename` may be called with `RENAME_NOREPLACE`
+    pub fn rename_noreplace(&self) -> bool {
+        self.rename_flags & RENAME_NOREPLACE != 0
+    }
+
+    /// Whether `rename` may be called with `RENAME_EXCHANGE`
+    pub fn rename_exchange(&self) -> bool {
+        self.rename_flags & RENAME_EXCHANGE != 0
+    }
+
+    /// Whether `setattr` may be asked to set the access or modification time to the current
+    /// time ([`TimeOrNow::Now`](crate::TimeOrNow::Now)) rather than a specific time
+    pub fn atime_now(&self) -> bool {
+        self.atime_now
+    }
+
+    /// Returns the error to fail `rename` with, if the platform doesn't support the flags.
+    /// Requests with unsupported flags are rejected before they reach the filesystem.
+    pub fn check_rename_flags(&self, flags: u32) -> Result<(), c_int> {
+        if flags & !self.rename_flags != 0 {
+            Err(libc::EINVAL)
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Returns the error to fail a `setattr` of `mode` on a file of the given kind with, if the
+    /// platform doesn't allow unprivileged users to set the sticky bit on it (FreeBSD fails
+    /// with EFTYPE). Filesystems that don't use `default_permissions` should call it from
+    /// [`Filesystem::setattr`](crate::Filesystem::setattr).
+    pub fn check_sticky(&self, kind: FileType, mode: u32, uid: u32) -> Result<(), c_int> {
+        match self.sticky_file_error {
+            Some(err) if kind != FileType::Directory && mode & S_ISVTX != 0 && uid != 0 => Err(err),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// "Inappropriate file type or format", only defined by the BSDs. Other targets only use it
+/// to describe FreeBSD, so they fall back to the nearest error they have.
+#[cfg(any(
+    target_os = "freebsd",
+    target_os = "dragonfly",
+    target_os = "macos",
+    target_os = "netbsd",
+    target_os = "openbsd"
+))]
+const EFTYPE: c_int = libc::EFTYPE;
+#[cfg(not(any(
+    target_os = "freebsd",
+    target_os = "dragonfly",
+    target_os = "macos",
+    target_os = "netbsd",
+    target_os = "openbsd"
+)))]
+const EFTYPE: c_int = libc::EINVAL;
+
+#[cfg(test)]
+mod test {
+    use super::Platform;
+    use crate::FileType;
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn linux() {
+        let platform = Platform::current();
+        assert!(platform.rename_exchange());
+        assert!(platform.atime_now());
+        assert_eq!(platform.check_rename_flags(libc::RENAME_EXCHANGE), Ok(()));
+        assert_eq!(platform.check_rename_flags(1 << 10), Err(libc::EINVAL));
+        assert_eq!(
+            platform.check_sticky(FileType::RegularFile, 0o1644, 1000),
+            Ok(())
+        );
+    }
+
+    #[test]
+    fn freebsd_quirks() {
+        let platform = Platform {
+            rename_flags: 0,
+            atime_now: false,
+            sticky_file_error: Some(super::EFTYPE),
+        };
+        assert!(!platform.rename_exchange());
+        assert_eq!(platform.check_rename_flags(0), Ok(()));
+        assert_eq!(platform.check_rename_flags(2), Err(libc::EINVAL));
+        assert_eq!(
+            platform.check_sticky(FileType::RegularFile, 0o1644, 1000),
+            Err(super::EFTYPE)
+        );
+        assert_eq!(
+            platform.check_sticky(FileType::RegularFile, 0o1644, 0),
+            Ok(())
+        );
+        assert_eq!(
+            platform.check_sticky(FileType::Directory, 0o1755, 1000),
+            Ok(())
+        );
+    }
+}
@@ -0,0 +1,73 @@
+//! Typed poll events
+//!
+//! The events of `poll` requests and replies are the `POLL*` flags of `poll(2)`. libc defines
+//! them as `c_short`, while the FUSE protocol carries them as `u32`, so filesystems had to cast
+//! every flag they reported. [`PollEvents`] holds them as flags instead, and converts from and
+//! to both types.
+
+use bitflags::bitflags;
+use libc::c_short;
+
+bitflags! {
+    /// Events of a [`Filesystem::poll`](crate::Filesystem::poll) request or reply
+    ///
+    /// Bits without a name here are kept, so the events the kernel asks for are passed on
+    /// unchanged.
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+    pub struct PollEvents: u32 {
+        /// `POLLIN`: there is data to read
+        const IN = libc::POLLIN as u16 as u32;
+        /// `POLLPRI`: there is urgent data to read
+        const PRI = libc::POLLPRI as u16 as u32;
+        /// `POLLOUT`: writing won't block
+        const OUT = libc::POLLOUT as u16 as u32;
+        /// `POLLERR`: an error occurred (only valid in replies)
+        const ERR = libc::POLLERR as u16 as u32;
+        /// `POLLHUP`: the other end hung up (only valid in replies)
+        const HUP = libc::POLLHUP as u16 as u32;
+        /// `POLLRDNORM`: there is normal data to read
+        const RDNORM = libc::POLLRDNORM as u16 as u32;
+        /// `POLLWRNORM`: writing normal data won't block
+        const WRNORM = libc::POLLWRNORM as u16 as u32;
+        /// `POLLRDHUP`: the other end shut down writing
+        #[cfg(target_os = "linux")]
+        const RDHUP = libc::POLLRDHUP as u16 as u32;
+
+        const _ = !0;
+    }
+}
+
+impl PollEvents {
+    /// The events the kernel reports for files of filesystems that don't implement `poll`, see
+    /// [`DEFAULT_POLLMASK`](crate::consts::DEFAULT_POLLMASK)
+    pub const DEFAULT: Self = Self::from_bits_retain(crate::consts::DEFAULT_POLLMASK);
+}
+
+impl From<c_short> for PollEvents {
+    fn from(events: c_short) -> Self {
+        Self::from_bits_retain(events as u16 as u32)
+    }
+}
+
+impl From<PollEvents> for c_short {
+    /// Drops the bits that don't fit a `c_short`, which `poll(2)` doesn't define.
+    fn from(events: PollEvents) -> Self {
+        events.bits() as u16 as c_short
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::PollEvents;
+    use libc::c_short;
+
+    #[test]
+    fn libc_conversions() {
+        let events = PollEvents::from(libc::POLLIN | libc::POLLOUT);
+        assert_eq!(events, PollEvents::IN | PollEvents::OUT);
+        assert_eq!(c_short::from(events), libc::POLLIN | libc::POLLOUT);
+        assert!(PollEvents::DEFAULT.contains(PollEvents::IN | PollEvents::WRNORM));
+        // Unknown bits survive the round trip
+        assert_eq!(PollEvents::from_bits_retain(1 << 20).bits(), 1 << 20);
+    }
+}
@@ -0,0 +1,117 @@
+//! Bookkeeping of pending polls
+//!
+//! A poll handle is only good for a single notification: once notified, the kernel polls the
+//! file again and, if it still isn't ready, sends a new handle to wait on. Filesystems that
+//! notify from another thread therefore have to keep the latest handle of each open file,
+//! drop it when it was used, and forget it when the file is released, or they notify polls
+//! that are long gone. A [`PollRegistry`] does this bookkeeping, shared between the filesystem
+//! and the threads that notify.
+
+use std::collections::HashMap;
+use std::io;
+use std::sync::{Arc, Mutex};
+
+use crate::PollHandle;
+
+/// The pending poll of each open file handle. Clones share the same registry, so one can be
+/// kept by the filesystem and another one moved into the thread producing events.
+#[derive(Debug, Clone, Default)]
+pub struct PollRegistry {
+    handles: Arc<Mutex<HashMap<u64, PollHandle>>>,
+}
+
+impl PollRegistry {
+    /// Create an empty registry.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Wait for the file handle `fh` to become ready, replacing the handle of an earlier poll
+    /// of it. Call it from [`Filesystem::poll`](crate::Filesystem::poll) when the flags contain
+    /// [`FUSE_POLL_SCHEDULE_NOTIFY`](crate::consts::FUSE_POLL_SCHEDULE_NOTIFY).
+    pub fn register(&self, fh: u64, ph: PollHandle) {
+        self.handles.lock().unwrap().insert(fh, ph);
+    }
+
+    /// Notify the kernel that `fh` is ready. Returns whether a poll was waiting for it; the
+    /// handle is used up either way, and the next poll registers a new one.
+    pub fn notify(&self, fh: u64) -> io::Result<bool> {
+        // Don't hold the lock while writing to the channel
+        let ph = self.handles.lock().unwrap().remove(&fh);
+        match ph {
+            Some(ph) => ph.notify().map(|()| true),
+            None => Ok(false),
+        }
+    }
+
+    /// Notify the kernel that all file handles with a pending poll are ready. Returns how many
+    /// polls were notified. Stops at the first error; handles that weren't notified yet stay
+    /// registered.
+    pub fn notify_all(&self) -> io::Result<usize> {
+        let handles = std::mem::take(&mut *self.handles.lock().unwrap());
+        let mut handles = handles.into_iter();
+        let mut notified = 0;
+        while let Some((_, ph)) = handles.next() {
+            if let Err(err) = ph.notify() {
+                let mut registered = self.handles.lock().unwrap();
+                for (fh, ph) in handles {
+                    registered.entry(fh).or_insert(ph);
+                }
+                return Err(err);
+            }
+            notified += 1;
+        }
+        Ok(notified)
+    }
+
+    /// Forget the pending poll of `fh`, if any. Call it from
+    /// [`Filesystem::release`](crate::Filesystem::release).
+    pub fn release(&self, fh: u64) {
+        self.handles.lock().unwrap().remove(&fh);
+    }
+
+    /// Returns the number of file handles with a pending poll
+    pub fn len(&self) -> usize {
+        self.handles.lock().unwrap().len()
+    }
+
+    /// Returns whether no poll is pending
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::PollRegistry;
+    use crate::channel::Channel;
+    use crate::PollHandle;
+    use std::io::{Read, Seek, SeekFrom};
+    use std::sync::Arc;
+
+    #[test]
+    fn handles_are_used_once() {
+        let mut device = tempfile::tempfile().unwrap();
+        let channel = Channel::new(Arc::new(device.try_clone().unwrap()));
+        let registry = PollRegistry::new();
+        registry.register(1, PollHandle::new(channel.sender(), 10));
+        registry.register(1, PollHandle::new(channel.sender(), 11));
+        registry.register(2, PollHandle::new(channel.sender(), 20));
+        registry.register(3, PollHandle::new(channel.sender(), 30));
+        assert_eq!(registry.len(), 3);
+
+        registry.release(3);
+        assert!(registry.notify(1).unwrap());
+        assert!(!registry.notify(1).unwrap());
+        assert_eq!(registry.notify_all().unwrap(), 1);
+        assert!(registry.is_empty());
+
+        // Two notifications, each a header and the kernel's handle
+        let mut sent = vec![];
+        device.seek(SeekFrom::Start(0)).unwrap();
+        device.read_to_end(&mut sent).unwrap();
+        assert_eq!(sent.len(), 2 * 24);
+        assert_eq!(sent[16..24], 11u64.to_ne_bytes());
+        assert_eq!(sent[40..48], 20u64.to_ne_bytes());
+    }
+}
@@ -0,0 +1,315 @@
+//! Crash consistency of renames
+//!
+//! Network filesystems often can't rename atomically: the backend copies the object to its new
+//! name and deletes the old one, or updates two directory listings one after the other. A crash
+//! between the steps leaves both names, or neither. A [`RenameJournal`] records the intent of a
+//! rename durably before the backend is touched and marks it done once the rename was answered,
+//! so that on restart the renames that were interrupted can be completed or rolled back.
+//! Installed on the session (see
+//! [`Session::set_rename_journal`](crate::Session::set_rename_journal)), it records every
+//! `rename` and `exchange` before the filesystem sees them.
+
+use std::ffi::{OsStr, OsString};
+use std::fs::{File, OpenOptions};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::os::unix::ffi::{OsStrExt, OsStringExt};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+
+use libc::c_int;
+use log::warn;
+
+const BEGIN: u8 = 1;
+const DONE: u8 = 2;
+
+/// A rename that was started
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RenameIntent {
+    /// Identifies the rename within the journal
+    pub id: u64,
+    /// The directory of the entry
+    pub parent: u64,
+    /// The name of the entry
+    pub name: OsString,
+    /// The directory the entry is moved into
+    pub new_parent: u64,
+    /// The new name of the entry
+    pub new_name: OsString,
+    /// The flags of the rename (e.g. `RENAME_NOREPLACE`), or the options of an `exchange`
+    pub flags: u32,
+    /// Whether the two entries are swapped instead of moving one onto the other
+    pub exchange: bool,
+}
+
+impl RenameIntent {
+    fn encode(&self, buf: &mut Vec<u8>) {
+        buf.push(BEGIN);
+        buf.extend_from_slice(&self.id.to_le_bytes());
+        buf.extend_from_slice(&self.parent.to_le_bytes());
+        buf.extend_from_slice(&self.new_parent.to_le_bytes());
+        buf.extend_from_slice(&self.flags.to_le_bytes());
+        buf.push(self.exchange.into());
+        for name in [&self.name, &self.new_name] {
+            buf.extend_from_slice(&(name.len() as u32).to_le_bytes());
+            buf.extend_from_slice(name.as_bytes());
+        }
+    }
+}
+
+/// Reads the records of a journal. Stops at the first record that is incomplete, which is
+/// the one a crash interrupted.
+struct Records<'a>(&'a [u8]);
+
+impl Records<'_> {
+    fn take(&mut self, len: usize) -> Option<&[u8]> {
+        if self.0.len() < len {
+            return None;
+        }
+        let (head, rest) = self.0.split_at(len);
+        self.0 = rest;
+        Some(head)
+    }
+
+    fn u64(&mut self) -> Option<u64> {
+        Some(u64::from_le_bytes(self.take(8)?.try_into().unwrap()))
+    }
+
+    fn u32(&mut self) -> Option<u32> {
+        Some(u32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    fn name(&mut self) -> Option<OsString> {
+        let len = self.u32()? as usize;
+        Some(OsString::from_vec(self.take(len)?.to_vec()))
+    }
+}
+
+/// A `BEGIN` record with its intent, or a `DONE` record with the id of its rename
+enum Record {
+    Begin(RenameIntent),
+    Done(u64),
+}
+
+impl Iterator for Records<'_> {
+    type Item = Record;
+
+    fn next(&mut self) -> Option<Record> {
+        match *self.take(1)?.first()? {
+            BEGIN => {
+                let id = self.u64()?;
+                let parent = self.u64()?;
+                let new_parent = self.u64()?;
+                let flags = self.u32()?;
+                let exchange = *self.take(1)?.first()? != 0;
+                Some(Record::Begin(RenameIntent {
+                    id,
+                    parent,
+                    name: self.name()?,
+                    new_parent,
+                    new_name: self.name()?,
+                    flags,
+                    exchange,
+                }))
+            }
+            DONE => Some(Record::Done(self.u64()?)),
+            _ => None,
+        }
+    }
+}
+
+/// A durable log of the renames in progress
+///
+/// [`begin`](RenameJournal::begin) returns once the intent is on stable storage, so a rename
+/// whose backend changes started is always found by [`pending`](RenameJournal::pending) after
+/// a crash. Marking it [done](RenameJournal::complete) isn't synced, so a rename may still be
+/// pending after it completed: completing or rolling back a rename must be idempotent. The file
+/// is truncated whenever no rename is pending.
+#[derive(Debug)]
+pub struct RenameJournal {
+    file: File,
+    /// Renames that were started but not completed
+    pending: Vec<RenameIntent>,
+    next_id: u64,
+}
+
+impl RenameJournal {
+    /// Open the journal stored at `path`, creating it if it doesn't exist. The renames a crash
+    /// interrupted are [pending](RenameJournal::pending).
+    pub fn open<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let mut file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(path)?;
+        let mut data = vec![];
+        file.read_to_end(&mut data)?;
+        let mut pending: Vec<RenameIntent> = vec![];
+        let mut next_id = 1;
+        for record in Records(&data) {
+            match record {
+                Record::Begin(intent) => {
+                    next_id = next_id.max(intent.id + 1);
+                    pending.push(intent);
+                }
+                Record::Done(id) => pending.retain(|i| i.id != id),
+            }
+        }
+        let mut journal = Self {
+            file,
+            pending,
+            next_id,
+        };
+        // Drop completed renames and a record torn by the crash
+        journal.rewrite()?;
+        Ok(journal)
+    }
+
+    /// Returns the renames that were started but not completed, in the order they started
+    pub fn pending(&self) -> &[RenameIntent] {
+        &self.pending
+    }
+
+    /// Record durably that a rename is about to start. Returns the intent to pass to
+    /// [`RenameJournal::complete`].
+    pub fn begin(
+        &mut self,
+        parent: u64,
+        name: &OsStr,
+        new_parent: u64,
+        new_name: &OsStr,
+        flags: u32,
+        exchange: bool,
+    ) -> io::Result<RenameIntent> {
+        let intent = RenameIntent {
+            id: self.next_id,
+            parent,
+            name: name.to_owned(),
+            new_parent,
+            new_name: new_name.to_owned(),
+            flags,
+            exchange,
+        };
+        let mut record = vec![];
+        intent.encode(&mut record);
+        self.file.write_all(&record)?;
+        self.file.sync_data()?;
+        self.next_id += 1;
+        self.pending.push(intent.clone());
+        Ok(intent)
+    }
+
+    /// Record that the rename `id` completed or failed without leaving anything to clean up.
+    pub fn complete(&mut self, id: u64) -> io::Result<()> {
+        self.pending.retain(|i| i.id != id);
+        if self.pending.is_empty() {
+            return self.rewrite();
+        }
+        let mut record = vec![DONE];
+        record.extend_from_slice(&id.to_le_bytes());
+        self.file.write_all(&record)
+    }
+
+    /// Complete or roll back every pending rename with `resolve`, and mark those it succeeded
+    /// for as done. Call it on startup, before the filesystem serves requests. Returns the
+    /// number of renames that were resolved; those that failed stay pending.
+    pub fn recover(
+        &mut self,
+        mut resolve: impl FnMut(&RenameIntent) -> Result<(), c_int>,
+    ) -> io::Result<usize> {
+        let mut resolved = 0;
+        for intent in self.pending.clone() {
+            match resolve(&intent) {
+                Ok(()) => {
+                    self.complete(intent.id)?;
+                    resolved += 1;
+                }
+                Err(err) => warn!("Failed to recover rename {:?}: error {}", intent, err),
+            }
+        }
+        Ok(resolved)
+    }
+
+    /// Replace the file's contents with the pending renames
+    fn rewrite(&mut self) -> io::Result<()> {
+        let mut data = vec![];
+        for intent in &self.pending {
+            intent.encode(&mut data);
+        }
+        self.file.set_len(0)?;
+        self.file.seek(SeekFrom::Start(0))?;
+        self.file.write_all(&data)?;
+        self.file.sync_data()
+    }
+}
+
+/// A rename recorded by the session, which is completed once the reply to it is gone
+#[derive(Debug)]
+pub(crate) struct PendingRename {
+    journal: Arc<Mutex<RenameJournal>>,
+    id: u64,
+}
+
+impl PendingRename {
+    pub(crate) fn new(journal: Arc<Mutex<RenameJournal>>, id: u64) -> Self {
+        Self { journal, id }
+    }
+}
+
+impl Drop for PendingRename {
+    fn drop(&mut self) {
+        if let Err(err) = self.journal.lock().unwrap().complete(self.id) {
+            warn!(
+                "Failed to complete rename {} in the journal: {}",
+                self.id, err
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::RenameJournal;
+    use std::ffi::OsStr;
+    use std::io::Write;
+
+    #[test]
+    fn recover_after_crash() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("renames");
+        {
+            let mut journal = RenameJournal::open(&path).unwrap();
+            let a = journal
+                .begin(1, OsStr::new("a"), 2, OsStr::new("b"), 0, false)
+                .unwrap();
+            journal
+                .begin(1, OsStr::new("c"), 1, OsStr::new("d"), 0, true)
+                .unwrap();
+            journal.complete(a.id).unwrap();
+            // A crash in the middle of the next record
+            journal.file.write_all(&[1, 7, 0]).unwrap();
+        }
+
+        let mut journal = RenameJournal::open(&path).unwrap();
+        assert_eq!(journal.pending().len(), 1);
+        let intent = journal.pending()[0].clone();
+        assert_eq!(intent.id, 2);
+        assert_eq!(intent.name, "c");
+        assert_eq!(intent.new_name, "d");
+        assert!(intent.exchange);
+        let next = journal
+            .begin(3, OsStr::new("e"), 3, OsStr::new("f"), 1, false)
+            .unwrap();
+        assert_eq!(next.id, 3);
+
+        assert_eq!(
+            journal
+                .recover(|i| if i.id == 2 { Ok(()) } else { Err(libc::EIO) })
+                .unwrap(),
+            1
+        );
+        drop(journal);
+        let journal = RenameJournal::open(&path).unwrap();
+        assert_eq!(journal.pending(), [next]);
+    }
+}
@@ -15,18 +15,27 @@ use crate::ll::{
     reply::{DirEntList, DirEntOffset, DirEntry},
     INodeNo,
 };
+#[cfg(feature = "abi-7-11")]
+use crate::PollEvents;
 use libc::c_int;
 use log::{error, warn};
+use std::any::Any;
+use std::backtrace::Backtrace;
 use std::convert::AsRef;
 use std::ffi::OsStr;
 use std::fmt;
 use std::io::IoSlice;
+use std::thread;
 use std::time::Duration;
 
 #[cfg(target_os = "macos")]
 use std::time::SystemTime;
 
-use crate::{FileAttr, FileType};
+use crate::channel::ChannelSender;
+use crate::ll::fuse_abi::consts;
+use crate::ll::fuse_abi::fuse_opcode;
+use crate::time_gran;
+use crate::{DroppedReply, FileAttr, FileType, IdMap, Ttl};
 
 /// Generic reply callback to send data
 pub trait ReplySender: Send + Sync + Unpin + 'static {
@@ -46,6 +55,45 @@ pub trait Reply {
     fn new<S: ReplySender>(unique: u64, sender: S) -> Self;
 }
 
+/// Sender of a raw reply. Replies created by a session send through its channel, which is
+/// stored inline to save allocating a box for every request.
+#[derive(Debug)]
+enum RawSender {
+    Channel(ChannelSender),
+    Boxed(Box<dyn ReplySender>),
+}
+
+impl RawSender {
+    fn new<S: ReplySender>(sender: S) -> RawSender {
+        let mut sender = Some(sender);
+        match (&mut sender as &mut dyn Any).downcast_mut::<Option<ChannelSender>>() {
+            Some(ch) => RawSender::Channel(ch.take().unwrap()),
+            None => RawSender::Boxed(Box::new(sender.unwrap())),
+        }
+    }
+
+    fn send(&self, data: &[IoSlice<'_>]) -> std::io::Result<()> {
+        match self {
+            RawSender::Channel(ch) => ch.send(data),
+            RawSender::Boxed(sender) => sender.send(data),
+        }
+    }
+
+    fn time_granularity(&self) -> Option<Duration> {
+        match self {
+            RawSender::Channel(ch) => ch.time_granularity(),
+            RawSender::Boxed(_) => None,
+        }
+    }
+
+    fn id_map(&self) -> Option<&IdMap> {
+        match self {
+            RawSender::Channel(ch) => ch.id_map(),
+            RawSender::Boxed(_) => None,
+        }
+    }
+}
+
 ///
 /// Raw reply
 ///
@@ -54,15 +102,33 @@ pub(crate) struct ReplyRaw {
     /// Unique id of the request to reply to
     unique: ll::RequestId,
     /// Closure to call for sending the reply
-    sender: Option<Box<dyn ReplySender>>,
+    sender: Option<RawSender>,
+    /// Whether the kernel must not cache the replied entry, attributes or directory listing
+    uncached: bool,
+    /// Whether the kernel must not send `flush` requests for the opened file
+    noflush: bool,
+    /// Called with the response right before it is sent
+    on_sent: Option<OnSent>,
+}
+
+/// Callback observing the response a reply is sent with, so that wrapping filesystems can
+/// learn how the inner filesystem answered
+struct OnSent(Box<dyn FnOnce(&ll::Response<'_>) + Send>);
+
+impl fmt::Debug for OnSent {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "OnSent")
+    }
 }
 
 impl Reply for ReplyRaw {
     fn new<S: ReplySender>(unique: u64, sender: S) -> ReplyRaw {
-        let sender = Box::new(sender);
         ReplyRaw {
             unique: ll::RequestId(unique),
-            sender: Some(sender),
+            sender: Some(RawSender::new(sender)),
+            uncached: false,
+            noflush: false,
+            on_sent: None,
         }
     }
 }
@@ -73,6 +139,9 @@ impl ReplyRaw {
     fn send_ll_mut(&mut self, response: &ll::Response<'_>) {
         assert!(self.sender.is_some());
         let sender = self.sender.take().unwrap();
+        if let Some(OnSent(on_sent)) = self.on_sent.take() {
+            on_sent(response);
+        }
         let res = response.with_iovec(self.unique, |iov| sender.send(iov));
         if let Err(err) = res {
             error!("Failed to send FUSE reply: {}", err);
@@ -82,6 +151,50 @@ impl ReplyRaw {
         self.send_ll_mut(response)
     }
 
+    /// Returns the attributes as the kernel should see them: with their times rounded to the
+    /// granularity negotiated with the kernel, and their owner mapped to the ids of the mount
+    fn outgoing_attr(&self, attr: &FileAttr) -> FileAttr {
+        let attr = match self.sender.as_ref().and_then(RawSender::time_granularity) {
+            Some(gran) => time_gran::round_attr(attr, gran),
+            None => *attr,
+        };
+        match self.sender.as_ref().and_then(RawSender::id_map) {
+            Some(map) => map.attr_to_mount(&attr),
+            None => attr,
+        }
+    }
+
+    /// Returns the open flags to reply with
+    fn open_flags(&self, flags: u32) -> u32 {
+        if self.noflush {
+            flags | consts::FOPEN_NOFLUSH
+        } else {
+            flags
+        }
+    }
+
+    /// Returns how long the kernel may cache the replied entry or attributes
+    fn ttl(&self, ttl: Ttl) -> Duration {
+        if self.uncached {
+            Duration::ZERO
+        } else {
+            ttl.duration()
+        }
+    }
+
+    /// Call `f` with the response this reply is sent with, including the error sent when the
+    /// reply is dropped
+    fn on_sent(&mut self, f: impl FnOnce(&ll::Response<'_>) + Send + 'static) {
+        self.on_sent = Some(OnSent(Box::new(f)));
+    }
+
+    /// Attach a correlation token to the request, which is reported with the reply
+    fn set_correlation(&self, token: String) {
+        if let Some(RawSender::Channel(ch)) = &self.sender {
+            ch.correlate(self.unique.0, token);
+        }
+    }
+
     /// Reply to a request with the given error code
     pub fn error(self, err: c_int) {
         assert_ne!(err, 0);
@@ -91,13 +204,35 @@ impl ReplyRaw {
 
 impl Drop for ReplyRaw {
     fn drop(&mut self) {
-        if self.sender.is_some() {
+        let (policy, opcode) = match &self.sender {
+            None => return,
+            Some(RawSender::Channel(ch)) => ch.reply_dropped(),
+            Some(RawSender::Boxed(_)) => (DroppedReply::Warn, None),
+        };
+        // Replies dropped while unwinding from a panic are reported by the session
+        if policy == DroppedReply::Warn || thread::panicking() {
             warn!(
                 "Reply not sent for operation {}, replying with I/O error",
                 self.unique.0
             );
             self.send_ll_mut(&ll::Response::new_error(ll::Errno::EIO));
+            return;
         }
+        let opcode = opcode
+            .and_then(|op| fuse_opcode::try_from(op).ok())
+            .map_or_else(|| "unknown".to_owned(), |op| format!("{:?}", op));
+        error!(
+            "Reply not sent for operation {} ({}), replying with I/O error. Dropped at:\n{}",
+            self.unique.0,
+            opcode,
+            Backtrace::force_capture()
+        );
+        self.send_ll_mut(&ll::Response::new_error(ll::Errno::EIO));
+        #[cfg(debug_assertions)]
+        panic!(
+            "Reply not sent for operation {} ({})",
+            self.unique.0, opcode
+        );
     }
 }
 
@@ -123,12 +258,95 @@ impl ReplyEmpty {
         self.reply.send_ll(&ll::Response::new_empty());
     }
 
+    /// Call `f` with the error code this reply is sent with, or 0 on success
+    pub(crate) fn on_sent(mut self, f: impl FnOnce(c_int) + Send + 'static) -> Self {
+        self.reply.on_sent(move |response| match response {
+            ll::Response::Error(err) => f(*err),
+            _ => f(0),
+        });
+        self
+    }
+
+    /// Attach a correlation token to the request, see
+    /// [`Request::set_correlation`](crate::Request::set_correlation)
+    pub fn set_correlation(&self, token: impl Into<String>) {
+        self.reply.set_correlation(token.into());
+    }
+
     /// Reply to a request with the given error code
     pub fn error(self, err: c_int) {
         self.reply.error(err);
     }
 }
 
+/// Size of the buffer runs of zero bytes are sent from
+const ZERO_BUF_SIZE: usize = 64 * 1024;
+
+/// Zero bytes shared by all replies, so that holes don't have to be allocated
+static ZEROES: [u8; ZERO_BUF_SIZE] = [0; ZERO_BUF_SIZE];
+
+#[derive(Debug, Clone, Copy)]
+enum Chunk<'a> {
+    Slice(&'a [u8]),
+    Zeroes(usize),
+}
+
+/// A part of a data reply: borrowed data, or a run of zero bytes that is never allocated (see
+/// [`ReplyData::data_vectored`])
+#[derive(Debug, Clone, Copy)]
+pub struct Bytes<'a>(Chunk<'a>);
+
+impl<'a> Bytes<'a> {
+    /// The given data
+    pub fn new(data: &'a [u8]) -> Self {
+        Bytes(Chunk::Slice(data))
+    }
+
+    /// `len` zero bytes, e.g. a hole of a sparse file
+    pub fn zeroes(len: usize) -> Bytes<'static> {
+        Bytes(Chunk::Zeroes(len))
+    }
+
+    /// Number of bytes
+    pub fn len(&self) -> usize {
+        match self.0 {
+            Chunk::Slice(data) => data.len(),
+            Chunk::Zeroes(len) => len,
+        }
+    }
+
+    /// Whether there are no bytes
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl<'a> From<&'a [u8]> for Bytes<'a> {
+    fn from(data: &'a [u8]) -> Self {
+        Bytes::new(data)
+    }
+}
+
+/// Returns the buffers to send for the given parts, with runs of zero bytes pointing into the
+/// shared buffer of zeroes
+fn bytes_iovec<'a>(parts: &[Bytes<'a>]) -> Vec<IoSlice<'a>> {
+    let mut iov = Vec::with_capacity(parts.len());
+    for part in parts {
+        match part.0 {
+            Chunk::Slice(data) if !data.is_empty() => iov.push(IoSlice::new(data)),
+            Chunk::Slice(_) => {}
+            Chunk::Zeroes(mut len) => {
+                while len > 0 {
+                    let n = len.min(ZERO_BUF_SIZE);
+                    iov.push(IoSlice::new(&ZEROES[..n]));
+                    len -= n;
+                }
+            }
+        }
+    }
+    iov
+}
+
 ///
 /// Data reply
 ///
@@ -151,6 +369,20 @@ impl ReplyData {
         self.reply.send_ll(&ll::Response::new_slice(data));
     }
 
+    /// Reply to a request with the concatenation of the given parts, without copying them.
+    /// Lets `read` of sparse files reply with holes without allocating and zeroing a buffer:
+    /// `reply.data_vectored(&[Bytes::new(&extent), Bytes::zeroes(hole_len)])`.
+    pub fn data_vectored(self, parts: &[Bytes<'_>]) {
+        self.reply
+            .send_ll(&ll::Response::new_vectored(bytes_iovec(parts)));
+    }
+
+    /// Attach a correlation token to the request, see
+    /// [`Request::set_correlation`](crate::Request::set_correlation)
+    pub fn set_correlation(&self, token: impl Into<String>) {
+        self.reply.set_correlation(token.into());
+    }
+
     /// Reply to a request with the given error code
     pub fn error(self, err: c_int) {
         self.reply.error(err);
@@ -175,20 +407,34 @@ impl Reply for ReplyEntry {
 
 impl ReplyEntry {
     /// Reply to a request with the given entry
-    pub fn entry(self, ttl: &Duration, attr: &FileAttr, generation: u64) {
+    pub fn entry(self, ttl: impl Into<Ttl>, attr: &FileAttr, generation: u64) {
+        let attr = &self.reply.outgoing_attr(attr);
+        let ttl = self.reply.ttl(ttl.into());
         self.reply.send_ll(&ll::Response::new_entry(
             ll::INodeNo(attr.ino),
             ll::Generation(generation),
             &attr.into(),
-            *ttl,
-            *ttl,
+            ttl,
+            ttl,
         ));
     }
 
+    /// Attach a correlation token to the request, see
+    /// [`Request::set_correlation`](crate::Request::set_correlation)
+    pub fn set_correlation(&self, token: impl Into<String>) {
+        self.reply.set_correlation(token.into());
+    }
+
     /// Reply to a request with the given error code
     pub fn error(self, err: c_int) {
         self.reply.error(err);
     }
+
+    /// Reply with a TTL of zero, whatever the filesystem passes
+    pub(crate) fn uncached(mut self) -> Self {
+        self.reply.uncached = true;
+        self
+    }
 }
 
 ///
@@ -209,15 +455,29 @@ impl Reply for ReplyAttr {
 
 impl ReplyAttr {
     /// Reply to a request with the given attribute
-    pub fn attr(self, ttl: &Duration, attr: &FileAttr) {
+    pub fn attr(self, ttl: impl Into<Ttl>, attr: &FileAttr) {
+        let attr = &self.reply.outgoing_attr(attr);
+        let ttl = self.reply.ttl(ttl.into());
         self.reply
-            .send_ll(&ll::Response::new_attr(ttl, &attr.into()));
+            .send_ll(&ll::Response::new_attr(&ttl, &attr.into()));
+    }
+
+    /// Attach a correlation token to the request, see
+    /// [`Request::set_correlation`](crate::Request::set_correlation)
+    pub fn set_correlation(&self, token: impl Into<String>) {
+        self.reply.set_correlation(token.into());
     }
 
     /// Reply to a request with the given error code
     pub fn error(self, err: c_int) {
         self.reply.error(err);
     }
+
+    /// Reply with a TTL of zero, whatever the filesystem passes
+    pub(crate) fn uncached(mut self) -> Self {
+        self.reply.uncached = true;
+        self
+    }
 }
 
 ///
@@ -246,6 +506,12 @@ impl ReplyXTimes {
             .send_ll(&ll::Response::new_xtimes(bkuptime, crtime))
     }
 
+    /// Attach a correlation token to the request, see
+    /// [`Request::set_correlation`](crate::Request::set_correlation)
+    pub fn set_correlation(&self, token: impl Into<String>) {
+        self.reply.set_correlation(token.into());
+    }
+
     /// Reply to a request with the given error code
     pub fn error(self, err: c_int) {
         self.reply.error(err);
@@ -271,14 +537,39 @@ impl Reply for ReplyOpen {
 impl ReplyOpen {
     /// Reply to a request with the given open result
     pub fn opened(self, fh: u64, flags: u32) {
+        let flags = self.reply.open_flags(flags);
+        #[cfg(feature = "abi-7-28")]
+        let flags = if self.reply.uncached {
+            flags & !consts::FOPEN_CACHE_DIR
+        } else {
+            flags
+        };
         self.reply
             .send_ll(&ll::Response::new_open(ll::FileHandle(fh), flags))
     }
 
+    /// Attach a correlation token to the request, see
+    /// [`Request::set_correlation`](crate::Request::set_correlation)
+    pub fn set_correlation(&self, token: impl Into<String>) {
+        self.reply.set_correlation(token.into());
+    }
+
     /// Reply to a request with the given error code
     pub fn error(self, err: c_int) {
         self.reply.error(err);
     }
+
+    /// Don't let the kernel cache the listing of the opened directory
+    pub(crate) fn uncached(mut self) -> Self {
+        self.reply.uncached = true;
+        self
+    }
+
+    /// Tell the kernel not to send `flush` requests for the opened file
+    pub(crate) fn noflush(mut self) -> Self {
+        self.reply.noflush = true;
+        self
+    }
 }
 
 ///
@@ -303,6 +594,26 @@ impl ReplyWrite {
         self.reply.send_ll(&ll::Response::new_write(size))
     }
 
+    /// Call `f` with the number of bytes this reply reports as written, or the error code it
+    /// is sent with
+    pub(crate) fn on_written(
+        mut self,
+        f: impl FnOnce(Result<u32, c_int>) + Send + 'static,
+    ) -> Self {
+        self.reply.on_sent(move |response| match response {
+            ll::Response::Data(data) => f(Ok(u32::from_ne_bytes(data[..4].try_into().unwrap()))),
+            ll::Response::Error(err) => f(Err(*err)),
+            _ => unreachable!(),
+        });
+        self
+    }
+
+    /// Attach a correlation token to the request, see
+    /// [`Request::set_correlation`](crate::Request::set_correlation)
+    pub fn set_correlation(&self, token: impl Into<String>) {
+        self.reply.set_correlation(token.into());
+    }
+
     /// Reply to a request with the given error code
     pub fn error(self, err: c_int) {
         self.reply.error(err);
@@ -344,6 +655,12 @@ impl ReplyStatfs {
         ))
     }
 
+    /// Attach a correlation token to the request, see
+    /// [`Request::set_correlation`](crate::Request::set_correlation)
+    pub fn set_correlation(&self, token: impl Into<String>) {
+        self.reply.set_correlation(token.into());
+    }
+
     /// Reply to a request with the given error code
     pub fn error(self, err: c_int) {
         self.reply.error(err);
@@ -368,9 +685,19 @@ impl Reply for ReplyCreate {
 
 impl ReplyCreate {
     /// Reply to a request with the given entry
-    pub fn created(self, ttl: &Duration, attr: &FileAttr, generation: u64, fh: u64, flags: u32) {
+    pub fn created(
+        self,
+        ttl: impl Into<Ttl>,
+        attr: &FileAttr,
+        generation: u64,
+        fh: u64,
+        flags: u32,
+    ) {
+        let attr = &self.reply.outgoing_attr(attr);
+        let ttl = self.reply.ttl(ttl.into());
+        let flags = self.reply.open_flags(flags);
         self.reply.send_ll(&ll::Response::new_create(
-            ttl,
+            &ttl,
             &attr.into(),
             ll::Generation(generation),
             ll::FileHandle(fh),
@@ -378,10 +705,28 @@ impl ReplyCreate {
         ))
     }
 
+    /// Attach a correlation token to the request, see
+    /// [`Request::set_correlation`](crate::Request::set_correlation)
+    pub fn set_correlation(&self, token: impl Into<String>) {
+        self.reply.set_correlation(token.into());
+    }
+
     /// Reply to a request with the given error code
     pub fn error(self, err: c_int) {
         self.reply.error(err);
     }
+
+    /// Reply with a TTL of zero, whatever the filesystem passes
+    pub(crate) fn uncached(mut self) -> Self {
+        self.reply.uncached = true;
+        self
+    }
+
+    /// Tell the kernel not to send `flush` requests for the created file
+    pub(crate) fn noflush(mut self) -> Self {
+        self.reply.noflush = true;
+        self
+    }
 }
 
 ///
@@ -410,6 +755,12 @@ impl ReplyLock {
         }))
     }
 
+    /// Attach a correlation token to the request, see
+    /// [`Request::set_correlation`](crate::Request::set_correlation)
+    pub fn set_correlation(&self, token: impl Into<String>) {
+        self.reply.set_correlation(token.into());
+    }
+
     /// Reply to a request with the given error code
     pub fn error(self, err: c_int) {
         self.reply.error(err);
@@ -438,6 +789,12 @@ impl ReplyBmap {
         self.reply.send_ll(&ll::Response::new_bmap(block))
     }
 
+    /// Attach a correlation token to the request, see
+    /// [`Request::set_correlation`](crate::Request::set_correlation)
+    pub fn set_correlation(&self, token: impl Into<String>) {
+        self.reply.set_correlation(token.into());
+    }
+
     /// Reply to a request with the given error code
     pub fn error(self, err: c_int) {
         self.reply.error(err);
@@ -467,6 +824,12 @@ impl ReplyIoctl {
             .send_ll(&ll::Response::new_ioctl(result, &[IoSlice::new(data)]));
     }
 
+    /// Attach a correlation token to the request, see
+    /// [`Request::set_correlation`](crate::Request::set_correlation)
+    pub fn set_correlation(&self, token: impl Into<String>) {
+        self.reply.set_correlation(token.into());
+    }
+
     /// Reply to a request with the given error code
     pub fn error(self, err: c_int) {
         self.reply.error(err);
@@ -494,8 +857,14 @@ impl Reply for ReplyPoll {
 #[cfg(feature = "abi-7-11")]
 impl ReplyPoll {
     /// Reply to a request with the given poll result
-    pub fn poll(self, revents: u32) {
-        self.reply.send_ll(&ll::Response::new_poll(revents))
+    pub fn poll(self, revents: PollEvents) {
+        self.reply.send_ll(&ll::Response::new_poll(revents.bits()))
+    }
+
+    /// Attach a correlation token to the request, see
+    /// [`Request::set_correlation`](crate::Request::set_correlation)
+    pub fn set_correlation(&self, token: impl Into<String>) {
+        self.reply.set_correlation(token.into());
     }
 
     /// Reply to a request with the given error code
@@ -510,7 +879,49 @@ impl ReplyPoll {
 #[derive(Debug)]
 pub struct ReplyDirectory {
     reply: ReplyRaw,
-    data: DirEntList,
+    data: Listing,
+}
+
+/// Entries of a directory listing, in the format the kernel requested. `readdir` and
+/// `readdirplus` replies can be filled in either format, so that filesystems only need to
+/// implement one of them.
+#[derive(Debug)]
+enum Listing {
+    Plain(DirEntList),
+    Plus(DirEntPlusList),
+}
+
+impl Listing {
+    fn max_size(&self) -> usize {
+        match self {
+            Listing::Plain(l) => l.max_size(),
+            Listing::Plus(l) => l.max_size(),
+        }
+    }
+
+    fn reserve(&mut self, bytes: usize) {
+        match self {
+            Listing::Plain(l) => l.reserve(bytes),
+            Listing::Plus(l) => l.reserve(bytes),
+        }
+    }
+
+    /// Whether an entry with the given name and attributes fits in the buffer
+    fn fits_plus(&self, name: &OsStr) -> bool {
+        match self {
+            Listing::Plain(l) => l.fits(name),
+            Listing::Plus(l) => l.fits(name),
+        }
+    }
+}
+
+impl From<Listing> for ll::Response<'_> {
+    fn from(l: Listing) -> Self {
+        match l {
+            Listing::Plain(l) => l.into(),
+            Listing::Plus(l) => l.into(),
+        }
+    }
 }
 
 impl ReplyDirectory {
@@ -518,22 +929,34 @@ impl ReplyDirectory {
     pub fn new<S: ReplySender>(unique: u64, sender: S, size: usize) -> ReplyDirectory {
         ReplyDirectory {
             reply: Reply::new(unique, sender),
-            data: DirEntList::new(size),
+            data: Listing::Plain(DirEntList::new(size)),
         }
     }
 
     /// Add an entry to the directory reply buffer. Returns true if the buffer is full.
     /// A transparent offset value can be provided for each entry. The kernel uses these
-    /// value to request the next entries in further readdir calls
+    /// value to request the next entries in further readdir calls, so the offset must not be
+    /// 0, which requests the start of the directory (see [`DirEntOffset`]).
     #[must_use]
     pub fn add<T: AsRef<OsStr>>(&mut self, ino: u64, offset: i64, kind: FileType, name: T) -> bool {
-        let name = name.as_ref();
-        self.data.push(&DirEntry::new(
-            INodeNo(ino),
-            DirEntOffset(offset),
-            kind,
-            name,
-        ))
+        let offset = entry_offset(offset);
+        let entry = DirEntry::new(INodeNo(ino), offset, kind, name.as_ref());
+        match &mut self.data {
+            Listing::Plain(l) => l.push(&entry),
+            Listing::Plus(l) => l.push_plain(&entry),
+        }
+    }
+
+    /// Returns how many bytes of entries the kernel accepts in this reply
+    pub fn max_bytes(&self) -> usize {
+        self.data.max_size()
+    }
+
+    /// Allocate room for `bytes` of entries up front, at most [`max_bytes`](Self::max_bytes).
+    /// The buffer starts with room for 4 KiB and grows as entries are added, so filesystems
+    /// that know the listing fills a large reply can save regrowing it.
+    pub fn reserve(&mut self, bytes: usize) {
+        self.data.reserve(bytes);
     }
 
     /// Reply to a request with the filled directory buffer
@@ -541,10 +964,40 @@ impl ReplyDirectory {
         self.reply.send_ll(&self.data.into());
     }
 
+    /// Attach a correlation token to the request, see
+    /// [`Request::set_correlation`](crate::Request::set_correlation)
+    pub fn set_correlation(&self, token: impl Into<String>) {
+        self.reply.set_correlation(token.into());
+    }
+
     /// Reply to a request with the given error code
     pub fn error(self, err: c_int) {
         self.reply.error(err);
     }
+
+    /// Whether this reply answers a `readdirplus` request
+    pub(crate) fn answers_readdirplus(&self) -> bool {
+        matches!(self.data, Listing::Plus(_))
+    }
+
+    /// Add the entries with attributes instead. They are dropped when answering a `readdir`
+    /// request.
+    pub(crate) fn into_plus(self) -> ReplyDirectoryPlus {
+        ReplyDirectoryPlus {
+            reply: self.reply,
+            buf: self.data,
+        }
+    }
+}
+
+/// Checks the offset of a directory entry in debug builds. An entry with offset 0 makes the
+/// kernel restart the listing, which loops forever, so catch this common mistake early.
+fn entry_offset(offset: i64) -> DirEntOffset {
+    debug_assert_ne!(
+        offset, 0,
+        "directory entries must not have offset 0, which restarts the listing"
+    );
+    DirEntOffset(offset)
 }
 
 ///
@@ -553,7 +1006,7 @@ impl ReplyDirectory {
 #[derive(Debug)]
 pub struct ReplyDirectoryPlus {
     reply: ReplyRaw,
-    buf: DirEntPlusList,
+    buf: Listing,
 }
 
 impl ReplyDirectoryPlus {
@@ -561,32 +1014,68 @@ impl ReplyDirectoryPlus {
     pub fn new<S: ReplySender>(unique: u64, sender: S, size: usize) -> ReplyDirectoryPlus {
         ReplyDirectoryPlus {
             reply: Reply::new(unique, sender),
-            buf: DirEntPlusList::new(size),
+            buf: Listing::Plus(DirEntPlusList::new(size)),
         }
     }
 
     /// Add an entry to the directory reply buffer. Returns true if the buffer is full.
     /// A transparent offset value can be provided for each entry. The kernel uses these
-    /// value to request the next entries in further readdir calls
+    /// value to request the next entries in further readdir calls, so the offset must not be
+    /// 0, which requests the start of the directory (see [`DirEntOffset`]).
     pub fn add<T: AsRef<OsStr>>(
         &mut self,
         ino: u64,
         offset: i64,
         name: T,
-        ttl: &Duration,
+        ttl: impl Into<Ttl>,
         attr: &FileAttr,
         generation: u64,
     ) -> bool {
-        let name = name.as_ref();
-        self.buf.push(&DirEntryPlus::new(
+        let attr = &self.reply.outgoing_attr(attr);
+        let ttl = ttl.into().duration();
+        let entry = DirEntryPlus::new(
             INodeNo(ino),
             Generation(generation),
-            DirEntOffset(offset),
-            name,
-            *ttl,
+            entry_offset(offset),
+            name.as_ref(),
+            ttl,
             attr.into(),
-            *ttl,
-        ))
+            ttl,
+        );
+        match &mut self.buf {
+            Listing::Plus(l) => l.push(&entry),
+            Listing::Plain(l) => l.push_plus(&entry),
+        }
+    }
+
+    /// Add an entry like [`add`](ReplyDirectoryPlus::add), but only compute its attributes
+    /// if it fits in the buffer, for filesystems whose attributes are expensive to fetch.
+    /// Returns true if the buffer is full, without calling `attr`.
+    pub fn add_lazy<T: AsRef<OsStr>, F: FnOnce() -> FileAttr>(
+        &mut self,
+        ino: u64,
+        offset: i64,
+        name: T,
+        ttl: impl Into<Ttl>,
+        generation: u64,
+        attr: F,
+    ) -> bool {
+        if !self.buf.fits_plus(name.as_ref()) {
+            return true;
+        }
+        self.add(ino, offset, name, ttl, &attr(), generation)
+    }
+
+    /// Returns how many bytes of entries the kernel accepts in this reply
+    pub fn max_bytes(&self) -> usize {
+        self.buf.max_size()
+    }
+
+    /// Allocate room for `bytes` of entries up front, at most [`max_bytes`](Self::max_bytes).
+    /// The buffer starts with room for 4 KiB and grows as entries are added, so filesystems
+    /// that know the listing fills a large reply can save regrowing it.
+    pub fn reserve(&mut self, bytes: usize) {
+        self.buf.reserve(bytes);
     }
 
     /// Reply to a request with the filled directory buffer
@@ -594,10 +1083,30 @@ impl ReplyDirectoryPlus {
         self.reply.send_ll(&self.buf.into());
     }
 
+    /// Attach a correlation token to the request, see
+    /// [`Request::set_correlation`](crate::Request::set_correlation)
+    pub fn set_correlation(&self, token: impl Into<String>) {
+        self.reply.set_correlation(token.into());
+    }
+
     /// Reply to a request with the given error code
     pub fn error(self, err: c_int) {
         self.reply.error(err);
     }
+
+    /// Whether this reply answers a `readdirplus` request
+    pub(crate) fn answers_readdirplus(&self) -> bool {
+        matches!(self.buf, Listing::Plus(_))
+    }
+
+    /// Add the entries without attributes instead. When answering a `readdirplus` request, the
+    /// kernel lists them, but doesn't look them up.
+    pub(crate) fn into_plain(self) -> ReplyDirectory {
+        ReplyDirectory {
+            reply: self.reply,
+            data: self.buf,
+        }
+    }
 }
 
 ///
@@ -627,6 +1136,12 @@ impl ReplyXattr {
         self.reply.send_ll(&ll::Response::new_data(data))
     }
 
+    /// Attach a correlation token to the request, see
+    /// [`Request::set_correlation`](crate::Request::set_correlation)
+    pub fn set_correlation(&self, token: impl Into<String>) {
+        self.reply.set_correlation(token.into());
+    }
+
     /// Reply to a request with the given error code.
     pub fn error(self, err: c_int) {
         self.reply.error(err);
@@ -655,6 +1170,12 @@ impl ReplyLseek {
         self.reply.send_ll(&ll::Response::new_lseek(offset))
     }
 
+    /// Attach a correlation token to the request, see
+    /// [`Request::set_correlation`](crate::Request::set_correlation)
+    pub fn set_correlation(&self, token: impl Into<String>) {
+        self.reply.set_correlation(token.into());
+    }
+
     /// Reply to a request with the given error code
     pub fn error(self, err: c_int) {
         self.reply.error(err);
@@ -744,6 +1265,28 @@ mod test {
         reply.error(66);
     }
 
+    #[test]
+    fn reply_sender_inline() {
+        let file = std::fs::OpenOptions::new()
+            .write(true)
+            .open("/dev/null")
+            .unwrap();
+        let ch = crate::channel::Channel::new(std::sync::Arc::new(file));
+        let reply: ReplyRaw = Reply::new(0xdeadbeef, ch.sender());
+        assert!(matches!(reply.sender, Some(RawSender::Channel(_))));
+        reply.error(66);
+
+        let sender = AssertSender {
+            expected: vec![
+                0x10, 0x00, 0x00, 0x00, 0xbe, 0xff, 0xff, 0xff, 0xef, 0xbe, 0xad, 0xde, 0x00, 0x00,
+                0x00, 0x00,
+            ],
+        };
+        let reply: ReplyRaw = Reply::new(0xdeadbeef, sender);
+        assert!(matches!(reply.sender, Some(RawSender::Boxed(_))));
+        reply.error(66);
+    }
+
     #[test]
     fn reply_empty() {
         let sender = AssertSender {
@@ -768,6 +1311,30 @@ mod test {
         reply.data(&[0xde, 0xad, 0xbe, 0xef]);
     }
 
+    #[test]
+    fn reply_data_vectored() {
+        let sender = AssertSender {
+            expected: vec![
+                0x16, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0xef, 0xbe, 0xad, 0xde, 0x00, 0x00,
+                0x00, 0x00, 0xde, 0xad, 0x00, 0x00, 0x00, 0xef,
+            ],
+        };
+        let reply: ReplyData = Reply::new(0xdeadbeef, sender);
+        reply.data_vectored(&[
+            Bytes::new(&[0xde, 0xad]),
+            Bytes::zeroes(3),
+            Bytes::new(&[]),
+            Bytes::new(&[0xef]),
+        ]);
+    }
+
+    #[test]
+    fn large_zero_runs() {
+        let iov = bytes_iovec(&[Bytes::zeroes(2 * ZERO_BUF_SIZE + 1), Bytes::zeroes(0)]);
+        let lens: Vec<usize> = iov.iter().map(|b| b.len()).collect();
+        assert_eq!(lens, [ZERO_BUF_SIZE, ZERO_BUF_SIZE, 1]);
+    }
+
     #[test]
     fn reply_entry() {
         let mut expected = if cfg!(target_os = "macos") {
@@ -825,9 +1392,45 @@ mod test {
             flags: 0x99,
             blksize: 0xbb,
         };
+        // Replies used to take `&Duration`, keep that compiling
+        #[allow(clippy::needless_borrows_for_generic_args)]
         reply.entry(&ttl, &attr, 0xaa);
     }
 
+    struct ZeroTtlSender;
+
+    impl super::ReplySender for ZeroTtlSender {
+        fn send(&self, data: &[IoSlice<'_>]) -> std::io::Result<()> {
+            let v: Vec<u8> = data.iter().flat_map(|x| x.iter().copied()).collect();
+            // entry_valid, attr_valid, entry_valid_nsec and attr_valid_nsec of fuse_entry_out
+            assert_eq!(v[32..56], [0; 24]);
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn reply_entry_uncached() {
+        let reply: ReplyEntry = Reply::new(0xdeadbeef, ZeroTtlSender);
+        let attr = FileAttr {
+            ino: 0x11,
+            size: 0,
+            blocks: 0,
+            atime: UNIX_EPOCH,
+            mtime: UNIX_EPOCH,
+            ctime: UNIX_EPOCH,
+            crtime: UNIX_EPOCH,
+            kind: FileType::Directory,
+            perm: 0o755,
+            nlink: 2,
+            uid: 0,
+            gid: 0,
+            rdev: 0,
+            flags: 0,
+            blksize: 512,
+        };
+        reply.uncached().entry(Duration::from_secs(60), &attr, 0);
+    }
+
     #[test]
     fn reply_attr() {
         let mut expected = if cfg!(target_os = "macos") {
@@ -882,7 +1485,7 @@ mod test {
             flags: 0x99,
             blksize: 0xbb,
         };
-        reply.attr(&ttl, &attr);
+        reply.attr(ttl, &attr);
     }
 
     #[test]
@@ -913,6 +1516,19 @@ mod test {
         reply.opened(0x1122, 0x33);
     }
 
+    #[test]
+    fn reply_open_noflush() {
+        let sender = AssertSender {
+            expected: vec![
+                0x20, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0xef, 0xbe, 0xad, 0xde, 0x00, 0x00,
+                0x00, 0x00, 0x22, 0x11, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x21, 0x00, 0x00, 0x00,
+                0x00, 0x00, 0x00, 0x00,
+            ],
+        };
+        let reply: ReplyOpen = Reply::new(0xdeadbeef, sender);
+        reply.noflush().opened(0x1122, 0x01);
+    }
+
     #[test]
     fn reply_write() {
         let sender = AssertSender {
@@ -1005,7 +1621,7 @@ mod test {
             flags: 0x99,
             blksize: 0xdd,
         };
-        reply.created(&ttl, &attr, 0xaa, 0xbb, 0xcc);
+        reply.created(ttl, &attr, 0xaa, 0xbb, 0xcc);
     }
 
     #[test]
@@ -1051,6 +1667,122 @@ mod test {
         reply.ok();
     }
 
+    #[test]
+    #[cfg(debug_assertions)]
+    #[should_panic(expected = "offset 0")]
+    fn reply_directory_offset_zero() {
+        let (tx, _rx) = sync_channel::<()>(1);
+        let mut reply = ReplyDirectory::new(0xdeadbeef, tx, 4096);
+        let _ = reply.add(0xaabb, 0, FileType::Directory, "hello");
+    }
+
+    #[test]
+    fn dir_ent_offset() {
+        assert_eq!(DirEntOffset::new(0), None);
+        assert_eq!(DirEntOffset::new(-1).map(DirEntOffset::get), Some(-1));
+        assert_eq!(DirEntOffset::after_index(0).get(), 1);
+    }
+
+    #[test]
+    fn reply_directory_from_plus() {
+        // Entries added with attributes to a readdir reply are sent as plain entries
+        let sender = AssertSender {
+            expected: vec![
+                0x50, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0xef, 0xbe, 0xad, 0xde, 0x00, 0x00,
+                0x00, 0x00, 0xbb, 0xaa, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00,
+                0x00, 0x00, 0x00, 0x00, 0x05, 0x00, 0x00, 0x00, 0x04, 0x00, 0x00, 0x00, 0x68, 0x65,
+                0x6c, 0x6c, 0x6f, 0x00, 0x00, 0x00, 0xdd, 0xcc, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+                0x02, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x08, 0x00, 0x00, 0x00, 0x08, 0x00,
+                0x00, 0x00, 0x77, 0x6f, 0x72, 0x6c, 0x64, 0x2e, 0x72, 0x73,
+            ],
+        };
+        let time = UNIX_EPOCH + Duration::new(0x1234, 0x5678);
+        let ttl = Duration::new(0x8765, 0x4321);
+        let mut attr = FileAttr {
+            ino: 0xaabb,
+            size: 0,
+            blocks: 0,
+            atime: time,
+            mtime: time,
+            ctime: time,
+            crtime: time,
+            kind: FileType::Directory,
+            perm: 0o755,
+            nlink: 2,
+            uid: 0,
+            gid: 0,
+            rdev: 0,
+            flags: 0,
+            blksize: 512,
+        };
+        let reply = ReplyDirectory::new(0xdeadbeef, sender, 4096);
+        assert!(!reply.answers_readdirplus());
+        let mut reply = reply.into_plus();
+        assert!(!reply.add(0xaabb, 1, "hello", ttl, &attr, 1));
+        attr.ino = 0xccdd;
+        attr.kind = FileType::RegularFile;
+        assert!(!reply.add(0xccdd, 2, "world.rs", ttl, &attr, 1));
+        reply.ok();
+    }
+
+    #[test]
+    fn reply_directory_plus_without_attrs() {
+        // Entries added without attributes to a readdirplus reply have a zero node ID
+        let entry_out_size = std::mem::size_of::<crate::ll::fuse_abi::fuse_entry_out>();
+        let mut expected = vec![
+            0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0xef, 0xbe, 0xad, 0xde, 0x00, 0x00,
+            0x00, 0x00,
+        ];
+        expected.extend(vec![0; entry_out_size]);
+        expected.extend(vec![
+            0xbb, 0xaa, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00, 0x00, 0x00,
+            0x00, 0x00, 0x05, 0x00, 0x00, 0x00, 0x04, 0x00, 0x00, 0x00, 0x68, 0x65, 0x6c, 0x6c,
+            0x6f, 0x00, 0x00, 0x00,
+        ]);
+        expected[0] = (expected.len()) as u8;
+
+        let sender = AssertSender { expected };
+        let reply = ReplyDirectoryPlus::new(0xdeadbeef, sender, 4096);
+        assert!(reply.answers_readdirplus());
+        let mut reply = reply.into_plain();
+        assert!(!reply.add(0xaabb, 1, FileType::Directory, "hello"));
+        reply.ok();
+    }
+
+    #[test]
+    fn reply_directory_plus_lazy() {
+        let (tx, _rx) = sync_channel::<()>(1);
+        let size = std::mem::size_of::<crate::ll::fuse_abi::fuse_direntplus>() + 8;
+        let mut reply = ReplyDirectoryPlus::new(0xdeadbeef, tx, size);
+        let ttl = Duration::from_secs(1);
+        let mut fetched = vec![];
+        let mut attr = |ino: u64| {
+            fetched.push(ino);
+            FileAttr {
+                ino,
+                size: 0,
+                blocks: 0,
+                atime: UNIX_EPOCH,
+                mtime: UNIX_EPOCH,
+                ctime: UNIX_EPOCH,
+                crtime: UNIX_EPOCH,
+                kind: FileType::RegularFile,
+                perm: 0o644,
+                nlink: 1,
+                uid: 0,
+                gid: 0,
+                rdev: 0,
+                flags: 0,
+                blksize: 512,
+            }
+        };
+        assert!(!reply.add_lazy(2, 1, "hello", ttl, 0, || attr(2)));
+        // The buffer is full, the attributes of the second entry are never fetched
+        assert!(reply.add_lazy(3, 2, "world", ttl, 0, || attr(3)));
+        reply.ok();
+        assert_eq!(fetched, [2]);
+    }
+
     #[test]
     fn reply_xattr_size() {
         let sender = AssertSender {
@@ -11,17 +11,53 @@ use std::convert::TryFrom;
 #[cfg(feature = "abi-7-28")]
 use std::convert::TryInto;
 use std::path::Path;
+use std::sync::Arc;
+use std::time::{Instant, SystemTime};
 
-use crate::channel::ChannelSender;
+use crate::change_log;
+use crate::channel::{ChannelSender, Outstanding};
+use crate::control;
+#[cfg(feature = "abi-7-28")]
+use crate::copy_flags;
+use crate::hook::RequestInfo;
 use crate::ll::Request as _;
+use crate::rename_journal::PendingRename;
 #[cfg(feature = "abi-7-21")]
 use crate::reply::ReplyDirectoryPlus;
-use crate::reply::{Reply, ReplyDirectory, ReplySender};
-use crate::session::{Session, SessionACL};
-use crate::Filesystem;
+use crate::reply::{Reply, ReplyCreate, ReplyDirectory, ReplyOpen, ReplyRaw, ReplySender};
+use crate::session::{EmptyIoPolicy, Session, SessionACL, UnknownOpcodePolicy};
+use crate::time_gran;
+#[cfg(feature = "abi-7-23")]
+use crate::Platform;
 #[cfg(feature = "abi-7-11")]
 use crate::PollHandle;
-use crate::{ll, KernelConfig};
+use crate::{ll, ConnectionInfo, KernelConfig};
+use crate::{Access, AccessKind};
+use crate::{Filesystem, GetattrFh, PosixLock, TimeOrNow, WriteData};
+
+/// Tells whether the kernel interrupted a request, for operations that take long enough to be
+/// worth cancelling, see [`Request::interrupt_token`]. Tokens can be cloned and moved to the
+/// thread that answers the request.
+#[derive(Debug, Clone)]
+pub struct InterruptToken {
+    outstanding: Arc<Outstanding>,
+    unique: u64,
+}
+
+impl InterruptToken {
+    pub(crate) fn new(outstanding: Arc<Outstanding>, unique: u64) -> Self {
+        Self {
+            outstanding,
+            unique,
+        }
+    }
+
+    /// Whether the kernel interrupted the request. Once the request was answered, this is
+    /// always false.
+    pub fn is_interrupted(&self) -> bool {
+        self.outstanding.is_interrupted(self.unique)
+    }
+}
 
 /// Request data structure
 #[derive(Debug)]
@@ -29,15 +65,25 @@ pub struct Request<'a> {
     /// Channel sender for sending the reply
     ch: ChannelSender,
     /// Request raw data
-    #[allow(unused)]
     data: &'a [u8],
     /// Parsed request
     request: ll::AnyRequest<'a>,
+    /// Settings negotiated with the kernel
+    connection: ConnectionInfo,
+    /// When the request was read from the kernel
+    received: Instant,
+    /// Checksum of the data of a write request
+    write_checksum: Option<u64>,
 }
 
 impl<'a> Request<'a> {
     /// Create a new request from the given data
-    pub(crate) fn new(ch: ChannelSender, data: &'a [u8]) -> Option<Request<'a>> {
+    pub(crate) fn new(
+        ch: ChannelSender,
+        data: &'a [u8],
+        connection: ConnectionInfo,
+        received: Instant,
+    ) -> Option<Request<'a>> {
         let request = match ll::AnyRequest::try_from(data) {
             Ok(request) => request,
             Err(err) => {
@@ -46,7 +92,40 @@ impl<'a> Request<'a> {
             }
         };
 
-        Some(Self { ch, data, request })
+        // Replies know which request they answer, to rewrite them per operation
+        let mut ch = ch.for_opcode(request.opcode());
+        #[cfg(feature = "usdt")]
+        {
+            ch = ch.with_nodeid(request.nodeid().into());
+        }
+        if ch.tracks_changes() {
+            if let Some(change) = change_log::describe(&request) {
+                ch = ch.with_change(change);
+            }
+        }
+        if let Some(gran) = connection.rounding_granularity() {
+            ch = ch.with_time_granularity(gran);
+        }
+        if !ch.hooks().is_empty() {
+            ch = ch.with_hooked_request(hook_info(&request), received);
+        }
+        let write_checksum = ch
+            .write_checksum()
+            .and_then(|checksum| match request.operation() {
+                Ok(ll::Operation::Write(x)) => Some(checksum.checksum(x.data())),
+                _ => None,
+            });
+        if expects_reply(request.opcode()) && request.unique().0 != 0 {
+            ch.track(request.unique().0, data.len());
+        }
+        Some(Self {
+            ch,
+            data,
+            request,
+            connection,
+            received,
+            write_checksum,
+        })
     }
 
     /// Dispatch request to the given filesystem.
@@ -54,11 +133,34 @@ impl<'a> Request<'a> {
     /// request and sends back the returned reply to the kernel
     pub(crate) fn dispatch<FS: Filesystem>(&self, se: &mut Session<FS>) {
         debug!("{}", self.request);
+        se.stats.requests += 1;
+        *se.stats
+            .requests_by_opcode
+            .entry(self.request.opcode())
+            .or_default() += 1;
         let unique = self.request.unique();
+        #[cfg(feature = "usdt")]
+        crate::usdt::request_start(
+            self.request.opcode(),
+            self.request.nodeid().into(),
+            unique.into(),
+        );
 
-        let res = match self.dispatch_req(se) {
+        let hooked = match self.ch.hooks().before_dispatch(&hook_info(&self.request)) {
+            Err(err) if expects_reply(self.request.opcode()) => Err(Errno::from_i32(err)),
+            _ => self.dispatch_req(se),
+        };
+        let res = match hooked {
             Ok(Some(resp)) => resp,
-            Ok(None) => return,
+            Ok(None) => {
+                if se.memory_budget.is_some() {
+                    // Unless it was answered already, the filesystem holds the reply to answer
+                    // later
+                    self.ch
+                        .charge(unique.into(), std::mem::size_of::<ReplyRaw>());
+                }
+                return;
+            }
             Err(errno) => self.request.reply_err(errno),
         }
         .with_iovec(unique, |iov| self.ch.send(iov));
@@ -72,13 +174,15 @@ impl<'a> Request<'a> {
         &self,
         se: &mut Session<FS>,
     ) -> Result<Option<Response<'_>>, Errno> {
-        let op = self.request.operation().map_err(|_| Errno::ENOSYS)?;
+        let op = match self.request.operation() {
+            Ok(op) => op,
+            Err(ll::RequestError::UnknownOperation(opcode)) => {
+                return self.dispatch_unknown(se, opcode);
+            }
+            Err(_) => return Err(Errno::ENOSYS),
+        };
         // Implement allow_root & access check for auto_unmount
-        if (se.allowed == SessionACL::RootAndOwner
-            && self.request.uid() != se.session_owner
-            && self.request.uid() != 0)
-            || (se.allowed == SessionACL::Owner && self.request.uid() != se.session_owner)
-        {
+        if self.denied_by_acl(se) {
             #[cfg(feature = "abi-7-21")]
             {
                 match op {
@@ -155,9 +259,22 @@ impl<'a> Request<'a> {
 
                 let mut config = KernelConfig::new(x.capabilities(), x.max_readahead());
                 // Call filesystem init method and give it a chance to return an error
-                se.filesystem
-                    .init(self, &mut config)
-                    .map_err(Errno::from_i32)?;
+                let res = se.filesystem.init(self, &mut config);
+                if let Some(err) = config.unmet_requirements() {
+                    error!("{}", err);
+                    se.init_error = Some(err);
+                    return Err(Errno::ENOSYS);
+                }
+                res.map_err(Errno::from_i32)?;
+                if se.locks.is_some() {
+                    // Without it, the kernel keeps POSIX locks locally
+                    if let Err(unsupported) = config.add_capabilities(abi::consts::FUSE_POSIX_LOCKS)
+                    {
+                        warn!("Kernel doesn't support POSIX locks: {:#x}", unsupported);
+                    }
+                }
+                config.requested &= !se.disabled_init_flags;
+                se.connection = ConnectionInfo::negotiated(v.major(), v.minor(), &config);
 
                 // Reply with our desired version and settings. If the kernel supports a
                 // larger major version, it'll re-send a matching init message. If it
@@ -170,6 +287,7 @@ impl<'a> Request<'a> {
                     config.max_readahead,
                     config.max_write
                 );
+                *se.negotiated.lock().unwrap() = Some(se.connection);
                 se.initialized = true;
                 return Ok(Some(x.reply(&config)));
             }
@@ -180,6 +298,8 @@ impl<'a> Request<'a> {
             }
             // Filesystem destroyed
             ll::Operation::Destroy(x) => {
+                #[cfg(feature = "abi-7-16")]
+                se.flush_forgets();
                 se.filesystem.destroy();
                 se.destroyed = true;
                 return Ok(Some(x.reply()));
@@ -189,12 +309,52 @@ impl<'a> Request<'a> {
                 warn!("Ignoring FUSE operation after destroy: {}", self.request);
                 return Err(Errno::EIO);
             }
-
-            ll::Operation::Interrupt(_) => {
-                // TODO: handle FUSE_INTERRUPT
+            // Operations disabled for the session
+            _ if se.disabled_opcodes.contains(&self.request.opcode()) => {
                 return Err(Errno::ENOSYS);
             }
 
+            // Control attributes handled by the session
+            ll::Operation::GetXAttr(x)
+                if control::is_control_xattr(se, self.request.nodeid().into(), x.name()) =>
+            {
+                return control::getxattr(se, x.name(), x.size_u32()).map(Some);
+            }
+            ll::Operation::SetXAttr(x)
+                if control::is_control_xattr(se, self.request.nodeid().into(), x.name()) =>
+            {
+                return control::setxattr(se, x.name(), x.value(), self.request.uid()).map(Some);
+            }
+            ll::Operation::RemoveXAttr(x)
+                if control::is_control_xattr(se, self.request.nodeid().into(), x.name()) =>
+            {
+                return Err(Errno::EPERM);
+            }
+
+            // Opcodes the filesystem serializes itself
+            _ if expects_reply(self.request.opcode())
+                && se.filesystem.raw_intercept(self.request.opcode()) =>
+            {
+                se.filesystem
+                    .handle_raw(self, self.request.opcode(), self.payload(), self.reply());
+            }
+
+            ll::Operation::Interrupt(x) => {
+                // The reply to the interrupted request is handled as configured (see
+                // `InterruptedReply`). An interrupt for a request that was already answered is
+                // ignored. Lock requests waiting in the session's lock table are failed right
+                // away, others are passed on to the filesystem.
+                if let Some(locks) = &mut se.locks {
+                    if locks.interrupt(x.unique().0) {
+                        return Ok(None);
+                    }
+                }
+                if self.ch.interrupt(x.unique().0) {
+                    se.filesystem.interrupt(self, x.unique().0);
+                }
+                return Ok(None);
+            }
+
             ll::Operation::Lookup(x) => {
                 se.filesystem.lookup(
                     self,
@@ -204,38 +364,56 @@ impl<'a> Request<'a> {
                 );
             }
             ll::Operation::Forget(x) => {
+                #[cfg(feature = "abi-7-16")]
+                if se.queue_forget(self.request.nodeid().into(), x.nlookup()) {
+                    return Ok(None);
+                }
                 se.filesystem
                     .forget(self, self.request.nodeid().into(), x.nlookup()); // no reply
             }
-            ll::Operation::GetAttr(_attr) => {
-                #[cfg(feature = "abi-7-9")]
+            ll::Operation::GetAttr(x) => {
                 se.filesystem.getattr(
                     self,
                     self.request.nodeid().into(),
-                    _attr.file_handle().map(|fh| fh.into()),
+                    x.fh().handle(),
                     self.reply(),
                 );
-
-                // Pre-abi-7-9 does not support providing a file handle.
-                #[cfg(not(feature = "abi-7-9"))]
-                se.filesystem
-                    .getattr(self, self.request.nodeid().into(), None, self.reply());
             }
             ll::Operation::SetAttr(x) => {
+                // Pass times as the filesystem is expected to store them
+                let gran = self.connection.rounding_granularity();
+                let time_or_now = |t: Option<TimeOrNow>| match gran {
+                    Some(gran) => t.map(|t| time_gran::round_time_or_now(t, gran)),
+                    None => t,
+                };
+                let time = |t: Option<SystemTime>| match gran {
+                    Some(gran) => t.map(|t| time_gran::round(t, gran)),
+                    None => t,
+                };
+                // Owners are passed as the ids of the filesystem
+                let (mut uid, mut gid) = (x.uid(), x.gid());
+                if let Some(map) = self.ch.id_map() {
+                    uid = uid
+                        .map(|uid| map.uid_to_fs(uid).ok_or(Errno::EOVERFLOW))
+                        .transpose()?;
+                    gid = gid
+                        .map(|gid| map.gid_to_fs(gid).ok_or(Errno::EOVERFLOW))
+                        .transpose()?;
+                }
                 se.filesystem.setattr(
                     self,
                     self.request.nodeid().into(),
                     x.mode(),
-                    x.uid(),
-                    x.gid(),
+                    uid,
+                    gid,
                     x.size(),
-                    x.atime(),
-                    x.mtime(),
-                    x.ctime(),
+                    time_or_now(x.atime()),
+                    time_or_now(x.mtime()),
+                    time(x.ctime()),
                     x.file_handle().map(|fh| fh.into()),
-                    x.crtime(),
-                    x.chgtime(),
-                    x.bkuptime(),
+                    time(x.crtime()),
+                    time(x.chgtime()),
+                    time(x.bkuptime()),
                     x.flags(),
                     self.reply(),
                 );
@@ -245,6 +423,13 @@ impl<'a> Request<'a> {
                     .readlink(self, self.request.nodeid().into(), self.reply());
             }
             ll::Operation::MkNod(x) => {
+                let mut access = self.access(AccessKind::MkNod {
+                    mode: x.mode(),
+                    rdev: x.rdev(),
+                });
+                access.parent = Some(self.request.nodeid().into());
+                access.name = Some(x.name().as_os_str().to_owned());
+                se.authorize(access)?;
                 se.filesystem.mknod(
                     self,
                     self.request.nodeid().into(),
@@ -256,6 +441,10 @@ impl<'a> Request<'a> {
                 );
             }
             ll::Operation::MkDir(x) => {
+                let mut access = self.access(AccessKind::MkDir { mode: x.mode() });
+                access.parent = Some(self.request.nodeid().into());
+                access.name = Some(x.name().as_os_str().to_owned());
+                se.authorize(access)?;
                 se.filesystem.mkdir(
                     self,
                     self.request.nodeid().into(),
@@ -266,6 +455,10 @@ impl<'a> Request<'a> {
                 );
             }
             ll::Operation::Unlink(x) => {
+                let mut access = self.access(AccessKind::Unlink);
+                access.parent = Some(self.request.nodeid().into());
+                access.name = Some(x.name().as_os_str().to_owned());
+                se.authorize(access)?;
                 se.filesystem.unlink(
                     self,
                     self.request.nodeid().into(),
@@ -274,6 +467,10 @@ impl<'a> Request<'a> {
                 );
             }
             ll::Operation::RmDir(x) => {
+                let mut access = self.access(AccessKind::RmDir);
+                access.parent = Some(self.request.nodeid().into());
+                access.name = Some(x.name().as_os_str().to_owned());
+                se.authorize(access)?;
                 se.filesystem.rmdir(
                     self,
                     self.request.nodeid().into(),
@@ -282,6 +479,12 @@ impl<'a> Request<'a> {
                 );
             }
             ll::Operation::SymLink(x) => {
+                let mut access = self.access(AccessKind::SymLink {
+                    target: x.target().to_owned(),
+                });
+                access.parent = Some(self.request.nodeid().into());
+                access.name = Some(x.link_name().as_os_str().to_owned());
+                se.authorize(access)?;
                 se.filesystem.symlink(
                     self,
                     self.request.nodeid().into(),
@@ -291,6 +494,13 @@ impl<'a> Request<'a> {
                 );
             }
             ll::Operation::Rename(x) => {
+                let mut access = self.access(AccessKind::Rename { flags: 0 });
+                access.parent = Some(self.request.nodeid().into());
+                access.name = Some(x.src().name.as_os_str().to_owned());
+                access.new_parent = Some(x.dest().dir.into());
+                access.new_name = Some(x.dest().name.as_os_str().to_owned());
+                se.authorize(access)?;
+                let reply = self.rename_reply(se, x.src(), x.dest(), 0, false)?;
                 se.filesystem.rename(
                     self,
                     self.request.nodeid().into(),
@@ -298,10 +508,15 @@ impl<'a> Request<'a> {
                     x.dest().dir.into(),
                     x.dest().name.as_ref(),
                     0,
-                    self.reply(),
+                    reply,
                 );
             }
             ll::Operation::Link(x) => {
+                let mut access = self.access(AccessKind::Link);
+                access.ino = Some(x.inode_no().into());
+                access.parent = Some(self.request.nodeid().into());
+                access.name = Some(x.dest().name.as_os_str().to_owned());
+                se.authorize(access)?;
                 se.filesystem.link(
                     self,
                     x.inode_no().into(),
@@ -311,8 +526,20 @@ impl<'a> Request<'a> {
                 );
             }
             ll::Operation::Open(x) => {
+                let mut access = self.access(AccessKind::Open { flags: x.flags() });
+                access.ino = Some(self.request.nodeid().into());
+                se.authorize(access)?;
+                let mut reply: ReplyOpen = self.reply();
+                if se.noflush() {
+                    reply = reply.noflush();
+                }
                 se.filesystem
-                    .open(self, self.request.nodeid().into(), x.flags(), self.reply());
+                    .open(self, self.request.nodeid().into(), x.flags(), reply);
+            }
+            ll::Operation::Read(x)
+                if x.size() == 0 && se.empty_io_policy == EmptyIoPolicy::Answer =>
+            {
+                return Ok(Some(Response::new_data(&[][..])));
             }
             ll::Operation::Read(x) => {
                 se.filesystem.read(
@@ -326,13 +553,20 @@ impl<'a> Request<'a> {
                     self.reply(),
                 );
             }
+            ll::Operation::Write(x)
+                if x.data().is_empty() && se.empty_io_policy == EmptyIoPolicy::Answer =>
+            {
+                return Ok(Some(Response::new_write(0)));
+            }
             ll::Operation::Write(x) => {
-                se.filesystem.write(
+                #[cfg(debug_assertions)]
+                se.check_append(self.request.nodeid().into(), x.offset(), x.flags());
+                se.filesystem.write_stream(
                     self,
                     self.request.nodeid().into(),
                     x.file_handle().into(),
                     x.offset(),
-                    x.data(),
+                    WriteData::new(x.data()),
                     x.write_flags(),
                     x.flags(),
                     x.lock_owner().map(|l| l.into()),
@@ -340,6 +574,12 @@ impl<'a> Request<'a> {
                 );
             }
             ll::Operation::Flush(x) => {
+                if let Some(locks) = &mut se.locks {
+                    locks.release(self.request.nodeid().into(), x.lock_owner().into());
+                }
+                if se.noop_flush {
+                    return Ok(Some(Response::new_empty()));
+                }
                 se.filesystem.flush(
                     self,
                     self.request.nodeid().into(),
@@ -349,6 +589,9 @@ impl<'a> Request<'a> {
                 );
             }
             ll::Operation::Release(x) => {
+                if let (Some(locks), Some(owner)) = (&mut se.locks, x.lock_owner()) {
+                    locks.release(self.request.nodeid().into(), owner.into());
+                }
                 se.filesystem.release(
                     self,
                     self.request.nodeid().into(),
@@ -444,6 +687,17 @@ impl<'a> Request<'a> {
                     .access(self, self.request.nodeid().into(), x.mask(), self.reply());
             }
             ll::Operation::Create(x) => {
+                let mut access = self.access(AccessKind::Create {
+                    mode: x.mode(),
+                    flags: x.flags(),
+                });
+                access.parent = Some(self.request.nodeid().into());
+                access.name = Some(x.name().as_os_str().to_owned());
+                se.authorize(access)?;
+                let mut reply: ReplyCreate = self.reply();
+                if se.noflush() {
+                    reply = reply.noflush();
+                }
                 se.filesystem.create(
                     self,
                     self.request.nodeid().into(),
@@ -451,10 +705,28 @@ impl<'a> Request<'a> {
                     x.mode(),
                     x.umask(),
                     x.flags(),
-                    self.reply(),
+                    reply,
                 );
             }
             ll::Operation::GetLk(x) => {
+                if let Some(locks) = &se.locks {
+                    let lock = posix_lock(x.lock_owner().into(), x.lock());
+                    let conflict = locks
+                        .table
+                        .test(self.request.nodeid().into(), &lock)
+                        .map_or(
+                            ll::Lock {
+                                typ: libc::F_UNLCK,
+                                ..x.lock()
+                            },
+                            |l| ll::Lock {
+                                range: (l.start, l.end),
+                                typ: l.typ,
+                                pid: l.pid,
+                            },
+                        );
+                    return Ok(Some(Response::new_lock(&conflict)));
+                }
                 se.filesystem.getlk(
                     self,
                     self.request.nodeid().into(),
@@ -468,6 +740,13 @@ impl<'a> Request<'a> {
                 );
             }
             ll::Operation::SetLk(x) => {
+                if let Some(locks) = &mut se.locks {
+                    let lock = posix_lock(x.lock_owner().into(), x.lock());
+                    locks
+                        .set(self.request.nodeid().into(), lock)
+                        .map_err(Errno::from_i32)?;
+                    return Ok(Some(Response::new_empty()));
+                }
                 se.filesystem.setlk(
                     self,
                     self.request.nodeid().into(),
@@ -482,6 +761,16 @@ impl<'a> Request<'a> {
                 );
             }
             ll::Operation::SetLkW(x) => {
+                if let Some(locks) = &mut se.locks {
+                    let lock = posix_lock(x.lock_owner().into(), x.lock());
+                    locks.set_wait(
+                        self.request.unique().into(),
+                        self.request.nodeid().into(),
+                        lock,
+                        self.reply(),
+                    );
+                    return Ok(None);
+                }
                 se.filesystem.setlk(
                     self,
                     self.request.nodeid().into(),
@@ -573,6 +862,17 @@ impl<'a> Request<'a> {
             }
             #[cfg(feature = "abi-7-23")]
             ll::Operation::Rename2(x) => {
+                Platform::current()
+                    .check_rename_flags(x.flags())
+                    .map_err(Errno::from_i32)?;
+                let mut access = self.access(AccessKind::Rename { flags: x.flags() });
+                access.parent = Some(x.from().dir.into());
+                access.name = Some(x.from().name.as_os_str().to_owned());
+                access.new_parent = Some(x.to().dir.into());
+                access.new_name = Some(x.to().name.as_os_str().to_owned());
+                se.authorize(access)?;
+                let exchange = x.flags() & crate::platform::RENAME_EXCHANGE != 0;
+                let reply = self.rename_reply(se, x.from(), x.to(), x.flags(), exchange)?;
                 se.filesystem.rename(
                     self,
                     x.from().dir.into(),
@@ -580,7 +880,7 @@ impl<'a> Request<'a> {
                     x.to().dir.into(),
                     x.to().name.as_ref(),
                     x.flags(),
-                    self.reply(),
+                    reply,
                 );
             }
             #[cfg(feature = "abi-7-24")]
@@ -597,6 +897,17 @@ impl<'a> Request<'a> {
             #[cfg(feature = "abi-7-28")]
             ll::Operation::CopyFileRange(x) => {
                 let (i, o) = (x.src(), x.dest());
+                if x.flags().unknown() != 0
+                    || !copy_flags::valid_copy(
+                        i.inode.into(),
+                        i.offset,
+                        o.inode.into(),
+                        o.offset,
+                        x.len(),
+                    )
+                {
+                    return Err(Errno::EINVAL);
+                }
                 se.filesystem.copy_file_range(
                     self,
                     i.inode.into(),
@@ -606,7 +917,7 @@ impl<'a> Request<'a> {
                     o.file_handle.into(),
                     o.offset,
                     x.len(),
-                    x.flags().try_into().unwrap(),
+                    x.flags().bits().try_into().unwrap(),
                     self.reply(),
                 );
             }
@@ -621,6 +932,15 @@ impl<'a> Request<'a> {
             }
             #[cfg(target_os = "macos")]
             ll::Operation::Exchange(x) => {
+                let mut access = self.access(AccessKind::Rename {
+                    flags: crate::platform::RENAME_EXCHANGE,
+                });
+                access.parent = Some(x.from().dir.into());
+                access.name = Some(x.from().name.as_os_str().to_owned());
+                access.new_parent = Some(x.to().dir.into());
+                access.new_name = Some(x.to().name.as_os_str().to_owned());
+                se.authorize(access)?;
+                let reply = self.rename_reply(se, x.from(), x.to(), x.options() as u32, true)?;
                 se.filesystem.exchange(
                     self,
                     x.from().dir.into(),
@@ -628,7 +948,7 @@ impl<'a> Request<'a> {
                     x.to().dir.into(),
                     x.to().name.as_ref(),
                     x.options(),
-                    self.reply(),
+                    reply,
                 );
             }
 
@@ -647,22 +967,190 @@ impl<'a> Request<'a> {
         Reply::new(self.request.unique().into(), self.ch.clone())
     }
 
-    /// Returns the unique identifier of this request
+    /// Create a reply to a rename, which is recorded in the session's rename journal first
+    fn rename_reply<FS: Filesystem, T: Reply>(
+        &self,
+        se: &Session<FS>,
+        from: ll::FilenameInDir<'_>,
+        to: ll::FilenameInDir<'_>,
+        flags: u32,
+        exchange: bool,
+    ) -> Result<T, Errno> {
+        let Some(journal) = &se.rename_journal else {
+            return Ok(self.reply());
+        };
+        let intent = journal
+            .lock()
+            .unwrap()
+            .begin(
+                from.dir.into(),
+                from.name.as_os_str(),
+                to.dir.into(),
+                to.name.as_os_str(),
+                flags,
+                exchange,
+            )
+            .map_err(|err| {
+                error!("Failed to record rename in the journal: {}", err);
+                Errno::EIO
+            })?;
+        let pending = PendingRename::new(journal.clone(), intent.id);
+        Ok(Reply::new(
+            self.request.unique().into(),
+            self.ch.clone().with_rename(pending),
+        ))
+    }
+
+    /// Describe an operation of this request's caller for the authorizer
+    fn access(&self, kind: AccessKind) -> Access {
+        Access::new(kind, self.uid(), self.gid())
+    }
+
+    /// Returns the settings negotiated with the kernel during init
+    #[inline]
+    pub fn connection(&self) -> &ConnectionInfo {
+        &self.connection
+    }
+
+    /// Returns the unique identifier of this request. It is unique among the requests pending
+    /// at the same time, and logged by the session, so it can be used to correlate logs and
+    /// calls to backends with the request.
     #[inline]
     pub fn unique(&self) -> u64 {
         self.request.unique().into()
     }
 
-    /// Returns the uid of this request
+    /// Returns a token telling whether the kernel interrupted this request, which a long
+    /// running operation can check to give up early and reply with `EINTR`. See also
+    /// [`Filesystem::interrupt`].
+    pub fn interrupt_token(&self) -> InterruptToken {
+        self.ch.interrupt_token(self.unique())
+    }
+
+    /// Attach a correlation token to the request, e.g. the id of the request the filesystem
+    /// sends to its backend. The session reports it with the request in
+    /// [`Session::debug_dump`](crate::Session::debug_dump) and logs it with the reply, so the
+    /// two can be matched up without a map keyed by the unique id. Replies that are answered
+    /// later can attach it with their `set_correlation` method instead. Does nothing once the
+    /// request was answered.
+    pub fn set_correlation(&self, token: impl Into<String>) {
+        self.ch.correlate(self.unique(), token.into());
+    }
+
+    /// Account `bytes` of memory the filesystem holds to answer this request later, like a copy
+    /// of the data of a write, against the memory budget of the session until the request is
+    /// answered (see [`Session::set_memory_budget`](crate::Session::set_memory_budget)). Does
+    /// nothing once the request was answered.
+    pub fn charge_memory(&self, bytes: usize) {
+        self.ch.charge(self.unique(), bytes);
+    }
+
+    /// Returns the checksum of the data of a write request, if the session computes them (see
+    /// [`Session::set_write_checksum`](crate::Session::set_write_checksum))
+    #[inline]
+    pub fn write_checksum(&self) -> Option<u64> {
+        self.write_checksum
+    }
+
+    /// Returns whether the session's ACL rejects the user of the request
+    fn denied_by_acl<FS: Filesystem>(&self, se: &Session<FS>) -> bool {
+        (se.allowed == SessionACL::RootAndOwner
+            && self.request.uid() != se.session_owner
+            && self.request.uid() != 0)
+            || (se.allowed == SessionACL::Owner && self.request.uid() != se.session_owner)
+    }
+
+    /// Handle an opcode this crate doesn't know according to the session's
+    /// [`UnknownOpcodePolicy`]
+    fn dispatch_unknown<FS: Filesystem>(
+        &self,
+        se: &mut Session<FS>,
+        opcode: u32,
+    ) -> Result<Option<Response<'_>>, Errno> {
+        let first = se.unknown_opcodes.insert(opcode);
+        let raw = se.filesystem.raw_intercept(opcode);
+        if !raw && se.unknown_opcode_policy == UnknownOpcodePolicy::Reject {
+            if first {
+                warn!("Unknown FUSE opcode {}, replying ENOSYS", opcode);
+            }
+            return Err(Errno::ENOSYS);
+        }
+        if !se.initialized || se.destroyed || se.disabled_opcodes.contains(&opcode) {
+            return Err(Errno::ENOSYS);
+        }
+        if self.denied_by_acl(se) {
+            return Err(Errno::EACCES);
+        }
+        if raw {
+            se.filesystem
+                .handle_raw(self, opcode, self.payload(), self.reply());
+        } else {
+            se.filesystem
+                .unknown(self, opcode, self.payload(), self.reply());
+        }
+        Ok(None)
+    }
+
+    /// Returns the arguments of the request as sent by the kernel, without the header
+    fn payload(&self) -> &'a [u8] {
+        &self.data[std::mem::size_of::<abi::fuse_in_header>()..]
+    }
+
+    /// Returns the size of the request as read from the kernel, header included
+    #[inline]
+    pub fn size(&self) -> usize {
+        self.data.len()
+    }
+
+    /// Returns the size of the request without its header, i.e. of the arguments of the
+    /// operation, which for writes are mostly the data
+    #[inline]
+    pub fn body_size(&self) -> usize {
+        self.data.len() - std::mem::size_of::<abi::fuse_in_header>()
+    }
+
+    /// Returns whether a `getattr` request is for an open file, and which. Returns `None` for
+    /// other requests.
+    #[inline]
+    pub fn getattr_fh(&self) -> Option<GetattrFh> {
+        match self.request.operation() {
+            Ok(ll::Operation::GetAttr(x)) => Some(x.fh()),
+            _ => None,
+        }
+    }
+
+    /// Returns the operation of this request, unless the opcode is unknown to this crate
+    #[inline]
+    pub fn opcode(&self) -> Option<abi::fuse_opcode> {
+        abi::fuse_opcode::try_from(self.request.opcode()).ok()
+    }
+
+    /// Returns when the request was read from the kernel, according to the clock of the session
+    /// (see [`Session::set_clock`](crate::Session::set_clock)). Filesystems can use it to
+    /// enforce their own deadlines, or to measure how long requests waited.
+    #[inline]
+    pub fn received(&self) -> Instant {
+        self.received
+    }
+
+    /// Returns the uid of this request, mapped to the ids of the filesystem if the session has
+    /// an [`IdMap`](crate::IdMap)
     #[inline]
     pub fn uid(&self) -> u32 {
-        self.request.uid()
+        match self.ch.id_map() {
+            Some(map) => map.request_uid(self.request.uid()),
+            None => self.request.uid(),
+        }
     }
 
-    /// Returns the gid of this request
+    /// Returns the gid of this request, mapped to the ids of the filesystem if the session has
+    /// an [`IdMap`](crate::IdMap)
     #[inline]
     pub fn gid(&self) -> u32 {
-        self.request.gid()
+        match self.ch.id_map() {
+            Some(map) => map.request_gid(self.request.gid()),
+            None => self.request.gid(),
+        }
     }
 
     /// Returns the pid of this request
@@ -671,3 +1159,38 @@ impl<'a> Request<'a> {
         self.request.pid()
     }
 }
+
+/// The lock of a `getlk` or `setlk` request, as kept by the session's lock table
+fn posix_lock(owner: u64, lock: ll::Lock) -> PosixLock {
+    PosixLock {
+        owner,
+        start: lock.range.0,
+        end: lock.range.1,
+        typ: lock.typ,
+        pid: lock.pid,
+    }
+}
+
+/// Describe the request for the hooks of the session
+fn hook_info(request: &ll::AnyRequest<'_>) -> RequestInfo {
+    RequestInfo {
+        unique: request.unique().0,
+        opcode: request.opcode(),
+        ino: request.nodeid().0,
+        uid: request.uid(),
+        pid: request.pid(),
+    }
+}
+
+/// Whether the kernel waits for a reply to requests with the given opcode
+fn expects_reply(opcode: u32) -> bool {
+    use abi::fuse_opcode::*;
+    match abi::fuse_opcode::try_from(opcode) {
+        Ok(FUSE_FORGET | FUSE_INTERRUPT) => false,
+        #[cfg(feature = "abi-7-16")]
+        Ok(FUSE_BATCH_FORGET) => false,
+        #[cfg(feature = "abi-7-15")]
+        Ok(FUSE_NOTIFY_REPLY) => false,
+        _ => true,
+    }
+}
@@ -0,0 +1,527 @@
+//! Per-user views of a filesystem
+//!
+//! A mount shared by several users sometimes should show each of them only their own part of
+//! the filesystem, like a chroot per user. [`ScopedRootFs`] wraps any filesystem and answers
+//! requests on the root of the mount with the root of the requesting user's subtree instead.
+
+use libc::{c_int, EACCES};
+use std::collections::HashMap;
+use std::ffi::OsStr;
+use std::path::Path;
+use std::time::SystemTime;
+
+use crate::forward::forward_filesystem;
+#[cfg(target_os = "macos")]
+use crate::ReplyXTimes;
+use crate::{
+    Filesystem, ReplyAttr, ReplyCreate, ReplyDirectory, ReplyDirectoryPlus, ReplyEmpty, ReplyEntry,
+    ReplyIoctl, ReplyLock, ReplyLseek, ReplyOpen, ReplyStatfs, ReplyXattr, Request, TimeOrNow,
+    FUSE_ROOT_ID,
+};
+#[cfg(feature = "abi-7-11")]
+use crate::{PollEvents, PollHandle, ReplyPoll};
+
+/// Replace an inode of a request by the inode the requester sees it as, or reply with an error
+/// if the requester has no root.
+macro_rules! scope {
+    ($self:ident, $req:ident, $reply:ident, $($ino:ident),+) => {
+        $(
+            let $ino = match $self.scope($req, $ino) {
+                Ok(ino) => ino,
+                Err(err) => return $reply.error(err),
+            };
+        )+
+    };
+}
+
+/// A filesystem wrapper that shows each user a different directory of the inner filesystem as
+/// the root of the mount.
+///
+/// The root of each user's subtree is configured with [`with_root`](ScopedRootFs::with_root).
+/// Requests of users without a root fail with `EACCES`, unless a
+/// [default root](ScopedRootFs::with_default_root) is set. Only the root inode is translated:
+/// all other inodes are passed to the inner filesystem unchanged, so the inner filesystem must
+/// not hand out inodes of a subtree to anyone but its user.
+///
+/// The kernel caches directory entries, attributes and directory listings per inode, not per
+/// user. So that one user never sees what was cached for another one, entries and attributes
+/// of the root and the entries directly below it are replied with a TTL of zero, `readdirplus`
+/// of the root is answered without attributes and the listing of the root is never cached.
+/// Inodes below the root are cached as usual.
+///
+/// This is not a security boundary: a process that already holds a file descriptor of another
+/// user's subtree (e.g. because it changed its uid) keeps access to it, and handles of the
+/// root directory are shared by whoever the kernel passes them to. Use it together with
+/// `default_permissions` or the filesystem's own permission checks.
+#[derive(Debug)]
+pub struct ScopedRootFs<F> {
+    inner: F,
+    /// Inode of the root of each user's subtree
+    roots: HashMap<u32, u64>,
+    /// Inode of the root of users without a subtree
+    default_root: Option<u64>,
+}
+
+impl<F: Filesystem> ScopedRootFs<F> {
+    /// Wrap a filesystem. Until roots are added, all requests fail with `EACCES`.
+    pub fn new(inner: F) -> Self {
+        Self {
+            inner,
+            roots: HashMap::new(),
+            default_root: None,
+        }
+    }
+
+    /// Show the user `uid` the directory `ino` of the inner filesystem as the root of the mount.
+    pub fn with_root(mut self, uid: u32, ino: u64) -> Self {
+        self.roots.insert(uid, ino);
+        self
+    }
+
+    /// Show users without a root of their own the directory `ino` of the inner filesystem as
+    /// the root of the mount.
+    pub fn with_default_root(mut self, ino: u64) -> Self {
+        self.default_root = Some(ino);
+        self
+    }
+
+    /// Returns the inode of the inner filesystem the user `uid` sees as the root, if any.
+    pub fn root(&self, uid: u32) -> Option<u64> {
+        self.roots.get(&uid).copied().or(self.default_root)
+    }
+
+    /// Returns a reference to the inner filesystem.
+    pub fn inner(&self) -> &F {
+        &self.inner
+    }
+
+    /// Returns a mutable reference to the inner filesystem.
+    pub fn inner_mut(&mut self) -> &mut F {
+        &mut self.inner
+    }
+
+    /// Unwrap the inner filesystem.
+    pub fn into_inner(self) -> F {
+        self.inner
+    }
+
+    /// Returns the inode of the inner filesystem for an inode of a request.
+    fn scope(&self, req: &Request<'_>, ino: u64) -> Result<u64, c_int> {
+        if ino != FUSE_ROOT_ID {
+            return Ok(ino);
+        }
+        self.root(req.uid()).ok_or(EACCES)
+    }
+}
+
+impl<F: Filesystem> Filesystem for ScopedRootFs<F> {
+    forward_filesystem!(
+        inner,
+        except: lookup, forget, getattr, setattr, mknod, mkdir, unlink, rmdir, symlink, rename,
+        link, open, opendir, readdir, readdirplus, releasedir, fsyncdir, statfs, setxattr, getxattr,
+        listxattr, removexattr, access, create, getlk, setlk, ioctl, poll, lseek, exchange,
+        getxtimes
+    );
+
+    fn lookup(&mut self, req: &Request<'_>, parent: u64, name: &OsStr, reply: ReplyEntry) {
+        let reply = if parent == FUSE_ROOT_ID {
+            reply.uncached()
+        } else {
+            reply
+        };
+        scope!(self, req, reply, parent);
+        self.inner.lookup(req, parent, name, reply)
+    }
+
+    fn forget(&mut self, req: &Request<'_>, ino: u64, nlookup: u64) {
+        // The kernel never forgets the root
+        self.inner.forget(req, ino, nlookup)
+    }
+
+    fn getattr(&mut self, req: &Request<'_>, ino: u64, fh: Option<u64>, reply: ReplyAttr) {
+        let reply = if ino == FUSE_ROOT_ID {
+            reply.uncached()
+        } else {
+            reply
+        };
+        scope!(self, req, reply, ino);
+        self.inner.getattr(req, ino, fh, reply)
+    }
+
+    fn setattr(
+        &mut self,
+        req: &Request<'_>,
+        ino: u64,
+        mode: Option<u32>,
+        uid: Option<u32>,
+        gid: Option<u32>,
+        size: Option<u64>,
+        atime: Option<TimeOrNow>,
+        mtime: Option<TimeOrNow>,
+        ctime: Option<SystemTime>,
+        fh: Option<u64>,
+        crtime: Option<SystemTime>,
+        chgtime: Option<SystemTime>,
+        bkuptime: Option<SystemTime>,
+        flags: Option<u32>,
+        reply: ReplyAttr,
+    ) {
+        let reply = if ino == FUSE_ROOT_ID {
+            reply.uncached()
+        } else {
+            reply
+        };
+        scope!(self, req, reply, ino);
+        self.inner.setattr(
+            req, ino, mode, uid, gid, size, atime, mtime, ctime, fh, crtime, chgtime, bkuptime,
+            flags, reply,
+        )
+    }
+
+    fn mknod(
+        &mut self,
+        req: &Request<'_>,
+        parent: u64,
+        name: &OsStr,
+        mode: u32,
+        umask: u32,
+        rdev: u32,
+        reply: ReplyEntry,
+    ) {
+        let reply = if parent == FUSE_ROOT_ID {
+            reply.uncached()
+        } else {
+            reply
+        };
+        scope!(self, req, reply, parent);
+        self.inner
+            .mknod(req, parent, name, mode, umask, rdev, reply)
+    }
+
+    fn mkdir(
+        &mut self,
+        req: &Request<'_>,
+        parent: u64,
+        name: &OsStr,
+        mode: u32,
+        umask: u32,
+        reply: ReplyEntry,
+    ) {
+        let reply = if parent == FUSE_ROOT_ID {
+            reply.uncached()
+        } else {
+            reply
+        };
+        scope!(self, req, reply, parent);
+        self.inner.mkdir(req, parent, name, mode, umask, reply)
+    }
+
+    fn unlink(&mut self, req: &Request<'_>, parent: u64, name: &OsStr, reply: ReplyEmpty) {
+        scope!(self, req, reply, parent);
+        self.inner.unlink(req, parent, name, reply)
+    }
+
+    fn rmdir(&mut self, req: &Request<'_>, parent: u64, name: &OsStr, reply: ReplyEmpty) {
+        scope!(self, req, reply, parent);
+        self.inner.rmdir(req, parent, name, reply)
+    }
+
+    fn symlink(
+        &mut self,
+        req: &Request<'_>,
+        parent: u64,
+        link_name: &OsStr,
+        target: &Path,
+        reply: ReplyEntry,
+    ) {
+        let reply = if parent == FUSE_ROOT_ID {
+            reply.uncached()
+        } else {
+            reply
+        };
+        scope!(self, req, reply, parent);
+        self.inner.symlink(req, parent, link_name, target, reply)
+    }
+
+    fn rename(
+        &mut self,
+        req: &Request<'_>,
+        parent: u64,
+        name: &OsStr,
+        newparent: u64,
+        newname: &OsStr,
+        flags: u32,
+        reply: ReplyEmpty,
+    ) {
+        scope!(self, req, reply, parent, newparent);
+        self.inner
+            .rename(req, parent, name, newparent, newname, flags, reply)
+    }
+
+    fn link(
+        &mut self,
+        req: &Request<'_>,
+        ino: u64,
+        newparent: u64,
+        newname: &OsStr,
+        reply: ReplyEntry,
+    ) {
+        let reply = if newparent == FUSE_ROOT_ID {
+            reply.uncached()
+        } else {
+            reply
+        };
+        scope!(self, req, reply, ino, newparent);
+        self.inner.link(req, ino, newparent, newname, reply)
+    }
+
+    fn open(&mut self, req: &Request<'_>, ino: u64, flags: i32, reply: ReplyOpen) {
+        scope!(self, req, reply, ino);
+        self.inner.open(req, ino, flags, reply)
+    }
+
+    fn opendir(&mut self, req: &Request<'_>, ino: u64, flags: i32, reply: ReplyOpen) {
+        let reply = if ino == FUSE_ROOT_ID {
+            reply.uncached()
+        } else {
+            reply
+        };
+        scope!(self, req, reply, ino);
+        self.inner.opendir(req, ino, flags, reply)
+    }
+
+    fn readdir(
+        &mut self,
+        req: &Request<'_>,
+        ino: u64,
+        fh: u64,
+        offset: i64,
+        reply: ReplyDirectory,
+    ) {
+        scope!(self, req, reply, ino);
+        self.inner.readdir(req, ino, fh, offset, reply)
+    }
+
+    fn readdirplus(
+        &mut self,
+        req: &Request<'_>,
+        ino: u64,
+        fh: u64,
+        offset: i64,
+        reply: ReplyDirectoryPlus,
+    ) {
+        if ino == FUSE_ROOT_ID {
+            // Entries of the root must be looked up by each user
+            let reply = reply.into_plain();
+            scope!(self, req, reply, ino);
+            return self.inner.readdir(req, ino, fh, offset, reply);
+        }
+        self.inner.readdirplus(req, ino, fh, offset, reply)
+    }
+
+    fn releasedir(&mut self, req: &Request<'_>, ino: u64, fh: u64, flags: i32, reply: ReplyEmpty) {
+        scope!(self, req, reply, ino);
+        self.inner.releasedir(req, ino, fh, flags, reply)
+    }
+
+    fn fsyncdir(
+        &mut self,
+        req: &Request<'_>,
+        ino: u64,
+        fh: u64,
+        datasync: bool,
+        reply: ReplyEmpty,
+    ) {
+        scope!(self, req, reply, ino);
+        self.inner.fsyncdir(req, ino, fh, datasync, reply)
+    }
+
+    fn statfs(&mut self, req: &Request<'_>, ino: u64, reply: ReplyStatfs) {
+        scope!(self, req, reply, ino);
+        self.inner.statfs(req, ino, reply)
+    }
+
+    fn setxattr(
+        &mut self,
+        req: &Request<'_>,
+        ino: u64,
+        name: &OsStr,
+        value: &[u8],
+        flags: i32,
+        position: u32,
+        reply: ReplyEmpty,
+    ) {
+        scope!(self, req, reply, ino);
+        self.inner
+            .setxattr(req, ino, name, value, flags, position, reply)
+    }
+
+    fn getxattr(
+        &mut self,
+        req: &Request<'_>,
+        ino: u64,
+        name: &OsStr,
+        size: u32,
+        reply: ReplyXattr,
+    ) {
+        scope!(self, req, reply, ino);
+        self.inner.getxattr(req, ino, name, size, reply)
+    }
+
+    fn listxattr(&mut self, req: &Request<'_>, ino: u64, size: u32, reply: ReplyXattr) {
+        scope!(self, req, reply, ino);
+        self.inner.listxattr(req, ino, size, reply)
+    }
+
+    fn removexattr(&mut self, req: &Request<'_>, ino: u64, name: &OsStr, reply: ReplyEmpty) {
+        scope!(self, req, reply, ino);
+        self.inner.removexattr(req, ino, name, reply)
+    }
+
+    fn access(&mut self, req: &Request<'_>, ino: u64, mask: i32, reply: ReplyEmpty) {
+        scope!(self, req, reply, ino);
+        self.inner.access(req, ino, mask, reply)
+    }
+
+    fn create(
+        &mut self,
+        req: &Request<'_>,
+        parent: u64,
+        name: &OsStr,
+        mode: u32,
+        umask: u32,
+        flags: i32,
+        reply: ReplyCreate,
+    ) {
+        let reply = if parent == FUSE_ROOT_ID {
+            reply.uncached()
+        } else {
+            reply
+        };
+        scope!(self, req, reply, parent);
+        self.inner
+            .create(req, parent, name, mode, umask, flags, reply)
+    }
+
+    fn getlk(
+        &mut self,
+        req: &Request<'_>,
+        ino: u64,
+        fh: u64,
+        lock_owner: u64,
+        start: u64,
+        end: u64,
+        typ: i32,
+        pid: u32,
+        reply: ReplyLock,
+    ) {
+        scope!(self, req, reply, ino);
+        self.inner
+            .getlk(req, ino, fh, lock_owner, start, end, typ, pid, reply)
+    }
+
+    fn setlk(
+        &mut self,
+        req: &Request<'_>,
+        ino: u64,
+        fh: u64,
+        lock_owner: u64,
+        start: u64,
+        end: u64,
+        typ: i32,
+        pid: u32,
+        sleep: bool,
+        reply: ReplyEmpty,
+    ) {
+        scope!(self, req, reply, ino);
+        self.inner
+            .setlk(req, ino, fh, lock_owner, start, end, typ, pid, sleep, reply)
+    }
+
+    fn ioctl(
+        &mut self,
+        req: &Request<'_>,
+        ino: u64,
+        fh: u64,
+        flags: u32,
+        cmd: u32,
+        in_data: &[u8],
+        out_size: u32,
+        reply: ReplyIoctl,
+    ) {
+        scope!(self, req, reply, ino);
+        self.inner
+            .ioctl(req, ino, fh, flags, cmd, in_data, out_size, reply)
+    }
+
+    #[cfg(feature = "abi-7-11")]
+    fn poll(
+        &mut self,
+        req: &Request<'_>,
+        ino: u64,
+        fh: u64,
+        ph: PollHandle,
+        events: PollEvents,
+        flags: u32,
+        reply: ReplyPoll,
+    ) {
+        scope!(self, req, reply, ino);
+        self.inner.poll(req, ino, fh, ph, events, flags, reply)
+    }
+
+    fn lseek(
+        &mut self,
+        req: &Request<'_>,
+        ino: u64,
+        fh: u64,
+        offset: i64,
+        whence: i32,
+        reply: ReplyLseek,
+    ) {
+        scope!(self, req, reply, ino);
+        self.inner.lseek(req, ino, fh, offset, whence, reply)
+    }
+
+    #[cfg(target_os = "macos")]
+    fn exchange(
+        &mut self,
+        req: &Request<'_>,
+        parent: u64,
+        name: &OsStr,
+        newparent: u64,
+        newname: &OsStr,
+        options: u64,
+        reply: ReplyEmpty,
+    ) {
+        scope!(self, req, reply, parent, newparent);
+        self.inner
+            .exchange(req, parent, name, newparent, newname, options, reply)
+    }
+
+    #[cfg(target_os = "macos")]
+    fn getxtimes(&mut self, req: &Request<'_>, ino: u64, reply: ReplyXTimes) {
+        scope!(self, req, reply, ino);
+        self.inner.getxtimes(req, ino, reply)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::ScopedRootFs;
+
+    struct NullFs;
+    impl crate::Filesystem for NullFs {}
+
+    #[test]
+    fn roots() {
+        let fs = ScopedRootFs::new(NullFs)
+            .with_root(1000, 5)
+            .with_root(1001, 6);
+        assert_eq!(fs.root(1000), Some(5));
+        assert_eq!(fs.root(1001), Some(6));
+        assert_eq!(fs.root(0), None);
+        let fs = fs.with_default_root(7);
+        assert_eq!(fs.root(0), Some(7));
+        assert_eq!(fs.root(1000), Some(5));
+    }
+}
@@ -5,23 +5,57 @@
 //! filesystem is mounted, the session loop receives, dispatches and replies to kernel requests
 //! for filesystem operations under its mount point.
 
-use libc::{EAGAIN, EINTR, ENODEV, ENOENT};
-use log::{info, warn};
+use libc::{c_int, EAGAIN, ECONNABORTED, EINTR, ENODEV, ENOENT};
+use log::{debug, error, info, warn};
 use nix::unistd::geteuid;
+use std::collections::HashSet;
 use std::fmt;
+#[cfg(feature = "abi-7-16")]
+use std::mem::size_of;
+#[cfg(feature = "abi-7-16")]
+use std::os::fd::AsRawFd;
 use std::os::fd::{AsFd, BorrowedFd, OwnedFd};
+use std::panic::{self, AssertUnwindSafe};
 use std::path::{Path, PathBuf};
-use std::sync::{Arc, Mutex};
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::sync::{Arc, Condvar, Mutex};
 use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
 use std::{io, ops::DerefMut};
 
-use crate::ll::fuse_abi as abi;
+use crate::authorizer::CachedAuthorizer;
+use crate::change_log::{ChangeLog, ChangeLogHandle};
+use crate::channel::ChannelSender;
+use crate::clock::{Clock, SystemClock};
+use crate::control::Stats;
+use crate::ll::fuse_abi::{self as abi, consts, fuse_opcode};
+use crate::ll::Errno;
+use crate::lock_table::SessionLocks;
+use crate::metrics::MetricsCollector;
+use crate::mnt::mount_options::check_option_values;
+#[cfg(feature = "abi-7-11")]
+use crate::notify::Notifier;
 use crate::request::Request;
+use crate::Checksum;
+use crate::ConnectionInfo;
+use crate::DebugDump;
+use crate::ErrnoPolicy;
 use crate::Filesystem;
+use crate::IdMap;
+use crate::Ino32Remap;
+use crate::KernelConnection;
+use crate::LockTable;
 use crate::MountOption;
-use crate::{channel::Channel, mnt::Mount};
-#[cfg(feature = "abi-7-11")]
-use crate::{channel::ChannelSender, notify::Notifier};
+#[cfg(debug_assertions)]
+use crate::OpenFlags;
+use crate::RenameJournal;
+use crate::{
+    channel::Channel,
+    mnt::{apply_post_mount_options, join_namespaces, mount_with_retry, Mount},
+};
+use crate::{Access, Authorizer, Metrics, RequestHook};
+#[cfg(feature = "abi-7-16")]
+use zerocopy::IntoBytes;
 
 /// The max size of write requests from the kernel. The absolute minimum is 4k,
 /// FUSE recommends at least 128k, max 16M. The FUSE default is 16M on macOS
@@ -32,6 +66,9 @@ pub const MAX_WRITE_SIZE: usize = 16 * 1024 * 1024;
 /// up to MAX_WRITE_SIZE bytes in a write request, we use that value plus some extra space.
 const BUFFER_SIZE: usize = MAX_WRITE_SIZE + 4096;
 
+/// How often a session waiting for memory to be freed checks whether it was unmounted
+const BUDGET_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
 #[derive(Default, Debug, Eq, PartialEq)]
 /// How requests should be filtered based on the calling UID.
 pub enum SessionACL {
@@ -44,6 +81,295 @@ pub enum SessionACL {
     Owner,
 }
 
+/// What a session does when a filesystem callback panics. In both cases, the request is failed
+/// with EIO and [`Filesystem::on_panic`] is called.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum PanicPolicy {
+    /// Keep serving requests.
+    #[default]
+    Continue,
+    /// Unmount the filesystem and end the session loop with an error.
+    Unmount,
+}
+
+/// What a session does when outstanding requests hold more memory than its budget, see
+/// [`Session::set_memory_budget`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum MemoryBudgetPolicy {
+    /// Stop reading requests until enough of them are answered.
+    #[default]
+    Wait,
+    /// Unmount the filesystem and end the session loop with an out of memory error, instead of
+    /// letting the process grow until the system kills it.
+    Abort,
+}
+
+/// What a session does with requests whose opcode this crate doesn't know, which newer kernels
+/// may send.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum UnknownOpcodePolicy {
+    /// Fail the request with ENOSYS, and log the first request of each unknown opcode.
+    #[default]
+    Reject,
+    /// Pass the request to [`Filesystem::unknown`], to prototype support for kernel features
+    /// this crate doesn't have yet.
+    Forward,
+}
+
+/// What a session does with reads of zero bytes and writes without data. The kernel sends
+/// them for `read(2)` and `write(2)` calls with a length of zero on files opened with
+/// `FOPEN_DIRECT_IO`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum EmptyIoPolicy {
+    /// Pass them to [`Filesystem::read`] and [`Filesystem::write`] like any other request.
+    #[default]
+    Forward,
+    /// Answer reads with no data and writes with zero bytes written, without calling the
+    /// filesystem. Requests on invalid file handles succeed as well.
+    Answer,
+}
+
+/// What a session does with replies to requests the kernel sent a `FUSE_INTERRUPT` for. The
+/// kernel still waits for an answer to an interrupted request (unless its caller was killed, in
+/// which case the reply is rejected with ENOENT), so a reply is always sent.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum InterruptedReply {
+    /// Send the reply the filesystem made.
+    #[default]
+    Send,
+    /// Send the reply the filesystem made, and log that it answers an interrupted request.
+    Log,
+    /// Discard the reply the filesystem made and fail the request with EINTR instead, as if the
+    /// filesystem aborted the operation.
+    Discard,
+}
+
+/// What a session does when the filesystem drops a reply without answering the request. The
+/// kernel waits for an answer, so the request is failed with EIO in any case.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum DroppedReply {
+    /// Log a warning.
+    #[default]
+    Warn,
+    /// Log an error with the operation and a backtrace of where the reply was dropped, and
+    /// panic in debug builds, so that the bug doesn't go unnoticed as an occasional EIO.
+    Strict,
+}
+
+/// A family of operations that can be disabled on a session (see
+/// [`Session::disable_operations`]).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OperationFamily {
+    /// `getxattr`, `setxattr`, `listxattr` and `removexattr`
+    Xattr,
+    /// `getlk`, `setlk` and `setlkw`. Also stops requesting POSIX and BSD lock support from the
+    /// kernel, which then handles locks locally.
+    Locks,
+    /// `mknod`
+    Mknod,
+    /// `symlink` and `readlink`
+    Symlinks,
+    /// `link`
+    HardLinks,
+    /// `ioctl`
+    Ioctl,
+    /// `poll`
+    Poll,
+    /// `fallocate`
+    Fallocate,
+    /// `lseek`
+    Lseek,
+    /// `copy_file_range`
+    CopyFileRange,
+    /// `bmap`
+    Bmap,
+}
+
+impl OperationFamily {
+    /// Returns the opcodes of the operations in this family
+    fn opcodes(self) -> Vec<fuse_opcode> {
+        match self {
+            OperationFamily::Xattr => vec![
+                fuse_opcode::FUSE_GETXATTR,
+                fuse_opcode::FUSE_SETXATTR,
+                fuse_opcode::FUSE_LISTXATTR,
+                fuse_opcode::FUSE_REMOVEXATTR,
+            ],
+            OperationFamily::Locks => vec![
+                fuse_opcode::FUSE_GETLK,
+                fuse_opcode::FUSE_SETLK,
+                fuse_opcode::FUSE_SETLKW,
+            ],
+            OperationFamily::Mknod => vec![fuse_opcode::FUSE_MKNOD],
+            OperationFamily::Symlinks => {
+                vec![fuse_opcode::FUSE_SYMLINK, fuse_opcode::FUSE_READLINK]
+            }
+            OperationFamily::HardLinks => vec![fuse_opcode::FUSE_LINK],
+            #[cfg(feature = "abi-7-11")]
+            OperationFamily::Ioctl => vec![fuse_opcode::FUSE_IOCTL],
+            #[cfg(feature = "abi-7-11")]
+            OperationFamily::Poll => vec![fuse_opcode::FUSE_POLL],
+            #[cfg(feature = "abi-7-19")]
+            OperationFamily::Fallocate => vec![fuse_opcode::FUSE_FALLOCATE],
+            #[cfg(feature = "abi-7-24")]
+            OperationFamily::Lseek => vec![fuse_opcode::FUSE_LSEEK],
+            #[cfg(feature = "abi-7-28")]
+            OperationFamily::CopyFileRange => vec![fuse_opcode::FUSE_COPY_FILE_RANGE],
+            OperationFamily::Bmap => vec![fuse_opcode::FUSE_BMAP],
+            // Not supported by the ABI version, so the kernel never sends these
+            #[allow(unreachable_patterns)]
+            _ => vec![],
+        }
+    }
+
+    /// Returns the init flags that must not be requested if this family is disabled
+    fn init_flags(self) -> u32 {
+        match self {
+            #[cfg(feature = "abi-7-17")]
+            OperationFamily::Locks => consts::FUSE_POSIX_LOCKS | consts::FUSE_FLOCK_LOCKS,
+            #[cfg(not(feature = "abi-7-17"))]
+            OperationFamily::Locks => consts::FUSE_POSIX_LOCKS,
+            _ => 0,
+        }
+    }
+}
+
+/// Why the session loop ended
+#[derive(Debug)]
+pub enum SessionExit {
+    /// The filesystem was unmounted
+    Unmounted,
+    /// The filesystem was unmounted after the kernel sent `destroy`
+    Destroyed,
+    /// The kernel aborted the connection, see [`Filesystem::aborted`]
+    Aborted,
+    /// The session failed
+    Error(io::Error),
+}
+
+impl SessionExit {
+    /// Returns true if the session ended because the filesystem was unmounted.
+    pub fn is_unmounted(&self) -> bool {
+        matches!(self, SessionExit::Unmounted | SessionExit::Destroyed)
+    }
+
+    /// Converts the exit reason into a result, which is an error if the session failed or the
+    /// connection was aborted.
+    pub fn into_result(self) -> io::Result<()> {
+        match self {
+            SessionExit::Unmounted | SessionExit::Destroyed => Ok(()),
+            SessionExit::Aborted => Err(io::Error::new(
+                io::ErrorKind::ConnectionAborted,
+                "Connection aborted by the kernel",
+            )),
+            SessionExit::Error(err) => Err(err),
+        }
+    }
+}
+
+/// Fails requests the filesystem doesn't answer in time, see [`Session::set_request_timeout`].
+/// Stops when dropped.
+#[derive(Debug)]
+struct RequestWatchdog {
+    stop: Arc<(Mutex<bool>, Condvar)>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl RequestWatchdog {
+    fn spawn(sender: ChannelSender, timeout: Duration, error: c_int) -> Self {
+        let stop = Arc::new((Mutex::new(false), Condvar::new()));
+        let thread = thread::spawn({
+            let stop = stop.clone();
+            move || {
+                // Fail requests at most a quarter of the timeout late
+                let interval = (timeout / 4).max(Duration::from_millis(1));
+                let (stopped, wake) = &*stop;
+                let mut stopped = stopped.lock().unwrap();
+                while !*stopped {
+                    stopped = wake.wait_timeout(stopped, interval).unwrap().0;
+                    if let Err(err) = sender.expire(timeout, error) {
+                        warn!("Failed to fail timed out requests: {}", err);
+                    }
+                }
+            }
+        });
+        Self {
+            stop,
+            thread: Some(thread),
+        }
+    }
+}
+
+impl Drop for RequestWatchdog {
+    fn drop(&mut self) {
+        let (stopped, wake) = &*self.stop;
+        *stopped.lock().unwrap() = true;
+        wake.notify_all();
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+/// Forgets accumulated to be delivered to `batch_forget` together
+#[cfg(feature = "abi-7-16")]
+#[derive(Debug)]
+struct ForgetBatch {
+    /// Deliver the batch once it has this many entries
+    max_entries: usize,
+    /// Deliver the batch once its first entry is this old
+    max_delay: Duration,
+    /// (nodeid, nlookup) of the accumulated forgets
+    pending: Vec<(u64, u64)>,
+    /// When the first pending forget was received
+    since: Option<Instant>,
+}
+
+#[cfg(feature = "abi-7-16")]
+impl ForgetBatch {
+    fn push(&mut self, nodeid: u64, nlookup: u64, now: Instant) {
+        if self.pending.is_empty() {
+            self.since = Some(now);
+        }
+        self.pending.push((nodeid, nlookup));
+    }
+
+    fn is_full(&self) -> bool {
+        self.pending.len() >= self.max_entries
+    }
+
+    /// Returns how long until the batch must be delivered, if there is one
+    fn remaining(&self, now: Instant) -> Option<Duration> {
+        self.since.map(|since| {
+            self.max_delay
+                .saturating_sub(now.saturating_duration_since(since))
+        })
+    }
+
+    /// Take the pending forgets as a FUSE_BATCH_FORGET request, aligned for parsing
+    fn take_request(&mut self) -> Vec<u64> {
+        let pending = std::mem::take(&mut self.pending);
+        self.since = None;
+        let len = size_of::<abi::fuse_in_header>()
+            + size_of::<abi::fuse_batch_forget_in>()
+            + pending.len() * size_of::<abi::fuse_forget_one>();
+        let mut data = Vec::with_capacity(len);
+        data.extend_from_slice(&(len as u32).to_ne_bytes());
+        data.extend_from_slice(&(fuse_opcode::FUSE_BATCH_FORGET as u32).to_ne_bytes());
+        // unique, nodeid, uid, gid, pid and padding are all zero
+        data.resize(size_of::<abi::fuse_in_header>(), 0);
+        data.extend_from_slice(&(pending.len() as u32).to_ne_bytes());
+        data.extend_from_slice(&0u32.to_ne_bytes());
+        for (nodeid, nlookup) in pending {
+            data.extend_from_slice(&nodeid.to_ne_bytes());
+            data.extend_from_slice(&nlookup.to_ne_bytes());
+        }
+        data.chunks(size_of::<u64>())
+            .map(|word| u64::from_ne_bytes(word.try_into().unwrap()))
+            .collect()
+    }
+}
+
 /// The session data structure
 #[derive(Debug)]
 pub struct Session<FS: Filesystem> {
@@ -62,10 +388,64 @@ pub struct Session<FS: Filesystem> {
     pub(crate) proto_major: u32,
     /// FUSE protocol minor version
     pub(crate) proto_minor: u32,
+    /// Settings negotiated with the kernel during init
+    pub(crate) connection: ConnectionInfo,
+    /// The same settings once init is done, shared with the background session handle
+    pub(crate) negotiated: Arc<Mutex<Option<ConnectionInfo>>>,
     /// True if the filesystem is initialized (init operation done)
     pub(crate) initialized: bool,
     /// True if the filesystem was destroyed (destroy operation done)
     pub(crate) destroyed: bool,
+    /// Name of the thread running the session in the background
+    thread_name: Option<String>,
+    /// CPUs the thread running the session in the background is restricted to
+    cpu_affinity: Option<Vec<usize>>,
+    /// What to do when a filesystem callback panics
+    panic_policy: PanicPolicy,
+    /// Opcodes that are replied with ENOSYS without calling the filesystem
+    pub(crate) disabled_opcodes: Vec<u32>,
+    /// Init flags that are never requested from the kernel
+    pub(crate) disabled_init_flags: u32,
+    /// Policy asked before operations that open, create or remove files
+    authorizer: Option<CachedAuthorizer>,
+    /// Whether the control attributes of the mount root are handled by the session
+    pub(crate) control: bool,
+    /// Counters shown through the control attributes
+    pub(crate) stats: Stats,
+    /// Source of time for everything timing related
+    pub(crate) clock: Arc<dyn Clock>,
+    /// Whether the filesystem was warned about handling O_APPEND itself
+    #[cfg(debug_assertions)]
+    warned_append: bool,
+    /// Memory requests that weren't answered yet may hold, before the session stops reading
+    /// requests
+    pub(crate) memory_budget: Option<usize>,
+    /// What to do once the memory budget is exceeded
+    memory_budget_policy: MemoryBudgetPolicy,
+    /// Forgets waiting to be delivered to `batch_forget`, if batching is enabled
+    #[cfg(feature = "abi-7-16")]
+    forget_batch: Option<ForgetBatch>,
+    /// POSIX locks managed by the session instead of the filesystem
+    pub(crate) locks: Option<SessionLocks>,
+    /// How long `destroy` may take when the session calls it on exit, if it guarantees it
+    destroy_timeout: Option<Duration>,
+    /// Why init failed, to end the session with
+    pub(crate) init_error: Option<io::Error>,
+    /// What to do with requests of unknown opcodes
+    pub(crate) unknown_opcode_policy: UnknownOpcodePolicy,
+    /// Unknown opcodes the kernel sent so far
+    pub(crate) unknown_opcodes: HashSet<u32>,
+    /// Journal the renames are recorded in, if enabled
+    pub(crate) rename_journal: Option<Arc<Mutex<RenameJournal>>>,
+    /// What to do with reads and writes of zero bytes
+    pub(crate) empty_io_policy: EmptyIoPolicy,
+    /// Whether the filesystem's `flush` does nothing
+    pub(crate) noop_flush: bool,
+    /// How long the filesystem may take to answer a request, and the error it is failed with
+    /// afterwards
+    request_timeout: Option<(Duration, c_int)>,
+    /// Collects the metrics of requests, once enabled
+    metrics: Option<MetricsCollector>,
 }
 
 impl<FS: Filesystem> AsFd for Session<FS> {
@@ -82,6 +462,8 @@ impl<FS: Filesystem> Session<FS> {
         options: &[MountOption],
     ) -> io::Result<Session<FS>> {
         let mountpoint = mountpoint.as_ref();
+        check_option_values(options)?;
+        join_namespaces(options)?;
         info!("Mounting {}", mountpoint.display());
         // If AutoUnmount is requested, but not AllowRoot or AllowOther we enforce the ACL
         // ourself and implicitly set AllowOther because fusermount needs allow_root or allow_other
@@ -93,10 +475,12 @@ impl<FS: Filesystem> Session<FS> {
             warn!("Given auto_unmount without allow_root or allow_other; adding allow_other, with userspace permission handling");
             let mut modified_options = options.to_vec();
             modified_options.push(MountOption::AllowOther);
-            Mount::new(mountpoint, &modified_options)?
+            mount_with_retry(mountpoint, &modified_options)?
         } else {
-            Mount::new(mountpoint, options)?
+            mount_with_retry(mountpoint, options)?
         };
+        // Dropping the mount on error unmounts it again
+        apply_post_mount_options(mountpoint, options)?;
 
         let ch = Channel::new(file);
         let allowed = if options.contains(&MountOption::AllowRoot) {
@@ -107,41 +491,519 @@ impl<FS: Filesystem> Session<FS> {
             SessionACL::Owner
         };
 
-        Ok(Session {
+        Ok(Session::with_channel(
             filesystem,
             ch,
-            mount: Arc::new(Mutex::new(Some((mountpoint.to_owned(), mount)))),
+            Some((mountpoint.to_owned(), mount)),
             allowed,
-            session_owner: geteuid().as_raw(),
-            proto_major: 0,
-            proto_minor: 0,
-            initialized: false,
-            destroyed: false,
-        })
+        ))
     }
 
     /// Wrap an existing /dev/fuse file descriptor. This doesn't mount the
     /// filesystem anywhere; that must be done separately.
     pub fn from_fd(filesystem: FS, fd: OwnedFd, acl: SessionACL) -> Self {
         let ch = Channel::new(Arc::new(fd.into()));
+        Session::with_channel(filesystem, ch, None, acl)
+    }
+
+    /// Create a session with default settings on an open channel
+    fn with_channel(
+        filesystem: FS,
+        ch: Channel,
+        mount: Option<(PathBuf, Mount)>,
+        allowed: SessionACL,
+    ) -> Self {
         Session {
             filesystem,
             ch,
-            mount: Arc::new(Mutex::new(None)),
-            allowed: acl,
+            mount: Arc::new(Mutex::new(mount)),
+            allowed,
             session_owner: geteuid().as_raw(),
             proto_major: 0,
             proto_minor: 0,
+            connection: ConnectionInfo::default(),
+            negotiated: Arc::new(Mutex::new(None)),
             initialized: false,
             destroyed: false,
+            thread_name: None,
+            cpu_affinity: None,
+            panic_policy: PanicPolicy::default(),
+            disabled_opcodes: vec![],
+            disabled_init_flags: 0,
+            authorizer: None,
+            memory_budget: None,
+            memory_budget_policy: MemoryBudgetPolicy::default(),
+            #[cfg(debug_assertions)]
+            warned_append: false,
+            control: false,
+            stats: Stats::new(SystemClock.now()),
+            clock: Arc::new(SystemClock),
+            #[cfg(feature = "abi-7-16")]
+            forget_batch: None,
+            locks: None,
+            destroy_timeout: None,
+            init_error: None,
+            unknown_opcode_policy: UnknownOpcodePolicy::default(),
+            unknown_opcodes: HashSet::new(),
+            rename_journal: None,
+            empty_io_policy: EmptyIoPolicy::default(),
+            noop_flush: false,
+            request_timeout: None,
+            metrics: None,
         }
     }
 
+    /// Present the inode numbers of the filesystem to the kernel remapped into a dense 32-bit
+    /// space. Must be called before running the session.
+    pub fn set_ino32_remap(&mut self, remap: Ino32Remap) {
+        self.ch.set_ino32_remap(remap);
+    }
+
+    /// Set the name of the thread that runs the session when it is [spawned](Session::spawn).
+    /// Defaults to `fuser-<mountpoint>-w0`, with the last component of the mountpoint.
+    pub fn set_thread_name(&mut self, name: &str) {
+        self.thread_name = Some(name.to_owned());
+    }
+
+    /// Restrict the thread that runs the session when it is [spawned](Session::spawn) to the
+    /// given CPUs. Only supported on Linux, ignored with a warning elsewhere.
+    pub fn set_cpu_affinity(&mut self, cpus: &[usize]) {
+        self.cpu_affinity = Some(cpus.to_vec());
+    }
+
+    /// Rewrite the error codes the filesystem replies with according to `policy`. Must be called
+    /// before running the session.
+    pub fn set_errno_policy(&mut self, policy: ErrnoPolicy) {
+        self.ch.set_errno_policy(policy);
+    }
+
+    /// Record every successful mutating operation (creating, removing, renaming and linking
+    /// entries, and changing contents, attributes or extended attributes) in the given log.
+    /// Must be called before running the session.
+    pub fn set_change_log<L: ChangeLog + 'static>(&mut self, log: L) {
+        self.ch.set_change_log(ChangeLogHandle(Arc::new(log)));
+    }
+
+    /// Record every `rename` and `exchange` in the given journal before the filesystem handles
+    /// it, and mark it done once it was answered. Renames that were interrupted by a crash are
+    /// left pending in the journal, to be resolved with [`RenameJournal::recover`] before the
+    /// next session starts. Requests fail with EIO if the journal can't be written. Must be
+    /// called before running the session.
+    pub fn set_rename_journal(&mut self, journal: RenameJournal) {
+        self.rename_journal = Some(Arc::new(Mutex::new(journal)));
+    }
+
+    /// Invalidate the kernel's cache of entries once the filesystem removed or renamed them
+    /// (with `unlink`, `rmdir` or `rename`), so that the next access looks them up again. Keeps
+    /// the entry caches coherent when the files are also changed through other clients of the
+    /// filesystem, without sending [`Notifier::inval_entry`](crate::Notifier::inval_entry)
+    /// from every such operation. The invalidations are sent by a thread of their own, since
+    /// the kernel locks the directory while invalidating an entry and would deadlock with a
+    /// lookup waiting for the session. Must be called before running the session.
+    #[cfg(feature = "abi-7-12")]
+    pub fn set_auto_inval_entries(&mut self, enabled: bool) {
+        self.ch.set_inval_entries(enabled);
+    }
+
+    /// Handle the `user.fuser.*` extended attributes of the mount root in the session, to
+    /// inspect and control it at runtime. Must be called before running the session.
+    ///
+    /// * `user.fuser.version` reads the version of fuser.
+    /// * `user.fuser.stats` reads statistics of the session, one `key: value` pair per line.
+    /// * Setting `user.fuser.command` runs the command given as the value: `log-level=<level>`
+    ///   (`off`, `error`, `warn`, `info`, `debug` or `trace`) or `flush-forgets`, which delivers
+    ///   batched forgets right away. Commands are only accepted from the user running the
+    ///   session and root.
+    ///
+    /// The log level is the maximum level of the [`log`] crate, which applies to the whole
+    /// process: changing it affects all sessions and everything else in the process that logs.
+    ///
+    /// The attributes aren't listed by `listxattr`. For example, `getfattr -n user.fuser.stats
+    /// <mountpoint>` prints the statistics.
+    pub fn enable_control(&mut self) {
+        self.control = true;
+    }
+
+    /// Translate user and group ids between the mount and the filesystem according to the
+    /// given map: the ids of requests and of `setattr` are mapped to the ids of the
+    /// filesystem, and the owners of replied attributes to the ids of the mount. Must be
+    /// called before running the session.
+    pub fn set_id_map(&mut self, map: IdMap) {
+        self.ch.set_id_map(map);
+    }
+
+    /// Compute a checksum of the data of every write request with the given algorithm, which
+    /// the `write` handler gets from [`Request::write_checksum`](crate::Request::write_checksum).
+    /// Must be called before running the session.
+    pub fn set_write_checksum<C: Checksum + 'static>(&mut self, checksum: C) {
+        self.ch.set_write_checksum(Arc::new(checksum));
+    }
+
+    /// Ask the given authorizer before `open` and every operation that changes the namespace
+    /// (see [`AccessKind`](crate::AccessKind)) reaches the filesystem, and fail them with its
+    /// error code if it denies them. Decisions are reused for `cache_ttl`, a zero TTL asks the
+    /// authorizer every time. Must be called before running the session.
+    pub fn set_authorizer<A: Authorizer + 'static>(&mut self, authorizer: A, cache_ttl: Duration) {
+        self.authorizer = Some(CachedAuthorizer::new(Box::new(authorizer), cache_ttl));
+    }
+
+    /// Whether files are opened with `FOPEN_NOFLUSH`, see [`Session::set_noop_flush`]
+    pub(crate) fn noflush(&self) -> bool {
+        self.noop_flush && self.locks.is_none()
+    }
+
+    /// Fails if the authorizer denies the given access.
+    pub(crate) fn authorize(&mut self, access: Access) -> Result<(), Errno> {
+        let now = self.clock.now();
+        match &mut self.authorizer {
+            Some(authorizer) => authorizer.check(access, now).map_err(Errno::from_i32),
+            None => Ok(()),
+        }
+    }
+
+    /// Call the given hook before every request is dispatched and once its reply was sent.
+    /// Hooks are called in the order they were added. Must be called before running the
+    /// session.
+    pub fn add_hook<H: RequestHook + 'static>(&mut self, hook: H) {
+        self.ch.add_hook(Arc::new(hook));
+    }
+
+    /// Count the requests and errors of each opcode and how long they took to answer, see
+    /// [`Session::metrics`]. Must be called before running the session.
+    pub fn enable_metrics(&mut self) {
+        if self.metrics.is_none() {
+            let collector = MetricsCollector::default();
+            self.add_hook(collector.clone());
+            self.metrics = Some(collector);
+        }
+    }
+
+    /// Returns a snapshot of the metrics of the requests so far, or `None` if metrics weren't
+    /// [enabled](Session::enable_metrics)
+    pub fn metrics(&self) -> Option<Metrics> {
+        self.metrics.as_ref().map(MetricsCollector::snapshot)
+    }
+
+    /// Keep POSIX locks in the given table instead of passing `getlk` and `setlk` to the
+    /// filesystem. The session answers them from the table, and drops the locks of a lock owner
+    /// when it flushes or releases a file, before the filesystem's `flush` and `release` are
+    /// called. Must be called before running the session.
+    pub fn set_lock_table(&mut self, table: LockTable) {
+        self.locks = Some(SessionLocks::new(table));
+    }
+
+    /// Declare that the filesystem's [`flush`](Filesystem::flush) does nothing, e.g. because
+    /// the filesystem is read-only or writes through. The session answers `flush` requests
+    /// itself, and opens and creates files with `FOPEN_NOFLUSH` so that kernels since Linux
+    /// 5.16 don't send them on every `close(2)` in the first place. Kernels with write-back
+    /// caching enabled send them anyway. With a [lock table](Session::set_lock_table), files
+    /// are opened without `FOPEN_NOFLUSH`, since the locks of a process are dropped on flush.
+    ///
+    /// Filesystems that implement [`setlk`](Filesystem::setlk) themselves mustn't declare
+    /// this: `flush` is where they learn that a lock owner closed the file and drop its locks,
+    /// so skipping it leaks them.
+    pub fn set_noop_flush(&mut self, noop: bool) {
+        self.noop_flush = noop;
+    }
+
+    /// Returns the lock table of the session, if it manages locks
+    pub fn lock_table(&self) -> Option<&LockTable> {
+        self.locks.as_ref().map(|locks| &locks.table)
+    }
+
+    /// Reply to all operations of the given families with ENOSYS, without calling the
+    /// filesystem. Must be called before running the session.
+    pub fn disable_operations(&mut self, families: &[OperationFamily]) {
+        for family in families {
+            self.disabled_opcodes
+                .extend(family.opcodes().into_iter().map(|op| op as u32));
+            self.disabled_init_flags |= family.init_flags();
+        }
+    }
+
+    /// Accumulate `forget` requests and deliver them to [`Filesystem::batch_forget`] together,
+    /// once `max_entries` were received or the oldest one is `max_delay` old. Helps filesystems
+    /// whose inode tables are expensive to lock, even on kernels that send forgets one by one.
+    #[cfg(feature = "abi-7-16")]
+    pub fn set_forget_batching(&mut self, max_entries: usize, max_delay: Duration) {
+        self.forget_batch = Some(ForgetBatch {
+            max_entries: max_entries.max(1),
+            max_delay,
+            pending: vec![],
+            since: None,
+        });
+    }
+
+    /// Queue a forget if batching is enabled. Returns false if it must be delivered right away.
+    #[cfg(feature = "abi-7-16")]
+    pub(crate) fn queue_forget(&mut self, nodeid: u64, nlookup: u64) -> bool {
+        let Some(batch) = &mut self.forget_batch else {
+            return false;
+        };
+        batch.push(nodeid, nlookup, self.clock.now());
+        if batch.is_full() {
+            self.flush_forgets();
+        }
+        true
+    }
+
+    /// Deliver all accumulated forgets to the filesystem
+    #[cfg(feature = "abi-7-16")]
+    pub(crate) fn flush_forgets(&mut self) {
+        let Some(batch) = &mut self.forget_batch else {
+            return;
+        };
+        if batch.pending.is_empty() {
+            return;
+        }
+        let data = batch.take_request();
+        let received = self.clock.now();
+        if let Some(req) =
+            Request::new(self.ch.sender(), data.as_bytes(), self.connection, received)
+        {
+            req.dispatch(self);
+        }
+    }
+
+    /// Warn once if the filesystem has to append writes to files opened with O_APPEND itself,
+    /// since a lot of filesystems write at the given offset instead, corrupting data when the
+    /// file is also written elsewhere.
+    #[cfg(debug_assertions)]
+    pub(crate) fn check_append(&mut self, ino: u64, offset: i64, flags: i32) {
+        #[cfg(feature = "abi-7-23")]
+        let writeback = self.connection.flags() & consts::FUSE_WRITEBACK_CACHE != 0;
+        #[cfg(not(feature = "abi-7-23"))]
+        let writeback = false;
+        if self.warned_append || writeback || !OpenFlags::from(flags).append() {
+            return;
+        }
+        self.warned_append = true;
+        warn!(
+            "Write to inode {} opened with O_APPEND at offset {}: without the writeback cache, \
+             this offset may be stale and the filesystem must append to its own end of file",
+            ino, offset
+        );
+    }
+
+    /// Number of forgets waiting to be delivered
+    #[cfg(feature = "abi-7-16")]
+    pub(crate) fn pending_forgets(&self) -> usize {
+        self.forget_batch.as_ref().map_or(0, |b| b.pending.len())
+    }
+
+    /// Wait until a request can be received, delivering accumulated forgets when they are due
+    #[cfg(feature = "abi-7-16")]
+    fn wait_for_request(&mut self) -> io::Result<()> {
+        loop {
+            let now = self.clock.now();
+            let Some(timeout) = self.forget_batch.as_ref().and_then(|b| b.remaining(now)) else {
+                return Ok(());
+            };
+            if timeout.is_zero() {
+                self.flush_forgets();
+                continue;
+            }
+            let mut pollfd = libc::pollfd {
+                fd: self.ch.as_fd().as_raw_fd(),
+                events: libc::POLLIN,
+                revents: 0,
+            };
+            let timeout_ms = timeout.as_millis().clamp(1, c_int::MAX as u128) as c_int;
+            match unsafe { libc::poll(&mut pollfd, 1, timeout_ms) } {
+                rc if rc < 0 => return Err(io::Error::last_os_error()),
+                0 => self.flush_forgets(),
+                _ => return Ok(()),
+            }
+        }
+    }
+
+    /// Read the time from the given clock instead of the system clock, e.g. a [`ManualClock`]
+    /// to test timing-sensitive behavior deterministically. Must be called before running the
+    /// session.
+    ///
+    /// [`ManualClock`]: crate::ManualClock
+    pub fn set_clock<C: Clock + 'static>(&mut self, clock: C) {
+        self.stats.started = clock.now();
+        self.clock = Arc::new(clock);
+        self.ch.set_clock(self.clock.clone());
+    }
+
+    /// Set what to do when a filesystem callback panics. Defaults to [`PanicPolicy::Continue`].
+    /// Has no effect if panics abort the process (`panic = "abort"`).
+    pub fn set_panic_policy(&mut self, policy: PanicPolicy) {
+        self.panic_policy = policy;
+    }
+
+    /// Set what to do with requests whose opcode this crate doesn't know. Defaults to
+    /// [`UnknownOpcodePolicy::Reject`].
+    pub fn set_unknown_opcode_policy(&mut self, policy: UnknownOpcodePolicy) {
+        self.unknown_opcode_policy = policy;
+    }
+
+    /// Set what to do with reads of zero bytes and writes without data. Defaults to
+    /// [`EmptyIoPolicy::Forward`].
+    pub fn set_empty_io_policy(&mut self, policy: EmptyIoPolicy) {
+        self.empty_io_policy = policy;
+    }
+
+    /// Fail requests the filesystem didn't answer within `timeout` with the error code `error`
+    /// (e.g. `ETIMEDOUT` or `EIO`), so that a stalled backend doesn't hang the processes
+    /// using the mount. A thread checks for such requests while the session runs, so they are
+    /// failed up to a quarter of the timeout late. The filesystem's own replies to them are
+    /// rejected afterwards. Must be called before running the session.
+    pub fn set_request_timeout(&mut self, timeout: Duration, error: c_int) {
+        self.request_timeout = Some((timeout, error));
+    }
+
+    /// Limit the memory held by requests the filesystem answers later, from another thread, to
+    /// `bytes`. Such a request holds its reply, plus what the filesystem reports with
+    /// [`Request::charge_memory`](crate::Request::charge_memory) (like a copy of the data of a
+    /// write). Once the budget is exceeded, the session stops reading new requests until enough
+    /// of them are answered, or aborts (see [`Session::set_memory_budget_policy`]). Protects
+    /// the filesystem from being flooded with more work than it can keep in memory. Must be
+    /// called before running the session.
+    ///
+    /// The filesystem must not wait for further requests before answering deferred ones, or
+    /// the session stalls once the budget is exceeded.
+    pub fn set_memory_budget(&mut self, bytes: usize) {
+        self.memory_budget = Some(bytes);
+    }
+
+    /// Set what to do when the memory budget is exceeded. Defaults to
+    /// [`MemoryBudgetPolicy::Wait`]. Must be called before running the session.
+    pub fn set_memory_budget_policy(&mut self, policy: MemoryBudgetPolicy) {
+        self.memory_budget_policy = policy;
+    }
+
+    /// Memory held by requests that weren't answered yet, see [`Session::set_memory_budget`]
+    pub fn memory_in_use(&self) -> usize {
+        self.ch.outstanding().bytes()
+    }
+
+    /// Set what to do when the filesystem drops a reply without answering the request.
+    /// Defaults to [`DroppedReply::Warn`]. Must be called before running the session.
+    pub fn set_dropped_reply(&mut self, policy: DroppedReply) {
+        self.ch.set_dropped_reply(policy);
+    }
+
+    /// Returns the connection settings negotiated with the kernel, like the init flags and the
+    /// maximum write size in effect, or `None` before init. [`Session::debug_dump`] includes
+    /// them too.
+    pub fn kernel_config(&self) -> Option<ConnectionInfo> {
+        self.initialized.then_some(self.connection)
+    }
+
+    /// Returns a snapshot of the state of the session for debugging, like the requests the
+    /// filesystem hasn't answered yet
+    pub fn debug_dump(&self) -> DebugDump {
+        #[cfg(feature = "abi-7-16")]
+        let pending_forgets = self.pending_forgets();
+        #[cfg(not(feature = "abi-7-16"))]
+        let pending_forgets = 0;
+        DebugDump {
+            connection: self.connection,
+            uptime: self
+                .clock
+                .now()
+                .saturating_duration_since(self.stats.started),
+            requests: self.stats.requests,
+            requests_by_opcode: self.stats.requests_by_opcode.clone(),
+            pending: self.ch.outstanding().snapshot(),
+            memory_in_use: self.memory_in_use(),
+            dropped_replies: self.ch.dropped_replies(),
+            pending_forgets,
+        }
+    }
+
+    /// Set what to do with replies to interrupted requests. Defaults to
+    /// [`InterruptedReply::Send`]. Must be called before running the session.
+    pub fn set_interrupted_reply(&mut self, policy: InterruptedReply) {
+        self.ch.set_interrupted_reply(policy);
+    }
+
+    /// Call [`Filesystem::destroy`] as soon as the session loop ends, however it ends, if the
+    /// kernel didn't send `destroy` itself. The kernel only does so for some unmounts (e.g.
+    /// not when the connection is aborted), so without this option `destroy` is otherwise only
+    /// called when the session is dropped. A `destroy` that takes longer than `timeout` is
+    /// reported, and [`BackgroundSession::join`] gives up waiting for it with a
+    /// [`TimedOut`](io::ErrorKind::TimedOut) error. `destroy` itself can't be interrupted, so
+    /// [`Session::run`] still returns only once it did.
+    ///
+    /// Nothing is called if the process ends without unwinding: a process killed by a signal
+    /// it doesn't handle, or ending while the session still runs in the background, skips
+    /// `destroy`. Processes that want it on `SIGINT` or `SIGTERM` have to handle the signal by
+    /// unmounting (e.g. with [`SessionUnmounter`]) and joining the session. Must be called
+    /// before running the session.
+    pub fn set_destroy_timeout(&mut self, timeout: Duration) {
+        self.destroy_timeout = Some(timeout);
+    }
+
+    /// Call `destroy` unless it was called already, reporting it if it takes too long
+    fn destroy(&mut self) {
+        if self.destroyed {
+            return;
+        }
+        #[cfg(feature = "abi-7-16")]
+        self.flush_forgets();
+        let (done, watchdog) = mpsc::channel::<()>();
+        if let Some(timeout) = self.destroy_timeout {
+            thread::spawn(move || {
+                if watchdog.recv_timeout(timeout) == Err(RecvTimeoutError::Timeout) {
+                    warn!("Filesystem destroy didn't return within {:?}", timeout);
+                }
+            });
+        }
+        self.filesystem.destroy();
+        self.destroyed = true;
+        drop(done);
+    }
+
     /// Run the session loop that receives kernel requests and dispatches them to method
     /// calls into the filesystem. This read-dispatch-loop is non-concurrent to prevent
     /// having multiple buffers (which take up much memory), but the filesystem methods
-    /// may run concurrent by spawning threads.
-    pub fn run(&mut self) -> io::Result<()> {
+    /// may run concurrent by spawning threads. Returns why the loop ended.
+    pub fn run(&mut self) -> SessionExit {
+        let exit = self.receive_loop();
+        if self.destroy_timeout.is_some() {
+            self.destroy();
+        }
+        exit
+    }
+
+    /// Wait until outstanding requests hold at most `budget` bytes, or abort if configured to.
+    /// Stops waiting once the connection ended, so that the session notices the unmount.
+    fn enforce_memory_budget(&mut self, budget: usize) -> io::Result<()> {
+        let outstanding = self.ch.outstanding();
+        if outstanding.bytes() <= budget {
+            return Ok(());
+        }
+        if self.memory_budget_policy == MemoryBudgetPolicy::Abort {
+            let message = format!(
+                "Outstanding requests hold {} bytes, more than the budget of {}",
+                outstanding.bytes(),
+                budget
+            );
+            error!("{}, unmounting", message);
+            self.unmount();
+            return Err(io::Error::new(io::ErrorKind::OutOfMemory, message));
+        }
+        debug!(
+            "Outstanding requests hold {} bytes, waiting for replies before reading more",
+            outstanding.bytes()
+        );
+        while !outstanding.wait_for_budget(budget, BUDGET_POLL_INTERVAL) {
+            if self.ch.is_disconnected() {
+                break;
+            }
+        }
+        Ok(())
+    }
+
+    fn receive_loop(&mut self) -> SessionExit {
+        let _watchdog = self.request_timeout.map(|(timeout, error)| {
+            self.ch.outstanding().enable_timeouts();
+            RequestWatchdog::spawn(self.ch.sender(), timeout, error)
+        });
         // Buffer for receiving requests from the kernel. Only one is allocated and
         // it is reused immediately after dispatching to conserve memory and allocations.
         let mut buffer = vec![0; BUFFER_SIZE];
@@ -150,14 +1012,58 @@ impl<FS: Filesystem> Session<FS> {
             std::mem::align_of::<abi::fuse_in_header>(),
         );
         loop {
+            if let Some(budget) = self.memory_budget {
+                if let Err(err) = self.enforce_memory_budget(budget) {
+                    return SessionExit::Error(err);
+                }
+            }
+            #[cfg(feature = "abi-7-16")]
+            if let Err(err) = self.wait_for_request() {
+                if err.raw_os_error() != Some(EINTR) {
+                    return SessionExit::Error(err);
+                }
+            }
             // Read the next request from the given channel to kernel driver
             // The kernel driver makes sure that we get exactly one request per read
             match self.ch.receive(buf) {
-                Ok(size) => match Request::new(self.ch.sender(), &buf[..size]) {
-                    // Dispatch request
-                    Some(req) => req.dispatch(self),
+                Ok(size) => match Request::new(
+                    self.ch.sender(),
+                    &buf[..size],
+                    self.connection,
+                    self.clock.now(),
+                ) {
+                    // Dispatch request. If the filesystem panics, the reply is dropped while
+                    // unwinding, which replies with EIO.
+                    Some(req) => {
+                        let res = panic::catch_unwind(AssertUnwindSafe(|| req.dispatch(self)));
+                        if let Err(payload) = res {
+                            let message = panic_message(&*payload);
+                            error!(
+                                "Filesystem panicked while handling request {}: {}",
+                                req.unique(),
+                                message
+                            );
+                            self.filesystem.on_panic(&req, message);
+                            if self.panic_policy == PanicPolicy::Unmount {
+                                self.unmount();
+                                return SessionExit::Error(io::Error::new(
+                                    io::ErrorKind::Other,
+                                    format!("Filesystem panicked: {}", message),
+                                ));
+                            }
+                        }
+                        if let Some(err) = self.init_error.take() {
+                            self.unmount();
+                            return SessionExit::Error(err);
+                        }
+                    }
                     // Quit loop on illegal request
-                    None => break,
+                    None => {
+                        return SessionExit::Error(io::Error::new(
+                            io::ErrorKind::InvalidData,
+                            "Received illegal request from the kernel",
+                        ))
+                    }
                 },
                 Err(err) => match err.raw_os_error() {
                     // Operation interrupted. Accordingly to FUSE, this is safe to retry
@@ -167,13 +1073,25 @@ impl<FS: Filesystem> Session<FS> {
                     // Explicitly try again
                     Some(EAGAIN) => continue,
                     // Filesystem was unmounted, quit the loop
-                    Some(ENODEV) => break,
+                    Some(ENODEV) => {
+                        #[cfg(feature = "abi-7-16")]
+                        self.flush_forgets();
+                        if self.destroyed {
+                            return SessionExit::Destroyed;
+                        }
+                        return SessionExit::Unmounted;
+                    }
+                    // The connection was aborted, with FUSE_ABORT_ERROR
+                    Some(ECONNABORTED) => {
+                        warn!("Connection aborted by the kernel");
+                        self.filesystem.aborted();
+                        return SessionExit::Aborted;
+                    }
                     // Unhandled error
-                    _ => return Err(err),
+                    _ => return SessionExit::Error(err),
                 },
             }
         }
-        Ok(())
     }
 
     /// Unmount the filesystem
@@ -193,6 +1111,47 @@ impl<FS: Filesystem> Session<FS> {
     pub fn notifier(&self) -> Notifier {
         Notifier::new(self.ch.sender())
     }
+
+    /// Returns a handle to the kernel state of the connection in the fusectl filesystem
+    /// (`/sys/fs/fuse/connections`), which can also be used while the session is running.
+    /// Only available on Linux, while mounted.
+    pub fn kernel_connection(&self) -> io::Result<KernelConnection> {
+        let mount = self.mount.lock().unwrap();
+        let Some((mountpoint, _)) = mount.as_ref() else {
+            return Err(io::Error::new(
+                io::ErrorKind::NotConnected,
+                "Session is not mounted",
+            ));
+        };
+        KernelConnection::for_mountpoint(mountpoint)
+    }
+
+    /// Abort the connection in the kernel, failing all outstanding requests
+    pub fn abort(&self) -> io::Result<()> {
+        self.kernel_connection()?.abort()
+    }
+
+    /// Number of requests waiting to be read or answered, as seen by the kernel
+    pub fn waiting_requests(&self) -> io::Result<u32> {
+        self.kernel_connection()?.waiting_requests()
+    }
+
+    /// Maximum number of outstanding background requests, as configured in the kernel
+    pub fn max_background(&self) -> io::Result<u32> {
+        self.kernel_connection()?.max_background()
+    }
+
+    /// How far the kernel currently reads ahead of sequential reads, in bytes
+    pub fn max_readahead(&self) -> io::Result<u32> {
+        self.kernel_connection()?.max_readahead()
+    }
+
+    /// Change how far the kernel reads ahead of sequential reads after init, see
+    /// [`KernelConnection::set_max_readahead`]. To adjust it while the session is running,
+    /// keep the [`KernelConnection`] of the session.
+    pub fn set_max_readahead(&self, bytes: u32) -> io::Result<()> {
+        self.kernel_connection()?.set_max_readahead(bytes)
+    }
 }
 
 #[derive(Debug)]
@@ -209,6 +1168,54 @@ impl SessionUnmounter {
     }
 }
 
+/// Returns the message of a caught panic
+fn panic_message(payload: &(dyn std::any::Any + Send)) -> &str {
+    if let Some(message) = payload.downcast_ref::<&str>() {
+        message
+    } else if let Some(message) = payload.downcast_ref::<String>() {
+        message
+    } else {
+        "Box<dyn Any>"
+    }
+}
+
+/// Default name of the background thread of a session mounted at `mountpoint`
+fn default_thread_name(mountpoint: Option<&Path>) -> String {
+    match mountpoint.and_then(|mp| mp.file_name()) {
+        Some(name) => format!("fuser-{}-w0", name.to_string_lossy()),
+        None => "fuser-w0".to_owned(),
+    }
+}
+
+/// Restrict the current thread to the given CPUs
+#[cfg(target_os = "linux")]
+fn set_cpu_affinity(cpus: &[usize]) -> io::Result<()> {
+    let mut set: libc::cpu_set_t = unsafe { std::mem::zeroed() };
+    for &cpu in cpus {
+        if cpu >= libc::CPU_SETSIZE as usize {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("CPU {} out of range", cpu),
+            ));
+        }
+        unsafe { libc::CPU_SET(cpu, &mut set) };
+    }
+    let rc = unsafe { libc::sched_setaffinity(0, std::mem::size_of_val(&set), &set) };
+    if rc < 0 {
+        Err(io::Error::last_os_error())
+    } else {
+        Ok(())
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+fn set_cpu_affinity(_cpus: &[usize]) -> io::Result<()> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "CPU affinity is not supported on this platform",
+    ))
+}
+
 fn aligned_sub_buf(buf: &mut [u8], alignment: usize) -> &mut [u8] {
     let off = alignment - (buf.as_ptr() as usize) % alignment;
     if off == alignment {
@@ -227,10 +1234,7 @@ impl<FS: 'static + Filesystem + Send> Session<FS> {
 
 impl<FS: Filesystem> Drop for Session<FS> {
     fn drop(&mut self) {
-        if !self.destroyed {
-            self.filesystem.destroy();
-            self.destroyed = true;
-        }
+        self.destroy();
 
         if let Some((mountpoint, _mount)) = std::mem::take(&mut *self.mount.lock().unwrap()) {
             info!("unmounting session at {}", mountpoint.display());
@@ -241,12 +1245,27 @@ impl<FS: Filesystem> Drop for Session<FS> {
 /// The background session data structure
 pub struct BackgroundSession {
     /// Thread guard of the background session
-    pub guard: JoinHandle<io::Result<()>>,
+    pub guard: JoinHandle<SessionExit>,
     /// Object for creating Notifiers for client use
     #[cfg(feature = "abi-7-11")]
     sender: ChannelSender,
     /// Ensures the filesystem is unmounted when the session ends
-    _mount: Option<Mount>,
+    _mount: MountGuard,
+    /// How long `join` waits for the session to end after unmounting
+    destroy_timeout: Option<Duration>,
+    /// Connection settings negotiated with the kernel, once init is done
+    negotiated: Arc<Mutex<Option<ConnectionInfo>>>,
+}
+
+/// Unmounts the filesystem of a background session when dropped. The mount is shared with the
+/// session, so that it can unmount itself as well.
+#[derive(Debug)]
+struct MountGuard(Arc<Mutex<Option<(PathBuf, Mount)>>>);
+
+impl Drop for MountGuard {
+    fn drop(&mut self) {
+        drop(std::mem::take(&mut *self.0.lock().unwrap()));
+    }
 }
 
 impl BackgroundSession {
@@ -256,29 +1275,59 @@ impl BackgroundSession {
     pub fn new<FS: Filesystem + Send + 'static>(se: Session<FS>) -> io::Result<BackgroundSession> {
         #[cfg(feature = "abi-7-11")]
         let sender = se.ch.sender();
-        // Take the fuse_session, so that we can unmount it
-        let mount = std::mem::take(&mut *se.mount.lock().unwrap()).map(|(_, mount)| mount);
-        let guard = thread::spawn(move || {
+        let name = se.thread_name.clone().unwrap_or_else(|| {
+            let mount = se.mount.lock().unwrap();
+            default_thread_name(mount.as_ref().map(|(mountpoint, _)| mountpoint.as_path()))
+        });
+        let mount = MountGuard(se.mount.clone());
+        let destroy_timeout = se.destroy_timeout;
+        let negotiated = se.negotiated.clone();
+        let guard = thread::Builder::new().name(name).spawn(move || {
             let mut se = se;
+            if let Some(cpus) = se.cpu_affinity.take() {
+                if let Err(err) = set_cpu_affinity(&cpus) {
+                    warn!("Failed to set CPU affinity of session thread: {}", err);
+                }
+            }
             se.run()
-        });
+        })?;
         Ok(BackgroundSession {
             guard,
             #[cfg(feature = "abi-7-11")]
             sender,
             _mount: mount,
+            destroy_timeout,
+            negotiated,
         })
     }
-    /// Unmount the filesystem and join the background thread.
-    pub fn join(self) {
+    /// Unmount the filesystem and join the background thread, returning why the session ended
+    /// as an error (see [`SessionExit::into_result`]). With a destroy timeout (see
+    /// [`Session::set_destroy_timeout`]), the thread is left behind and a
+    /// [`TimedOut`](io::ErrorKind::TimedOut) error returned if the session didn't end within
+    /// the timeout.
+    pub fn join(self) -> io::Result<()> {
         let Self {
             guard,
             #[cfg(feature = "abi-7-11")]
                 sender: _,
             _mount,
+            destroy_timeout,
+            negotiated: _,
         } = self;
         drop(_mount);
-        guard.join().unwrap().unwrap();
+        if let Some(timeout) = destroy_timeout {
+            let deadline = Instant::now() + timeout;
+            while !guard.is_finished() {
+                if Instant::now() >= deadline {
+                    return Err(io::Error::new(
+                        io::ErrorKind::TimedOut,
+                        format!("Session didn't end within {:?} after unmounting", timeout),
+                    ));
+                }
+                thread::sleep(Duration::from_millis(10));
+            }
+        }
+        guard.join().unwrap().into_result()
     }
 
     /// Returns an object that can be used to send notifications to the kernel
@@ -286,6 +1335,12 @@ impl BackgroundSession {
     pub fn notifier(&self) -> Notifier {
         Notifier::new(self.sender.clone())
     }
+
+    /// Returns the connection settings negotiated with the kernel, or `None` while the session
+    /// hasn't been initialized yet (see [`Session::kernel_config`])
+    pub fn kernel_config(&self) -> Option<ConnectionInfo> {
+        *self.negotiated.lock().unwrap()
+    }
 }
 
 // replace with #[derive(Debug)] if Debug ever gets implemented for
@@ -295,3 +1350,519 @@ impl fmt::Debug for BackgroundSession {
         write!(f, "BackgroundSession {{ guard: JoinGuard<()> }}",)
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::{default_thread_name, panic_message, OperationFamily, Session};
+    use crate::ll::fuse_abi::{consts, fuse_opcode};
+    use crate::request::Request;
+    use crate::Filesystem;
+    use std::path::Path;
+    use std::time::Instant;
+
+    /// Dispatch a request as if the session read it from the kernel
+    fn dispatch_raw<FS: Filesystem>(
+        session: &mut Session<FS>,
+        opcode: u32,
+        unique: u64,
+        nodeid: u64,
+        body: &[u8],
+    ) {
+        // fuse_in_header: len, opcode, unique, nodeid, uid, gid, pid, padding
+        let mut data = vec![];
+        data.extend_from_slice(&(40 + body.len() as u32).to_ne_bytes());
+        data.extend_from_slice(&opcode.to_ne_bytes());
+        data.extend_from_slice(&unique.to_ne_bytes());
+        data.extend_from_slice(&nodeid.to_ne_bytes());
+        data.extend_from_slice(&[0; 16]);
+        data.extend_from_slice(body);
+        Request::new(
+            session.ch.sender(),
+            &data,
+            session.connection,
+            Instant::now(),
+        )
+        .unwrap()
+        .dispatch(session);
+    }
+
+    #[test]
+    fn thread_names() {
+        assert_eq!(
+            default_thread_name(Some(Path::new("/mnt/data"))),
+            "fuser-data-w0"
+        );
+        assert_eq!(default_thread_name(Some(Path::new("/"))), "fuser-w0");
+        assert_eq!(default_thread_name(None), "fuser-w0");
+    }
+
+    #[test]
+    fn operation_families() {
+        assert_eq!(
+            OperationFamily::Mknod.opcodes(),
+            vec![fuse_opcode::FUSE_MKNOD]
+        );
+        assert_eq!(OperationFamily::Xattr.opcodes().len(), 4);
+        assert_ne!(
+            OperationFamily::Locks.init_flags() & consts::FUSE_POSIX_LOCKS,
+            0
+        );
+        assert_eq!(OperationFamily::Xattr.init_flags(), 0);
+    }
+
+    #[cfg(feature = "abi-7-16")]
+    #[test]
+    fn forget_batch_request() {
+        use crate::ll::{self, Request as _};
+        use crate::{Clock, ManualClock};
+        use std::time::Duration;
+        use zerocopy::IntoBytes;
+
+        let clock = ManualClock::new();
+        let mut batch = super::ForgetBatch {
+            max_entries: 2,
+            max_delay: Duration::from_secs(60),
+            pending: vec![],
+            since: None,
+        };
+        assert_eq!(batch.remaining(clock.now()), None);
+        batch.push(2, 1, clock.now());
+        assert!(!batch.is_full());
+        assert_eq!(batch.remaining(clock.now()), Some(Duration::from_secs(60)));
+        clock.advance(Duration::from_secs(45));
+        batch.push(3, 4, clock.now());
+        assert!(batch.is_full());
+        assert_eq!(batch.remaining(clock.now()), Some(Duration::from_secs(15)));
+
+        let data = batch.take_request();
+        assert_eq!(batch.remaining(clock.now()), None);
+        let req = ll::AnyRequest::try_from(data.as_bytes()).unwrap();
+        assert_eq!(req.unique(), ll::RequestId(0));
+        match req.operation().unwrap() {
+            ll::Operation::BatchForget(x) => {
+                let nodes: Vec<_> = x.nodes().iter().map(|n| (n.nodeid, n.nlookup)).collect();
+                assert_eq!(nodes, vec![(2, 1), (3, 4)]);
+            }
+            _ => panic!("Unexpected request"),
+        }
+    }
+
+    #[test]
+    fn panic_messages() {
+        let payload = std::panic::catch_unwind(|| panic!("static")).unwrap_err();
+        assert_eq!(panic_message(&*payload), "static");
+        let payload = std::panic::catch_unwind(|| panic!("formatted {}", 1)).unwrap_err();
+        assert_eq!(panic_message(&*payload), "formatted 1");
+        let payload = std::panic::catch_unwind(|| std::panic::panic_any(1)).unwrap_err();
+        assert_eq!(panic_message(&*payload), "Box<dyn Any>");
+    }
+
+    #[test]
+    fn destroy_on_exit() {
+        use super::SessionACL;
+        use std::sync::atomic::{AtomicUsize, Ordering};
+        use std::sync::Arc;
+        use std::time::Duration;
+
+        struct Counted(Arc<AtomicUsize>);
+        impl Filesystem for Counted {
+            fn destroy(&mut self) {
+                self.0.fetch_add(1, Ordering::SeqCst);
+            }
+        }
+
+        // An empty device ends the session loop right away
+        let destroyed = Arc::new(AtomicUsize::new(0));
+        let device = tempfile::tempfile().unwrap();
+        let mut session =
+            Session::from_fd(Counted(destroyed.clone()), device.into(), SessionACL::All);
+        session.set_destroy_timeout(Duration::from_secs(1));
+        assert!(!session.run().is_unmounted());
+        assert_eq!(destroyed.load(Ordering::SeqCst), 1);
+        drop(session);
+        assert_eq!(destroyed.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn join_times_out() {
+        use super::{BackgroundSession, SessionACL};
+        use std::time::Duration;
+
+        struct Slow;
+        impl Filesystem for Slow {
+            fn destroy(&mut self) {
+                std::thread::sleep(Duration::from_millis(500));
+            }
+        }
+
+        let device = tempfile::tempfile().unwrap();
+        let mut session = Session::from_fd(Slow, device.into(), SessionACL::All);
+        session.set_destroy_timeout(Duration::from_millis(50));
+        let background = BackgroundSession::new(session).unwrap();
+        let err = background.join().unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::TimedOut);
+    }
+
+    #[test]
+    fn unknown_and_raw_opcodes() {
+        use super::{SessionACL, UnknownOpcodePolicy};
+        use crate::ReplyData;
+        use std::io::{Read, Seek, SeekFrom};
+
+        struct Echo;
+        impl Filesystem for Echo {
+            fn unknown(
+                &mut self,
+                _req: &crate::Request<'_>,
+                _opcode: u32,
+                payload: &[u8],
+                reply: ReplyData,
+            ) {
+                reply.data(payload);
+            }
+
+            fn raw_intercept(&self, opcode: u32) -> bool {
+                opcode == fuse_opcode::FUSE_STATFS as u32
+            }
+
+            fn handle_raw(
+                &mut self,
+                _req: &crate::Request<'_>,
+                _opcode: u32,
+                _payload: &[u8],
+                reply: ReplyData,
+            ) {
+                reply.data(b"raw");
+            }
+        }
+
+        let mut device = tempfile::tempfile().unwrap();
+        let mut session =
+            Session::from_fd(Echo, device.try_clone().unwrap().into(), SessionACL::All);
+        session.initialized = true;
+        let dispatch = |session: &mut Session<Echo>, opcode: u32, unique: u64| {
+            dispatch_raw(session, opcode, unique, 1, b"ping");
+        };
+        dispatch(&mut session, 9999, 1);
+        dispatch(&mut session, 9999, 2);
+        session.set_unknown_opcode_policy(UnknownOpcodePolicy::Forward);
+        dispatch(&mut session, 9999, 3);
+        assert_eq!(session.unknown_opcodes.len(), 1);
+        dispatch(&mut session, fuse_opcode::FUSE_STATFS as u32, 4);
+
+        let mut sent = vec![];
+        device.seek(SeekFrom::Start(0)).unwrap();
+        device.read_to_end(&mut sent).unwrap();
+        // Two errors, then the replies of the filesystem
+        assert_eq!(sent.len(), 4 * 16 + 4 + 3);
+        assert_eq!(sent[4..8], (-libc::ENOSYS).to_ne_bytes());
+        assert_eq!(sent[36..40], 0i32.to_ne_bytes());
+        assert_eq!(sent[48..52], *b"ping");
+        assert_eq!(sent[68..], *b"raw");
+    }
+
+    #[test]
+    fn authorizer_covers_namespace_ops() {
+        use super::SessionACL;
+        use crate::ll::fuse_abi::{fuse_link_in, fuse_mkdir_in, fuse_mknod_in};
+        use crate::{Access, AccessKind};
+        use std::io::{Read, Seek, SeekFrom};
+        use std::mem::size_of;
+        use std::path::PathBuf;
+        use std::sync::{Arc, Mutex};
+        use std::time::Duration;
+
+        struct Empty;
+        impl Filesystem for Empty {}
+
+        let mut device = tempfile::tempfile().unwrap();
+        let mut session =
+            Session::from_fd(Empty, device.try_clone().unwrap().into(), SessionACL::All);
+        session.initialized = true;
+        let asked = Arc::new(Mutex::new(vec![]));
+        let log = asked.clone();
+        session.set_authorizer(
+            move |access: &Access| {
+                log.lock().unwrap().push(access.kind.clone());
+                Err(libc::EACCES)
+            },
+            Duration::ZERO,
+        );
+        let ops: [(fuse_opcode, Vec<u8>); 5] = [
+            (
+                fuse_opcode::FUSE_MKNOD,
+                [&[0; size_of::<fuse_mknod_in>()][..], b"node\0"].concat(),
+            ),
+            (
+                fuse_opcode::FUSE_MKDIR,
+                [&[0; size_of::<fuse_mkdir_in>()][..], b"dir\0"].concat(),
+            ),
+            (fuse_opcode::FUSE_SYMLINK, b"link\0target\0".to_vec()),
+            (
+                fuse_opcode::FUSE_LINK,
+                [&[0; size_of::<fuse_link_in>()][..], b"hard\0"].concat(),
+            ),
+            (fuse_opcode::FUSE_RMDIR, b"dir\0".to_vec()),
+        ];
+        for (unique, (opcode, arg)) in ops.iter().enumerate() {
+            dispatch_raw(&mut session, *opcode as u32, unique as u64 + 1, 1, arg);
+        }
+
+        assert_eq!(
+            *asked.lock().unwrap(),
+            [
+                AccessKind::MkNod { mode: 0, rdev: 0 },
+                AccessKind::MkDir { mode: 0 },
+                AccessKind::SymLink {
+                    target: PathBuf::from("target")
+                },
+                AccessKind::Link,
+                AccessKind::RmDir,
+            ]
+        );
+        let mut sent = vec![];
+        device.seek(SeekFrom::Start(0)).unwrap();
+        device.read_to_end(&mut sent).unwrap();
+        assert_eq!(sent.len(), ops.len() * 16);
+        for reply in sent.chunks(16) {
+            assert_eq!(reply[4..8], (-libc::EACCES).to_ne_bytes());
+        }
+    }
+
+    #[test]
+    fn exit_reasons() {
+        use super::SessionExit;
+
+        assert!(SessionExit::Destroyed.is_unmounted());
+        assert!(SessionExit::Unmounted.into_result().is_ok());
+        assert!(!SessionExit::Aborted.is_unmounted());
+        assert_eq!(
+            SessionExit::Aborted.into_result().unwrap_err().kind(),
+            std::io::ErrorKind::ConnectionAborted
+        );
+    }
+
+    #[test]
+    fn empty_io() {
+        use super::{EmptyIoPolicy, SessionACL};
+        use crate::ll::fuse_abi::{fuse_read_in, fuse_write_in};
+        use crate::{ReplyData, ReplyWrite};
+        use std::io::{Read, Seek, SeekFrom};
+        use std::mem::size_of;
+
+        struct Counting(usize);
+        impl Filesystem for Counting {
+            fn read(
+                &mut self,
+                _req: &crate::Request<'_>,
+                _ino: u64,
+                _fh: u64,
+                _offset: i64,
+                _size: u32,
+                _flags: i32,
+                _lock_owner: Option<u64>,
+                reply: ReplyData,
+            ) {
+                self.0 += 1;
+                reply.error(libc::EBADF);
+            }
+
+            fn write(
+                &mut self,
+                _req: &crate::Request<'_>,
+                _ino: u64,
+                _fh: u64,
+                _offset: i64,
+                _data: &[u8],
+                _write_flags: u32,
+                _flags: i32,
+                _lock_owner: Option<u64>,
+                reply: ReplyWrite,
+            ) {
+                self.0 += 1;
+                reply.error(libc::EBADF);
+            }
+        }
+
+        let mut device = tempfile::tempfile().unwrap();
+        let mut session = Session::from_fd(
+            Counting(0),
+            device.try_clone().unwrap().into(),
+            SessionACL::All,
+        );
+        session.initialized = true;
+        let dispatch = |session: &mut Session<Counting>, opcode: fuse_opcode, unique: u64| {
+            // An argument of zeros reads or writes zero bytes
+            let arg = match opcode {
+                fuse_opcode::FUSE_READ => size_of::<fuse_read_in>(),
+                _ => size_of::<fuse_write_in>(),
+            };
+            dispatch_raw(session, opcode as u32, unique, 2, &vec![0; arg]);
+        };
+        dispatch(&mut session, fuse_opcode::FUSE_READ, 1);
+        dispatch(&mut session, fuse_opcode::FUSE_WRITE, 2);
+        assert_eq!(session.filesystem.0, 2);
+        session.set_empty_io_policy(EmptyIoPolicy::Answer);
+        dispatch(&mut session, fuse_opcode::FUSE_READ, 3);
+        dispatch(&mut session, fuse_opcode::FUSE_WRITE, 4);
+        assert_eq!(session.filesystem.0, 2);
+
+        let mut sent = vec![];
+        device.seek(SeekFrom::Start(0)).unwrap();
+        device.read_to_end(&mut sent).unwrap();
+        // Two errors from the filesystem, then an empty read and a write of zero bytes
+        assert_eq!(sent.len(), 4 * 16 + 8);
+        assert_eq!(sent[4..8], (-libc::EBADF).to_ne_bytes());
+        assert_eq!(sent[20..24], (-libc::EBADF).to_ne_bytes());
+        assert_eq!(sent[32..36], 16u32.to_ne_bytes());
+        assert_eq!(sent[52..56], 0i32.to_ne_bytes());
+        assert_eq!(sent[64..68], 0u32.to_ne_bytes());
+    }
+
+    #[test]
+    fn memory_budget() {
+        use super::{MemoryBudgetPolicy, SessionACL};
+        use crate::ll::fuse_abi::fuse_read_in;
+        use crate::reply::ReplyRaw;
+        use crate::ReplyData;
+        use std::mem::size_of;
+
+        /// Answers reads of inode 2 right away, and keeps the others to answer later
+        #[derive(Default)]
+        struct Deferring(Vec<ReplyData>);
+        impl Filesystem for Deferring {
+            fn read(
+                &mut self,
+                req: &crate::Request<'_>,
+                ino: u64,
+                _fh: u64,
+                _offset: i64,
+                _size: u32,
+                _flags: i32,
+                _lock_owner: Option<u64>,
+                reply: ReplyData,
+            ) {
+                req.charge_memory(1000);
+                match ino {
+                    2 => reply.data(b"data"),
+                    _ => self.0.push(reply),
+                }
+            }
+        }
+
+        let device = tempfile::tempfile().unwrap();
+        let mut session = Session::from_fd(Deferring::default(), device.into(), SessionACL::All);
+        session.initialized = true;
+        session.set_memory_budget(100);
+        session.set_memory_budget_policy(MemoryBudgetPolicy::Abort);
+        for (unique, ino) in [(1u64, 2u64), (2, 3)] {
+            let arg = [0; size_of::<fuse_read_in>()];
+            dispatch_raw(
+                &mut session,
+                fuse_opcode::FUSE_READ as u32,
+                unique,
+                ino,
+                &arg,
+            );
+        }
+        // Only the deferred request holds memory
+        assert_eq!(session.memory_in_use(), 1000 + size_of::<ReplyRaw>());
+        let err = session.enforce_memory_budget(100).unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::OutOfMemory);
+
+        session.filesystem.0.pop().unwrap().data(b"late");
+        assert_eq!(session.memory_in_use(), 0);
+        session.enforce_memory_budget(100).unwrap();
+    }
+
+    #[test]
+    fn hooks() {
+        use super::SessionACL;
+        use crate::ll::fuse_abi::fuse_read_in;
+        use crate::{ReplyData, RequestHook, RequestInfo};
+        use std::mem::size_of;
+        use std::sync::{Arc, Mutex};
+        use std::time::Duration;
+
+        struct Counting(usize);
+        impl Filesystem for Counting {
+            fn read(
+                &mut self,
+                _req: &crate::Request<'_>,
+                _ino: u64,
+                _fh: u64,
+                _offset: i64,
+                _size: u32,
+                _flags: i32,
+                _lock_owner: Option<u64>,
+                reply: ReplyData,
+            ) {
+                self.0 += 1;
+                reply.data(b"data");
+            }
+        }
+
+        /// Fails the requests on inode 3 and records the replies
+        #[derive(Default)]
+        struct Faults(Mutex<Vec<(u64, i32)>>);
+        impl RequestHook for Arc<Faults> {
+            fn before_dispatch(&self, request: &RequestInfo) -> Result<(), i32> {
+                match request.ino {
+                    3 => Err(libc::EIO),
+                    _ => Ok(()),
+                }
+            }
+
+            fn after_reply(&self, request: &RequestInfo, _latency: Duration, errno: i32) {
+                self.0.lock().unwrap().push((request.unique, errno));
+            }
+        }
+
+        let device = tempfile::tempfile().unwrap();
+        let mut session = Session::from_fd(Counting(0), device.into(), SessionACL::All);
+        session.initialized = true;
+        let faults = Arc::new(Faults::default());
+        session.add_hook(faults.clone());
+        for (unique, ino) in [(1u64, 2u64), (2, 3)] {
+            let arg = [0; size_of::<fuse_read_in>()];
+            dispatch_raw(
+                &mut session,
+                fuse_opcode::FUSE_READ as u32,
+                unique,
+                ino,
+                &arg,
+            );
+        }
+        assert_eq!(session.filesystem.0, 1);
+        assert_eq!(*faults.0.lock().unwrap(), [(1, 0), (2, libc::EIO)]);
+    }
+
+    #[test]
+    fn kernel_config() {
+        use super::SessionACL;
+        use crate::ll::fuse_abi::fuse_init_in;
+        use std::mem::size_of;
+
+        struct NullFs;
+        impl Filesystem for NullFs {}
+
+        let device = tempfile::tempfile().unwrap();
+        let mut session = Session::from_fd(NullFs, device.into(), SessionACL::All);
+        assert_eq!(session.kernel_config(), None);
+        // fuse_init_in: major, minor, max_readahead, flags
+        let mut arg = [7u32.to_ne_bytes(), 31u32.to_ne_bytes()].concat();
+        arg.resize(size_of::<fuse_init_in>(), 0);
+        dispatch_raw(&mut session, fuse_opcode::FUSE_INIT as u32, 1, 0, &arg);
+        let config = session.kernel_config().unwrap();
+        let minor = crate::ll::fuse_abi::FUSE_KERNEL_MINOR_VERSION.min(31);
+        assert_eq!(config.protocol_version(), (7, minor));
+        assert_ne!(config.max_write(), 0);
+        assert_eq!(*session.negotiated.lock().unwrap(), Some(config));
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn cpu_affinity() {
+        assert!(super::set_cpu_affinity(&[libc::CPU_SETSIZE as usize]).is_err());
+    }
+}
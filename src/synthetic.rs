@@ -0,0 +1,526 @@
+//! Synthetic filesystems
+//!
+//! Filesystems like `/proc` consist of virtual files whose contents are generated when they are
+//! read. Their size isn't known before, so they are reported with a size of 0, and the kernel
+//! must be told to read them anyway. [`SyntheticFs`] builds such a filesystem from a tree of
+//! directories and content generators.
+
+use libc::{
+    c_int, EACCES, EBADF, EFBIG, EINVAL, EISDIR, ENOENT, ENOTDIR, O_ACCMODE, O_RDONLY, O_TRUNC,
+};
+use std::collections::HashMap;
+use std::ffi::{OsStr, OsString};
+use std::fmt;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::consts::FOPEN_DIRECT_IO;
+use crate::{
+    DirEntOffset, FileAttr, FileType, Filesystem, ReplyAttr, ReplyData, ReplyDirectory, ReplyEmpty,
+    ReplyEntry, ReplyOpen, ReplyWrite, Request, TimeOrNow, FUSE_ROOT_ID,
+};
+
+type Generate = Box<dyn Fn() -> Vec<u8> + Send>;
+type Store = Box<dyn Fn(&[u8]) -> Result<(), c_int> + Send>;
+
+enum Node {
+    Dir(Vec<(OsString, u64)>),
+    File {
+        read: Generate,
+        write: Option<Store>,
+    },
+}
+
+impl fmt::Debug for Node {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Node::Dir(children) => f.debug_tuple("Dir").field(children).finish(),
+            Node::File { write, .. } => f
+                .debug_struct("File")
+                .field("writable", &write.is_some())
+                .finish(),
+        }
+    }
+}
+
+/// A directory of a [`SyntheticFs`] under construction
+#[derive(Debug)]
+pub struct SyntheticDir<'a> {
+    nodes: &'a mut Vec<Node>,
+    ino: u64,
+}
+
+impl SyntheticDir<'_> {
+    fn add(&mut self, name: &str, node: Node) -> u64 {
+        self.nodes.push(node);
+        let ino = self.nodes.len() as u64;
+        let Node::Dir(children) = &mut self.nodes[(self.ino - 1) as usize] else {
+            unreachable!("Entries are only added to directories");
+        };
+        children.retain(|(n, _)| n != name);
+        children.push((name.into(), ino));
+        ino
+    }
+
+    /// Add a read-only file whose contents are generated by `read` whenever it is opened
+    pub fn file<F, T>(&mut self, name: &str, read: F) -> &mut Self
+    where
+        F: Fn() -> T + Send + 'static,
+        T: Into<Vec<u8>>,
+    {
+        self.add(
+            name,
+            Node::File {
+                read: Box::new(move || read().into()),
+                write: None,
+            },
+        );
+        self
+    }
+
+    /// Add a file whose contents are generated by `read` whenever it is opened, and passed to
+    /// `write` when they were written and the file is closed. Errors of `write` are returned to
+    /// `close`.
+    pub fn writable_file<F, T, W>(&mut self, name: &str, read: F, write: W) -> &mut Self
+    where
+        F: Fn() -> T + Send + 'static,
+        T: Into<Vec<u8>>,
+        W: Fn(&[u8]) -> Result<(), c_int> + Send + 'static,
+    {
+        self.add(
+            name,
+            Node::File {
+                read: Box::new(move || read().into()),
+                write: Some(Box::new(write)),
+            },
+        );
+        self
+    }
+
+    /// Add a subdirectory, whose entries are added by `build`
+    pub fn dir<B: FnOnce(&mut SyntheticDir<'_>)>(&mut self, name: &str, build: B) -> &mut Self {
+        let ino = self.add(name, Node::Dir(vec![]));
+        build(&mut SyntheticDir {
+            nodes: self.nodes,
+            ino,
+        });
+        self
+    }
+}
+
+/// An open file of a [`SyntheticFs`]
+#[derive(Debug)]
+struct OpenFile {
+    ino: u64,
+    /// Contents generated on open, or written through this handle
+    data: Vec<u8>,
+    /// Whether `data` was written and must be stored on flush
+    dirty: bool,
+}
+
+/// A read-only (or, per file, writable) filesystem of virtual files with generated contents.
+///
+/// ```no_run
+/// # use fuser::SyntheticFs;
+/// let mut fs = SyntheticFs::new();
+/// fs.root()
+///     .file("version", || "1.0\n")
+///     .dir("status", |dir| {
+///         dir.file("time", || format!("{:?}\n", std::time::SystemTime::now()));
+///     });
+/// ```
+///
+/// The contents of a file are generated once per `open`, so that reading it in several chunks
+/// returns a consistent snapshot. Like in `/proc`, files report a size of 0 and are opened in
+/// direct I/O mode, so the kernel reads them until the end of the generated contents instead of
+/// until the reported size. Writing beyond the maximum file size (see
+/// [`with_max_file_size`](Self::with_max_file_size)) fails with `EFBIG`.
+#[derive(Debug)]
+pub struct SyntheticFs {
+    nodes: Vec<Node>,
+    open: HashMap<u64, OpenFile>,
+    next_fh: u64,
+    max_file_size: usize,
+    ttl: Duration,
+    uid: u32,
+    gid: u32,
+}
+
+impl Default for SyntheticFs {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl SyntheticFs {
+    /// Create a filesystem with an empty root directory
+    pub fn new() -> Self {
+        Self {
+            nodes: vec![Node::Dir(vec![])],
+            open: HashMap::new(),
+            next_fh: 1,
+            max_file_size: 1 << 20,
+            ttl: Duration::from_secs(1),
+            uid: nix::unistd::geteuid().as_raw(),
+            gid: nix::unistd::getegid().as_raw(),
+        }
+    }
+
+    /// Returns the root directory, to add entries to it
+    pub fn root(&mut self) -> SyntheticDir<'_> {
+        SyntheticDir {
+            nodes: &mut self.nodes,
+            ino: FUSE_ROOT_ID,
+        }
+    }
+
+    /// Set how long the kernel caches attributes and entries. Defaults to 1 second.
+    pub fn with_ttl(mut self, ttl: Duration) -> Self {
+        self.ttl = ttl;
+        self
+    }
+
+    /// Set the size the contents written to a file may grow to. Defaults to 1 MiB.
+    pub fn with_max_file_size(mut self, max_file_size: usize) -> Self {
+        self.max_file_size = max_file_size;
+        self
+    }
+
+    /// Set the owner of all files. Defaults to the user running the process.
+    pub fn with_owner(mut self, uid: u32, gid: u32) -> Self {
+        self.uid = uid;
+        self.gid = gid;
+        self
+    }
+
+    fn node(&self, ino: u64) -> Result<&Node, c_int> {
+        ino.checked_sub(1)
+            .and_then(|i| self.nodes.get(i as usize))
+            .ok_or(ENOENT)
+    }
+
+    /// Returns the directory containing the given node, the root is its own parent
+    fn parent(&self, ino: u64) -> u64 {
+        self.nodes
+            .iter()
+            .position(
+                |node| matches!(node, Node::Dir(children) if children.iter().any(|c| c.1 == ino)),
+            )
+            .map_or(FUSE_ROOT_ID, |i| i as u64 + 1)
+    }
+
+    fn attr(&self, ino: u64) -> Result<FileAttr, c_int> {
+        let (kind, perm, nlink) = match self.node(ino)? {
+            Node::Dir(_) => (FileType::Directory, 0o555, 2),
+            Node::File { write: None, .. } => (FileType::RegularFile, 0o444, 1),
+            Node::File { write: Some(_), .. } => (FileType::RegularFile, 0o644, 1),
+        };
+        Ok(FileAttr {
+            ino,
+            size: 0,
+            blocks: 0,
+            atime: UNIX_EPOCH,
+            mtime: UNIX_EPOCH,
+            ctime: UNIX_EPOCH,
+            crtime: UNIX_EPOCH,
+            kind,
+            perm,
+            nlink,
+            uid: self.uid,
+            gid: self.gid,
+            rdev: 0,
+            blksize: 4096,
+            flags: 0,
+        })
+    }
+
+    fn open_file(&mut self, ino: u64, flags: i32) -> Result<u64, c_int> {
+        let writing = flags & O_ACCMODE != O_RDONLY;
+        let data = match self.node(ino)? {
+            Node::Dir(_) => return Err(EISDIR),
+            Node::File { write: None, .. } if writing => return Err(EACCES),
+            Node::File { .. } if flags & O_TRUNC != 0 => vec![],
+            Node::File { read, .. } => read(),
+        };
+        let fh = self.next_fh;
+        self.next_fh += 1;
+        self.open.insert(
+            fh,
+            OpenFile {
+                ino,
+                data,
+                dirty: false,
+            },
+        );
+        Ok(fh)
+    }
+
+    /// Write to an open file, extending it as needed
+    fn write_at(&mut self, fh: u64, offset: i64, data: &[u8]) -> Result<(), c_int> {
+        let file = self.open.get_mut(&fh).ok_or(EBADF)?;
+        let start = usize::try_from(offset).map_err(|_| EINVAL)?;
+        let end = start
+            .checked_add(data.len())
+            .filter(|end| *end <= self.max_file_size)
+            .ok_or(EFBIG)?;
+        if file.data.len() < end {
+            file.data.resize(end, 0);
+        }
+        file.data[start..end].copy_from_slice(data);
+        file.dirty = true;
+        Ok(())
+    }
+
+    /// Pass the written contents of an open file to its write callback
+    fn store(&mut self, fh: u64) -> Result<(), c_int> {
+        let file = self.open.get_mut(&fh).ok_or(EBADF)?;
+        if !file.dirty {
+            return Ok(());
+        }
+        file.dirty = false;
+        match &self.nodes[(file.ino - 1) as usize] {
+            Node::File {
+                write: Some(write), ..
+            } => write(&file.data),
+            _ => Err(EACCES),
+        }
+    }
+}
+
+impl Filesystem for SyntheticFs {
+    fn lookup(&mut self, _req: &Request<'_>, parent: u64, name: &OsStr, reply: ReplyEntry) {
+        let ino = match self.node(parent) {
+            Ok(Node::Dir(children)) => children.iter().find(|(n, _)| n == name).map(|e| e.1),
+            Ok(Node::File { .. }) => return reply.error(ENOTDIR),
+            Err(err) => return reply.error(err),
+        };
+        match ino.ok_or(ENOENT).and_then(|ino| self.attr(ino)) {
+            Ok(attr) => reply.entry(self.ttl, &attr, 0),
+            Err(err) => reply.error(err),
+        }
+    }
+
+    fn getattr(&mut self, _req: &Request<'_>, ino: u64, _fh: Option<u64>, reply: ReplyAttr) {
+        match self.attr(ino) {
+            Ok(attr) => reply.attr(self.ttl, &attr),
+            Err(err) => reply.error(err),
+        }
+    }
+
+    fn setattr(
+        &mut self,
+        _req: &Request<'_>,
+        ino: u64,
+        mode: Option<u32>,
+        uid: Option<u32>,
+        gid: Option<u32>,
+        size: Option<u64>,
+        _atime: Option<TimeOrNow>,
+        _mtime: Option<TimeOrNow>,
+        _ctime: Option<SystemTime>,
+        fh: Option<u64>,
+        _crtime: Option<SystemTime>,
+        _chgtime: Option<SystemTime>,
+        _bkuptime: Option<SystemTime>,
+        _flags: Option<u32>,
+        reply: ReplyAttr,
+    ) {
+        if mode.is_some() || uid.is_some() || gid.is_some() {
+            return reply.error(libc::EPERM);
+        }
+        // Truncating writable files, e.g. by `echo 1 > file`, is allowed
+        if let Some(size) = size {
+            if !matches!(self.node(ino), Ok(Node::File { write: Some(_), .. })) {
+                return reply.error(EACCES);
+            }
+            if let Some(file) = fh.and_then(|fh| self.open.get_mut(&fh)) {
+                file.data.truncate(size as usize);
+                file.dirty = true;
+            }
+        }
+        match self.attr(ino) {
+            Ok(attr) => reply.attr(self.ttl, &attr),
+            Err(err) => reply.error(err),
+        }
+    }
+
+    fn open(&mut self, _req: &Request<'_>, ino: u64, flags: i32, reply: ReplyOpen) {
+        match self.open_file(ino, flags) {
+            Ok(fh) => reply.opened(fh, FOPEN_DIRECT_IO),
+            Err(err) => reply.error(err),
+        }
+    }
+
+    fn read(
+        &mut self,
+        _req: &Request<'_>,
+        _ino: u64,
+        fh: u64,
+        offset: i64,
+        size: u32,
+        _flags: i32,
+        _lock_owner: Option<u64>,
+        reply: ReplyData,
+    ) {
+        let Some(file) = self.open.get(&fh) else {
+            return reply.error(EBADF);
+        };
+        let start = (offset.max(0) as usize).min(file.data.len());
+        let end = start.saturating_add(size as usize).min(file.data.len());
+        reply.data(&file.data[start..end]);
+    }
+
+    fn write(
+        &mut self,
+        _req: &Request<'_>,
+        _ino: u64,
+        fh: u64,
+        offset: i64,
+        data: &[u8],
+        _write_flags: u32,
+        _flags: i32,
+        _lock_owner: Option<u64>,
+        reply: ReplyWrite,
+    ) {
+        match self.write_at(fh, offset, data) {
+            Ok(()) => reply.written(data.len() as u32),
+            Err(err) => reply.error(err),
+        }
+    }
+
+    fn flush(
+        &mut self,
+        _req: &Request<'_>,
+        _ino: u64,
+        fh: u64,
+        _lock_owner: u64,
+        reply: ReplyEmpty,
+    ) {
+        match self.store(fh) {
+            Ok(()) => reply.ok(),
+            Err(err) => reply.error(err),
+        }
+    }
+
+    fn release(
+        &mut self,
+        _req: &Request<'_>,
+        _ino: u64,
+        fh: u64,
+        _flags: i32,
+        _lock_owner: Option<u64>,
+        _flush: bool,
+        reply: ReplyEmpty,
+    ) {
+        let result = self.store(fh);
+        self.open.remove(&fh);
+        match result {
+            Ok(()) => reply.ok(),
+            Err(err) => reply.error(err),
+        }
+    }
+
+    fn readdir(
+        &mut self,
+        _req: &Request<'_>,
+        ino: u64,
+        _fh: u64,
+        offset: i64,
+        mut reply: ReplyDirectory,
+    ) {
+        let children = match self.node(ino) {
+            Ok(Node::Dir(children)) => children,
+            Ok(Node::File { .. }) => return reply.error(ENOTDIR),
+            Err(err) => return reply.error(err),
+        };
+        let parent = self.parent(ino);
+        let entries = [(ino, OsStr::new(".")), (parent, OsStr::new(".."))]
+            .into_iter()
+            .chain(children.iter().map(|(name, ino)| (*ino, name.as_os_str())));
+        for (i, (child, name)) in entries.enumerate().skip(offset as usize) {
+            let kind = match self.nodes[(child - 1) as usize] {
+                Node::Dir(_) => FileType::Directory,
+                Node::File { .. } => FileType::RegularFile,
+            };
+            if reply.add(child, DirEntOffset::after_index(i).get(), kind, name) {
+                break;
+            }
+        }
+        reply.ok();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Node, SyntheticFs};
+    use crate::{FileType, FUSE_ROOT_ID};
+    use libc::{EACCES, EFBIG, EINVAL, O_RDONLY, O_WRONLY};
+    use std::sync::{Arc, Mutex};
+
+    #[test]
+    fn build_tree() {
+        let mut fs = SyntheticFs::new();
+        fs.root().file("a", || "1").dir("d", |dir| {
+            dir.file("b", || vec![2]).dir("e", |_| {});
+        });
+        match fs.node(1).unwrap() {
+            Node::Dir(children) => assert_eq!(children.len(), 2),
+            _ => panic!("Root is not a directory"),
+        }
+        assert_eq!(fs.attr(2).unwrap().kind, FileType::RegularFile);
+        assert_eq!(fs.attr(3).unwrap().kind, FileType::Directory);
+        assert_eq!(fs.attr(4).unwrap().perm, 0o444);
+        assert_eq!(fs.attr(5).unwrap().kind, FileType::Directory);
+        assert!(fs.attr(6).is_err());
+        assert_eq!(fs.parent(FUSE_ROOT_ID), FUSE_ROOT_ID);
+        assert_eq!(fs.parent(3), FUSE_ROOT_ID);
+        assert_eq!(fs.parent(5), 3);
+    }
+
+    #[test]
+    fn snapshot_and_write() {
+        let counter = Arc::new(Mutex::new(0));
+        let written = Arc::new(Mutex::new(vec![]));
+        let mut fs = SyntheticFs::new();
+        fs.root()
+            .file("counter", {
+                let counter = counter.clone();
+                move || {
+                    *counter.lock().unwrap() += 1;
+                    format!("{}\n", counter.lock().unwrap())
+                }
+            })
+            .writable_file("level", || "0\n", {
+                let written = written.clone();
+                move |data: &[u8]| {
+                    *written.lock().unwrap() = data.to_vec();
+                    Ok(())
+                }
+            });
+
+        let fh = fs.open_file(2, O_RDONLY).unwrap();
+        assert_eq!(fs.open[&fh].data, b"1\n");
+        assert_eq!(fs.open_file(2, O_WRONLY), Err(EACCES));
+        let fh = fs.open_file(2, O_RDONLY).unwrap();
+        assert_eq!(fs.open[&fh].data, b"2\n");
+
+        let fh = fs.open_file(3, O_WRONLY).unwrap();
+        fs.open.get_mut(&fh).unwrap().data = b"5\n".to_vec();
+        fs.store(fh).unwrap();
+        // Nothing was written
+        assert!(written.lock().unwrap().is_empty());
+        fs.open.get_mut(&fh).unwrap().dirty = true;
+        fs.store(fh).unwrap();
+        assert_eq!(*written.lock().unwrap(), b"5\n");
+    }
+
+    #[test]
+    fn write_limit() {
+        let mut fs = SyntheticFs::new().with_max_file_size(4);
+        fs.root().writable_file("level", || "", |_: &[u8]| Ok(()));
+        let fh = fs.open_file(2, O_WRONLY).unwrap();
+        fs.write_at(fh, 2, b"12").unwrap();
+        assert_eq!(fs.open[&fh].data, [0, 0, b'1', b'2']);
+        assert_eq!(fs.write_at(fh, 3, b"12"), Err(EFBIG));
+        assert_eq!(fs.write_at(fh, -1, b"1"), Err(EINVAL));
+    }
+}
@@ -0,0 +1,181 @@
+//! Kernel side state of a FUSE connection
+//!
+//! The kernel exposes every FUSE connection in the fusectl filesystem, usually mounted at
+//! `/sys/fs/fuse/connections`, in a directory named after the device number of the mount.
+//! Settings of the page cache of the mount, like its readahead, are in the directory of its
+//! backing device in `/sys/class/bdi`.
+
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// Where the fusectl filesystem is usually mounted
+const CONNECTIONS_DIR: &str = "/sys/fs/fuse/connections";
+
+/// Where the kernel lists backing devices
+const BDI_DIR: &str = "/sys/class/bdi";
+
+/// Handle to the fusectl directory of a mounted FUSE connection (see
+/// [`Session::kernel_connection`](crate::Session::kernel_connection)). It stays usable while the
+/// session is running, from any thread.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct KernelConnection {
+    dir: PathBuf,
+    /// Directory of the backing device of the mount
+    bdi: PathBuf,
+}
+
+impl KernelConnection {
+    /// Find the connection of the FUSE filesystem mounted at `mountpoint`.
+    pub fn for_mountpoint(mountpoint: &Path) -> io::Result<KernelConnection> {
+        if !cfg!(target_os = "linux") {
+            return Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "fusectl is only available on Linux",
+            ));
+        }
+        // Don't canonicalize or stat the mountpoint, that would send a request to the
+        // filesystem which might be served by the calling thread.
+        let mountpoint = std::env::current_dir()?.join(mountpoint);
+        let mountinfo = fs::read_to_string("/proc/self/mountinfo")?;
+        let dev = find_device(&mountinfo, &mountpoint).ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("No FUSE filesystem mounted at {}", mountpoint.display()),
+            )
+        })?;
+        Ok(KernelConnection {
+            dir: Path::new(CONNECTIONS_DIR).join(dev.to_string()),
+            bdi: Path::new(BDI_DIR).join(format!("{}:{}", dev >> 20, dev & 0xf_ffff)),
+        })
+    }
+
+    /// Directory of the connection in the fusectl filesystem
+    pub fn path(&self) -> &Path {
+        &self.dir
+    }
+
+    /// Abort the connection. All outstanding and future requests fail with ECONNABORTED. The
+    /// session calls [`Filesystem::aborted`](crate::Filesystem::aborted) and ends with
+    /// [`SessionExit::Aborted`](crate::SessionExit::Aborted), which
+    /// [`into_result`](crate::SessionExit::into_result) turns into a
+    /// [`ConnectionAborted`](io::ErrorKind::ConnectionAborted) error. Without `FUSE_ABORT_ERROR`
+    /// (the `abi-7-27` feature), the session ends as if the filesystem was unmounted instead.
+    pub fn abort(&self) -> io::Result<()> {
+        fs::write(self.dir.join("abort"), "1")
+    }
+
+    /// Number of requests waiting to be read or answered by the filesystem
+    pub fn waiting_requests(&self) -> io::Result<u32> {
+        self.read("waiting")
+    }
+
+    /// Maximum number of background requests the kernel keeps outstanding
+    pub fn max_background(&self) -> io::Result<u32> {
+        self.read("max_background")
+    }
+
+    /// Change the maximum number of outstanding background requests. Requires root.
+    pub fn set_max_background(&self, value: u32) -> io::Result<()> {
+        fs::write(self.dir.join("max_background"), value.to_string())
+    }
+
+    /// Number of outstanding background requests at which the kernel considers the
+    /// connection congested
+    pub fn congestion_threshold(&self) -> io::Result<u32> {
+        self.read("congestion_threshold")
+    }
+
+    /// Maximum number of bytes the kernel reads ahead of sequential reads. Starts out as the
+    /// `max_readahead` negotiated during init.
+    pub fn max_readahead(&self) -> io::Result<u32> {
+        Ok(read_u32(&self.bdi.join("read_ahead_kb"))?.saturating_mul(1024))
+    }
+
+    /// Change how far the kernel reads ahead of sequential reads, e.g. to raise it for
+    /// streaming workloads and lower it for random access. Takes effect for reads started
+    /// afterwards, the value is rounded down to whole KiB. Requires root.
+    pub fn set_max_readahead(&self, bytes: u32) -> io::Result<()> {
+        fs::write(self.bdi.join("read_ahead_kb"), (bytes / 1024).to_string())
+    }
+
+    fn read(&self, name: &str) -> io::Result<u32> {
+        read_u32(&self.dir.join(name))
+    }
+}
+
+fn read_u32(path: &Path) -> io::Result<u32> {
+    let value = fs::read_to_string(path)?;
+    value.trim().parse().map_err(|_| {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("Unexpected content of {}: {:?}", path.display(), value),
+        )
+    })
+}
+
+/// Returns the kernel device number of the FUSE filesystem most recently mounted at
+/// `mountpoint`, given the contents of `/proc/self/mountinfo`.
+fn find_device(mountinfo: &str, mountpoint: &Path) -> Option<u32> {
+    let mountpoint = mountpoint.to_str()?;
+    mountinfo
+        .lines()
+        .filter_map(|line| {
+            let mut fields = line.split(' ');
+            let dev = fields.nth(2)?;
+            let mnt = fields.nth(1)?;
+            let fstype = line.split(" - ").nth(1)?.split(' ').next()?;
+            if !(fstype == "fuse" || fstype.starts_with("fuse.") || fstype == "fuseblk") {
+                return None;
+            }
+            if unescape(mnt) != mountpoint {
+                return None;
+            }
+            let (major, minor) = dev.split_once(':')?;
+            Some((major.parse::<u32>().ok()? << 20) | minor.parse::<u32>().ok()?)
+        })
+        .last()
+}
+
+/// Undo the octal escaping of whitespace and backslashes in mountinfo
+fn unescape(field: &str) -> String {
+    let mut out = String::with_capacity(field.len());
+    let mut rest = field;
+    while let Some(pos) = rest.find('\\') {
+        out.push_str(&rest[..pos]);
+        match rest
+            .get(pos + 1..pos + 4)
+            .and_then(|o| u8::from_str_radix(o, 8).ok())
+        {
+            Some(byte) => {
+                out.push(byte as char);
+                rest = &rest[pos + 4..];
+            }
+            None => {
+                out.push('\\');
+                rest = &rest[pos + 1..];
+            }
+        }
+    }
+    out.push_str(rest);
+    out
+}
+
+#[cfg(test)]
+mod test {
+    use super::find_device;
+    use std::path::Path;
+
+    #[test]
+    fn find_device_in_mountinfo() {
+        let mountinfo = "\
+22 1 259:2 / / rw,relatime shared:1 - ext4 /dev/nvme0n1p2 rw
+41 22 0:45 / /tmp/my\\040mnt rw,nosuid,nodev,relatime shared:20 - fuse.hello hello rw,user_id=1000
+42 22 0:46 / /tmp/other rw,nosuid,nodev,relatime shared:21 - fuse /dev/fuse rw,user_id=1000
+";
+        assert_eq!(find_device(mountinfo, Path::new("/tmp/my mnt")), Some(45));
+        assert_eq!(find_device(mountinfo, Path::new("/tmp/other")), Some(46));
+        assert_eq!(find_device(mountinfo, Path::new("/")), None);
+        assert_eq!(find_device(mountinfo, Path::new("/tmp/missing")), None);
+    }
+}
@@ -0,0 +1,85 @@
+//! Rounding of timestamps to the granularity negotiated with the kernel
+//!
+//! With a coarser granularity than 1ns configured through
+//! [`KernelConfig::set_time_granularity`](crate::KernelConfig::set_time_granularity), times in
+//! attributes replied to the kernel and in `setattr` requests are truncated to it. This way a
+//! filesystem which stores times with less precision returns the same values it was given,
+//! instead of the kernel seeing cached times change once the attributes are looked up again.
+
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::{FileAttr, TimeOrNow};
+
+const NANOS_PER_SEC: u128 = 1_000_000_000;
+
+/// Returns the given time truncated to a multiple of the granularity. Like the timespecs of the
+/// kernel, times before the epoch are rounded down (away from it).
+pub(crate) fn round(time: SystemTime, gran: Duration) -> SystemTime {
+    let gran = gran.as_nanos().max(1);
+    let (nanos, before_epoch) = match time.duration_since(UNIX_EPOCH) {
+        Ok(d) => (d.as_nanos(), false),
+        Err(e) => (e.duration().as_nanos(), true),
+    };
+    let rem = nanos % gran;
+    let nanos = if rem == 0 {
+        nanos
+    } else if before_epoch {
+        nanos + gran - rem
+    } else {
+        nanos - rem
+    };
+    let d = Duration::new(
+        (nanos / NANOS_PER_SEC) as u64,
+        (nanos % NANOS_PER_SEC) as u32,
+    );
+    if before_epoch {
+        UNIX_EPOCH - d
+    } else {
+        UNIX_EPOCH + d
+    }
+}
+
+/// Returns the attributes with all times truncated to the granularity
+pub(crate) fn round_attr(attr: &FileAttr, gran: Duration) -> FileAttr {
+    FileAttr {
+        atime: round(attr.atime, gran),
+        mtime: round(attr.mtime, gran),
+        ctime: round(attr.ctime, gran),
+        crtime: round(attr.crtime, gran),
+        ..*attr
+    }
+}
+
+/// Returns the time to set truncated to the granularity. The current time is left to the
+/// filesystem.
+pub(crate) fn round_time_or_now(time: TimeOrNow, gran: Duration) -> TimeOrNow {
+    match time {
+        TimeOrNow::SpecificTime(t) => TimeOrNow::SpecificTime(round(t, gran)),
+        TimeOrNow::Now => TimeOrNow::Now,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::round;
+    use std::time::{Duration, UNIX_EPOCH};
+
+    #[test]
+    fn round_to_granularity() {
+        let ms = Duration::from_millis(1);
+        let t = UNIX_EPOCH + Duration::new(5, 123_456_789);
+        assert_eq!(round(t, ms), UNIX_EPOCH + Duration::new(5, 123_000_000));
+        assert_eq!(
+            round(t, Duration::from_secs(1)),
+            UNIX_EPOCH + Duration::from_secs(5)
+        );
+        assert_eq!(round(t, Duration::from_nanos(1)), t);
+
+        let t = UNIX_EPOCH - Duration::new(5, 123_456_789);
+        assert_eq!(round(t, ms), UNIX_EPOCH - Duration::new(5, 124_000_000));
+        assert_eq!(
+            round(t, Duration::from_secs(1)),
+            UNIX_EPOCH - Duration::from_secs(6)
+        );
+    }
+}
@@ -0,0 +1,80 @@
+//! Cache lifetimes of entries and attributes
+//!
+//! Replies with entries or attributes tell the kernel how long it may cache them. A bare
+//! `Duration::new(0, 0)` doesn't say whether the filesystem means to disable caching or just
+//! picked a number, so [`Ttl`] names the common choices.
+
+use std::time::Duration;
+
+/// How long the kernel may cache a replied entry or attributes
+///
+/// Reply methods take anything that converts into a `Ttl`, including `Duration` and
+/// `&Duration`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Ttl(Duration);
+
+impl Ttl {
+    /// Don't cache: the kernel asks the filesystem again on the next access. Needed for
+    /// filesystems whose contents change behind the kernel's back.
+    pub const NONE: Self = Self(Duration::ZERO);
+
+    /// Cache until the filesystem invalidates the entry or attributes with a notification, or
+    /// the kernel evicts them
+    pub const FOREVER: Self = Self(Duration::from_secs(i64::MAX as u64));
+
+    /// Cache for the given duration
+    pub const fn new(duration: Duration) -> Self {
+        Self(duration)
+    }
+
+    /// Cache for the given number of seconds
+    pub const fn from_secs(secs: u64) -> Self {
+        Self(Duration::from_secs(secs))
+    }
+
+    /// Returns how long the kernel may cache
+    pub const fn duration(&self) -> Duration {
+        self.0
+    }
+}
+
+impl From<Duration> for Ttl {
+    fn from(duration: Duration) -> Self {
+        Self(duration)
+    }
+}
+
+impl From<&Duration> for Ttl {
+    fn from(duration: &Duration) -> Self {
+        Self(*duration)
+    }
+}
+
+impl From<&Ttl> for Ttl {
+    fn from(ttl: &Ttl) -> Self {
+        *ttl
+    }
+}
+
+impl From<Ttl> for Duration {
+    fn from(ttl: Ttl) -> Self {
+        ttl.0
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::Ttl;
+    use std::time::Duration;
+
+    #[test]
+    fn conversions() {
+        let second = Duration::from_secs(1);
+        assert_eq!(Ttl::from(second), Ttl::from_secs(1));
+        assert_eq!(Ttl::from(&second), Ttl::new(second));
+        assert_eq!(Duration::from(Ttl::NONE), Duration::ZERO);
+        assert!(Ttl::FOREVER > Ttl::from_secs(u32::MAX.into()));
+        // The kernel reads the seconds as a signed number
+        assert!(i64::try_from(Ttl::FOREVER.duration().as_secs()).is_ok());
+    }
+}
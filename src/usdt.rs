@@ -0,0 +1,139 @@
+//! Statically defined tracepoints
+//!
+//! With the `usdt` feature, the session contains USDT probes (the `sys/sdt.h` format of
+//! SystemTap, which bpftrace and other eBPF tools attach to) under the provider `fuser`:
+//!
+//! - `request_start(opcode, ino, unique)` when a request is dispatched to the filesystem
+//! - `request_end(opcode, ino, unique, error)` when the reply to it is sent, with the negated
+//!   error code of the reply (0 on success). Requests without a reply, like `forget`, have no
+//!   end.
+//!
+//! A probe is a single `nop` until a tracer attaches to it, so the feature can be enabled in
+//! production builds. For example, to count requests by opcode:
+//!
+//! ```text
+//! bpftrace -e 'usdt:/path/to/binary:fuser:request_start { @[arg0] = count(); }'
+//! ```
+//!
+//! Probes are only emitted on Linux on x86_64 and aarch64; elsewhere they compile to nothing.
+
+#[cfg(all(
+    target_os = "linux",
+    any(target_arch = "x86_64", target_arch = "aarch64")
+))]
+macro_rules! probe {
+    ($name:literal, $count:tt, $($arg:expr),*) => {
+        // SAFETY: only emits a nop and an ELF note describing it
+        unsafe {
+            std::arch::asm!(
+                "990: nop",
+                ".pushsection .note.stapsdt, \"\", \"note\"",
+                ".balign 4",
+                ".4byte 992f-991f, 994f-993f, 3",
+                "991: .asciz \"stapsdt\"",
+                "992: .balign 4",
+                "993: .8byte 990b",
+                ".8byte _.stapsdt.base",
+                // No semaphore, the probe is always enabled
+                ".8byte 0",
+                ".asciz \"fuser\"",
+                concat!(".asciz \"", $name, "\""),
+                concat!(".asciz \"", args!($count), "\""),
+                "994: .balign 4",
+                ".popsection",
+                ".ifndef _.stapsdt.base",
+                ".pushsection .stapsdt.base, \"aG\", \"progbits\", .stapsdt.base, comdat",
+                ".weak _.stapsdt.base",
+                ".hidden _.stapsdt.base",
+                "_.stapsdt.base: .space 1",
+                ".size _.stapsdt.base, 1",
+                ".popsection",
+                ".endif",
+                $(in(reg) $arg,)*
+                options(nomem, nostack, preserves_flags),
+            )
+        }
+    };
+}
+
+#[cfg(all(target_os = "linux", target_arch = "x86_64"))]
+macro_rules! args {
+    (3) => {
+        "8@%{0:r} 8@%{1:r} 8@%{2:r}"
+    };
+    (4) => {
+        "8@%{0:r} 8@%{1:r} 8@%{2:r} -8@%{3:r}"
+    };
+}
+
+#[cfg(all(target_os = "linux", target_arch = "aarch64"))]
+macro_rules! args {
+    (3) => {
+        "8@{0:x} 8@{1:x} 8@{2:x}"
+    };
+    (4) => {
+        "8@{0:x} 8@{1:x} 8@{2:x} -8@{3:x}"
+    };
+}
+
+/// Fire the `request_start` probe
+#[inline]
+#[allow(named_asm_labels)]
+pub(crate) fn request_start(opcode: u32, ino: u64, unique: u64) {
+    #[cfg(all(
+        target_os = "linux",
+        any(target_arch = "x86_64", target_arch = "aarch64")
+    ))]
+    probe!("request_start", 3, u64::from(opcode), ino, unique);
+    #[cfg(not(all(
+        target_os = "linux",
+        any(target_arch = "x86_64", target_arch = "aarch64")
+    )))]
+    let _ = (opcode, ino, unique);
+}
+
+/// Fire the `request_end` probe
+#[inline]
+#[allow(named_asm_labels)]
+pub(crate) fn request_end(opcode: u32, ino: u64, unique: u64, error: i32) {
+    #[cfg(all(
+        target_os = "linux",
+        any(target_arch = "x86_64", target_arch = "aarch64")
+    ))]
+    probe!(
+        "request_end",
+        4,
+        u64::from(opcode),
+        ino,
+        unique,
+        i64::from(error)
+    );
+    #[cfg(not(all(
+        target_os = "linux",
+        any(target_arch = "x86_64", target_arch = "aarch64")
+    )))]
+    let _ = (opcode, ino, unique, error);
+}
+
+#[cfg(all(
+    test,
+    target_os = "linux",
+    any(target_arch = "x86_64", target_arch = "aarch64")
+))]
+mod test {
+    use super::{request_end, request_start};
+
+    fn contains(haystack: &[u8], needle: &[u8]) -> bool {
+        haystack.windows(needle.len()).any(|w| w == needle)
+    }
+
+    #[test]
+    fn probes_are_in_binary() {
+        request_start(1, 2, 3);
+        request_end(1, 2, 3, -2);
+        let binary = std::fs::read("/proc/self/exe").unwrap();
+        assert!(contains(&binary, b"stapsdt\0"));
+        assert!(contains(&binary, b"fuser\0request_start\0"));
+        assert!(contains(&binary, b"fuser\0request_end\0"));
+    }
+}
@@ -0,0 +1,680 @@
+//! Whole-file I/O
+//!
+//! Filesystems backed by configuration stores or key-value databases read and write files as a
+//! whole, so implementing `read` and `write` per block is pointless complexity. A
+//! [`WholeFileFilesystem`] only reads and writes complete files, and [`WholeFileFs`] implements
+//! the file I/O of the [`Filesystem`] trait on top of it.
+
+use libc::{
+    c_int, EBADF, EFBIG, EINVAL, ENOSYS, ENXIO, EOPNOTSUPP, O_ACCMODE, O_RDONLY, O_TRUNC, SEEK_CUR,
+    SEEK_DATA, SEEK_END, SEEK_HOLE, SEEK_SET,
+};
+use std::collections::HashMap;
+use std::ffi::OsStr;
+use std::time::SystemTime;
+
+use crate::forward::forward_filesystem;
+use crate::{
+    FileAttr, Filesystem, ReplyAttr, ReplyCreate, ReplyData, ReplyEmpty, ReplyLseek, ReplyOpen,
+    ReplyWrite, Request, TimeOrNow, Ttl,
+};
+
+/// A filesystem that reads and writes the contents of its files as a whole (see
+/// [`WholeFileFs`]).
+pub trait WholeFileFilesystem {
+    /// Read the complete contents of a file.
+    fn read_file(&mut self, ino: u64) -> Result<Vec<u8>, c_int>;
+
+    /// Replace the complete contents of a file.
+    fn write_file(&mut self, ino: u64, data: Vec<u8>) -> Result<(), c_int>;
+
+    /// Create an empty regular file, returning how long the kernel may cache its entry and its
+    /// attributes. Unless this is implemented, `create` fails with `ENOSYS` and the kernel
+    /// creates files with `mknod` and `open` instead.
+    fn create_file(
+        &mut self,
+        _parent: u64,
+        _name: &OsStr,
+        _mode: u32,
+        _umask: u32,
+    ) -> Result<(Ttl, FileAttr), c_int> {
+        Err(ENOSYS)
+    }
+}
+
+/// Default limit of the size of files, see [`WholeFileFs::with_max_file_size`]
+const DEFAULT_MAX_FILE_SIZE: u64 = 1 << 30;
+
+/// A file opened through [`WholeFileFs`]
+#[derive(Debug)]
+struct OpenFile {
+    ino: u64,
+    data: Vec<u8>,
+    /// Whether `data` was changed since it was last written back
+    dirty: bool,
+    writable: bool,
+}
+
+/// Implements `open`, `create`, `read`, `write`, `flush`, `fsync`, `release`, `fallocate`,
+/// `lseek`, `copy_file_range` and truncating `setattr` of a filesystem on top of its
+/// [`WholeFileFilesystem`] implementation. All other operations are passed through.
+///
+/// Opening a file reads its contents into memory, where reads and writes are served from. Changed
+/// contents are written back with `write_file` on `flush`, `fsync` and `release`, and errors of
+/// writing them back are replied to these operations (so `close` reports them). Truncating
+/// through `setattr` changes the contents first and then passes the request through, so the
+/// filesystem only has to update the attributes. File handles are assigned by the adapter; the
+/// `open` and `release` methods of the filesystem aren't called. Every handle has its own copy
+/// of the contents, so of concurrent writers through different handles, the last one to write
+/// back wins. Growing a file beyond the maximum size (see
+/// [`with_max_file_size`](Self::with_max_file_size)) fails with `EFBIG`.
+#[derive(Debug)]
+pub struct WholeFileFs<F> {
+    inner: F,
+    open: HashMap<u64, OpenFile>,
+    next_fh: u64,
+    max_file_size: u64,
+}
+
+impl<F: Filesystem + WholeFileFilesystem> WholeFileFs<F> {
+    /// Wrap a filesystem
+    pub fn new(inner: F) -> Self {
+        Self {
+            inner,
+            open: HashMap::new(),
+            next_fh: 1,
+            max_file_size: DEFAULT_MAX_FILE_SIZE,
+        }
+    }
+
+    /// Set the size files may grow to in memory. Defaults to 1 GiB.
+    pub fn with_max_file_size(mut self, max_file_size: u64) -> Self {
+        self.max_file_size = max_file_size;
+        self
+    }
+
+    /// Returns a reference to the inner filesystem.
+    pub fn inner(&self) -> &F {
+        &self.inner
+    }
+
+    /// Returns a mutable reference to the inner filesystem.
+    pub fn inner_mut(&mut self) -> &mut F {
+        &mut self.inner
+    }
+
+    /// Unwrap the inner filesystem. Changes of files that are still open are lost.
+    pub fn into_inner(self) -> F {
+        self.inner
+    }
+
+    /// Open a file, returning its file handle
+    fn open_file(&mut self, ino: u64, flags: i32) -> Result<u64, c_int> {
+        let truncate = flags & O_TRUNC != 0;
+        let data = if truncate {
+            vec![]
+        } else {
+            self.inner.read_file(ino)?
+        };
+        let fh = self.next_fh;
+        self.next_fh += 1;
+        self.open.insert(
+            fh,
+            OpenFile {
+                ino,
+                data,
+                dirty: truncate,
+                writable: flags & O_ACCMODE != O_RDONLY,
+            },
+        );
+        Ok(fh)
+    }
+
+    /// Write the contents of an open file back if they changed
+    fn write_back(&mut self, fh: u64) -> Result<(), c_int> {
+        let file = self.open.get_mut(&fh).ok_or(EBADF)?;
+        if file.dirty {
+            self.inner.write_file(file.ino, file.data.clone())?;
+            file.dirty = false;
+        }
+        Ok(())
+    }
+
+    /// Returns the end of a range of an open file as an index into its contents, failing if the
+    /// file can't grow that large
+    fn end(&self, offset: i64, len: u64) -> Result<usize, c_int> {
+        let offset = u64::try_from(offset).map_err(|_| EINVAL)?;
+        let end = offset.checked_add(len).ok_or(EFBIG)?;
+        if end > self.max_file_size {
+            return Err(EFBIG);
+        }
+        usize::try_from(end).map_err(|_| EFBIG)
+    }
+
+    /// Write to an open file, extending it as needed
+    fn write_at(&mut self, fh: u64, offset: i64, data: &[u8]) -> Result<(), c_int> {
+        if !self.open.get(&fh).is_some_and(|f| f.writable) {
+            return Err(EBADF);
+        }
+        let end = self.end(offset, data.len() as u64)?;
+        let file = self.open.get_mut(&fh).unwrap();
+        if file.data.len() < end {
+            file.data.resize(end, 0);
+        }
+        file.data[end - data.len()..end].copy_from_slice(data);
+        file.dirty = true;
+        Ok(())
+    }
+
+    /// Truncate or extend a file to the given size, through the given handle if it is open
+    fn truncate(&mut self, ino: u64, fh: Option<u64>, size: u64) -> Result<(), c_int> {
+        let size = self.end(0, size)?;
+        match fh.filter(|fh| self.open.get(fh).is_some_and(|f| f.ino == ino)) {
+            Some(fh) => {
+                let file = self.open.get_mut(&fh).unwrap();
+                file.data.resize(size, 0);
+                file.dirty = true;
+                self.write_back(fh)?;
+            }
+            None => {
+                let mut data = self.inner.read_file(ino)?;
+                data.resize(size, 0);
+                self.inner.write_file(ino, data)?;
+            }
+        }
+        // Other handles of the file see the new size as well
+        for file in self.open.values_mut().filter(|f| f.ino == ino) {
+            file.data.resize(size, 0);
+        }
+        Ok(())
+    }
+}
+
+impl<F: Filesystem + WholeFileFilesystem> Filesystem for WholeFileFs<F> {
+    // `write_stream` keeps its default, which passes the data to `write` below
+    forward_filesystem!(
+        inner,
+        except: setattr, open, read, write, flush, release, fsync, create, fallocate, lseek,
+        copy_file_range, write_stream
+    );
+
+    fn setattr(
+        &mut self,
+        req: &Request<'_>,
+        ino: u64,
+        mode: Option<u32>,
+        uid: Option<u32>,
+        gid: Option<u32>,
+        size: Option<u64>,
+        atime: Option<TimeOrNow>,
+        mtime: Option<TimeOrNow>,
+        ctime: Option<SystemTime>,
+        fh: Option<u64>,
+        crtime: Option<SystemTime>,
+        chgtime: Option<SystemTime>,
+        bkuptime: Option<SystemTime>,
+        flags: Option<u32>,
+        reply: ReplyAttr,
+    ) {
+        if let Some(size) = size {
+            if let Err(err) = self.truncate(ino, fh, size) {
+                reply.error(err);
+                return;
+            }
+        }
+        self.inner.setattr(
+            req, ino, mode, uid, gid, size, atime, mtime, ctime, fh, crtime, chgtime, bkuptime,
+            flags, reply,
+        )
+    }
+
+    fn open(&mut self, _req: &Request<'_>, ino: u64, flags: i32, reply: ReplyOpen) {
+        match self.open_file(ino, flags) {
+            Ok(fh) => reply.opened(fh, 0),
+            Err(err) => reply.error(err),
+        }
+    }
+
+    fn read(
+        &mut self,
+        _req: &Request<'_>,
+        _ino: u64,
+        fh: u64,
+        offset: i64,
+        size: u32,
+        _flags: i32,
+        _lock_owner: Option<u64>,
+        reply: ReplyData,
+    ) {
+        let Some(file) = self.open.get(&fh) else {
+            reply.error(EBADF);
+            return;
+        };
+        let start = (offset.max(0) as usize).min(file.data.len());
+        let end = start.saturating_add(size as usize).min(file.data.len());
+        reply.data(&file.data[start..end]);
+    }
+
+    fn write(
+        &mut self,
+        _req: &Request<'_>,
+        _ino: u64,
+        fh: u64,
+        offset: i64,
+        data: &[u8],
+        _write_flags: u32,
+        _flags: i32,
+        _lock_owner: Option<u64>,
+        reply: ReplyWrite,
+    ) {
+        match self.write_at(fh, offset, data) {
+            Ok(()) => reply.written(data.len() as u32),
+            Err(err) => reply.error(err),
+        }
+    }
+
+    fn flush(
+        &mut self,
+        _req: &Request<'_>,
+        _ino: u64,
+        fh: u64,
+        _lock_owner: u64,
+        reply: ReplyEmpty,
+    ) {
+        match self.write_back(fh) {
+            Ok(()) => reply.ok(),
+            Err(err) => reply.error(err),
+        }
+    }
+
+    fn release(
+        &mut self,
+        _req: &Request<'_>,
+        _ino: u64,
+        fh: u64,
+        _flags: i32,
+        _lock_owner: Option<u64>,
+        _flush: bool,
+        reply: ReplyEmpty,
+    ) {
+        let result = self.write_back(fh);
+        self.open.remove(&fh);
+        match result {
+            Ok(()) => reply.ok(),
+            Err(err) => reply.error(err),
+        }
+    }
+
+    fn fsync(
+        &mut self,
+        _req: &Request<'_>,
+        _ino: u64,
+        fh: u64,
+        _datasync: bool,
+        reply: ReplyEmpty,
+    ) {
+        match self.write_back(fh) {
+            Ok(()) => reply.ok(),
+            Err(err) => reply.error(err),
+        }
+    }
+
+    fn create(
+        &mut self,
+        _req: &Request<'_>,
+        parent: u64,
+        name: &OsStr,
+        mode: u32,
+        umask: u32,
+        flags: i32,
+        reply: ReplyCreate,
+    ) {
+        let result = self
+            .inner
+            .create_file(parent, name, mode, umask)
+            .and_then(|(ttl, attr)| Ok((ttl, attr, self.open_file(attr.ino, flags)?)));
+        match result {
+            Ok((ttl, attr, fh)) => reply.created(ttl, &attr, 0, fh, 0),
+            Err(err) => reply.error(err),
+        }
+    }
+
+    fn fallocate(
+        &mut self,
+        _req: &Request<'_>,
+        _ino: u64,
+        fh: u64,
+        offset: i64,
+        length: i64,
+        mode: i32,
+        reply: ReplyEmpty,
+    ) {
+        // Contents are held in memory, so there is nothing to allocate but the size
+        if mode != 0 {
+            return reply.error(EOPNOTSUPP);
+        }
+        if !self.open.get(&fh).is_some_and(|f| f.writable) {
+            return reply.error(EBADF);
+        }
+        let end = match u64::try_from(length).map_err(|_| EINVAL) {
+            Ok(length) => self.end(offset, length),
+            Err(err) => Err(err),
+        };
+        match end {
+            Ok(end) => {
+                let file = self.open.get_mut(&fh).unwrap();
+                if file.data.len() < end {
+                    file.data.resize(end, 0);
+                    file.dirty = true;
+                }
+                reply.ok();
+            }
+            Err(err) => reply.error(err),
+        }
+    }
+
+    fn lseek(
+        &mut self,
+        _req: &Request<'_>,
+        _ino: u64,
+        fh: u64,
+        offset: i64,
+        whence: i32,
+        reply: ReplyLseek,
+    ) {
+        let Some(file) = self.open.get(&fh) else {
+            return reply.error(EBADF);
+        };
+        let len = file.data.len() as i64;
+        // Files have no holes, their contents are one run of data
+        let result = match whence {
+            SEEK_SET | SEEK_CUR => Some(offset),
+            SEEK_END => len.checked_add(offset),
+            SEEK_DATA if (0..len).contains(&offset) => Some(offset),
+            SEEK_HOLE if (0..len).contains(&offset) => Some(len),
+            SEEK_DATA | SEEK_HOLE => return reply.error(ENXIO),
+            _ => None,
+        };
+        match result.filter(|offset| *offset >= 0) {
+            Some(offset) => reply.offset(offset),
+            None => reply.error(EINVAL),
+        }
+    }
+
+    fn copy_file_range(
+        &mut self,
+        _req: &Request<'_>,
+        _ino_in: u64,
+        fh_in: u64,
+        offset_in: i64,
+        _ino_out: u64,
+        fh_out: u64,
+        offset_out: i64,
+        len: u64,
+        _flags: u32,
+        reply: ReplyWrite,
+    ) {
+        let Some(source) = self.open.get(&fh_in) else {
+            return reply.error(EBADF);
+        };
+        let Ok(start) = usize::try_from(offset_in) else {
+            return reply.error(EINVAL);
+        };
+        let start = start.min(source.data.len());
+        let len = len.min(u32::MAX.into()) as usize;
+        let data = source.data[start..start.saturating_add(len).min(source.data.len())].to_vec();
+        match self.write_at(fh_out, offset_out, &data) {
+            Ok(()) => reply.written(data.len() as u32),
+            Err(err) => reply.error(err),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{WholeFileFilesystem, WholeFileFs};
+    use crate::channel::Channel;
+    use crate::reply::{Reply, ReplySender};
+    use crate::{ConnectionInfo, FileAttr, FileType, Filesystem, Request, Ttl};
+    use libc::{
+        c_int, EBADF, EFBIG, ENOENT, ENXIO, O_RDONLY, O_RDWR, O_TRUNC, SEEK_DATA, SEEK_HOLE,
+    };
+    use std::collections::HashMap;
+    use std::ffi::OsStr;
+    use std::io::IoSlice;
+    use std::sync::{Arc, Mutex};
+    use std::time::{Instant, UNIX_EPOCH};
+
+    #[derive(Default)]
+    struct MapFs(HashMap<u64, Vec<u8>>);
+
+    impl crate::Filesystem for MapFs {}
+
+    impl WholeFileFilesystem for MapFs {
+        fn read_file(&mut self, ino: u64) -> Result<Vec<u8>, c_int> {
+            self.0.get(&ino).cloned().ok_or(ENOENT)
+        }
+
+        fn write_file(&mut self, ino: u64, data: Vec<u8>) -> Result<(), c_int> {
+            self.0.insert(ino, data);
+            Ok(())
+        }
+
+        fn create_file(
+            &mut self,
+            _parent: u64,
+            _name: &OsStr,
+            _mode: u32,
+            _umask: u32,
+        ) -> Result<(Ttl, FileAttr), c_int> {
+            let ino = self.0.len() as u64 + 2;
+            self.0.insert(ino, vec![]);
+            let attr = FileAttr {
+                ino,
+                size: 0,
+                blocks: 0,
+                atime: UNIX_EPOCH,
+                mtime: UNIX_EPOCH,
+                ctime: UNIX_EPOCH,
+                crtime: UNIX_EPOCH,
+                kind: FileType::RegularFile,
+                perm: 0o644,
+                nlink: 1,
+                uid: 0,
+                gid: 0,
+                rdev: 0,
+                blksize: 4096,
+                flags: 0,
+            };
+            Ok((Ttl::NONE, attr))
+        }
+    }
+
+    /// Collects the replies sent through it
+    #[derive(Clone, Default)]
+    struct Replies(Arc<Mutex<Vec<Vec<u8>>>>);
+
+    impl ReplySender for Replies {
+        fn send(&self, data: &[IoSlice<'_>]) -> std::io::Result<()> {
+            let reply = data.iter().flat_map(|x| x.iter().copied()).collect();
+            self.0.lock().unwrap().push(reply);
+            Ok(())
+        }
+    }
+
+    impl Replies {
+        fn reply<R: Reply>(&self) -> R {
+            Reply::new(1, self.clone())
+        }
+
+        /// Returns the error code and the data of the last reply
+        fn last(&self) -> (c_int, Vec<u8>) {
+            let reply = self.0.lock().unwrap().last().unwrap().clone();
+            let error = i32::from_ne_bytes(reply[4..8].try_into().unwrap());
+            (-error, reply[16..].to_vec())
+        }
+
+        /// Returns the file handle of the last `open` or `create` reply, which ends with
+        /// `fuse_open_out`
+        fn fh(&self) -> u64 {
+            let (error, data) = self.last();
+            assert_eq!(error, 0);
+            u64::from_ne_bytes(data[data.len() - 16..][..8].try_into().unwrap())
+        }
+    }
+
+    /// A `statfs` request, the filesystem methods don't look at it
+    fn header() -> Vec<u8> {
+        let mut data = vec![];
+        data.extend_from_slice(&40u32.to_ne_bytes());
+        data.extend_from_slice(&17u32.to_ne_bytes());
+        data.extend_from_slice(&1u64.to_ne_bytes());
+        data.extend_from_slice(&1u64.to_ne_bytes());
+        data.extend_from_slice(&[0; 16]);
+        data
+    }
+
+    fn request(header: &[u8]) -> Request<'_> {
+        let channel = Channel::new(Arc::new(tempfile::tempfile().unwrap()));
+        let connection = ConnectionInfo::default();
+        Request::new(channel.sender(), header, connection, Instant::now()).unwrap()
+    }
+
+    #[test]
+    fn open_write_back() {
+        let header = header();
+        let req = request(&header);
+        let replies = Replies::default();
+        let mut fs = WholeFileFs::new(MapFs::default());
+        fs.inner_mut().0.insert(2, b"hello".to_vec());
+
+        fs.open(&req, 3, O_RDONLY, replies.reply());
+        assert_eq!(replies.last().0, ENOENT);
+        fs.open(&req, 2, O_RDONLY, replies.reply());
+        let readonly = replies.fh();
+        fs.write(&req, 2, readonly, 0, b"x", 0, 0, None, replies.reply());
+        assert_eq!(replies.last().0, EBADF);
+
+        fs.open(&req, 2, O_RDWR, replies.reply());
+        let fh = replies.fh();
+        fs.write(&req, 2, fh, 5, b" world", 0, 0, None, replies.reply());
+        assert_eq!(
+            replies.last(),
+            (0, 6u32.to_ne_bytes().into_iter().chain([0; 4]).collect())
+        );
+        fs.read(&req, 2, fh, 0, 100, 0, None, replies.reply());
+        assert_eq!(replies.last(), (0, b"hello world".to_vec()));
+        // Changes are only written back on flush
+        assert_eq!(fs.inner().0[&2], b"hello");
+        fs.flush(&req, 2, fh, 0, replies.reply());
+        assert_eq!(fs.inner().0[&2], b"hello world");
+        fs.release(&req, 2, fh, 0, None, false, replies.reply());
+        fs.read(&req, 2, fh, 0, 100, 0, None, replies.reply());
+        assert_eq!(replies.last().0, EBADF);
+
+        fs.open(&req, 2, O_RDWR | O_TRUNC, replies.reply());
+        let truncated = replies.fh();
+        fs.release(&req, 2, truncated, 0, None, false, replies.reply());
+        assert_eq!(fs.inner().0[&2], b"");
+    }
+
+    #[test]
+    fn create() {
+        let header = header();
+        let req = request(&header);
+        let replies = Replies::default();
+        let mut fs = WholeFileFs::new(MapFs::default());
+
+        fs.create(&req, 1, OsStr::new("f"), 0o644, 0, O_RDWR, replies.reply());
+        let fh = replies.fh();
+        fs.write(&req, 2, fh, 0, b"new", 0, 0, None, replies.reply());
+        assert_eq!(replies.last().0, 0);
+        fs.release(&req, 2, fh, 0, None, false, replies.reply());
+        assert_eq!(fs.inner().0[&2], b"new");
+    }
+
+    #[test]
+    fn truncate() {
+        let header = header();
+        let req = request(&header);
+        let replies = Replies::default();
+        let mut fs = WholeFileFs::new(MapFs::default());
+        fs.inner_mut().0.insert(2, b"hello".to_vec());
+        fs.open(&req, 2, O_RDONLY, replies.reply());
+        let fh = replies.fh();
+
+        let setattr = |fs: &mut WholeFileFs<MapFs>, fh: Option<u64>, size: u64| {
+            fs.setattr(
+                &req,
+                2,
+                None,
+                None,
+                None,
+                Some(size),
+                None,
+                None,
+                None,
+                fh,
+                None,
+                None,
+                None,
+                None,
+                replies.reply(),
+            );
+        };
+        setattr(&mut fs, None, 2);
+        assert_eq!(fs.inner().0[&2], b"he");
+        // Open handles see the new size
+        fs.read(&req, 2, fh, 0, 100, 0, None, replies.reply());
+        assert_eq!(replies.last(), (0, b"he".to_vec()));
+        setattr(&mut fs, Some(fh), 4);
+        assert_eq!(fs.inner().0[&2], b"he\0\0");
+    }
+
+    #[test]
+    fn max_file_size() {
+        let header = header();
+        let req = request(&header);
+        let replies = Replies::default();
+        let mut fs = WholeFileFs::new(MapFs::default()).with_max_file_size(8);
+        fs.inner_mut().0.insert(2, vec![]);
+        fs.open(&req, 2, O_RDWR, replies.reply());
+        let fh = replies.fh();
+
+        fs.write(&req, 2, fh, 4, b"1234", 0, 0, None, replies.reply());
+        assert_eq!(replies.last().0, 0);
+        fs.write(&req, 2, fh, 5, b"1234", 0, 0, None, replies.reply());
+        assert_eq!(replies.last().0, EFBIG);
+        fs.write(&req, 2, fh, i64::MAX, b"1", 0, 0, None, replies.reply());
+        assert_eq!(replies.last().0, EFBIG);
+        fs.fallocate(&req, 2, fh, 0, 9, 0, replies.reply());
+        assert_eq!(replies.last().0, EFBIG);
+        fs.copy_file_range(&req, 2, fh, 0, 2, fh, 4, 8, 0, replies.reply());
+        assert_eq!(replies.last().0, EFBIG);
+    }
+
+    #[test]
+    fn cached_operations() {
+        let header = header();
+        let req = request(&header);
+        let replies = Replies::default();
+        let mut fs = WholeFileFs::new(MapFs::default());
+        fs.inner_mut().0.insert(2, b"hello".to_vec());
+        fs.open(&req, 2, O_RDWR, replies.reply());
+        let fh = replies.fh();
+
+        fs.copy_file_range(&req, 2, fh, 1, 2, fh, 5, 100, 0, replies.reply());
+        assert_eq!(replies.last().1[..4], 4u32.to_ne_bytes());
+        fs.fallocate(&req, 2, fh, 0, 12, 0, replies.reply());
+        assert_eq!(replies.last().0, 0);
+        fs.read(&req, 2, fh, 0, 100, 0, None, replies.reply());
+        assert_eq!(replies.last(), (0, b"helloello\0\0\0".to_vec()));
+
+        fs.lseek(&req, 2, fh, 3, SEEK_DATA, replies.reply());
+        assert_eq!(replies.last(), (0, 3i64.to_ne_bytes().to_vec()));
+        fs.lseek(&req, 2, fh, 3, SEEK_HOLE, replies.reply());
+        assert_eq!(replies.last(), (0, 12i64.to_ne_bytes().to_vec()));
+        fs.lseek(&req, 2, fh, 12, SEEK_DATA, replies.reply());
+        assert_eq!(replies.last().0, ENXIO);
+    }
+}
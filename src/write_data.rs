@@ -0,0 +1,112 @@
+//! Streaming access to the data of write requests
+//!
+//! With a large `max_write`, a single write request carries up to 16 MiB. Filesystems that
+//! forward writes to a backend in smaller chunks (e.g. the parts of a multipart upload) would
+//! otherwise copy the payload into buffers of their own. [`WriteData`] reads it in place, from
+//! the buffer the session read the request into, as an [`io::Read`](std::io::Read) that hands
+//! it out in pieces of the caller's choosing.
+
+use std::io::{self, BufRead, Read};
+
+/// The data of a write request, see
+/// [`Filesystem::write_stream`](crate::Filesystem::write_stream)
+///
+/// The data is only borrowed from the session's buffer, which is reused for the next request
+/// once the handler returns. Handlers that reply later have to copy what they still need.
+#[derive(Debug, Clone)]
+pub struct WriteData<'a> {
+    data: &'a [u8],
+    /// Bytes consumed so far
+    pos: usize,
+}
+
+impl<'a> WriteData<'a> {
+    pub(crate) fn new(data: &'a [u8]) -> Self {
+        Self { data, pos: 0 }
+    }
+
+    /// Shorten the data to its first `len` bytes.
+    pub(crate) fn truncate(mut self, len: usize) -> Self {
+        self.data = &self.data[..len.min(self.data.len())];
+        self.pos = self.pos.min(self.data.len());
+        self
+    }
+
+    /// Returns the size of the whole write
+    pub fn len(&self) -> usize {
+        self.data.len()
+    }
+
+    /// Returns whether the write carries no data
+    pub fn is_empty(&self) -> bool {
+        self.data.is_empty()
+    }
+
+    /// Returns the number of bytes not read yet
+    pub fn remaining(&self) -> usize {
+        self.data.len() - self.pos
+    }
+
+    /// Returns the data not read yet, without consuming it
+    pub fn unread(&self) -> &'a [u8] {
+        &self.data[self.pos..]
+    }
+
+    /// Returns the next chunk of at most `size` bytes and consumes it, or `None` once all data
+    /// was read.
+    pub fn next_chunk(&mut self, size: usize) -> Option<&'a [u8]> {
+        let chunk = &self.unread()[..size.min(self.remaining())];
+        if chunk.is_empty() {
+            return None;
+        }
+        self.pos += chunk.len();
+        Some(chunk)
+    }
+}
+
+impl Read for WriteData<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let len = buf.len().min(self.remaining());
+        buf[..len].copy_from_slice(&self.unread()[..len]);
+        self.pos += len;
+        Ok(len)
+    }
+}
+
+impl BufRead for WriteData<'_> {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        Ok(self.unread())
+    }
+
+    fn consume(&mut self, amt: usize) {
+        self.pos += amt.min(self.remaining());
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::WriteData;
+    use std::io::Read;
+
+    #[test]
+    fn read_in_chunks() {
+        let payload: Vec<u8> = (0..100).collect();
+        let mut data = WriteData::new(&payload);
+        let mut buf = [0; 30];
+        assert_eq!(data.read(&mut buf).unwrap(), 30);
+        assert_eq!(buf[..], payload[..30]);
+        assert_eq!(data.remaining(), 70);
+        assert_eq!(data.next_chunk(64), Some(&payload[30..94]));
+        assert_eq!(data.next_chunk(64), Some(&payload[94..]));
+        assert_eq!(data.next_chunk(64), None);
+        assert_eq!(data.read(&mut buf).unwrap(), 0);
+        assert_eq!(data.len(), 100);
+
+        let mut rest = vec![];
+        WriteData::new(&payload)
+            .truncate(10)
+            .read_to_end(&mut rest)
+            .unwrap();
+        assert_eq!(rest, payload[..10]);
+    }
+}
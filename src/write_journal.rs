@@ -0,0 +1,325 @@
+//! Bookkeeping of written data for `flush` and `fsync`
+//!
+//! Filesystems that buffer writes have to keep track of what was written through which file
+//! handle, so that `flush` writes it back and `fsync` makes it durable, in that order, and an
+//! `fsync` that succeeds really means that all data written before it survives a crash. A
+//! [`WriteJournal`] does this bookkeeping: the filesystem records writes and directory changes,
+//! and passes hooks that write back and persist data to `flush`, `fsync` and `fsyncdir`.
+
+use libc::c_int;
+use std::collections::{HashMap, HashSet};
+use std::ops::Range;
+
+/// Sorted, non-overlapping byte ranges
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+struct RangeSet(Vec<Range<u64>>);
+
+impl RangeSet {
+    fn insert(&mut self, range: Range<u64>) {
+        if range.is_empty() {
+            return;
+        }
+        // Ranges that overlap or touch the new one are merged into it
+        let first = self.0.partition_point(|r| r.end < range.start);
+        let last = self.0.partition_point(|r| r.start <= range.end);
+        let merged = if first < last {
+            self.0[first].start.min(range.start)..self.0[last - 1].end.max(range.end)
+        } else {
+            range
+        };
+        self.0.splice(first..last, [merged]);
+    }
+
+    fn extend(&mut self, other: &RangeSet) {
+        for range in &other.0 {
+            self.insert(range.clone());
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+}
+
+/// Data written through a file handle that wasn't written back yet
+#[derive(Debug)]
+struct Handle {
+    ino: u64,
+    dirty: RangeSet,
+}
+
+/// Tracks which data still has to be written back and which still has to be made durable.
+///
+/// Data is *dirty* from when it is [recorded](WriteJournal::write) until a
+/// [`flush`](WriteJournal::flush) of its file handle wrote it back, and *unsynced* from then on
+/// until an [`fsync`](WriteJournal::fsync) of its inode made it durable. Hooks are only told
+/// about ranges they have to handle, and data only changes state when the hook succeeds, so a
+/// failed hook can be retried by the next call. Ranges are byte ranges of the file.
+///
+/// Directories are dirty from a [recorded change](WriteJournal::dir_changed) of their entries
+/// until an [`fsyncdir`](WriteJournal::fsyncdir) made them durable.
+#[derive(Debug, Default)]
+pub struct WriteJournal {
+    /// Dirty data of each open file handle
+    handles: HashMap<u64, Handle>,
+    /// Unsynced data of each inode, whichever handle it was written through
+    unsynced: HashMap<u64, RangeSet>,
+    /// Directories with unsynced entries
+    dirs: HashSet<u64>,
+}
+
+impl WriteJournal {
+    /// Create an empty journal.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Record that `len` bytes were written at `offset` to inode `ino` through file handle `fh`.
+    pub fn write(&mut self, ino: u64, fh: u64, offset: u64, len: u64) {
+        let handle = self.handles.entry(fh).or_insert_with(|| Handle {
+            ino,
+            dirty: RangeSet::default(),
+        });
+        handle.dirty.insert(offset..offset.saturating_add(len));
+    }
+
+    /// Record that entries of the directory `ino` changed (e.g. by `create`, `unlink` or
+    /// `rename`).
+    pub fn dir_changed(&mut self, ino: u64) {
+        self.dirs.insert(ino);
+    }
+
+    /// Returns the data written through `fh` that wasn't written back yet.
+    pub fn dirty(&self, fh: u64) -> &[Range<u64>] {
+        self.handles.get(&fh).map_or(&[], |h| &h.dirty.0)
+    }
+
+    /// Returns the data of inode `ino` that was written back but isn't durable yet.
+    pub fn unsynced(&self, ino: u64) -> &[Range<u64>] {
+        self.unsynced.get(&ino).map_or(&[], |r| &r.0)
+    }
+
+    /// Returns whether entries of the directory `ino` changed since it was last synced.
+    pub fn dir_is_dirty(&self, ino: u64) -> bool {
+        self.dirs.contains(&ino)
+    }
+
+    /// Write back the dirty data of `fh`. `write_back` is called with the inode and the dirty
+    /// ranges, unless there are none. Call it from [`Filesystem::flush`](crate::Filesystem::flush).
+    pub fn flush(
+        &mut self,
+        fh: u64,
+        write_back: impl FnOnce(u64, &[Range<u64>]) -> Result<(), c_int>,
+    ) -> Result<(), c_int> {
+        let Some(handle) = self.handles.get_mut(&fh) else {
+            return Ok(());
+        };
+        if handle.dirty.is_empty() {
+            return Ok(());
+        }
+        write_back(handle.ino, &handle.dirty.0)?;
+        let dirty = std::mem::take(&mut handle.dirty);
+        self.unsynced.entry(handle.ino).or_default().extend(&dirty);
+        Ok(())
+    }
+
+    /// Make all data written to inode `ino` durable, through any file handle. The dirty data of
+    /// every handle of the inode is passed to `write_back` first, then `sync` is called with the
+    /// inode and all of its unsynced ranges. `sync` is also called if there is nothing to sync,
+    /// because the inode's metadata may have to be persisted, too. Call it from
+    /// [`Filesystem::fsync`](crate::Filesystem::fsync).
+    pub fn fsync(
+        &mut self,
+        ino: u64,
+        mut write_back: impl FnMut(u64, &[Range<u64>]) -> Result<(), c_int>,
+        sync: impl FnOnce(u64, &[Range<u64>]) -> Result<(), c_int>,
+    ) -> Result<(), c_int> {
+        let mut handles: Vec<u64> = self
+            .handles
+            .iter()
+            .filter(|(_, h)| h.ino == ino)
+            .map(|(fh, _)| *fh)
+            .collect();
+        handles.sort_unstable();
+        for fh in handles {
+            self.flush(fh, &mut write_back)?;
+        }
+        sync(ino, self.unsynced(ino))?;
+        self.unsynced.remove(&ino);
+        Ok(())
+    }
+
+    /// Make the entries of the directory `ino` durable. `sync` is called with the inode if its
+    /// entries changed since it was last synced. Call it from
+    /// [`Filesystem::fsyncdir`](crate::Filesystem::fsyncdir).
+    pub fn fsyncdir(
+        &mut self,
+        ino: u64,
+        sync: impl FnOnce(u64) -> Result<(), c_int>,
+    ) -> Result<(), c_int> {
+        if !self.dirs.contains(&ino) {
+            return Ok(());
+        }
+        sync(ino)?;
+        self.dirs.remove(&ino);
+        Ok(())
+    }
+
+    /// Stop tracking the file handle `fh`. Returns the data written through it that was never
+    /// written back, which is lost unless the caller writes it back now. Data that was written
+    /// back stays unsynced until the next `fsync` of the inode. Call it from
+    /// [`Filesystem::release`](crate::Filesystem::release).
+    pub fn release(&mut self, fh: u64) -> Vec<Range<u64>> {
+        self.handles.remove(&fh).map_or(vec![], |h| h.dirty.0)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{RangeSet, WriteJournal};
+    use libc::EIO;
+    use std::cell::RefCell;
+    use std::ops::Range;
+
+    #[test]
+    fn merge_ranges() {
+        let mut set = RangeSet::default();
+        set.insert(10..20);
+        set.insert(30..40);
+        set.insert(0..0);
+        assert_eq!(set.0, vec![10..20, 30..40]);
+        set.insert(20..25);
+        assert_eq!(set.0, vec![10..25, 30..40]);
+        set.insert(5..35);
+        assert_eq!(set.0, vec![5..40]);
+        set.insert(50..60);
+        set.insert(0..1);
+        assert_eq!(set.0, vec![0..1, 5..40, 50..60]);
+    }
+
+    /// Backend that logs what is written back and synced, and fails when told to, like a crash
+    #[derive(Default)]
+    struct Backend {
+        log: RefCell<Vec<String>>,
+        crash: RefCell<bool>,
+    }
+
+    impl Backend {
+        fn write_back(&self, ino: u64, ranges: &[Range<u64>]) -> Result<(), i32> {
+            if *self.crash.borrow() {
+                return Err(EIO);
+            }
+            self.log
+                .borrow_mut()
+                .push(format!("write {} {:?}", ino, ranges));
+            Ok(())
+        }
+
+        fn sync(&self, ino: u64, ranges: &[Range<u64>]) -> Result<(), i32> {
+            if *self.crash.borrow() {
+                return Err(EIO);
+            }
+            self.log
+                .borrow_mut()
+                .push(format!("sync {} {:?}", ino, ranges));
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn fsync_writes_back_first() {
+        let backend = Backend::default();
+        let mut journal = WriteJournal::new();
+        journal.write(2, 10, 0, 100);
+        journal.write(2, 11, 4096, 100);
+        journal.write(3, 12, 0, 1);
+        journal
+            .flush(10, |ino, r| backend.write_back(ino, r))
+            .unwrap();
+        assert_eq!(journal.dirty(10), []);
+        assert_eq!(journal.unsynced(2), vec![0..100]);
+        journal
+            .fsync(
+                2,
+                |ino, r| backend.write_back(ino, r),
+                |ino, r| backend.sync(ino, r),
+            )
+            .unwrap();
+        assert_eq!(
+            *backend.log.borrow(),
+            [
+                "write 2 [0..100]",
+                "write 2 [4096..4196]",
+                "sync 2 [0..100, 4096..4196]"
+            ]
+        );
+        assert_eq!(journal.unsynced(2), []);
+        assert_eq!(journal.dirty(12), vec![0..1]);
+    }
+
+    #[test]
+    fn crash_before_sync() {
+        let backend = Backend::default();
+        let mut journal = WriteJournal::new();
+        journal.write(2, 10, 0, 100);
+        journal
+            .flush(10, |ino, r| backend.write_back(ino, r))
+            .unwrap();
+        assert_eq!(journal.release(10), vec![]);
+        *backend.crash.borrow_mut() = true;
+        let res = journal.fsync(
+            2,
+            |ino, r| backend.write_back(ino, r),
+            |ino, r| backend.sync(ino, r),
+        );
+        assert_eq!(res, Err(EIO));
+        // Nothing was made durable, the data must still be synced
+        assert_eq!(journal.unsynced(2), vec![0..100]);
+        *backend.crash.borrow_mut() = false;
+        journal
+            .fsync(
+                2,
+                |ino, r| backend.write_back(ino, r),
+                |ino, r| backend.sync(ino, r),
+            )
+            .unwrap();
+        assert_eq!(journal.unsynced(2), []);
+    }
+
+    #[test]
+    fn crash_during_write_back() {
+        let backend = Backend::default();
+        let mut journal = WriteJournal::new();
+        journal.write(2, 10, 0, 100);
+        *backend.crash.borrow_mut() = true;
+        assert_eq!(
+            journal.flush(10, |ino, r| backend.write_back(ino, r)),
+            Err(EIO)
+        );
+        assert_eq!(journal.dirty(10), vec![0..100]);
+        assert_eq!(journal.release(10), vec![0..100]);
+    }
+
+    #[test]
+    fn fsyncdir() {
+        let mut journal = WriteJournal::new();
+        let mut synced = vec![];
+        journal
+            .fsyncdir(1, |ino| {
+                synced.push(ino);
+                Ok(())
+            })
+            .unwrap();
+        journal.dir_changed(1);
+        assert!(journal.dir_is_dirty(1));
+        assert_eq!(journal.fsyncdir(1, |_| Err(EIO)), Err(EIO));
+        journal
+            .fsyncdir(1, |ino| {
+                synced.push(ino);
+                Ok(())
+            })
+            .unwrap();
+        assert!(!journal.dir_is_dirty(1));
+        assert_eq!(synced, [1]);
+    }
+}
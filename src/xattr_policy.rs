@@ -0,0 +1,411 @@
+//! Filtering of extended attribute namespaces
+//!
+//! Filesystems that pass extended attributes through to some backend usually shouldn't expose
+//! all of them, e.g. unprivileged users shouldn't be able to set `trusted.*` attributes, and ACL
+//! attributes might have to be hidden when the backend doesn't enforce them. [`XattrPolicy`]
+//! wraps any filesystem and enforces such rules before the filesystem sees the request, so that
+//! they are the same across backends.
+
+use libc::{c_int, EIO, ENOTSUP, EPERM, ERANGE};
+use std::ffi::OsStr;
+use std::io::{self, IoSlice};
+use std::mem::size_of;
+use std::os::unix::ffi::OsStrExt;
+use std::sync::{Arc, Mutex};
+
+use crate::forward::forward_filesystem;
+use crate::ll::fuse_abi::fuse_out_header;
+use crate::ll::Errno;
+use crate::reply::{Reply, ReplySender};
+use crate::{fuse_opcode, Filesystem, ReplyEmpty, ReplyXattr, Request};
+
+/// Size of the buffer the inner filesystem is asked to list the attribute names into, the
+/// largest list the kernel accepts (`XATTR_LIST_MAX`)
+const XATTR_LIST_MAX: u32 = 65536;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Rule {
+    /// Fail with EPERM, unless the requester is root and `allow_root` is set
+    Reject { allow_root: bool },
+    /// Behave as if the attribute didn't exist and can't be set
+    Hide,
+}
+
+/// Rules by name prefix, in the order they were added
+#[derive(Debug, Clone, Default)]
+struct Rules(Vec<(Vec<u8>, Rule)>);
+
+impl Rules {
+    fn rule(&self, name: &[u8], uid: u32) -> Option<Rule> {
+        self.0
+            .iter()
+            .find(|(prefix, _)| name.starts_with(prefix))
+            .map(|(_, rule)| *rule)
+            .filter(|rule| *rule != Rule::Reject { allow_root: true } || uid != 0)
+    }
+
+    /// Returns the error to fail the given operation on the named attribute with, if the rules
+    /// don't allow it.
+    fn check(&self, op: fuse_opcode, name: &OsStr, uid: u32) -> Result<(), c_int> {
+        match self.rule(name.as_bytes(), uid) {
+            None => Ok(()),
+            Some(Rule::Reject { .. }) => Err(EPERM),
+            Some(Rule::Hide) if op == fuse_opcode::FUSE_SETXATTR => Err(ENOTSUP),
+            Some(Rule::Hide) => Err(Errno::NO_XATTR.into()),
+        }
+    }
+
+    /// Remove names the given user may not see from a `listxattr` reply body (a sequence of
+    /// NUL terminated names).
+    fn filter_names(&self, names: &[u8], uid: u32) -> Vec<u8> {
+        let mut filtered = Vec::with_capacity(names.len());
+        for name in names.split_inclusive(|b| *b == 0) {
+            let bare = name.strip_suffix(&[0]).unwrap_or(name);
+            if self.rule(bare, uid).is_none() {
+                filtered.extend_from_slice(name);
+            }
+        }
+        filtered
+    }
+}
+
+/// A filesystem wrapper that rejects or hides extended attributes by name prefix.
+///
+/// Attributes matched by a rule are removed from `listxattr` replies, and `getxattr`,
+/// `setxattr` and `removexattr` of them fail without calling the inner filesystem. If several
+/// rules match a name, the first one added wins.
+#[derive(Debug)]
+pub struct XattrPolicy<F> {
+    inner: F,
+    /// Shared with the `listxattr` replies that filter the names
+    rules: Arc<Rules>,
+}
+
+impl<F: Filesystem> XattrPolicy<F> {
+    /// Wrap a filesystem, allowing all extended attributes.
+    pub fn new(inner: F) -> Self {
+        Self {
+            inner,
+            rules: Arc::default(),
+        }
+    }
+
+    /// Fail all operations on attributes starting with `prefix` with EPERM.
+    pub fn reject(self, prefix: &str) -> Self {
+        self.add(prefix, Rule::Reject { allow_root: false })
+    }
+
+    /// Fail operations on attributes starting with `prefix` with EPERM, unless they are made by
+    /// root.
+    pub fn reject_unprivileged(self, prefix: &str) -> Self {
+        self.add(prefix, Rule::Reject { allow_root: true })
+    }
+
+    /// Pretend attributes starting with `prefix` don't exist: reading and removing them fails
+    /// with ENODATA (ENOATTR on macOS) and setting them with ENOTSUP.
+    pub fn hide(self, prefix: &str) -> Self {
+        self.add(prefix, Rule::Hide)
+    }
+
+    fn add(mut self, prefix: &str, rule: Rule) -> Self {
+        Arc::make_mut(&mut self.rules)
+            .0
+            .push((prefix.as_bytes().to_vec(), rule));
+        self
+    }
+
+    /// Returns whether the attribute with the given name is listed for the given user.
+    pub fn is_visible(&self, name: &OsStr, uid: u32) -> bool {
+        self.rules.rule(name.as_bytes(), uid).is_none()
+    }
+
+    /// Returns a reference to the inner filesystem.
+    pub fn inner(&self) -> &F {
+        &self.inner
+    }
+
+    /// Returns a mutable reference to the inner filesystem.
+    pub fn inner_mut(&mut self) -> &mut F {
+        &mut self.inner
+    }
+
+    /// Unwrap the inner filesystem.
+    pub fn into_inner(self) -> F {
+        self.inner
+    }
+}
+
+/// Answers a `listxattr` request with the names the inner filesystem listed, minus the hidden
+/// ones
+struct ListFilter {
+    rules: Arc<Rules>,
+    uid: u32,
+    /// Size of the buffer of the request, 0 if it only asks for the length of the list
+    size: u32,
+    reply: Mutex<Option<ReplyXattr>>,
+}
+
+impl ReplySender for ListFilter {
+    fn send(&self, data: &[IoSlice<'_>]) -> io::Result<()> {
+        let Some(reply) = self.reply.lock().unwrap().take() else {
+            return Ok(());
+        };
+        let data: Vec<u8> = data.iter().flat_map(|b| b.iter().copied()).collect();
+        if data.len() < size_of::<fuse_out_header>() {
+            reply.error(EIO);
+            return Ok(());
+        }
+        let (header, names) = data.split_at(size_of::<fuse_out_header>());
+        let error = i32::from_ne_bytes(header[4..8].try_into().unwrap());
+        if error != 0 {
+            reply.error(-error);
+            return Ok(());
+        }
+        let names = self.rules.filter_names(names, self.uid);
+        match self.size {
+            0 => reply.size(names.len() as u32),
+            size if names.len() > size as usize => reply.error(ERANGE),
+            _ => reply.data(&names),
+        }
+        Ok(())
+    }
+}
+
+impl<F: Filesystem> Filesystem for XattrPolicy<F> {
+    forward_filesystem!(inner, except: setxattr, getxattr, listxattr, removexattr);
+
+    fn setxattr(
+        &mut self,
+        req: &Request<'_>,
+        ino: u64,
+        name: &OsStr,
+        value: &[u8],
+        flags: i32,
+        position: u32,
+        reply: ReplyEmpty,
+    ) {
+        match self
+            .rules
+            .check(fuse_opcode::FUSE_SETXATTR, name, req.uid())
+        {
+            Ok(()) => self
+                .inner
+                .setxattr(req, ino, name, value, flags, position, reply),
+            Err(err) => reply.error(err),
+        }
+    }
+
+    fn getxattr(
+        &mut self,
+        req: &Request<'_>,
+        ino: u64,
+        name: &OsStr,
+        size: u32,
+        reply: ReplyXattr,
+    ) {
+        match self
+            .rules
+            .check(fuse_opcode::FUSE_GETXATTR, name, req.uid())
+        {
+            Ok(()) => self.inner.getxattr(req, ino, name, size, reply),
+            Err(err) => reply.error(err),
+        }
+    }
+
+    fn listxattr(&mut self, req: &Request<'_>, ino: u64, size: u32, reply: ReplyXattr) {
+        // The inner filesystem is always asked for the names, because the length of the
+        // filtered list can't be told from the length of the whole list
+        let filter = ListFilter {
+            rules: self.rules.clone(),
+            uid: req.uid(),
+            size,
+            reply: Mutex::new(Some(reply)),
+        };
+        self.inner
+            .listxattr(req, ino, XATTR_LIST_MAX, Reply::new(req.unique(), filter))
+    }
+
+    fn removexattr(&mut self, req: &Request<'_>, ino: u64, name: &OsStr, reply: ReplyEmpty) {
+        match self
+            .rules
+            .check(fuse_opcode::FUSE_REMOVEXATTR, name, req.uid())
+        {
+            Ok(()) => self.inner.removexattr(req, ino, name, reply),
+            Err(err) => reply.error(err),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::XattrPolicy;
+    use crate::channel::Channel;
+    use crate::reply::{Reply, ReplySender};
+    use crate::{ConnectionInfo, Filesystem, ReplyEmpty, ReplyXattr, Request};
+    use libc::{c_int, ENOTSUP, EPERM, ERANGE};
+    use std::ffi::OsStr;
+    use std::io::IoSlice;
+    use std::sync::{Arc, Mutex};
+    use std::time::Instant;
+
+    struct ListFs;
+    impl Filesystem for ListFs {
+        fn getxattr(
+            &mut self,
+            _req: &Request<'_>,
+            _ino: u64,
+            _name: &OsStr,
+            _size: u32,
+            reply: ReplyXattr,
+        ) {
+            reply.data(b"value");
+        }
+
+        fn setxattr(
+            &mut self,
+            _req: &Request<'_>,
+            _ino: u64,
+            _name: &OsStr,
+            _value: &[u8],
+            _flags: i32,
+            _position: u32,
+            reply: ReplyEmpty,
+        ) {
+            reply.ok();
+        }
+
+        fn listxattr(&mut self, _req: &Request<'_>, _ino: u64, size: u32, reply: ReplyXattr) {
+            let names = b"user.a\0trusted.b\0system.posix_acl_access\0user.c\0";
+            if size == 0 {
+                reply.size(names.len() as u32);
+            } else {
+                reply.data(names);
+            }
+        }
+    }
+
+    /// Collects the replies sent through it
+    #[derive(Clone, Default)]
+    struct Replies(Arc<Mutex<Vec<Vec<u8>>>>);
+
+    impl ReplySender for Replies {
+        fn send(&self, data: &[IoSlice<'_>]) -> std::io::Result<()> {
+            let reply = data.iter().flat_map(|x| x.iter().copied()).collect();
+            self.0.lock().unwrap().push(reply);
+            Ok(())
+        }
+    }
+
+    impl Replies {
+        fn reply<R: Reply>(&self) -> R {
+            Reply::new(1, self.clone())
+        }
+
+        /// Returns the error code and the data of the last reply
+        fn last(&self) -> (c_int, Vec<u8>) {
+            let reply = self.0.lock().unwrap().last().unwrap().clone();
+            let error = i32::from_ne_bytes(reply[4..8].try_into().unwrap());
+            (-error, reply[16..].to_vec())
+        }
+    }
+
+    /// A `statfs` request of the given user, the filesystem methods don't look at the rest
+    fn header(uid: u32) -> Vec<u8> {
+        let mut data = vec![];
+        data.extend_from_slice(&40u32.to_ne_bytes());
+        data.extend_from_slice(&17u32.to_ne_bytes());
+        data.extend_from_slice(&1u64.to_ne_bytes());
+        data.extend_from_slice(&1u64.to_ne_bytes());
+        data.extend_from_slice(&uid.to_ne_bytes());
+        data.extend_from_slice(&[0; 12]);
+        data
+    }
+
+    fn request(header: &[u8]) -> Request<'_> {
+        let channel = Channel::new(Arc::new(tempfile::tempfile().unwrap()));
+        let connection = ConnectionInfo::default();
+        Request::new(channel.sender(), header, connection, Instant::now()).unwrap()
+    }
+
+    fn policy() -> XattrPolicy<ListFs> {
+        XattrPolicy::new(ListFs)
+            .reject_unprivileged("trusted.")
+            .hide("system.posix_acl_")
+            .reject("security.")
+    }
+
+    #[test]
+    fn check() {
+        let mut fs = policy();
+        let replies = Replies::default();
+        let (user, root) = (header(1000), header(0));
+        let (user, root) = (request(&user), request(&root));
+        let name = OsStr::new;
+
+        fs.setxattr(
+            &user,
+            1,
+            name("trusted.overlay.opaque"),
+            b"y",
+            0,
+            0,
+            replies.reply(),
+        );
+        assert_eq!(replies.last().0, EPERM);
+        fs.setxattr(
+            &root,
+            1,
+            name("trusted.overlay.opaque"),
+            b"y",
+            0,
+            0,
+            replies.reply(),
+        );
+        assert_eq!(replies.last().0, 0);
+        fs.getxattr(
+            &root,
+            1,
+            name("system.posix_acl_access"),
+            0,
+            replies.reply(),
+        );
+        assert_ne!(replies.last().0, 0);
+        fs.setxattr(
+            &root,
+            1,
+            name("system.posix_acl_access"),
+            b"",
+            0,
+            0,
+            replies.reply(),
+        );
+        assert_eq!(replies.last().0, ENOTSUP);
+        fs.removexattr(&root, 1, name("security.selinux"), replies.reply());
+        assert_eq!(replies.last().0, EPERM);
+        fs.getxattr(&user, 1, name("user.foo"), 16, replies.reply());
+        assert_eq!(replies.last(), (0, b"value".to_vec()));
+        assert!(!fs.is_visible(name("trusted.b"), 1000));
+        assert!(fs.is_visible(name("trusted.b"), 0));
+    }
+
+    #[test]
+    fn filter_names() {
+        let mut fs = policy();
+        let replies = Replies::default();
+        let (user, root) = (header(1000), header(0));
+        let (user, root) = (request(&user), request(&root));
+
+        fs.listxattr(&user, 1, 64, replies.reply());
+        assert_eq!(replies.last(), (0, b"user.a\0user.c\0".to_vec()));
+        fs.listxattr(&root, 1, 64, replies.reply());
+        assert_eq!(replies.last(), (0, b"user.a\0trusted.b\0user.c\0".to_vec()));
+        // Asking for the length gets the length of the filtered list
+        fs.listxattr(&user, 1, 0, replies.reply());
+        assert_eq!(
+            replies.last(),
+            (0, 14u32.to_ne_bytes().into_iter().chain([0; 4]).collect())
+        );
+        fs.listxattr(&user, 1, 10, replies.reply());
+        assert_eq!(replies.last().0, ERANGE);
+    }
+}
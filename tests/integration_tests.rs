@@ -1,10 +1,16 @@
 // No integration tests for non-Linux targets, so turn off the module for now.
 #![cfg(target_os = "linux")]
 
-use fuser::{Filesystem, Session};
+use fuser::{FileAttr, FileType, Filesystem, ReplyAttr, ReplyEntry, Request, Session, SessionExit};
+use std::collections::HashMap;
+use std::ffi::{OsStr, OsString};
+use std::fs;
+use std::io::{Read, Write};
+use std::os::unix::fs::{FileTypeExt, MetadataExt};
+use std::os::unix::net::{UnixListener, UnixStream};
 use std::rc::Rc;
 use std::thread;
-use std::time::Duration;
+use std::time::{Duration, UNIX_EPOCH};
 use tempfile::TempDir;
 
 #[test]
@@ -24,5 +30,250 @@ fn unmount_no_send() {
         thread::sleep(Duration::from_secs(1));
         unmounter.unmount().unwrap();
     });
-    session.run().unwrap();
+    assert!(matches!(session.run(), SessionExit::Unmounted));
+}
+
+#[test]
+fn special_files() {
+    // Flat filesystem which only supports creating special files in its root directory
+    #[derive(Default)]
+    struct SpecialFS {
+        nodes: HashMap<OsString, FileAttr>,
+    }
+
+    fn attr(ino: u64, kind: FileType, perm: u16, rdev: u32) -> FileAttr {
+        FileAttr {
+            ino,
+            size: 0,
+            blocks: 0,
+            atime: UNIX_EPOCH,
+            mtime: UNIX_EPOCH,
+            ctime: UNIX_EPOCH,
+            crtime: UNIX_EPOCH,
+            kind,
+            perm,
+            nlink: if kind == FileType::Directory { 2 } else { 1 },
+            uid: 0,
+            gid: 0,
+            rdev,
+            blksize: 512,
+            flags: 0,
+        }
+    }
+
+    impl Filesystem for SpecialFS {
+        fn lookup(&mut self, _req: &Request<'_>, _parent: u64, name: &OsStr, reply: ReplyEntry) {
+            match self.nodes.get(name) {
+                Some(attr) => reply.entry(Duration::from_secs(60), attr, 0),
+                None => reply.error(libc::ENOENT),
+            }
+        }
+
+        fn getattr(&mut self, _req: &Request<'_>, ino: u64, _fh: Option<u64>, reply: ReplyAttr) {
+            if ino == fuser::FUSE_ROOT_ID {
+                let root = attr(ino, FileType::Directory, 0o777, 0);
+                return reply.attr(Duration::from_secs(60), &root);
+            }
+            match self.nodes.values().find(|attr| attr.ino == ino) {
+                Some(attr) => reply.attr(Duration::from_secs(60), attr),
+                None => reply.error(libc::ENOENT),
+            }
+        }
+
+        fn mknod(
+            &mut self,
+            _req: &Request<'_>,
+            _parent: u64,
+            name: &OsStr,
+            mode: u32,
+            umask: u32,
+            rdev: u32,
+            reply: ReplyEntry,
+        ) {
+            let Some(kind) = FileType::from_mode(mode) else {
+                return reply.error(libc::EINVAL);
+            };
+            let perm = (mode & !umask & 0o7777) as u16;
+            let attr = attr(self.nodes.len() as u64 + 2, kind, perm, rdev);
+            reply.entry(Duration::from_secs(60), &attr, 0);
+            self.nodes.insert(name.to_owned(), attr);
+        }
+    }
+
+    let tmpdir: TempDir = tempfile::tempdir().unwrap();
+    let session = Session::new(SpecialFS::default(), tmpdir.path(), &[]).unwrap();
+    let background = session.spawn().unwrap();
+
+    let fifo = tmpdir.path().join("fifo");
+    nix::unistd::mkfifo(&fifo, nix::sys::stat::Mode::from_bits_truncate(0o640)).unwrap();
+    let metadata = fs::symlink_metadata(&fifo).unwrap();
+    assert!(metadata.file_type().is_fifo());
+    assert_eq!(metadata.mode() & libc::S_IFMT, libc::S_IFIFO);
+    // Pipes live in the kernel, so data flows without the filesystem serving any reads
+    let writer = thread::spawn({
+        let fifo = fifo.clone();
+        move || {
+            fs::OpenOptions::new()
+                .write(true)
+                .open(fifo)
+                .unwrap()
+                .write_all(b"hello")
+        }
+    });
+    let mut data = String::new();
+    fs::File::open(&fifo)
+        .unwrap()
+        .read_to_string(&mut data)
+        .unwrap();
+    writer.join().unwrap().unwrap();
+    assert_eq!(data, "hello");
+
+    let socket = tmpdir.path().join("socket");
+    let listener = UnixListener::bind(&socket).unwrap();
+    assert!(fs::symlink_metadata(&socket)
+        .unwrap()
+        .file_type()
+        .is_socket());
+    let mut client = UnixStream::connect(&socket).unwrap();
+    let (mut server, _) = listener.accept().unwrap();
+    client.write_all(b"ping").unwrap();
+    let mut buf = [0; 4];
+    server.read_exact(&mut buf).unwrap();
+    assert_eq!(&buf, b"ping");
+
+    if nix::unistd::geteuid().is_root() {
+        let device = tmpdir.path().join("null");
+        nix::sys::stat::mknod(
+            &device,
+            nix::sys::stat::SFlag::S_IFCHR,
+            nix::sys::stat::Mode::from_bits_truncate(0o600),
+            nix::sys::stat::makedev(1, 3),
+        )
+        .unwrap();
+        let metadata = fs::symlink_metadata(&device).unwrap();
+        assert!(metadata.file_type().is_char_device());
+        assert_eq!(metadata.rdev(), nix::sys::stat::makedev(1, 3));
+    }
+
+    drop(background);
+}
+
+#[test]
+#[cfg(all(target_os = "linux", feature = "abi-7-11"))]
+fn poll_wakeup_latency() {
+    use fuser::{consts, PollEvents, PollHandle, PollRegistry, ReplyOpen, ReplyPoll};
+    use std::os::fd::AsRawFd;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::{Arc, Mutex};
+    use std::time::Instant;
+
+    // A single file, which becomes readable when the test says so
+    struct PollFS {
+        ready: Arc<AtomicBool>,
+        polls: PollRegistry,
+    }
+
+    impl Filesystem for PollFS {
+        fn lookup(&mut self, _req: &Request<'_>, _parent: u64, name: &OsStr, reply: ReplyEntry) {
+            match name.to_str() {
+                Some("file") => reply.entry(Duration::ZERO, &file_attr(2), 0),
+                _ => reply.error(libc::ENOENT),
+            }
+        }
+
+        fn getattr(&mut self, _req: &Request<'_>, ino: u64, _fh: Option<u64>, reply: ReplyAttr) {
+            reply.attr(Duration::ZERO, &file_attr(ino));
+        }
+
+        fn open(&mut self, _req: &Request<'_>, _ino: u64, _flags: i32, reply: ReplyOpen) {
+            reply.opened(1, consts::FOPEN_DIRECT_IO);
+        }
+
+        fn poll(
+            &mut self,
+            _req: &Request<'_>,
+            _ino: u64,
+            fh: u64,
+            ph: PollHandle,
+            _events: PollEvents,
+            flags: u32,
+            reply: ReplyPoll,
+        ) {
+            if self.ready.load(Ordering::SeqCst) {
+                return reply.poll(PollEvents::IN);
+            }
+            if flags & consts::FUSE_POLL_SCHEDULE_NOTIFY != 0 {
+                self.polls.register(fh, ph);
+            }
+            reply.poll(PollEvents::empty());
+        }
+    }
+
+    fn file_attr(ino: u64) -> FileAttr {
+        let kind = if ino == fuser::FUSE_ROOT_ID {
+            FileType::Directory
+        } else {
+            FileType::RegularFile
+        };
+        FileAttr {
+            ino,
+            size: 0,
+            blocks: 0,
+            atime: UNIX_EPOCH,
+            mtime: UNIX_EPOCH,
+            ctime: UNIX_EPOCH,
+            crtime: UNIX_EPOCH,
+            kind,
+            perm: 0o755,
+            nlink: 1,
+            uid: 0,
+            gid: 0,
+            rdev: 0,
+            blksize: 512,
+            flags: 0,
+        }
+    }
+
+    let ready = Arc::new(AtomicBool::new(false));
+    let polls = PollRegistry::new();
+    let fs = PollFS {
+        ready: ready.clone(),
+        polls: polls.clone(),
+    };
+    let tmpdir: TempDir = tempfile::tempdir().unwrap();
+    let session = Session::new(fs, tmpdir.path(), &[]).unwrap();
+    let background = session.spawn().unwrap();
+
+    let file = fs::File::open(tmpdir.path().join("file")).unwrap();
+    let marked = Arc::new(Mutex::new(None));
+    let waker = thread::spawn({
+        let marked = marked.clone();
+        move || {
+            // Wait until the client polls, then mark the file ready
+            while polls.is_empty() {
+                thread::sleep(Duration::from_millis(1));
+            }
+            ready.store(true, Ordering::SeqCst);
+            *marked.lock().unwrap() = Some(Instant::now());
+            assert!(polls.notify(1).unwrap());
+        }
+    });
+    let mut pollfd = libc::pollfd {
+        fd: file.as_raw_fd(),
+        events: libc::POLLIN,
+        revents: 0,
+    };
+    assert_eq!(unsafe { libc::poll(&mut pollfd, 1, 5000) }, 1);
+    let woken = Instant::now();
+    waker.join().unwrap();
+    assert_eq!(pollfd.revents & libc::POLLIN, libc::POLLIN);
+
+    // The kernel polls the file again after the notification, so this includes a round trip
+    // through the session. It takes microseconds; the bound only catches lost or stuck
+    // notifications.
+    let latency = woken - marked.lock().unwrap().unwrap();
+    assert!(latency < Duration::from_secs(1), "{:?}", latency);
+
+    drop(file);
+    drop(background);
 }